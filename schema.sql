@@ -1,5 +1,9 @@
 CREATE TYPE role AS ENUM ('admin', 'asdos', 'user');
-CREATE TYPE build_state AS ENUM ('pending', 'building', 'successful', 'failed');
+CREATE TYPE build_state AS ENUM ('pending', 'building', 'successful', 'failed', 'cancelled');
+CREATE TYPE egress_policy AS ENUM ('allow', 'deny', 'internal-only');
+CREATE TYPE announcement_severity AS ENUM ('info', 'warning', 'critical');
+CREATE TYPE rebuild_state AS ENUM ('pending', 'queued', 'successful', 'failed', 'cancelled');
+CREATE TYPE notification_type AS ENUM ('deploy_failed', 'crash_loop', 'quota_warning', 'collaborator_added');
 
 CREATE TABLE users (
   id          UUID          NOT NULL,
@@ -18,6 +22,8 @@ CREATE TABLE users (
 CREATE TABLE project_owners (
   id          UUID          NOT NULL,
   name        TEXT          NOT NULL,
+  -- replaces git.pushmessage for the owner's projects, set by admins
+  push_message TEXT,
   created_at  TIMESTAMPTZ   NOT NULL default now(),
   updated_at  TIMESTAMPTZ   NOT NULL default now(),
   deleted_at  TIMESTAMPTZ,
@@ -25,6 +31,10 @@ CREATE TABLE project_owners (
   PRIMARY KEY (id)
 );
 
+-- added once no two owners share a name, databases made before it are checked for duplicates
+-- first by projects::repo::unique_owner_names
+ALTER TABLE project_owners ADD CONSTRAINT unique_owner_name UNIQUE (name);
+
 CREATE TABLE users_owners (
   user_id     UUID          NOT NULL,
   owner_id    UUID          NOT NULL,
@@ -42,6 +52,22 @@ CREATE TABLE projects (
   owner_id    UUID          NOT NULL,
   name        TEXT          NOT NULL,
   environs    JSONB         NOT NULL default '{"PRODUCTION": "true"}'::jsonb,
+  -- bumped on every environs write, used as ETag for optimistic concurrency
+  environs_version BIGINT   NOT NULL default 0,
+  -- name of the project's containers, volume and Traefik router, see docker::container_name_for.
+  -- projects made before it was stored get their old name at startup, its constraints follow below
+  container_name TEXT,
+  -- outbound traffic of the project's containers, set by staff
+  egress_policy egress_policy NOT NULL default 'allow',
+  -- cron schedule of automatic rebuilds that refresh the base image, none when they are off
+  auto_rebuild TEXT,
+  -- pushes skip the secret and file size checks, set by staff
+  skip_push_checks BOOLEAN NOT NULL default false,
+  -- shown on the project page and in the project list
+  description TEXT,
+  website_url TEXT,
+  -- framework and Dockerfile detected from the repository, keyed by its key files' blob hashes
+  detection   JSONB,
   created_at  TIMESTAMPTZ   NOT NULL default now(),
   updated_at  TIMESTAMPTZ   NOT NULL default now(),
   deleted_at  TIMESTAMPTZ,
@@ -50,6 +76,16 @@ CREATE TABLE projects (
   FOREIGN KEY (owner_id) REFERENCES project_owners(id) ON DELETE CASCADE ON UPDATE CASCADE
 );
 
+-- added once every project has a container name of its own, databases made before names were
+-- stored are backfilled in between by projects::repo::backfill_container_names
+ALTER TABLE projects ALTER COLUMN container_name SET NOT NULL;
+ALTER TABLE projects ADD CONSTRAINT unique_container_name UNIQUE (container_name);
+
+-- project lookups filter on project_owners.name and users_owners.user_id, both already covered
+-- by unique_owner_name and the users_owners primary key
+CREATE INDEX projects_owner_id_name_idx ON projects (owner_id, name);
+CREATE INDEX users_owners_owner_id_idx ON users_owners (owner_id);
+
 CREATE TABLE domains (
   id          UUID          NOT NULL,
   project_id  UUID          NOT NULL,
@@ -71,11 +107,30 @@ CREATE TABLE api_token (
   created_at  TIMESTAMPTZ   NOT NULL default now(),
   updated_at  TIMESTAMPTZ   NOT NULL default now(),
   deleted_at  TIMESTAMPTZ,
+  last_push_at  TIMESTAMPTZ,
+  last_fetch_at TIMESTAMPTZ,
 
   PRIMARY KEY (id),
   FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE ON UPDATE CASCADE
 );
 
+-- redeploys of many projects at once started by staff, e.g. after a fix to a Dockerfile
+-- template. see rebuild.rs
+CREATE TABLE rebuild_batches (
+  id UUID NOT NULL PRIMARY KEY,
+  created_by UUID,
+  -- what the projects were picked by, see rebuild::Filter
+  filter JSONB NOT NULL,
+  -- projects rebuilding at once, and seconds between rounds
+  concurrency INTEGER NOT NULL,
+  pause_secs INTEGER NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  finished_at TIMESTAMPTZ,
+  cancelled_at TIMESTAMPTZ,
+
+  FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE SET NULL ON UPDATE CASCADE
+);
+
 CREATE TABLE builds (
   id UUID NOT NULL PRIMARY KEY,
   project_id UUID NOT NULL,
@@ -83,12 +138,217 @@ CREATE TABLE builds (
   status build_state NOT NULL DEFAULT 'pending',
   log TEXT NOT NULL DEFAULT '',
 
+  -- what got deployed, so later operations don't have to look the container up by name
+  container_id TEXT,
+  image_id TEXT,
+  image_digest TEXT,
+  -- image the project was restarted from after a failed deploy
+  recovered_from TEXT,
+  -- detected framework, and whether the Dockerfile came from the repository or was generated
+  framework TEXT,
+  dockerfile TEXT,
+  -- what started the build, push, scheduled or manual
+  trigger TEXT NOT NULL DEFAULT 'push',
+  -- rebuild batch of a manual build
+  batch_id UUID,
+  -- commit of the checkout the image was built from
+  commit_sha TEXT,
+  -- findings of the image scan by severity, see scan::ScanSummary
+  scan_summary JSONB,
+  -- what the container of a successful build runs with, see deploy_snapshot::Snapshot
+  runtime_config JSONB,
+
   created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
   updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  -- when a builder picked the build up, so build durations exclude the time spent queued
+  started_at TIMESTAMPTZ,
   finished_at TIMESTAMPTZ,
 
+  FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  FOREIGN KEY (batch_id) REFERENCES rebuild_batches(id) ON DELETE SET NULL ON UPDATE CASCADE
+);
+
+-- projects of a rebuild batch, the build of each is the one with the batch's id
+CREATE TABLE rebuild_batch_projects (
+  batch_id UUID NOT NULL,
+  project_id UUID NOT NULL,
+  status rebuild_state NOT NULL DEFAULT 'pending',
+  -- why the project failed when there is no build log telling it
+  error TEXT,
+  queued_at TIMESTAMPTZ,
+  finished_at TIMESTAMPTZ,
+
+  PRIMARY KEY (batch_id, project_id),
+  FOREIGN KEY (batch_id) REFERENCES rebuild_batches(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- last time the owner was warned about a project nearing a quota, cleared once usage drops
+-- below the threshold so the warning fires again next time
+CREATE TABLE quota_notifications (
+  project_id UUID NOT NULL,
+  quota TEXT NOT NULL,
+  notified_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+  PRIMARY KEY (project_id, quota),
+  FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- public keys that can push to and fetch a project over ssh
+CREATE TABLE ssh_keys (
+  id UUID NOT NULL PRIMARY KEY,
+  project_id UUID NOT NULL,
+  name TEXT NOT NULL,
+  key_type TEXT NOT NULL,
+  -- base64 key data, as in authorized_keys
+  public_key TEXT NOT NULL,
+  fingerprint TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+  UNIQUE (project_id, fingerprint),
   FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE ON UPDATE CASCADE
 );
+CREATE INDEX ssh_keys_public_key_idx ON ssh_keys (public_key);
+
+-- subdomain each project is served on, so two projects or a project and a platform service
+-- can never share a host. rows without a project are reserved for the platform
+CREATE TABLE subdomain_claims (
+  subdomain TEXT NOT NULL PRIMARY KEY,
+  project_id UUID UNIQUE,
+  claimed_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+  FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- actions taken through the admin api
+CREATE TABLE audit_log (
+  id UUID NOT NULL PRIMARY KEY,
+  user_id UUID NOT NULL,
+  action TEXT NOT NULL,
+  details JSONB NOT NULL DEFAULT '{}'::jsonb,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- platform-wide messages shown on the dashboard and in git push output while active
+CREATE TABLE announcements (
+  id UUID NOT NULL PRIMARY KEY,
+  message TEXT NOT NULL,
+  severity announcement_severity NOT NULL DEFAULT 'info',
+  starts_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  -- shown until removed when null
+  ends_at TIMESTAMPTZ NULL,
+  -- hold or reject new builds while the announcement is active
+  pause_deploys BOOLEAN NOT NULL DEFAULT false,
+  created_by UUID NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+  FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE SET NULL ON UPDATE CASCADE
+);
+
+CREATE INDEX announcements_window_idx ON announcements (starts_at, ends_at);
+
+-- requests served to each project per hour, read from the Traefik access log
+CREATE TABLE project_traffic (
+  project_id UUID NOT NULL,
+  hour TIMESTAMPTZ NOT NULL,
+  requests BIGINT NOT NULL DEFAULT 0,
+  status_2xx BIGINT NOT NULL DEFAULT 0,
+  status_3xx BIGINT NOT NULL DEFAULT 0,
+  status_4xx BIGINT NOT NULL DEFAULT 0,
+  status_5xx BIGINT NOT NULL DEFAULT 0,
+
+  PRIMARY KEY (project_id, hour),
+  FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- how far the access log has been counted, updated with the counts so a restart doesn't count
+-- a line twice. a different inode means the log was rotated
+CREATE TABLE traffic_log_offsets (
+  path TEXT NOT NULL PRIMARY KEY,
+  inode BIGINT NOT NULL,
+  "offset" BIGINT NOT NULL,
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- read-only links to the status and build logs of a project, for people without an account
+CREATE TABLE project_shares (
+  id UUID NOT NULL PRIMARY KEY,
+  project_id UUID NOT NULL,
+  -- sha256 of the token, the token itself is only shown when the share is created
+  token_hash TEXT NOT NULL UNIQUE,
+  created_by UUID,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  -- never expires when null
+  expires_at TIMESTAMPTZ,
+
+  FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE SET NULL ON UPDATE CASCADE
+);
+
+-- responses to POST requests sent with an Idempotency-Key, replayed when the request is retried
+CREATE TABLE idempotency_keys (
+  user_id UUID NOT NULL,
+  key TEXT NOT NULL,
+  -- method, path and body of the request, a key reused for another request is rejected
+  request_hash TEXT NOT NULL,
+  -- null while the request is running
+  response_status SMALLINT NULL,
+  -- null for responses holding credentials, see idempotency::Sensitive
+  response_body BYTEA NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+  PRIMARY KEY (user_id, key),
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX idempotency_keys_created_at_idx ON idempotency_keys (created_at);
+
+-- logins of a user, to limit how many sessions they have at once
+CREATE TABLE user_sessions (
+  -- kept in the session, it outlives the session id renewed on login
+  id            UUID          NOT NULL,
+  user_id       UUID          NOT NULL,
+  created_at    TIMESTAMPTZ   NOT NULL default now(),
+  -- written at most hourly, sessions unused for longer than auth.lifespan have expired
+  last_seen_at  TIMESTAMPTZ   NOT NULL default now(),
+  -- signed out for going over auth.maxsessions, on the session's next request
+  evicted_at    TIMESTAMPTZ,
+
+  PRIMARY KEY (id),
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX user_sessions_user_id_idx ON user_sessions (user_id, created_at);
+
+-- shown in the dashboard, one row per recipient
+CREATE TABLE notifications (
+  id UUID NOT NULL PRIMARY KEY,
+  user_id UUID NOT NULL,
+  type notification_type NOT NULL,
+  -- what it is about, e.g. the owner and project, shaped by the type
+  payload JSONB NOT NULL DEFAULT '{}'::jsonb,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  -- unread while null, read ones are pruned after notifications.retention days
+  read_at TIMESTAMPTZ NULL,
+
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX notifications_user_id_idx ON notifications (user_id, created_at);
+CREATE INDEX notifications_read_at_idx ON notifications (read_at);
+
+-- CAS tickets the sso callback accepted, a replayed ticket is rejected before it reaches CAS
+CREATE TABLE consumed_sso_tickets (
+  -- sha256 of the ticket
+  ticket_hash TEXT NOT NULL PRIMARY KEY,
+  -- purged after auth::sso_tickets::TICKET_TTL
+  used_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX consumed_sso_tickets_used_at_idx ON consumed_sso_tickets (used_at);
 
 -- for axum_auth_sessions library
 CREATE TABLE user_permissions (
@@ -0,0 +1,56 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{admin::audit, auth::Auth, projects::api::error_response, rebuild, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct CancelRebuildResponse {
+    /// projects that won't be rebuilt
+    cancelled: u64,
+}
+
+/// Stop a rebuild batch. Projects that weren't queued yet are cancelled, builds that already
+/// started run to the end.
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path(batch_id): Path<Uuid>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let cancelled = match rebuild::cancel(&pool, batch_id).await {
+        Ok(Some(cancelled)) => cancelled,
+        Ok(None) => {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                "Rebuild batch does not exist or has already ended".to_string(),
+            )
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't cancel rebuild: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    audit::record(
+        &pool,
+        user.id,
+        "rebuild.cancel",
+        serde_json::json!({ "batch_id": batch_id, "cancelled": cancelled }),
+    )
+    .await;
+
+    let json = serde_json::to_string(&CancelRebuildResponse { cancelled }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,148 @@
+use axum::extract::State;
+use axum::response::Response;
+use axum::Json;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    admin::{
+        audit,
+        orphans::{self, Orphan, Orphans},
+    },
+    auth::Auth,
+    projects::api::error_response,
+    startup::AppState,
+};
+
+fn dry_run_default() -> bool {
+    true
+}
+
+/// Ids or names of the resources to remove, as returned by the orphan listing
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CleanupOrphansRequest {
+    #[serde(default)]
+    pub containers: Vec<String>,
+    #[serde(default)]
+    pub images: Vec<String>,
+    #[serde(default)]
+    pub volumes: Vec<String>,
+    #[serde(default)]
+    pub networks: Vec<String>,
+    #[serde(default = "dry_run_default")]
+    pub dry_run: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct Skipped {
+    id: String,
+    reason: String,
+}
+
+#[derive(Serialize, Debug)]
+struct CleanupOrphansResponse {
+    dry_run: bool,
+    /// removed, or what would have been removed on a dry run
+    removed: Orphans,
+    skipped: Vec<Skipped>,
+}
+
+/// Pick the requested resources out of the current orphans. Anything else is skipped, so a
+/// resource that got referenced again since it was listed is never removed.
+fn select(orphans: Vec<Orphan>, requested: &[String], skipped: &mut Vec<Skipped>) -> Vec<Orphan> {
+    let mut selected: Vec<Orphan> = Vec::new();
+
+    for id in requested {
+        match orphans
+            .iter()
+            .find(|orphan| orphan.id == *id || orphan.name == *id)
+        {
+            Some(orphan) if selected.iter().any(|selected| selected.id == orphan.id) => {}
+            Some(orphan) => selected.push(orphan.clone()),
+            None => skipped.push(Skipped {
+                id: id.clone(),
+                reason: "Not an orphan".to_string(),
+            }),
+        }
+    }
+
+    selected
+}
+
+/// Remove a subset of the orphaned docker resources. Nothing is removed unless `dry_run` is
+/// explicitly set to false.
+#[tracing::instrument(skip(auth, pool, docker))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, docker, container_prefix, .. }): State<AppState>,
+    Json(req): Json<CleanupOrphansRequest>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let orphans = match orphans::scan(&pool, &docker, &container_prefix).await {
+        Ok(orphans) => orphans,
+        Err(err) => {
+            tracing::error!(?err, "Can't clean up orphans: Failed to scan resources");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, err.to_string());
+        }
+    };
+
+    let mut skipped = Vec::new();
+    let selected = Orphans {
+        containers: select(orphans.containers, &req.containers, &mut skipped),
+        images: select(orphans.images, &req.images, &mut skipped),
+        volumes: select(orphans.volumes, &req.volumes, &mut skipped),
+        networks: select(orphans.networks, &req.networks, &mut skipped),
+    };
+
+    let removed = if req.dry_run {
+        selected
+    } else {
+        // containers go first, docker refuses to remove images and volumes still in use
+        let mut removed = Orphans::default();
+        for (selected, removed) in [
+            (selected.containers, &mut removed.containers),
+            (selected.images, &mut removed.images),
+            (selected.volumes, &mut removed.volumes),
+            (selected.networks, &mut removed.networks),
+        ] {
+            for orphan in selected {
+                match orphan.remove(&docker).await {
+                    Ok(()) => removed.push(orphan),
+                    Err(err) => {
+                        tracing::error!(?err, id = orphan.id, "Can't remove orphan");
+                        skipped.push(Skipped {
+                            id: orphan.id,
+                            reason: format!("Failed to remove: {err}"),
+                        });
+                    }
+                }
+            }
+        }
+        removed
+    };
+
+    audit::record(
+        &pool,
+        user.id,
+        "orphans.cleanup",
+        serde_json::json!({
+            "request": req,
+            "removed": removed,
+            "skipped": skipped,
+        }),
+    )
+    .await;
+
+    let json = serde_json::to_string(&CleanupOrphansResponse {
+        dry_run: req.dry_run,
+        removed,
+        skipped,
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,87 @@
+use axum::extract::State;
+use axum::response::Response;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{
+    admin::audit,
+    announcements::{self, Severity},
+    auth::Auth,
+    projects::api::error_response,
+    startup::AppState,
+};
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CreateAnnouncementRequest {
+    pub message: String,
+    #[serde(default)]
+    pub severity: Severity,
+    /// right away when not given
+    pub starts_at: Option<DateTime<Utc>>,
+    /// shown until removed when not given
+    pub ends_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub pause_deploys: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct CreateAnnouncementResponse {
+    id: Uuid,
+}
+
+/// Announce something to every user, e.g. a maintenance window that pauses deploys
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Json(req): Json<CreateAnnouncementRequest>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let starts_at = req.starts_at.unwrap_or_else(Utc::now);
+    if let Err(message) = announcements::validate(&req.message, starts_at, req.ends_at) {
+        return error_response(StatusCode::BAD_REQUEST, message);
+    }
+
+    let id = Uuid::from(Ulid::new());
+    if let Err(err) = sqlx::query!(
+        r#"INSERT INTO announcements (id, message, severity, starts_at, ends_at, pause_deploys, created_by)
+           VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        id,
+        req.message.trim(),
+        req.severity as Severity,
+        starts_at,
+        req.ends_at,
+        req.pause_deploys,
+        user.id,
+    )
+    .execute(&pool)
+    .await
+    {
+        tracing::error!(?err, "Can't create announcement: Failed to query database");
+        return error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to query database: {}", err),
+        );
+    }
+
+    audit::record(
+        &pool,
+        user.id,
+        "announcement.create",
+        serde_json::json!({ "announcement_id": id, "request": req }),
+    )
+    .await;
+
+    let json = serde_json::to_string(&CreateAnnouncementResponse { id }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::CREATED)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,58 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{admin::audit, auth::Auth, projects::api::error_response, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct DeleteAnnouncementResponse {
+    message: String,
+}
+
+/// Remove an announcement. Builds it held start on their next check.
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path(announcement_id): Path<Uuid>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    match sqlx::query!(
+        "DELETE FROM announcements WHERE id = $1 RETURNING message",
+        announcement_id,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(record)) => {
+            audit::record(
+                &pool,
+                user.id,
+                "announcement.delete",
+                serde_json::json!({ "announcement_id": announcement_id, "message": record.message }),
+            )
+            .await;
+        }
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "Announcement does not exist".to_string()),
+        Err(err) => {
+            tracing::error!(?err, "Can't delete announcement: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    }
+
+    let json = serde_json::to_string(&DeleteAnnouncementResponse {
+        message: "Announcement deleted".to_string(),
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
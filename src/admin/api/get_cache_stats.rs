@@ -0,0 +1,25 @@
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::projects::repo::{self, CacheStats};
+
+#[derive(Serialize, Debug)]
+struct CacheStatsResponse {
+    /// project access checks of the api
+    ownership: CacheStats,
+}
+
+/// Hit rate of the per-process caches, counted since this server started
+#[tracing::instrument]
+pub async fn get() -> Response<Body> {
+    let json = serde_json::to_string(&CacheStatsResponse {
+        ownership: repo::cache_stats(),
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,15 @@
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+
+use crate::daemon_limits;
+
+/// Slots in use and wait times of the docker daemon calls, counted since this server started
+#[tracing::instrument]
+pub async fn get() -> Response<Body> {
+    let json = serde_json::to_string(&daemon_limits::stats()).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,30 @@
+use axum::extract::State;
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+
+use crate::{daemon_limits, projects::api::error_response, startup::AppState, system::usage};
+
+/// Memory and cpu the running project containers use against the host's, and the build queue
+/// depth, to tell whether the host can take more deploys. Sampling takes a few seconds.
+#[tracing::instrument(skip(pool, docker))]
+pub async fn get(State(AppState { pool, docker, build_max, .. }): State<AppState>) -> Response<Body> {
+    let _permit = match daemon_limits::interactive().await {
+        Ok(permit) => permit,
+        Err(busy) => return busy.response(),
+    };
+
+    let usage = match usage::current(&docker, &pool, build_max).await {
+        Ok(usage) => usage,
+        Err(err) => {
+            tracing::error!(?err, "Can't get platform usage");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, err.to_string());
+        }
+    };
+
+    let json = serde_json::to_string(&usage).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
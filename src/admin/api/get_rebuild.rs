@@ -0,0 +1,139 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{auth::Auth, projects::api::error_response, rebuild::RebuildState, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct RebuildProject {
+    owner: String,
+    project: String,
+    status: RebuildState,
+    /// none until the build queue took the project
+    build_id: Option<Uuid>,
+    error: Option<String>,
+    queued_at: Option<DateTime<Utc>>,
+    finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug, Default)]
+struct Progress {
+    total: usize,
+    pending: usize,
+    queued: usize,
+    successful: usize,
+    failed: usize,
+    cancelled: usize,
+}
+
+#[derive(Serialize, Debug)]
+struct RebuildResponse {
+    id: Uuid,
+    /// none when the user who started it was deleted
+    created_by: Option<String>,
+    filter: serde_json::Value,
+    concurrency: i32,
+    pause_secs: i32,
+    created_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+    cancelled_at: Option<DateTime<Utc>>,
+    progress: Progress,
+    projects: Vec<RebuildProject>,
+}
+
+/// Progress of a rebuild batch, with the outcome of every project
+#[tracing::instrument(skip(_auth, pool))]
+pub async fn get(
+    _auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path(batch_id): Path<Uuid>,
+) -> Response<Body> {
+    let batch = match sqlx::query!(
+        r#"SELECT rebuild_batches.id, users.username AS "created_by?", rebuild_batches.filter,
+                  rebuild_batches.concurrency, rebuild_batches.pause_secs, rebuild_batches.created_at,
+                  rebuild_batches.finished_at, rebuild_batches.cancelled_at
+           FROM rebuild_batches
+           LEFT JOIN users ON rebuild_batches.created_by = users.id
+           WHERE rebuild_batches.id = $1
+        "#,
+        batch_id
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(batch)) => batch,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "Rebuild batch does not exist".to_string()),
+        Err(err) => {
+            tracing::error!(?err, "Can't get rebuild: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    let projects = match sqlx::query_as!(
+        RebuildProject,
+        r#"SELECT project_owners.name AS owner, projects.name AS project,
+                  rebuild_batch_projects.status AS "status: RebuildState", builds.id AS "build_id?",
+                  rebuild_batch_projects.error, rebuild_batch_projects.queued_at,
+                  rebuild_batch_projects.finished_at
+           FROM rebuild_batch_projects
+           JOIN projects ON rebuild_batch_projects.project_id = projects.id
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           LEFT JOIN builds ON builds.batch_id = rebuild_batch_projects.batch_id
+             AND builds.project_id = rebuild_batch_projects.project_id
+           WHERE rebuild_batch_projects.batch_id = $1
+           ORDER BY project_owners.name, projects.name
+        "#,
+        batch_id
+    )
+    .fetch_all(&pool)
+    .await
+    {
+        Ok(projects) => projects,
+        Err(err) => {
+            tracing::error!(?err, "Can't get rebuild: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    let mut progress = Progress {
+        total: projects.len(),
+        ..Default::default()
+    };
+    for project in &projects {
+        match project.status {
+            RebuildState::Pending => progress.pending += 1,
+            RebuildState::Queued => progress.queued += 1,
+            RebuildState::Successful => progress.successful += 1,
+            RebuildState::Failed => progress.failed += 1,
+            RebuildState::Cancelled => progress.cancelled += 1,
+        }
+    }
+
+    let json = serde_json::to_string(&RebuildResponse {
+        id: batch.id,
+        created_by: batch.created_by,
+        filter: batch.filter,
+        concurrency: batch.concurrency,
+        pause_secs: batch.pause_secs,
+        created_at: batch.created_at,
+        finished_at: batch.finished_at,
+        cancelled_at: batch.cancelled_at,
+        progress,
+        projects,
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
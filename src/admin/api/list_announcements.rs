@@ -0,0 +1,54 @@
+use axum::extract::State;
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{announcements::Severity, auth::Auth, projects::api::error_response, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct AnnouncementItem {
+    id: Uuid,
+    message: String,
+    severity: Severity,
+    starts_at: DateTime<Utc>,
+    ends_at: Option<DateTime<Utc>>,
+    pause_deploys: bool,
+    /// whether it is shown right now
+    active: bool,
+    created_at: DateTime<Utc>,
+}
+
+/// List every announcement, past and upcoming ones included
+#[tracing::instrument(skip(_auth, pool))]
+pub async fn get(
+    _auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+) -> Response<Body> {
+    let announcements = match sqlx::query_as!(
+        AnnouncementItem,
+        r#"SELECT id, message, severity AS "severity: Severity", starts_at, ends_at, pause_deploys,
+                  (starts_at <= now() AND (ends_at IS NULL OR ends_at > now())) AS "active!",
+                  created_at
+           FROM announcements
+           ORDER BY starts_at DESC
+        "#
+    )
+    .fetch_all(&pool)
+    .await
+    {
+        Ok(announcements) => announcements,
+        Err(err) => {
+            tracing::error!(?err, "Can't list announcements: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to query database: {}", err));
+        }
+    };
+
+    let json = serde_json::to_string(&announcements).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
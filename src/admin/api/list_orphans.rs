@@ -0,0 +1,37 @@
+use axum::extract::State;
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+
+use crate::{admin::{audit, orphans}, auth::Auth, projects::api::error_response, startup::AppState};
+
+/// List the docker resources of projects that no longer exist
+#[tracing::instrument(skip(auth, pool, docker))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, docker, container_prefix, .. }): State<AppState>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let orphans = match orphans::scan(&pool, &docker, &container_prefix).await {
+        Ok(orphans) => orphans,
+        Err(err) => {
+            tracing::error!(?err, "Can't list orphans: Failed to scan resources");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, err.to_string());
+        }
+    };
+
+    audit::record(
+        &pool,
+        user.id,
+        "orphans.list",
+        serde_json::json!({ "found": orphans.len() }),
+    )
+    .await;
+
+    let json = serde_json::to_string(&orphans).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,77 @@
+use axum::extract::State;
+use axum::response::Response;
+use axum::Json;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    admin::audit,
+    auth::Auth,
+    projects::api::error_response,
+    startup::AppState,
+    telemetry::{self, LogFilterError},
+};
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct UpdateLogFilterRequest {
+    /// in `RUST_LOG` syntax, e.g. `info,pemasak_infra::docker=debug`
+    pub filter: String,
+}
+
+#[derive(Serialize, Debug)]
+struct LogFilterResponse {
+    filter: Option<String>,
+}
+
+fn filter_response() -> Response<Body> {
+    let json = serde_json::to_string(&LogFilterResponse {
+        filter: telemetry::log_filter(),
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Filter the server's logs are written with
+#[tracing::instrument]
+pub async fn get() -> Response<Body> {
+    filter_response()
+}
+
+/// Change which logs are written without restarting the server. The change lasts until the
+/// next restart, which goes back to `RUST_LOG`.
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Json(req): Json<UpdateLogFilterRequest>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+    let previous = telemetry::log_filter();
+
+    match telemetry::set_log_filter(&req.filter) {
+        Ok(()) => {}
+        Err(err @ LogFilterError::Invalid(_)) => return error_response(StatusCode::BAD_REQUEST, err.to_string()),
+        Err(err) => {
+            tracing::error!(?err, "Can't update log filter: Failed to reload filter");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, err.to_string());
+        }
+    }
+    tracing::warn!(filter = %req.filter, ?previous, "Log filter changed by {}", user.username);
+
+    audit::record(
+        &pool,
+        user.id,
+        "log.filter",
+        serde_json::json!({
+            "previous": previous,
+            "request": req,
+        }),
+    )
+    .await;
+
+    filter_response()
+}
@@ -0,0 +1,42 @@
+use axum::{middleware, Router, routing::{get, post}};
+use axum_extra::routing::RouterExt;
+use hyper::Body;
+
+use crate::{auth::admin, startup::AppState, configuration::Settings};
+
+mod list_orphans;
+mod cleanup_orphans;
+mod update_egress_policy;
+mod update_push_checks;
+mod update_push_message;
+mod list_announcements;
+mod create_announcement;
+mod update_announcement;
+mod delete_announcement;
+mod get_cache_stats;
+mod get_docker_stats;
+mod get_platform_usage;
+mod log_filter;
+mod start_rebuild;
+mod get_rebuild;
+mod cancel_rebuild;
+
+pub async fn router(_state: AppState, _config: &Settings) -> Router<AppState, Body> {
+    Router::new()
+        .route_with_tsr("/api/admin/orphans", get(list_orphans::get))
+        .route_with_tsr("/api/admin/orphans/cleanup", post(cleanup_orphans::post))
+        .route_with_tsr("/api/admin/project/:owner/:project/egress", post(update_egress_policy::post))
+        .route_with_tsr("/api/admin/project/:owner/:project/push-checks", post(update_push_checks::post))
+        .route_with_tsr("/api/admin/owner/:owner/push-message", post(update_push_message::post))
+        .route_with_tsr("/api/admin/announcements", get(list_announcements::get).post(create_announcement::post))
+        .route_with_tsr("/api/admin/announcements/:announcement_id", post(update_announcement::post))
+        .route_with_tsr("/api/admin/announcements/:announcement_id/delete", post(delete_announcement::post))
+        .route_with_tsr("/api/admin/cache", get(get_cache_stats::get))
+        .route_with_tsr("/api/admin/docker", get(get_docker_stats::get))
+        .route_with_tsr("/api/admin/usage", get(get_platform_usage::get))
+        .route_with_tsr("/api/admin/log-filter", get(log_filter::get).post(log_filter::post))
+        .route_with_tsr("/api/admin/rebuild", post(start_rebuild::post))
+        .route_with_tsr("/api/admin/rebuild/:batch_id", get(get_rebuild::get))
+        .route_with_tsr("/api/admin/rebuild/:batch_id/cancel", post(cancel_rebuild::post))
+        .route_layer(middleware::from_fn(admin))
+}
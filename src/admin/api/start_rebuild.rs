@@ -0,0 +1,120 @@
+use axum::extract::State;
+use axum::response::Response;
+use garde::Validate;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    admin::audit,
+    auth::Auth,
+    projects::api::error_response,
+    rebuild::{self, Candidate, Filter},
+    startup::AppState,
+    validation::ValidJson,
+};
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct StartRebuildRequest {
+    #[serde(flatten)]
+    #[garde(skip)]
+    pub filter: Filter,
+    /// only list the projects the filter picks
+    #[serde(default)]
+    #[garde(skip)]
+    pub dry_run: bool,
+    /// projects rebuilding at once, `rebuild.concurrency` when not given
+    #[garde(range(min = 1))]
+    pub concurrency: Option<usize>,
+    /// seconds between rounds, `rebuild.pause` when not given
+    #[garde(skip)]
+    pub pause_secs: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+struct StartRebuildResponse {
+    /// none on a dry run
+    batch_id: Option<Uuid>,
+    projects: Vec<Candidate>,
+}
+
+/// Redeploy every project matching a filter, e.g. after a fix to a Dockerfile template. The
+/// projects go through the build queue a few at a time, see `rebuild::run`.
+#[tracing::instrument(skip(auth, pool, base, build_channel))]
+pub async fn post(
+    auth: Auth,
+    State(AppState {
+        pool,
+        base,
+        build_channel,
+        rebuild: defaults,
+        ..
+    }): State<AppState>,
+    ValidJson(req): ValidJson<StartRebuildRequest>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let concurrency = req.concurrency.unwrap_or(defaults.concurrency);
+    if concurrency == 0 {
+        return error_response(StatusCode::BAD_REQUEST, "concurrency has to be at least 1".to_string());
+    }
+    let pause_secs = req.pause_secs.unwrap_or(defaults.pause);
+
+    let projects = match rebuild::matching(&pool, &req.filter).await {
+        Ok(projects) => projects,
+        Err(err) => {
+            tracing::error!(?err, "Can't start rebuild: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    let batch_id = match (req.dry_run, projects.is_empty()) {
+        (true, _) => None,
+        (false, true) => {
+            return error_response(StatusCode::BAD_REQUEST, "No deployed project matches the filter".to_string())
+        }
+        (false, false) => {
+            let ids = projects.iter().map(|project| project.id).collect::<Vec<_>>();
+            let batch_id = match rebuild::create(&pool, user.id, &req.filter, concurrency, pause_secs, &ids).await {
+                Ok(batch_id) => batch_id,
+                Err(err) => {
+                    tracing::error!(?err, "Can't start rebuild: Failed to query database");
+                    return error_response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("Failed to query database: {}", err),
+                    );
+                }
+            };
+
+            audit::record(
+                &pool,
+                user.id,
+                "rebuild.start",
+                serde_json::json!({
+                    "batch_id": batch_id,
+                    "filter": req.filter,
+                    "projects": ids.len(),
+                    "concurrency": concurrency,
+                    "pause_secs": pause_secs,
+                }),
+            )
+            .await;
+
+            tokio::spawn(rebuild::run(pool.clone(), base, build_channel, batch_id));
+            Some(batch_id)
+        }
+    };
+
+    let json = serde_json::to_string(&StartRebuildResponse { batch_id, projects }).unwrap();
+
+    Response::builder()
+        .status(match batch_id {
+            Some(_) => StatusCode::CREATED,
+            None => StatusCode::OK,
+        })
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,95 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    admin::audit,
+    announcements::{self, Severity},
+    auth::Auth,
+    projects::api::error_response,
+    startup::AppState,
+};
+
+/// Replaces the announcement, fields that aren't given get their defaults
+#[derive(Deserialize, Serialize, Debug)]
+pub struct UpdateAnnouncementRequest {
+    pub message: String,
+    #[serde(default)]
+    pub severity: Severity,
+    /// right away when not given
+    pub starts_at: Option<DateTime<Utc>>,
+    /// shown until removed when not given
+    pub ends_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub pause_deploys: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct UpdateAnnouncementResponse {
+    message: String,
+}
+
+/// Change an announcement, e.g. to end a maintenance window early
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path(announcement_id): Path<Uuid>,
+    Json(req): Json<UpdateAnnouncementRequest>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let starts_at = req.starts_at.unwrap_or_else(Utc::now);
+    if let Err(message) = announcements::validate(&req.message, starts_at, req.ends_at) {
+        return error_response(StatusCode::BAD_REQUEST, message);
+    }
+
+    match sqlx::query!(
+        r#"UPDATE announcements
+           SET message = $1, severity = $2, starts_at = $3, ends_at = $4, pause_deploys = $5, updated_at = now()
+           WHERE id = $6
+           RETURNING id
+        "#,
+        req.message.trim(),
+        req.severity as Severity,
+        starts_at,
+        req.ends_at,
+        req.pause_deploys,
+        announcement_id,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "Announcement does not exist".to_string()),
+        Err(err) => {
+            tracing::error!(?err, "Can't update announcement: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    }
+
+    audit::record(
+        &pool,
+        user.id,
+        "announcement.update",
+        serde_json::json!({ "announcement_id": announcement_id, "request": req }),
+    )
+    .await;
+
+    let json = serde_json::to_string(&UpdateAnnouncementResponse {
+        message: "Announcement updated".to_string(),
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
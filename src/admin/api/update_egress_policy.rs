@@ -0,0 +1,102 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use axum::Json;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    admin::audit,
+    auth::Auth,
+    docker::docker_name,
+    egress::{self, EgressPolicy},
+    projects::api::error_response,
+    startup::AppState,
+};
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct UpdateEgressPolicyRequest {
+    pub policy: EgressPolicy,
+}
+
+#[derive(Serialize, Debug)]
+struct UpdateEgressPolicyResponse {
+    policy: EgressPolicy,
+    /// running containers of the project moved to the policy's network
+    containers: usize,
+}
+
+/// Change what a project's containers can reach. The policy is stored for future deploys and
+/// the running containers are reconnected right away, so they don't have to be redeployed.
+#[tracing::instrument(skip(auth, pool, docker, network))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, docker, network, container_prefix, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+    Json(req): Json<UpdateEgressPolicyRequest>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let record = match sqlx::query!(
+        r#"UPDATE projects SET egress_policy = $1, updated_at = now()
+           FROM project_owners
+           WHERE projects.owner_id = project_owners.id
+           AND projects.name = $2
+           AND project_owners.name = $3
+           RETURNING projects.id, projects.container_name
+        "#,
+        req.policy as EgressPolicy,
+        project,
+        owner,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(record)) => record,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "Project does not exist".to_string()),
+        Err(err) => {
+            tracing::error!(?err, "Can't update egress policy: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    let container_name = docker_name(&container_prefix, &record.container_name);
+
+    let applied = egress::apply(&docker, req.policy, &container_name, &network).await;
+
+    audit::record(
+        &pool,
+        user.id,
+        "project.egress",
+        serde_json::json!({
+            "project_id": record.id,
+            "request": req,
+            "applied": applied.as_ref().map_err(|err| err.to_string()),
+        }),
+    )
+    .await;
+
+    let containers = match applied {
+        Ok(containers) => containers,
+        Err(err) => {
+            tracing::error!(?err, "Can't update egress policy: Failed to reconnect containers");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Policy saved, but the running containers could not be reconnected: {}", err),
+            );
+        }
+    };
+
+    let json = serde_json::to_string(&UpdateEgressPolicyResponse {
+        policy: req.policy,
+        containers,
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
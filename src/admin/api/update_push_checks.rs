@@ -0,0 +1,74 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use axum::Json;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{admin::audit, auth::Auth, projects::api::error_response, startup::AppState};
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct UpdatePushChecksRequest {
+    /// skip the secret and file size checks on pushes to the project
+    pub skip: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct UpdatePushChecksResponse {
+    skip: bool,
+}
+
+/// Let pushes to a project skip the pre-receive checks, e.g. for a course that ships a dataset
+/// on purpose. Takes effect on the next push.
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+    Json(req): Json<UpdatePushChecksRequest>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let record = match sqlx::query!(
+        r#"UPDATE projects SET skip_push_checks = $1, updated_at = now()
+           FROM project_owners
+           WHERE projects.owner_id = project_owners.id
+           AND projects.name = $2
+           AND project_owners.name = $3
+           RETURNING projects.id
+        "#,
+        req.skip,
+        project,
+        owner,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(record)) => record,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "Project does not exist".to_string()),
+        Err(err) => {
+            tracing::error!(?err, "Can't update push checks: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    audit::record(
+        &pool,
+        user.id,
+        "project.push_checks",
+        serde_json::json!({
+            "project_id": record.id,
+            "request": req,
+        }),
+    )
+    .await;
+
+    let json = serde_json::to_string(&UpdatePushChecksResponse { skip: req.skip }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
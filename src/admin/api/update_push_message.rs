@@ -0,0 +1,78 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use garde::Validate;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{admin::audit, auth::Auth, projects::api::error_response, push_message, startup::AppState, validation::ValidJson};
+
+#[derive(Deserialize, Serialize, Validate, Debug)]
+pub struct UpdatePushMessageRequest {
+    /// template of the push output footer for the owner's projects, the server's one when none
+    #[garde(custom(template))]
+    pub message: Option<String>,
+}
+
+fn template(value: &Option<String>, _ctx: &()) -> garde::Result {
+    match value.as_deref().map(push_message::validate) {
+        Some(Err(err)) => Err(garde::Error::new(format!("Invalid push message: {err}"))),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct UpdatePushMessageResponse {
+    message: Option<String>,
+}
+
+/// Give the projects of an owner their own push message, e.g. for a course sharing the server
+/// with others. Takes effect on the next push.
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path(owner): Path<String>,
+    ValidJson(req): ValidJson<UpdatePushMessageRequest>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let record = match sqlx::query!(
+        r#"UPDATE project_owners SET push_message = $1, updated_at = now()
+           WHERE name = $2 AND deleted_at IS NULL
+           RETURNING id
+        "#,
+        req.message,
+        owner,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(record)) => record,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "Owner does not exist".to_string()),
+        Err(err) => {
+            tracing::error!(?err, "Can't update push message: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    audit::record(
+        &pool,
+        user.id,
+        "owner.push_message",
+        serde_json::json!({
+            "owner_id": record.id,
+            "request": req,
+        }),
+    )
+    .await;
+
+    let json = serde_json::to_string(&UpdatePushMessageResponse { message: req.message }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
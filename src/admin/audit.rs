@@ -0,0 +1,22 @@
+use sqlx::PgPool;
+use ulid::Ulid;
+use uuid::Uuid;
+
+/// Record an action taken through the admin api. Failing to write the entry is logged but
+/// doesn't fail the action itself.
+pub async fn record(pool: &PgPool, user_id: Uuid, action: &str, details: serde_json::Value) {
+    tracing::info!(%user_id, action, %details, "Admin action");
+
+    if let Err(err) = sqlx::query!(
+        r#"INSERT INTO audit_log (id, user_id, action, details) VALUES ($1, $2, $3, $4)"#,
+        Uuid::from(Ulid::new()),
+        user_id,
+        action,
+        details,
+    )
+    .execute(pool)
+    .await
+    {
+        tracing::error!(?err, action, "Can't record admin action: Failed to insert into database");
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::{HashMap, HashSet};
+
+use bollard::{container::RemoveContainerOptions, network::ListNetworksOptions, Docker};
+use serde::Serialize;
+use sqlx::PgPool;
+use thiserror::Error;
+
+use crate::docker::{docker_name, PROJECT_LABEL};
+
+#[derive(Error, Debug)]
+pub enum ScanError {
+    #[error("Failed to query database: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("Failed to query docker: {0}")]
+    Docker(#[from] bollard::errors::Error),
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Container,
+    Image,
+    Volume,
+    Network,
+}
+
+/// A docker resource created for a project that no longer exists in the database
+#[derive(Serialize, Debug, Clone)]
+pub struct Orphan {
+    pub kind: Kind,
+    pub id: String,
+    pub name: String,
+    /// container name of the project the resource belonged to
+    pub project: String,
+    /// in bytes, when docker reports one
+    pub size: Option<i64>,
+}
+
+impl Orphan {
+    /// Remove the resource, running containers are stopped first
+    pub async fn remove(&self, docker: &Docker) -> Result<(), bollard::errors::Error> {
+        match self.kind {
+            Kind::Container => {
+                docker
+                    .remove_container(
+                        &self.id,
+                        Some(RemoveContainerOptions {
+                            force: true,
+                            ..Default::default()
+                        }),
+                    )
+                    .await
+            }
+            Kind::Image => docker.remove_image(&self.id, None, None).await.map(|_| ()),
+            Kind::Volume => docker.remove_volume(&self.name, None).await,
+            Kind::Network => docker.remove_network(&self.id).await,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct Orphans {
+    pub containers: Vec<Orphan>,
+    pub images: Vec<Orphan>,
+    pub volumes: Vec<Orphan>,
+    pub networks: Vec<Orphan>,
+}
+
+impl Orphans {
+    pub fn len(&self) -> usize {
+        self.containers.len() + self.images.len() + self.volumes.len() + self.networks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Docker names of every project row, deleted or not. The stored name is the one the
+/// project's containers run under, projects made before names were stored included, see
+/// `projects::repo::backfill_container_names`.
+async fn project_names(pool: &PgPool, prefix: &str) -> Result<HashSet<String>, sqlx::Error> {
+    let records = sqlx::query!("SELECT container_name FROM projects")
+        .fetch_all(pool)
+        .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|record| docker_name(prefix, &record.container_name))
+        .collect())
+}
+
+/// Find the project resources on the host that no project row references.
+///
+/// Resources are matched on the `pws.project` label. Containers deployed before the label was
+/// added are recognised by the Traefik router named after the container, and their images
+/// (`{name}:latest`, `{name}:old`) and volume (`{name}-volume`) by name. Projects share the
+/// `pemasak` network, so only labeled networks are considered. Resources without the
+/// instance's container prefix belong to another instance sharing the host and are skipped.
+pub async fn scan(pool: &PgPool, docker: &Docker, prefix: &str) -> Result<Orphans, ScanError> {
+    let known = project_names(pool, prefix).await?;
+    let usage = docker.df().await?;
+    let mut orphans = Orphans::default();
+
+    for container in usage.containers.unwrap_or_default() {
+        let Some(name) = container
+            .names
+            .as_ref()
+            .and_then(|names| names.first())
+            .map(|name| name.trim_start_matches('/').to_string())
+        else {
+            continue;
+        };
+
+        let labels = container.labels.unwrap_or_default();
+        let project = match labels.get(PROJECT_LABEL) {
+            Some(project) => project.clone(),
+            None if labels.contains_key(&format!("traefik.http.routers.{name}.rule")) => {
+                name.clone()
+            }
+            None => continue,
+        };
+        if known.contains(&project) || !project.starts_with(prefix) {
+            continue;
+        }
+
+        orphans.containers.push(Orphan {
+            kind: Kind::Container,
+            id: container.id.unwrap_or_else(|| name.clone()),
+            name,
+            project,
+            size: container.size_rw,
+        });
+    }
+
+    let orphaned = orphans
+        .containers
+        .iter()
+        .map(|orphan| orphan.project.clone())
+        .collect::<HashSet<_>>();
+
+    for image in usage.images.unwrap_or_default() {
+        let project = match image.labels.get(PROJECT_LABEL) {
+            Some(project) => project.clone(),
+            None => match image
+                .repo_tags
+                .iter()
+                .filter_map(|tag| tag.strip_suffix(":latest").or_else(|| tag.strip_suffix(":old")))
+                .find(|repo| orphaned.contains(*repo))
+            {
+                Some(repo) => repo.to_string(),
+                None => continue,
+            },
+        };
+        if known.contains(&project) || !project.starts_with(prefix) {
+            continue;
+        }
+
+        orphans.images.push(Orphan {
+            kind: Kind::Image,
+            name: image
+                .repo_tags
+                .first()
+                .cloned()
+                .unwrap_or_else(|| "<none>".to_string()),
+            id: image.id,
+            project,
+            size: Some(image.size),
+        });
+    }
+
+    for volume in usage.volumes.unwrap_or_default() {
+        let project = match volume.labels.get(PROJECT_LABEL) {
+            Some(project) => project.clone(),
+            None => match volume.name.strip_suffix("-volume") {
+                Some(project) if orphaned.contains(project) => project.to_string(),
+                _ => continue,
+            },
+        };
+        if known.contains(&project) || !project.starts_with(prefix) {
+            continue;
+        }
+
+        orphans.volumes.push(Orphan {
+            kind: Kind::Volume,
+            id: volume.name.clone(),
+            name: volume.name,
+            project,
+            // docker reports -1 when the size hasn't been computed
+            size: volume.usage_data.map(|usage| usage.size).filter(|size| *size >= 0),
+        });
+    }
+
+    let networks = docker
+        .list_networks(Some(ListNetworksOptions {
+            filters: HashMap::from([("label", vec![PROJECT_LABEL])]),
+        }))
+        .await?;
+    for network in networks {
+        let Some(project) = network
+            .labels
+            .as_ref()
+            .and_then(|labels| labels.get(PROJECT_LABEL))
+            .cloned()
+        else {
+            continue;
+        };
+        if known.contains(&project) || !project.starts_with(prefix) {
+            continue;
+        }
+
+        let name = network.name.unwrap_or_default();
+        orphans.networks.push(Orphan {
+            kind: Kind::Network,
+            id: network.id.unwrap_or_else(|| name.clone()),
+            name,
+            project,
+            size: None,
+        });
+    }
+
+    Ok(orphans)
+}
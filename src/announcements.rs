@@ -0,0 +1,96 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// How often a held build checks whether deploys are still paused
+const HOLD_POLL_SECS: u64 = 30;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, sqlx::Type)]
+#[sqlx(type_name = "announcement_severity", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    #[default]
+    Info,
+    Warning,
+    Critical,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Announcement {
+    pub id: Uuid,
+    pub message: String,
+    pub severity: Severity,
+    pub starts_at: DateTime<Utc>,
+    /// none when it runs until removed
+    pub ends_at: Option<DateTime<Utc>>,
+    pub pause_deploys: bool,
+}
+
+/// Announcements whose window contains the current time, newest first. Expired ones drop out
+/// on their own, nothing has to clean them up.
+pub async fn active(pool: &PgPool) -> Result<Vec<Announcement>, sqlx::Error> {
+    sqlx::query_as!(
+        Announcement,
+        r#"SELECT id, message, severity AS "severity: Severity", starts_at, ends_at, pause_deploys
+           FROM announcements
+           WHERE starts_at <= now() AND (ends_at IS NULL OR ends_at > now())
+           ORDER BY starts_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// The active announcement that pauses deploys, if any
+pub fn pausing(announcements: &[Announcement]) -> Option<&Announcement> {
+    announcements.iter().find(|announcement| announcement.pause_deploys)
+}
+
+/// Lines shown at the top of the git push output
+pub fn push_message(announcements: &[Announcement]) -> String {
+    announcements
+        .iter()
+        .map(|announcement| match announcement.severity {
+            Severity::Info => format!("NOTICE: {}", announcement.message),
+            Severity::Warning => format!("WARNING: {}", announcement.message),
+            Severity::Critical => format!("CRITICAL: {}", announcement.message),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Wait until no announcement pauses deploys. A failing query doesn't hold builds, an outage
+/// of the announcements shouldn't stop deploys.
+pub async fn wait_for_deploys(pool: &PgPool) {
+    loop {
+        match active(pool).await {
+            Ok(announcements) => match pausing(&announcements) {
+                Some(announcement) => {
+                    tracing::info!(announcement = %announcement.id, "Deploys are paused, holding build");
+                }
+                None => return,
+            },
+            Err(err) => {
+                tracing::error!(?err, "Can't check announcements: Failed to query database");
+                return;
+            }
+        }
+
+        tokio::time::sleep(Duration::from_secs(HOLD_POLL_SECS)).await;
+    }
+}
+
+/// Check an announcement before it is stored
+pub fn validate(message: &str, starts_at: DateTime<Utc>, ends_at: Option<DateTime<Utc>>) -> Result<(), String> {
+    if message.trim().is_empty() {
+        return Err("Message can't be empty".to_string());
+    }
+    if ends_at.is_some_and(|ends_at| ends_at <= starts_at) {
+        return Err("Announcement has to end after it starts".to_string());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::Serialize;
+
+use crate::auth::{Auth, CSRF_SESSION_KEY};
+
+const TOKEN_LENGTH: usize = 32;
+
+#[derive(Serialize, Debug)]
+struct CsrfResponse {
+    token: String,
+}
+
+/// CSRF token of the session, sent back in the `X-CSRF-Token` header of every POST. It stays
+/// the same until the session ends or the user logs in again.
+#[tracing::instrument(skip(auth))]
+pub async fn get(auth: Auth) -> Response<Body> {
+    let token = match auth.session.get::<String>(CSRF_SESSION_KEY) {
+        Some(token) => token,
+        None => {
+            let token = rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(TOKEN_LENGTH)
+                .map(char::from)
+                .collect::<String>();
+            auth.session.set(CSRF_SESSION_KEY, token.clone());
+            token
+        }
+    };
+
+    let json = serde_json::to_string(&CsrfResponse { token }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Cache-Control", "no-store")
+        .body(Body::from(json))
+        .unwrap()
+}
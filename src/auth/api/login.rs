@@ -1,11 +1,10 @@
-use argon2::{Argon2, PasswordHash, PasswordVerifier};
 use axum::{
     extract::State, response::Response, Json
 };
 use hyper::{Body, StatusCode};
 use secrecy::ExposeSecret;
 use serde::Deserialize;
-use crate::{startup::AppState, auth::{Auth, User, RegisterUserErrorType, ErrorResponse, Secret}};
+use crate::{startup::AppState, auth::{hashing::{self, Verification}, sessions, Auth, User, RegisterUserErrorType, ErrorResponse, Secret, CSRF_SESSION_KEY}};
 
 #[derive(Deserialize)]
 pub struct LoginRequest {
@@ -16,7 +15,7 @@ pub struct LoginRequest {
 #[tracing::instrument(skip(auth, pool, password))]
 pub async fn login_user(
     auth: Auth,
-    State(AppState { pool, .. }): State<AppState>,
+    State(AppState { pool, session_limit, hasher, .. }): State<AppState>,
     Json(LoginRequest { username, password }): Json<LoginRequest>,
 ) -> Response<Body> {
     // get user
@@ -36,10 +35,9 @@ pub async fn login_user(
     };
 
     // check password
-    let hasher = Argon2::default();
-    let hash = PasswordHash::new(&user.password).unwrap();
-    if let Err(err) = hasher.verify_password(password.expose_secret().as_bytes(), &hash) {
-        tracing::error!(?err, "Can't login: Failed to verify password");
+    let verification = hasher.verify(password.expose_secret().as_bytes(), &user.password);
+    if verification == Verification::Invalid {
+        tracing::error!("Can't login: Failed to verify password");
         let json = serde_json::to_string(&ErrorResponse {
             message: "Wrong username or password entered".to_string(),
             error_type: RegisterUserErrorType::BadRequestError,
@@ -49,8 +47,23 @@ pub async fn login_user(
             .body(Body::from(json))
             .unwrap();
     };
+    if let Verification::Rehashed(rehashed) = &verification {
+        hashing::store_password(&pool, user.id, &user.password, rehashed).await;
+    }
 
-    auth.login_user(user.id);
+    // a new session id and CSRF token, so ones known from before the login are worthless
+    auth.session.renew();
+    auth.session.remove(CSRF_SESSION_KEY);
+    if let Err(err) = sessions::start(&pool, &auth, user.id, session_limit).await {
+        let json = serde_json::to_string(&ErrorResponse {
+            message: err.message(),
+            error_type: RegisterUserErrorType::BadRequestError,
+        }).unwrap();
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body(Body::from(json))
+            .unwrap();
+    }
     Response::builder()
         .status(StatusCode::FOUND)
         .header("HX-Location", "/api/dashboard")
@@ -1,10 +1,14 @@
+use axum::extract::State;
 use axum::response::Response;
 use hyper::{Body, StatusCode};
-use crate::auth::Auth;
+use crate::auth::{sessions, Auth, CSRF_SESSION_KEY};
+use crate::startup::AppState;
 
-#[tracing::instrument(skip(auth))]
-pub async fn logout_user(auth: Auth) -> Response<Body> {
+#[tracing::instrument(skip(auth, pool))]
+pub async fn logout_user(auth: Auth, State(AppState { pool, .. }): State<AppState>) -> Response<Body> {
+    sessions::end(&pool, &auth).await;
     auth.logout_user();
+    auth.session.remove(CSRF_SESSION_KEY);
     Response::builder()
         .status(StatusCode::FOUND)
         .header("Location", "/api/login")
@@ -4,18 +4,23 @@ use hyper::Body;
 
 use crate::{configuration::Settings, startup::AppState};
 
+mod csrf;
 mod validate;
 mod login;
 mod logout;
 mod register;
+mod sso;
 
 pub async fn router(_state: AppState, _config: &Settings) -> Router<AppState, Body> {
     Router::new()
         .route_with_tsr("/api/register", post(register::register_user))
         .route_with_tsr("/api/login", post(login::login_user))
+        .route_with_tsr("/auth/sso/login", get(sso::login))
+        .route_with_tsr("/auth/sso/callback", get(sso::callback))
         .route_with_tsr(
             "/api/logout",
             get(logout::logout_user).post(logout::logout_user),
         )
         .route_with_tsr("/api/validate", get(validate::validate_auth))
+        .route_with_tsr("/api/csrf", get(csrf::get))
 }
@@ -1,8 +1,10 @@
+use std::time::Duration;
+
 use axum::{
-    extract::{Json, State},
+    extract::{Json, Query, State},
     response::Response,
 };
-use hyper::{Body, StatusCode};
+use hyper::{header, Body, HeaderMap, StatusCode};
 use secrecy::ExposeSecret;
 use serde::{Serialize, Deserialize};
 use ulid::Ulid;
@@ -10,13 +12,15 @@ use uuid::Uuid;
 
 use garde::Unvalidated;
 
-use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
-    Argon2,
-};
+use sqlx::PgPool;
 
 use crate::{
-    auth::{Auth, ErrorResponse, RegisterUserErrorType, UserRequest},
+    auth::{
+        hashing::Hasher,
+        sessions::{self, SessionLimit},
+        Auth, ErrorResponse, RegisterUserErrorType, User, UserRequest,
+    },
+    configuration::{CasAttributeNames, SsoConfig},
     startup::AppState,
 };
 
@@ -41,46 +45,105 @@ pub struct ServiceResponse {
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AuthenticationSuccess {
+    #[serde(default)]
     pub attributes: Attributes,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct Attributes {
-    pub jurusan: Jurusan,
-    #[serde(rename = "ldap_role")]
-    pub ldap_role: String,
-    #[serde(rename = "status_mahasiswa")]
-    pub status_mahasiswa: String,
-    #[serde(rename = "status_mahasiswa_aktif")]
-    pub status_mahasiswa_aktif: String,
-}
+/// Attributes as the CAS server names them, see [`Profile`]
+pub type Attributes = serde_json::Map<String, serde_json::Value>;
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct Jurusan {
+/// What the server needs to know of a CAS user, read from their attributes by the names in
+/// `auth.casattributes`
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Profile {
+    /// full name, kept in sync with the stored user on every sign in
+    pub name: String,
     pub faculty: String,
-    pub short_faculty: String,
-    pub major: String,
-    pub program: String,
 }
 
+impl Profile {
+    /// Attributes the CAS server doesn't send are left empty
+    pub fn from_attributes(attributes: &Attributes, names: &CasAttributeNames) -> Self {
+        Self {
+            name: attribute(attributes, &names.name),
+            faculty: attribute(attributes, &names.faculty),
+        }
+    }
+}
+
+/// An attribute by its dotted path, e.g. `jurusan.faculty`. Many CAS servers send every value
+/// as a list, the first entry is used then.
+fn attribute(attributes: &Attributes, path: &str) -> String {
+    let mut keys = path.split('.');
+    let mut value = keys.next().and_then(|key| attributes.get(key));
+    for key in keys {
+        value = value.map(first).and_then(|value| value.get(key));
+    }
+
+    match value.map(first) {
+        Some(serde_json::Value::String(value)) => value.clone(),
+        Some(serde_json::Value::Null) | None => String::new(),
+        Some(value) => value.to_string(),
+    }
+}
+
+fn first(value: &serde_json::Value) -> &serde_json::Value {
+    match value {
+        serde_json::Value::Array(values) => values.first().unwrap_or(value),
+        value => value,
+    }
+}
+
+/// How long the SSO proxy gets to verify the credentials with CAS
+pub(super) const SSO_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Where a registered user goes next
+pub(super) const REDIRECT_TARGET: &str = "/api/dashboard";
+
 #[derive(Serialize, Debug)]
 struct RegisterUserSuccessResponse {
     message: String,
 }
 
-#[tracing::instrument(skip(auth, pool))]
+/// For clients that can't follow `HX-Location`, like the SPA or a mobile app. The session is
+/// only in the cookie.
+#[derive(Serialize, Debug)]
+struct RegisterUserJsonResponse {
+    message: String,
+    redirect: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RegisterQuery {
+    /// `json` for the same response as `Accept: application/json`
+    format: Option<String>,
+}
+
+/// JSON when the client asks for it with `Accept: application/json` or `?format=json`, the
+/// htmx response otherwise
+fn wants_json(headers: &HeaderMap, format: Option<&str>) -> bool {
+    format == Some("json")
+        || headers
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(|accept| {
+                accept
+                    .split(',')
+                    .any(|media| media.split(';').next().unwrap_or("").trim() == "application/json")
+            })
+}
+
+#[tracing::instrument(skip(auth, pool, headers))]
 pub async fn register_user(
     auth: Auth,
-    State(AppState { pool, sso, .. }): State<AppState>,
+    State(AppState { pool, sso, sso_config, session_limit, hasher, .. }): State<AppState>,
+    headers: HeaderMap,
+    Query(RegisterQuery { format }): Query<RegisterQuery>,
     Json(req): Json<Unvalidated<UserRequest>>,
 ) -> Response<Body> {
-    let UserRequest {
-        username,
-        name,
-        password,
-    } = match req.validate(&()) {
+    let as_json = wants_json(&headers, format.as_deref());
+
+    let request = match req.validate(&()) {
         Ok(valid) => valid.into_inner(),
         Err(err) => {
             return Response::builder()
@@ -96,38 +159,108 @@ pub async fn register_user(
         }
     };
 
-    // check if user exists
-    match sqlx::query!("SELECT username FROM users WHERE username = $1", username)
-        .fetch_optional(&pool)
-        .await
-    {
-        Ok(None) => {}
-        Err(err) => {
-            tracing::error!(?err, "Can't get user: Failed to query database");
+    let profile = match sso {
+        true => match verify_sso(&sso_config, &request.username, request.password.expose_secret()).await {
+            Ok(profile) => Some(profile),
+            Err(err) => return err.response(),
+        },
+        false => None,
+    };
+
+    sign_up(&auth, &pool, &hasher, session_limit, request, profile.as_ref(), as_json).await
+}
+
+/// Register the user and sign them in. `profile` is what CAS vouched for when SSO is on.
+async fn sign_up(
+    auth: &Auth,
+    pool: &PgPool,
+    hasher: &Hasher,
+    session_limit: SessionLimit,
+    request: UserRequest,
+    profile: Option<&Profile>,
+    as_json: bool,
+) -> Response<Body> {
+    let UserRequest {
+        username,
+        name,
+        password,
+    } = request;
+
+    match provision_user(pool, hasher, &username, name, password.expose_secret(), profile).await {
+        Ok(user_id) => user_created(auth, pool, session_limit, user_id, as_json).await,
+        // a concurrent registration of the same user (double submit) won the race
+        Err(ProvisionError::Raced) => {
+            login_concurrent_user(auth, pool, hasher, session_limit, &username, password.expose_secret(), as_json).await
+        }
+        Err(ProvisionError::Exists) => {
             let json = serde_json::to_string(&ErrorResponse {
-                message: format!("failed to query database: {}", err.to_string()),
-                error_type: RegisterUserErrorType::InternalServerError,
+                message: "Username already exists".to_string(),
+                error_type: RegisterUserErrorType::BadRequestError,
             })
             .unwrap();
-
-            return Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
+            Response::builder()
+                .status(StatusCode::BAD_REQUEST)
                 .header("Content-Type", "text/html")
                 .body(Body::from(json))
-                .unwrap();
+                .unwrap()
         }
-
-        Ok(_) => {
+        Err(ProvisionError::Internal(message)) => {
             let json = serde_json::to_string(&ErrorResponse {
-                message: "Username already exists".to_string(),
-                error_type: RegisterUserErrorType::BadRequestError,
+                message,
+                error_type: RegisterUserErrorType::InternalServerError,
             })
             .unwrap();
-            return Response::builder()
-                .status(StatusCode::BAD_REQUEST)
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
                 .header("Content-Type", "text/html")
                 .body(Body::from(json))
-                .unwrap();
+                .unwrap()
+        }
+    }
+}
+
+/// Why a user couldn't be signed in or registered
+#[derive(Debug)]
+pub(super) enum ProvisionError {
+    /// the username is taken, by a user or by a group owner
+    Exists,
+    /// a concurrent registration of the same user committed first
+    Raced,
+    Internal(String),
+}
+
+/// The id of the user signing up, registered along with their owner when they are new.
+/// `profile` is what CAS vouched for: such a user is signed in even when they registered
+/// before, and their stored name is kept in sync with CAS.
+pub(super) async fn provision_user(
+    pool: &PgPool,
+    hasher: &Hasher,
+    username: &str,
+    name: String,
+    password: &str,
+    profile: Option<&Profile>,
+) -> Result<Uuid, ProvisionError> {
+    // check if user exists
+    match sqlx::query!("SELECT id FROM users WHERE username = $1", username)
+        .fetch_optional(pool)
+        .await
+    {
+        Ok(None) => {}
+        Err(err) => {
+            tracing::error!(?err, "Can't get user: Failed to query database");
+            return Err(ProvisionError::Internal(format!("failed to query database: {}", err)));
+        }
+        Ok(Some(user)) => {
+            // CAS vouched for the user, signing up again is signing in
+            return match profile {
+                Some(profile) => {
+                    if let Err(err) = sync_sso_user(pool, user.id, profile).await {
+                        tracing::error!(?err, "Can't update user: Failed to query database");
+                    }
+                    Ok(user.id)
+                }
+                None => Err(ProvisionError::Exists),
+            };
         }
     }
 
@@ -136,58 +269,29 @@ pub async fn register_user(
         r#"SELECT name FROM project_owners WHERE name = $1"#,
         username
     )
-    .fetch_optional(&pool)
+    .fetch_optional(pool)
     .await
     {
         Ok(None) => {}
         Err(err) => {
             tracing::error!(?err, "Can't get owners: Failed to query database");
-            let json = serde_json::to_string(&ErrorResponse {
-                message: format!("failed to query database: {}", err.to_string()),
-                error_type: RegisterUserErrorType::InternalServerError,
-            })
-            .unwrap();
-
-            return Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .header("Content-Type", "text/html")
-                .body(Body::from(json))
-                .unwrap();
-        }
-
-        Ok(_) => {
-            let json = serde_json::to_string(&ErrorResponse {
-                message: "Username already exists".to_string(),
-                error_type: RegisterUserErrorType::BadRequestError,
-            })
-            .unwrap();
-            return Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .header("Content-Type", "text/html")
-                .body(Body::from(json))
-                .unwrap();
+            return Err(ProvisionError::Internal(format!("failed to query database: {}", err)));
         }
+        Ok(_) => return Err(ProvisionError::Exists),
     }
 
-    let user_id = Uuid::from(Ulid::new());
-    let hasher = Argon2::default();
-    let salt = SaltString::generate(&mut OsRng);
+    // CAS knows the name better than the form
+    let name = match profile {
+        Some(profile) if !profile.name.is_empty() => profile.name.clone(),
+        _ => name,
+    };
 
-    let password_hash = match hasher.hash_password(password.expose_secret().as_bytes(), &salt) {
+    let user_id = Uuid::from(Ulid::new());
+    let password_hash = match hasher.hash(password.as_bytes()) {
         Ok(hash) => hash,
         Err(err) => {
             tracing::error!(?err, "Can't register User: Failed to hash password");
-            let json = serde_json::to_string(&ErrorResponse {
-                message: format!("failed to hash password: {}", err.to_string()),
-                error_type: RegisterUserErrorType::InternalServerError,
-            })
-            .unwrap();
-
-            return Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .header("Content-Type", "text/html")
-                .body(Body::from(json))
-                .unwrap();
+            return Err(ProvisionError::Internal(format!("failed to hash password: {}", err)));
         }
     };
 
@@ -195,136 +299,12 @@ pub async fn register_user(
         Ok(tx) => tx,
         Err(err) => {
             tracing::error!(?err, "Can't insert user: Failed to begin transaction");
-            let json = serde_json::to_string(&ErrorResponse {
-                message: "failed to request sso: Failed to begin transaction".to_string(),
-                error_type: RegisterUserErrorType::InternalServerError,
-            })
-            .unwrap();
-
-            return Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .header("Content-Type", "text/html")
-                .body(Body::from(json))
-                .unwrap();
+            return Err(ProvisionError::Internal(
+                "failed to request sso: Failed to begin transaction".to_string(),
+            ));
         }
     };
 
-    // TODO: use actual sso and not proxy
-    if sso {
-        // TODO: not sure if this is the best way to do this
-        let client = reqwest::Client::new();
-        let res = match client
-            .post("https://sso.mus.sh")
-            .body(
-                serde_json::json!({
-                    "username": username,
-                    "password": password.expose_secret(),
-                    "casUrl": "https://sso.ui.ac.id/cas/",
-                    "serviceUrl": "http%3A%2F%2Fberanda.ui.ac.id%2Fpersonal%2F",
-                    "EncodeUrl": true
-                })
-                .to_string(),
-            )
-            .send()
-            .await
-        {
-            Ok(res) => res,
-            Err(err) => {
-                tracing::error!(?err, "Can't register user: Failed to request sso");
-                if let Err(err) = tx.rollback().await {
-                    tracing::error!(?err, "Can't register user: Failed to rollback transaction");
-                }
-
-                let json = serde_json::to_string(&ErrorResponse {
-                    message: format!("failed to request sso: {}", err.to_string()),
-                    error_type: RegisterUserErrorType::InternalServerError,
-                })
-                .unwrap();
-
-                return Response::builder()
-                    .status(StatusCode::BAD_REQUEST)
-                    .header("Content-Type", "text/html")
-                    .body(Body::from(json))
-                    .unwrap();
-            }
-        };
-
-        let body = match res.bytes().await {
-            Ok(body) => body,
-            Err(err) => {
-                tracing::error!(?err, "Can't register user: Failed to get body");
-                if let Err(err) = tx.rollback().await {
-                    tracing::error!(?err, "Can't register user: Failed to rollback transaction");
-                }
-
-                let json = serde_json::to_string(&ErrorResponse {
-                    message: format!("failed to get body: {}", err.to_string()),
-                    error_type: RegisterUserErrorType::SSOError,
-                })
-                .unwrap();
-
-                return Response::builder()
-                    .status(StatusCode::BAD_REQUEST)
-                    .header("Content-Type", "text/html")
-                    .body(Body::from(json))
-                    .unwrap();
-            }
-        };
-
-        tracing::warn!(?body);
-
-        let sso_res = match serde_json::from_slice::<SsoResponse>(&body) {
-            Ok(SsoResponse::ServiceResponse { service_response }) => {
-                service_response.authentication_success.attributes
-            }
-            Ok(SsoResponse::Error { .. }) => {
-                let json = serde_json::to_string(&ErrorResponse {
-                    message: "Wrong username or password".to_string(),
-                    error_type: RegisterUserErrorType::SSOError,
-                })
-                .unwrap();
-
-                return Response::builder()
-                    .status(StatusCode::BAD_REQUEST)
-                    .header("Content-Type", "text/html")
-                    .body(Body::from(json))
-                    .unwrap();
-            }
-            Err(err) => {
-                tracing::error!(?err, "Can't register user: Failed to parse body");
-                if let Err(err) = tx.rollback().await {
-                    tracing::error!(?err, "Can't register user: Failed to rollback transaction");
-                }
-
-                let json = serde_json::to_string(&ErrorResponse {
-                    message: format!("failed to parse body: {}", err.to_string()),
-                    error_type: RegisterUserErrorType::SSOError,
-                })
-                .unwrap();
-
-                return Response::builder()
-                    .status(StatusCode::BAD_REQUEST)
-                    .header("Content-Type", "text/html")
-                    .body(Body::from(json))
-                    .unwrap();
-            }
-        };
-
-        if sso_res.jurusan.faculty != "Ilmu Komputer" {
-            let json = serde_json::to_string(&ErrorResponse {
-                message: "User is not from UI Faculty of Computer Science".to_string(),
-                error_type: RegisterUserErrorType::SSOError,
-            })
-            .unwrap();
-
-            return Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .header("Content-Type", "text/html")
-                .body(Body::from(json))
-                .unwrap();
-        }
-    }
-
     if let Err(err) = sqlx::query!(
         r#"INSERT INTO users (id, username, password, name) VALUES ($1, $2, $3, $4)"#,
         user_id,
@@ -340,17 +320,10 @@ pub async fn register_user(
             tracing::error!(?err, "Can't insert user: Failed to rollback transaction");
         }
 
-        let json = serde_json::to_string(&ErrorResponse {
-            message: format!("failed to insert into database: {}", err.to_string()),
-            error_type: RegisterUserErrorType::InternalServerError,
-        })
-        .unwrap();
-
-        return Response::builder()
-            .status(StatusCode::BAD_REQUEST)
-            .header("Content-Type", "text/html")
-            .body(Body::from(json))
-            .unwrap();
+        if is_unique_violation(&err) {
+            return raced(pool, username, profile).await;
+        }
+        return Err(ProvisionError::Internal(format!("failed to insert into database: {}", err)));
     };
 
     let owner_id = Uuid::from(Ulid::new());
@@ -374,16 +347,10 @@ pub async fn register_user(
             );
         }
 
-        let json = serde_json::to_string(&ErrorResponse {
-            message: format!("failed to insert into database: {}", err.to_string()),
-            error_type: RegisterUserErrorType::InternalServerError,
-        })
-        .unwrap();
-        return Response::builder()
-            .status(StatusCode::BAD_REQUEST)
-            .header("Content-Type", "text/html")
-            .body(Body::from(json))
-            .unwrap();
+        if is_unique_violation(&err) {
+            return raced(pool, username, profile).await;
+        }
+        return Err(ProvisionError::Internal(format!("failed to insert into database: {}", err)));
     };
 
     if let Err(err) = sqlx::query!(
@@ -405,25 +372,249 @@ pub async fn register_user(
                 "Can't insert users_owners: Failed to rollback transaction"
             );
         }
+        return Err(ProvisionError::Internal(format!("failed to insert into database: {}", err)));
+    }
+
+    match tx.commit().await {
+        Err(err) => {
+            tracing::error!(?err, "Can't register user: Failed to commit transaction");
+            Err(ProvisionError::Internal(format!("failed to commit transaction: {}", err)))
+        }
+        Ok(_) => Ok(user_id),
+    }
+}
+
+/// A concurrent registration of the user committed first. A user CAS vouched for is signed in
+/// as the stored one, others have to prove it's them with the password.
+async fn raced(pool: &PgPool, username: &str, profile: Option<&Profile>) -> Result<Uuid, ProvisionError> {
+    if profile.is_none() {
+        return Err(ProvisionError::Raced);
+    }
+
+    match User::get_from_username(username, pool).await {
+        Ok(user) => Ok(user.id),
+        Err(err) => {
+            tracing::error!(?err, "Can't get user: Failed to query database");
+            Err(ProvisionError::Exists)
+        }
+    }
+}
+
+async fn user_created(
+    auth: &Auth,
+    pool: &PgPool,
+    session_limit: SessionLimit,
+    user_id: Uuid,
+    as_json: bool,
+) -> Response<Body> {
+    if let Err(err) = sessions::start(pool, auth, user_id, session_limit).await {
         let json = serde_json::to_string(&ErrorResponse {
-            message: format!("failed to insert into database: {}", err.to_string()),
-            error_type: RegisterUserErrorType::InternalServerError,
+            message: err.message(),
+            error_type: RegisterUserErrorType::BadRequestError,
         })
         .unwrap();
-
         return Response::builder()
-            .status(StatusCode::BAD_REQUEST)
+            .status(StatusCode::FORBIDDEN)
             .header("Content-Type", "text/html")
             .body(Body::from(json))
             .unwrap();
     }
 
-    match tx.commit().await {
+    if as_json {
+        let json = serde_json::to_string(&RegisterUserJsonResponse {
+            message: "User Created".to_string(),
+            redirect: REDIRECT_TARGET.to_string(),
+        })
+        .unwrap();
+        return Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json))
+            .unwrap();
+    }
+
+    let json = serde_json::to_string(&RegisterUserSuccessResponse {
+        message: "User Created".to_string(),
+    })
+    .unwrap();
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/html")
+        .header("HX-Location", REDIRECT_TARGET)
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Why CAS didn't let a user in, answered as JSON by the register endpoint and as a page by the
+/// redirect flow
+#[derive(Debug)]
+pub(super) struct SsoError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl SsoError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn response(self) -> Response<Body> {
+        let json = serde_json::to_string(&ErrorResponse {
+            message: self.message,
+            error_type: RegisterUserErrorType::SSOError,
+        })
+        .unwrap();
+
+        Response::builder()
+            .status(self.status)
+            .header("Content-Type", "text/html")
+            .body(Body::from(json))
+            .unwrap()
+    }
+}
+
+/// Check the credentials with CAS through the SSO proxy. Only users of `auth.casfaculty` get
+/// through.
+async fn verify_sso(sso_config: &SsoConfig, username: &str, password: &str) -> Result<Profile, SsoError> {
+    // TODO: use actual sso and not proxy
+    // TODO: not sure if this is the best way to do this
+    let client = reqwest::Client::new();
+    let service_url = url::form_urlencoded::byte_serialize(sso_config.service.as_str().as_bytes())
+        .collect::<String>();
+    let res = match client
+        .post(sso_config.proxy.clone())
+        .body(
+            serde_json::json!({
+                "username": username,
+                "password": password,
+                "casUrl": sso_config.cas.as_str(),
+                "serviceUrl": service_url,
+                "EncodeUrl": true
+            })
+            .to_string(),
+        )
+        .timeout(SSO_TIMEOUT)
+        .send()
+        .await
+    {
+        Ok(res) => res,
         Err(err) => {
-            tracing::error!(?err, "Can't register user: Failed to commit transaction");
+            tracing::error!(?err, "Can't check sso: Failed to request sso");
+            return Err(SsoError::new(upstream_status(&err), format!("failed to request sso: {}", err)));
+        }
+    };
+
+    if res.status().is_server_error() {
+        tracing::error!(status = %res.status(), "Can't check sso: SSO failed");
+        return Err(SsoError::new(
+            StatusCode::BAD_GATEWAY,
+            format!("SSO failed with {}, try again later", res.status()),
+        ));
+    }
+
+    let body = match res.bytes().await {
+        Ok(body) => body,
+        Err(err) => {
+            tracing::error!(?err, "Can't check sso: Failed to get body");
+            return Err(SsoError::new(upstream_status(&err), format!("failed to get body: {}", err)));
+        }
+    };
+
+    tracing::warn!(?body);
+
+    let profile = match serde_json::from_slice::<SsoResponse>(&body) {
+        Ok(SsoResponse::ServiceResponse { service_response }) => {
+            Profile::from_attributes(&service_response.authentication_success.attributes, &sso_config.attributes)
+        }
+        Ok(SsoResponse::Error { .. }) => {
+            return Err(SsoError::new(StatusCode::UNAUTHORIZED, "Wrong username or password"));
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't check sso: Failed to parse body");
+            return Err(SsoError::new(StatusCode::BAD_GATEWAY, format!("failed to parse body: {}", err)));
+        }
+    };
+
+    check_faculty(&profile, sso_config)?;
+    Ok(profile)
+}
+
+/// Only users of `auth.casfaculty` may use the server, anyone CAS knows when it is empty
+pub(super) fn check_faculty(profile: &Profile, sso_config: &SsoConfig) -> Result<(), SsoError> {
+    if !sso_config.faculty.is_empty() && profile.faculty != sso_config.faculty {
+        return Err(SsoError::new(
+            StatusCode::FORBIDDEN,
+            format!("User is not from {}", sso_config.faculty),
+        ));
+    }
+    Ok(())
+}
+
+/// Take the name CAS has for a user that signed up before over. Nothing is written when it
+/// didn't change.
+async fn sync_sso_user(pool: &PgPool, user_id: Uuid, profile: &Profile) -> Result<(), sqlx::Error> {
+    if profile.name.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query!(
+        r#"UPDATE users SET name = $1, updated_at = now()
+           WHERE id = $2 AND name IS DISTINCT FROM $1
+        "#,
+        profile.name,
+        user_id,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// 504 when the SSO proxy didn't answer in time, 502 for anything else wrong on its side
+pub(super) fn upstream_status(err: &reqwest::Error) -> StatusCode {
+    match err.is_timeout() {
+        true => StatusCode::GATEWAY_TIMEOUT,
+        false => StatusCode::BAD_GATEWAY,
+    }
+}
+
+fn is_unique_violation(err: &sqlx::Error) -> bool {
+    err.as_database_error()
+        .map(|err| err.is_unique_violation())
+        .unwrap_or(false)
+}
+
+/// Two registrations of the same user raced and the other one committed first. Log in as the
+/// stored user when the password matches instead of failing the second request.
+async fn login_concurrent_user(
+    auth: &Auth,
+    pool: &PgPool,
+    hasher: &Hasher,
+    session_limit: SessionLimit,
+    username: &str,
+    password: &str,
+    as_json: bool,
+) -> Response<Body> {
+    let verified = match User::get_from_username(username, pool).await {
+        Ok(user) => hasher
+            .verify(password.as_bytes(), &user.password)
+            .is_valid()
+            .then_some(user.id),
+        Err(err) => {
+            tracing::error!(?err, "Can't get user: Failed to query database");
+            None
+        }
+    };
+
+    match verified {
+        Some(user_id) => user_created(auth, pool, session_limit, user_id, as_json).await,
+        None => {
             let json = serde_json::to_string(&ErrorResponse {
-                message: format!("failed to commit transaction: {}", err.to_string()),
-                error_type: RegisterUserErrorType::InternalServerError,
+                message: "Username already exists".to_string(),
+                error_type: RegisterUserErrorType::BadRequestError,
             })
             .unwrap();
             Response::builder()
@@ -432,18 +623,230 @@ pub async fn register_user(
                 .body(Body::from(json))
                 .unwrap()
         }
-        Ok(_) => {
-            auth.login_user(user_id);
-            let json = serde_json::to_string(&RegisterUserSuccessResponse {
-                message: "User Created".to_string(),
-            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing, Router};
+    use axum_session::{SessionLayer, SessionPgPool};
+    use axum_session_auth::AuthSessionLayer;
+    use secrecy::Secret;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::configuration::{self, Argon2Settings, SessionPolicy, Settings};
+
+    fn attributes(value: serde_json::Value) -> Attributes {
+        value.as_object().unwrap().clone()
+    }
+
+    fn names(name: &str, faculty: &str) -> CasAttributeNames {
+        CasAttributeNames {
+            name: name.to_string(),
+            faculty: faculty.to_string(),
+        }
+    }
+
+    #[test]
+    fn reads_the_default_attribute_names() {
+        let attributes = attributes(serde_json::json!({
+            "nama": "Alice Liddell",
+            "jurusan": { "faculty": "Ilmu Komputer", "major": "Ilmu Komputer (S1 Reguler)" },
+        }));
+
+        assert_eq!(
+            Profile::from_attributes(&attributes, &names("nama", "jurusan.faculty")),
+            Profile {
+                name: "Alice Liddell".to_string(),
+                faculty: "Ilmu Komputer".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn reads_remapped_attribute_names() {
+        // other CAS servers send every value as a list
+        let attributes = attributes(serde_json::json!({
+            "displayName": ["Alice Liddell"],
+            "organizationalUnit": ["Computer Science", "Mathematics"],
+            "nama": "not used",
+        }));
+
+        assert_eq!(
+            Profile::from_attributes(&attributes, &names("displayName", "organizationalUnit")),
+            Profile {
+                name: "Alice Liddell".to_string(),
+                faculty: "Computer Science".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn missing_attributes_are_left_empty() {
+        let attributes = attributes(serde_json::json!({ "displayName": null, "ou": { "code": 12 } }));
+
+        assert_eq!(
+            Profile::from_attributes(&attributes, &names("displayName", "ou.name")),
+            Profile::default()
+        );
+        assert_eq!(
+            Profile::from_attributes(&attributes, &names("cn", "ou.code")),
+            Profile {
+                name: String::new(),
+                faculty: "12".to_string(),
+            }
+        );
+    }
+
+    /// Signs alice up on every request, the way `register_user` does once CAS vouched for her
+    async fn cas_sign_up(pool: &PgPool) -> Router {
+        let config: Settings = configuration::defaults().unwrap().set_override("build.max", 1).unwrap().build().unwrap().try_deserialize().unwrap();
+        let (auth_config, session_store) = crate::auth::auth_layer(pool, &config).await;
+        let hasher = Hasher::new(&Argon2Settings {
+            memory: 16,
+            iterations: 2,
+            parallelism: 1,
+        })
+        .unwrap();
+        let limit = SessionLimit {
+            max: 0,
+            policy: SessionPolicy::Evict,
+            lifespan: 24,
+        };
+
+        let handler_pool = pool.clone();
+        Router::new()
+            .route(
+                "/",
+                routing::post(move |auth: Auth, headers: HeaderMap, Query(query): Query<RegisterQuery>| async move {
+                    let as_json = wants_json(&headers, query.format.as_deref());
+                    let request = UserRequest {
+                        username: "alice".to_string(),
+                        name: "alice".to_string(),
+                        password: Secret::new("hunter2".to_string()),
+                    };
+                    let profile = Profile {
+                        name: "Alice Liddell".to_string(),
+                        faculty: "Ilmu Komputer".to_string(),
+                    };
+                    sign_up(&auth, &handler_pool, &hasher, limit, request, Some(&profile), as_json).await
+                }),
+            )
+            .layer(AuthSessionLayer::<User, Uuid, SessionPgPool, PgPool>::new(Some(pool.clone())).with_config(auth_config))
+            .layer(SessionLayer::new(session_store))
+    }
+
+    async fn count(pool: &PgPool, query: &str) -> i64 {
+        sqlx::query_scalar(query).fetch_one(pool).await.unwrap()
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../../..", scripts("schema")))]
+    async fn concurrent_first_logins_share_the_user(pool: PgPool) {
+        let app = cas_sign_up(&pool).await;
+
+        // holds alice's username until both logins found no user and tried to insert her
+        let mut blocker = pool.begin().await.unwrap();
+        sqlx::query("INSERT INTO users (id, username, password, name) VALUES ($1, 'alice', '', 'Alice')")
+            .bind(Uuid::new_v4())
+            .execute(&mut *blocker)
+            .await
             .unwrap();
-            Response::builder()
-                .status(StatusCode::OK)
-                .header("Content-Type", "text/html")
-                .header("HX-Location", "/api/dashboard")
-                .body(Body::from(json))
-                .unwrap()
+
+        let logins = (0..2)
+            .map(|_| {
+                let request = hyper::Request::post("/").body(Body::empty()).unwrap();
+                tokio::spawn(app.clone().oneshot(request))
+            })
+            .collect::<Vec<_>>();
+
+        let waiting = "SELECT count(*) FROM pg_stat_activity WHERE datname = current_database() AND wait_event_type = 'Lock'";
+        for _ in 0..100 {
+            if count(&pool, waiting).await == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
         }
+        assert_eq!(count(&pool, waiting).await, 2, "both logins wait on the username");
+        blocker.rollback().await.unwrap();
+
+        let mut cookies = Vec::new();
+        for login in logins {
+            let response = login.await.unwrap().unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            // the session cookie comes along with others both logins set alike
+            let mut set = response
+                .headers()
+                .get_all(header::SET_COOKIE)
+                .iter()
+                .map(|cookie| cookie.to_str().unwrap().to_string())
+                .collect::<Vec<_>>();
+            set.sort();
+            cookies.push(set);
+        }
+        assert_ne!(cookies[0], cookies[1]);
+
+        assert_eq!(count(&pool, "SELECT count(*) FROM users WHERE username = 'alice'").await, 1);
+        assert_eq!(count(&pool, "SELECT count(*) FROM project_owners WHERE name = 'alice'").await, 1);
+        assert_eq!(count(&pool, "SELECT count(*) FROM user_sessions").await, 2);
+        // the winner's name is CAS's
+        let name: String = sqlx::query_scalar("SELECT name FROM users").fetch_one(&pool).await.unwrap();
+        assert_eq!(name, "Alice Liddell");
+    }
+
+    fn accept(value: &str) -> HeaderMap {
+        HeaderMap::from_iter([(header::ACCEPT, value.parse().unwrap())])
+    }
+
+    #[test]
+    fn json_is_asked_for_by_accept_or_format() {
+        assert!(wants_json(&accept("application/json"), None));
+        assert!(wants_json(&accept("text/html, application/json; q=0.9"), None));
+        assert!(wants_json(&HeaderMap::new(), Some("json")));
+
+        // htmx asks for html
+        assert!(!wants_json(&accept("text/html, */*"), None));
+        assert!(!wants_json(&accept("application/jsonx"), None));
+        assert!(!wants_json(&HeaderMap::new(), Some("html")));
+    }
+
+    async fn body<B>(response: Response<B>) -> serde_json::Value
+    where
+        B: hyper::body::HttpBody,
+        B::Error: std::fmt::Debug,
+    {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../../..", scripts("schema")))]
+    async fn json_clients_get_the_redirect_in_the_body(pool: PgPool) {
+        let app = cas_sign_up(&pool).await;
+        let request = hyper::Request::post("/?format=json").body(Body::empty()).unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        assert!(response.headers().get("HX-Location").is_none());
+        assert!(response.headers().contains_key(header::SET_COOKIE));
+        // the session is only in the cookie
+        assert_eq!(body(response).await, serde_json::json!({ "message": "User Created", "redirect": REDIRECT_TARGET }));
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../../..", scripts("schema")))]
+    async fn htmx_clients_are_sent_on_with_hx_location(pool: PgPool) {
+        let app = cas_sign_up(&pool).await;
+        let request = hyper::Request::post("/")
+            .header(header::ACCEPT, "text/html, */*")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["HX-Location"], REDIRECT_TARGET);
+        assert!(response.headers().contains_key(header::SET_COOKIE));
+        assert_eq!(body(response).await, serde_json::json!({ "message": "User Created" }));
     }
 }
@@ -0,0 +1,348 @@
+use axum::extract::{Query, State};
+use axum::response::Response;
+use hyper::{header, Body, StatusCode};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::Deserialize;
+use url::Url;
+
+use super::register::{
+    check_faculty, provision_user, upstream_status, Attributes, Profile, ProvisionError, SsoError,
+    REDIRECT_TARGET, SSO_TIMEOUT,
+};
+use crate::{
+    auth::{sessions, sso_tickets, username_check, Auth, CSRF_SESSION_KEY},
+    configuration::SsoConfig,
+    placeholder::escape_html,
+    startup::AppState,
+};
+
+/// Where CAS sends the browser back to with the ticket
+const CALLBACK_PATH: &str = "/auth/sso/callback";
+
+/// Length of the password of users registered through the redirect flow, nobody ever sees it
+const PASSWORD_LENGTH: usize = 32;
+
+#[derive(Deserialize, Debug)]
+pub struct LoginQuery {
+    /// path on this server to land on after signing in, the dashboard when none
+    return_to: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CallbackQuery {
+    ticket: Option<String>,
+    return_to: Option<String>,
+}
+
+/// Answer of the CAS `p3/serviceValidate` endpoint with `format=JSON`
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ValidationResponse {
+    service_response: ValidationResult,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ValidationResult {
+    authentication_success: Option<TicketSuccess>,
+    authentication_failure: Option<TicketFailure>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TicketSuccess {
+    user: String,
+    #[serde(default)]
+    attributes: Attributes,
+}
+
+#[derive(Deserialize, Debug)]
+struct TicketFailure {
+    code: String,
+    #[serde(default)]
+    description: String,
+}
+
+fn error_page(status: StatusCode, message: &str) -> Response<Body> {
+    let message = escape_html(message);
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Can't sign in</title>
+</head>
+<body style="font-family: sans-serif; text-align: center; margin-top: 15vh">
+<h1>Can't sign in</h1>
+<p>{message}</p>
+<a href="/auth/sso/login">Try again</a>
+</body>
+</html>
+"#
+    );
+
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::from(html))
+        .unwrap()
+}
+
+/// Only paths on this server are kept, so the flow can't send users to another site. Browsers
+/// drop tabs and newlines from a location and read `\\` as `/`, so those are refused before
+/// the path is resolved against a placeholder origin it has to stay on.
+fn local_path(return_to: Option<String>) -> Option<String> {
+    let origin = Url::parse("http://localhost/").ok()?;
+
+    return_to
+        .filter(|path| path.starts_with('/'))
+        .filter(|path| !path.chars().any(|c| c.is_control() || c.is_whitespace() || c == '\\'))
+        .filter(|path| origin.join(path).is_ok_and(|url| url.origin() == origin.origin()))
+}
+
+/// The service CAS sends the ticket to. `return_to` rides along in it, CAS only accepts the
+/// ticket for the exact service it was issued for.
+fn service_url(domain: &str, secure: bool, return_to: Option<&str>) -> Result<Url, url::ParseError> {
+    let scheme = match secure {
+        true => "https",
+        false => "http",
+    };
+    let mut url = Url::parse(&format!("{scheme}://{domain}{CALLBACK_PATH}"))?;
+    if let Some(return_to) = return_to {
+        url.query_pairs_mut().append_pair("return_to", return_to);
+    }
+
+    Ok(url)
+}
+
+/// Send the browser to the CAS login page, which comes back to the callback with a ticket
+#[tracing::instrument]
+pub async fn login(
+    State(AppState {
+        sso,
+        sso_config,
+        domain,
+        secure,
+        ..
+    }): State<AppState>,
+    Query(LoginQuery { return_to }): Query<LoginQuery>,
+) -> Response<Body> {
+    if !sso {
+        return error_page(StatusCode::NOT_FOUND, "Signing in with SSO is not enabled");
+    }
+
+    let return_to = local_path(return_to);
+    let login_url = service_url(&domain, secure, return_to.as_deref()).and_then(|service| {
+        let mut login_url = sso_config.cas.join("login")?;
+        login_url.query_pairs_mut().append_pair("service", service.as_str());
+        Ok(login_url)
+    });
+
+    match login_url {
+        Ok(login_url) => Response::builder()
+            .status(StatusCode::FOUND)
+            .header(header::LOCATION, login_url.as_str())
+            .body(Body::empty())
+            .unwrap(),
+        Err(err) => {
+            tracing::error!(?err, "Can't sign in with sso: Failed to build login url");
+            error_page(StatusCode::INTERNAL_SERVER_ERROR, "SSO is misconfigured on this server")
+        }
+    }
+}
+
+/// Where CAS sends the browser back to. Validates the ticket, signs the user in, registering
+/// them on their first visit, and sends them on to the dashboard or `return_to`.
+#[tracing::instrument(skip(auth, pool))]
+pub async fn callback(
+    auth: Auth,
+    State(AppState {
+        pool,
+        sso,
+        sso_config,
+        domain,
+        secure,
+        session_limit,
+        hasher,
+        ..
+    }): State<AppState>,
+    Query(CallbackQuery { ticket, return_to }): Query<CallbackQuery>,
+) -> Response<Body> {
+    if !sso {
+        return error_page(StatusCode::NOT_FOUND, "Signing in with SSO is not enabled");
+    }
+    let Some(ticket) = ticket.filter(|ticket| !ticket.is_empty()) else {
+        return error_page(StatusCode::BAD_REQUEST, "SSO didn't send a ticket, sign in again");
+    };
+
+    let return_to = local_path(return_to);
+    let service = match service_url(&domain, secure, return_to.as_deref()) {
+        Ok(service) => service,
+        Err(err) => {
+            tracing::error!(?err, "Can't sign in with sso: Failed to build service url");
+            return error_page(StatusCode::INTERNAL_SERVER_ERROR, "SSO is misconfigured on this server");
+        }
+    };
+
+    // a ticket leaked from a log or a referrer can't sign anyone in a second time
+    match sso_tickets::consume(&pool, &ticket, sso_tickets::TICKET_TTL).await {
+        Ok(true) => {}
+        Ok(false) => {
+            tracing::warn!("Can't sign in with sso: Ticket already used");
+            return error_page(StatusCode::BAD_REQUEST, "This SSO ticket was already used, sign in again");
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't sign in with sso: Failed to query database");
+            return error_page(StatusCode::INTERNAL_SERVER_ERROR, "Failed to sign in, try again later");
+        }
+    }
+
+    let (username, profile) = match validate_ticket(&sso_config, &service, &ticket).await {
+        Ok(validated) => validated,
+        Err(err) => return error_page(err.status, &err.message),
+    };
+    if let Err(err) = check_faculty(&profile, &sso_config) {
+        return error_page(err.status, &err.message);
+    }
+    if username_check(&username, &()).is_err() {
+        tracing::warn!(%username, "Can't sign in with sso: Username isn't allowed");
+        return error_page(StatusCode::FORBIDDEN, "Your SSO username can't be used on this server");
+    }
+
+    // users that come in this way keep signing in through SSO, their password is never shown
+    let password = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(PASSWORD_LENGTH)
+        .map(char::from)
+        .collect::<String>();
+
+    let user_id = match provision_user(&pool, &hasher, &username, username.clone(), &password, Some(&profile)).await {
+        Ok(user_id) => user_id,
+        Err(ProvisionError::Exists) | Err(ProvisionError::Raced) => {
+            return error_page(
+                StatusCode::CONFLICT,
+                "Your username is taken by a group on this server, ask an admin for help",
+            );
+        }
+        Err(ProvisionError::Internal(_)) => {
+            return error_page(StatusCode::INTERNAL_SERVER_ERROR, "Failed to sign in, try again later");
+        }
+    };
+
+    // a new session id and CSRF token, so ones known from before the login are worthless
+    auth.session.renew();
+    auth.session.remove(CSRF_SESSION_KEY);
+    if let Err(err) = sessions::start(&pool, &auth, user_id, session_limit).await {
+        return error_page(StatusCode::FORBIDDEN, &err.message());
+    }
+
+    Response::builder()
+        .status(StatusCode::FOUND)
+        .header(header::LOCATION, return_to.as_deref().unwrap_or(REDIRECT_TARGET))
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Ask CAS who a ticket was issued to
+async fn validate_ticket(sso_config: &SsoConfig, service: &Url, ticket: &str) -> Result<(String, Profile), SsoError> {
+    let mut url = sso_config.cas.join("p3/serviceValidate").map_err(|err| {
+        tracing::error!(?err, "Can't validate sso ticket: Failed to build validation url");
+        SsoError::new(StatusCode::INTERNAL_SERVER_ERROR, "SSO is misconfigured on this server")
+    })?;
+    url.query_pairs_mut()
+        .append_pair("service", service.as_str())
+        .append_pair("ticket", ticket)
+        .append_pair("format", "JSON");
+
+    let res = reqwest::Client::new()
+        .get(url)
+        .timeout(SSO_TIMEOUT)
+        .send()
+        .await
+        .map_err(|err| {
+            tracing::error!(?err, "Can't validate sso ticket: Failed to request sso");
+            SsoError::new(upstream_status(&err), "SSO didn't answer, try again later")
+        })?;
+
+    if !res.status().is_success() {
+        tracing::error!(status = %res.status(), "Can't validate sso ticket: SSO failed");
+        return Err(SsoError::new(
+            StatusCode::BAD_GATEWAY,
+            format!("SSO failed with {}, try again later", res.status()),
+        ));
+    }
+
+    let body = res.bytes().await.map_err(|err| {
+        tracing::error!(?err, "Can't validate sso ticket: Failed to get body");
+        SsoError::new(upstream_status(&err), "SSO didn't answer, try again later")
+    })?;
+
+    let result = serde_json::from_slice::<ValidationResponse>(&body)
+        .map_err(|err| {
+            tracing::error!(?err, "Can't validate sso ticket: Failed to parse body");
+            SsoError::new(StatusCode::BAD_GATEWAY, "SSO gave an answer this server doesn't understand")
+        })?
+        .service_response;
+
+    match (result.authentication_success, result.authentication_failure) {
+        (Some(success), _) => {
+            let profile = Profile::from_attributes(&success.attributes, &sso_config.attributes);
+            Ok((success.user, profile))
+        }
+        (None, Some(failure)) => {
+            tracing::info!(code = %failure.code, description = %failure.description, "SSO rejected ticket");
+            Err(SsoError::new(
+                StatusCode::UNAUTHORIZED,
+                "Your SSO sign in expired or was already used, sign in again",
+            ))
+        }
+        (None, None) => {
+            tracing::error!("Can't validate sso ticket: SSO answered neither success nor failure");
+            Err(SsoError::new(StatusCode::BAD_GATEWAY, "SSO gave an answer this server doesn't understand"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(path: &str) -> Option<String> {
+        local_path(Some(path.to_string()))
+    }
+
+    #[test]
+    fn local_path_keeps_paths_on_this_server() {
+        assert_eq!(local("/"), Some("/".to_string()));
+        assert_eq!(local("/alice/blog"), Some("/alice/blog".to_string()));
+        assert_eq!(local("/alice/blog?tab=logs#top"), Some("/alice/blog?tab=logs#top".to_string()));
+        assert_eq!(local_path(None), None);
+    }
+
+    #[test]
+    fn local_path_refuses_other_sites() {
+        for path in [
+            "",
+            "alice/blog",
+            "https://evil.com",
+            "//evil.com",
+            "/\\evil.com",
+            "\\/evil.com",
+            "/\t/evil.com",
+            "/\n/evil.com",
+            "/ /evil.com",
+            "/\u{a0}/evil.com",
+            "/\r\n/evil.com",
+        ] {
+            assert_eq!(local(path), None, "{path:?}");
+        }
+    }
+
+    #[test]
+    fn service_url_carries_return_to() {
+        let url = service_url("pws.example", true, Some("/alice/blog?tab=logs")).unwrap();
+        assert_eq!(url.as_str(), "https://pws.example/auth/sso/callback?return_to=%2Falice%2Fblog%3Ftab%3Dlogs");
+
+        let url = service_url("localhost:8080", false, None).unwrap();
+        assert_eq!(url.as_str(), "http://localhost:8080/auth/sso/callback");
+    }
+}
@@ -0,0 +1,205 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::configuration::Argon2Settings;
+
+/// Hashes passwords and git tokens with the Argon2id parameters of `auth.argon2`
+#[derive(Debug, Clone)]
+pub struct Hasher {
+    params: Params,
+}
+
+/// Outcome of checking a secret against its stored hash
+#[derive(Debug, PartialEq)]
+pub enum Verification {
+    Invalid,
+    Valid,
+    /// valid, but hashed with weaker parameters than the configured ones. Holds the hash to
+    /// store instead
+    Rehashed(String),
+}
+
+impl Verification {
+    pub fn is_valid(&self) -> bool {
+        !matches!(self, Verification::Invalid)
+    }
+}
+
+impl Hasher {
+    pub fn new(settings: &Argon2Settings) -> Result<Self, argon2::Error> {
+        let params = Params::new(settings.memory, settings.iterations, settings.parallelism, None)?;
+
+        Ok(Self { params })
+    }
+
+    fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+
+    pub fn hash(&self, secret: &[u8]) -> Result<String, argon2::password_hash::Error> {
+        let salt = SaltString::generate(&mut OsRng);
+
+        Ok(self.argon2().hash_password(secret, &salt)?.to_string())
+    }
+
+    /// Check `secret` against `stored`, which may have been hashed with any parameters. Ones
+    /// weaker than the configured parameters are hashed again.
+    pub fn verify(&self, secret: &[u8], stored: &str) -> Verification {
+        let Ok(hash) = PasswordHash::new(stored) else {
+            return Verification::Invalid;
+        };
+        // the parameters are read from the hash, so old hashes keep verifying
+        if self.argon2().verify_password(secret, &hash).is_err() {
+            return Verification::Invalid;
+        }
+        if !self.is_outdated(&hash) {
+            return Verification::Valid;
+        }
+
+        match self.hash(secret) {
+            Ok(rehashed) => Verification::Rehashed(rehashed),
+            Err(err) => {
+                tracing::warn!(?err, "Can't rehash: Failed to hash");
+                Verification::Valid
+            }
+        }
+    }
+
+    /// Not Argon2id or weaker in any of the parameters than the configured ones
+    fn is_outdated(&self, hash: &PasswordHash) -> bool {
+        let Ok(params) = Params::try_from(hash) else {
+            return true;
+        };
+
+        hash.algorithm != argon2::ARGON2ID_IDENT
+            || params.m_cost() < self.params.m_cost()
+            || params.t_cost() < self.params.t_cost()
+            || params.p_cost() < self.params.p_cost()
+    }
+}
+
+/// Replace the password hash of a user with its rehash. Only the hash that was verified is
+/// replaced, so concurrent logins store one rehash between them. Failing is logged, the old
+/// hash keeps working.
+pub async fn store_password(pool: &PgPool, user_id: Uuid, verified: &str, rehashed: &str) {
+    if let Err(err) = sqlx::query!(
+        "UPDATE users SET password = $1, updated_at = now() WHERE id = $2 AND password = $3",
+        rehashed,
+        user_id,
+        verified,
+    )
+    .execute(pool)
+    .await
+    {
+        tracing::error!(?err, "Can't rehash password: Failed to update database");
+    }
+}
+
+/// Replace the hash of a git token with its rehash, like `store_password`
+pub async fn store_token(pool: &PgPool, token_id: Uuid, verified: &str, rehashed: &str) {
+    if let Err(err) = sqlx::query!(
+        "UPDATE api_token SET token = $1 WHERE id = $2 AND token = $3",
+        rehashed,
+        token_id,
+        verified,
+    )
+    .execute(pool)
+    .await
+    {
+        tracing::error!(?err, "Can't rehash git token: Failed to update database");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hasher(memory: u32, iterations: u32) -> Hasher {
+        Hasher::new(&Argon2Settings {
+            memory,
+            iterations,
+            parallelism: 1,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn verify_accepts_hashes_with_the_configured_parameters() {
+        let hasher = hasher(16, 2);
+        let stored = hasher.hash(b"hunter2").unwrap();
+
+        assert_eq!(hasher.verify(b"hunter2", &stored), Verification::Valid);
+    }
+
+    #[test]
+    fn verify_rehashes_weaker_hashes() {
+        let hasher = hasher(16, 2);
+
+        for weaker in [self::hasher(8, 2), self::hasher(16, 1)] {
+            let stored = weaker.hash(b"hunter2").unwrap();
+            let Verification::Rehashed(rehashed) = hasher.verify(b"hunter2", &stored) else {
+                panic!("{stored} wasn't rehashed");
+            };
+            assert_eq!(hasher.verify(b"hunter2", &rehashed), Verification::Valid);
+        }
+
+        // argon2i rather than argon2id
+        let salt = SaltString::generate(&mut OsRng);
+        let stored = Argon2::new(Algorithm::Argon2i, Version::V0x13, hasher.params.clone())
+            .hash_password(b"hunter2", &salt)
+            .unwrap()
+            .to_string();
+        assert!(matches!(hasher.verify(b"hunter2", &stored), Verification::Rehashed(_)));
+    }
+
+    #[test]
+    fn verify_refuses_wrong_secrets() {
+        let hasher = hasher(16, 2);
+
+        let stored = hasher.hash(b"hunter2").unwrap();
+        assert_eq!(hasher.verify(b"hunter3", &stored), Verification::Invalid);
+
+        // wrong under weaker parameters isn't rehashed either
+        let stored = self::hasher(8, 1).hash(b"hunter2").unwrap();
+        assert_eq!(hasher.verify(b"hunter3", &stored), Verification::Invalid);
+
+        assert_eq!(hasher.verify(b"hunter2", "not a hash"), Verification::Invalid);
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn concurrent_rehashes_store_one(pool: PgPool) {
+        let (weaker, hasher) = (hasher(8, 1), hasher(16, 2));
+        let stored = weaker.hash(b"hunter2").unwrap();
+        let user_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO users (id, username, password, name) VALUES ($1, 'alice', $2, 'Alice')")
+            .bind(user_id)
+            .bind(&stored)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        // two logins verified the same old hash at once
+        let mut rehashes = Vec::new();
+        for _ in 0..2 {
+            let Verification::Rehashed(rehashed) = hasher.verify(b"hunter2", &stored) else {
+                panic!("{stored} wasn't rehashed");
+            };
+            rehashes.push(rehashed);
+        }
+        assert_ne!(rehashes[0], rehashes[1]);
+        for rehashed in &rehashes {
+            store_password(&pool, user_id, &stored, rehashed).await;
+        }
+
+        let password: String = sqlx::query_scalar("SELECT password FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(password, rehashes[0]);
+    }
+}
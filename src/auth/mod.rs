@@ -7,7 +7,7 @@ use axum::{
 use axum_session::SessionStore;
 use bytes::Bytes;
 use http_body::combinators::UnsyncBoxBody;
-use hyper::{Body, Request, StatusCode};
+use hyper::{Body, Method, Request, StatusCode};
 use regex::Regex;
 use secrecy::{ExposeSecret, Secret};
 use serde::{Deserialize, Serialize};
@@ -27,6 +27,9 @@ lazy_static! {
 }
 
 pub mod api;
+pub mod hashing;
+pub mod sessions;
+pub mod sso_tickets;
 
 pub type Auth = AuthSession<User, Uuid, SessionPgPool, PgPool>;
 
@@ -46,6 +49,98 @@ pub async fn auth<B>(
     Ok(next.run(request).await)
 }
 
+/// Permission token granting access to the `/api/admin` routes
+pub const ADMIN_PERMISSION: &str = "admin";
+
+pub async fn admin<B>(
+    auth: Auth,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response<UnsyncBoxBody<Bytes, axum::Error>>, hyper::Response<Body>> {
+    let Some(user) = auth.current_user.as_ref() else {
+        return Err(Response::builder()
+            .status(StatusCode::FOUND)
+            .header("Location", "/api/login")
+            .body(Body::empty())
+            .unwrap());
+    };
+
+    if !user.permissions.contains(ADMIN_PERMISSION) {
+        let json = serde_json::json!({ "message": "You are not allowed to access this resource" });
+        return Err(Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body(Body::from(json.to_string()))
+            .unwrap());
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Session key of the CSRF token, handed out by `GET /api/csrf`
+pub const CSRF_SESSION_KEY: &str = "csrf_token";
+
+/// Header state-changing requests carry the CSRF token in
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// Routes that run before there is a session to protect
+const CSRF_EXEMPT: [&str; 4] = ["/api/login", "/api/login/", "/api/register", "/api/register/"];
+
+fn csrf_error(code: &str, message: &str) -> hyper::Response<Body> {
+    let json = serde_json::json!({ "message": message, "code": code });
+
+    Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .body(Body::from(json.to_string()))
+        .unwrap()
+}
+
+/// Compare without stopping at the first difference, so the time taken doesn't tell how much
+/// of a guessed token is right
+fn tokens_match(expected: &str, sent: &str) -> bool {
+    expected.len() == sent.len()
+        && expected
+            .bytes()
+            .zip(sent.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Reject state-changing requests without the session's CSRF token in `X-CSRF-Token`. A page on
+/// another site can make the browser send the session cookie, but it can't read the token.
+/// Git routes authenticate with project credentials instead of the session and don't pass
+/// through here.
+pub async fn csrf<B>(
+    auth: Auth,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response<UnsyncBoxBody<Bytes, axum::Error>>, hyper::Response<Body>> {
+    if matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS)
+        || CSRF_EXEMPT.contains(&request.uri().path())
+    {
+        return Ok(next.run(request).await);
+    }
+
+    let sent = request
+        .headers()
+        .get(CSRF_HEADER)
+        .and_then(|token| token.to_str().ok());
+    let Some(sent) = sent else {
+        return Err(csrf_error(
+            "csrf_token_missing",
+            "Missing CSRF token, get one from /api/csrf and send it in the X-CSRF-Token header",
+        ));
+    };
+
+    // a new session after logging in starts without a token
+    match auth.session.get::<String>(CSRF_SESSION_KEY) {
+        Some(expected) if tokens_match(&expected, sent) => Ok(next.run(request).await),
+        _ => Err(csrf_error(
+            "csrf_token_invalid",
+            "Invalid CSRF token, get a new one from /api/csrf",
+        )),
+    }
+}
+
 pub async fn auth_layer(
     pool: &PgPool,
     config: &Settings,
@@ -178,6 +273,7 @@ pub struct UserRequest {
 }
 
 #[derive(Serialize, Debug)]
+#[allow(clippy::enum_variant_names)]
 enum RegisterUserErrorType {
     ValidationError,
     BadRequestError,
@@ -190,3 +286,17 @@ struct ErrorResponse {
     message: String,
     error_type: RegisterUserErrorType,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_match_only_the_same_token() {
+        assert!(tokens_match("3f9a7c", "3f9a7c"));
+        assert!(!tokens_match("3f9a7c", "3f9a7d"));
+        assert!(!tokens_match("3f9a7c", "3f9a7"));
+        assert!(!tokens_match("3f9a7c", "3f9a7c0"));
+        assert!(!tokens_match("3f9a7c", ""));
+    }
+}
@@ -0,0 +1,366 @@
+use axum::{
+    extract::State,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use hyper::{Body, Request, StatusCode};
+use sqlx::PgPool;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{admin::audit, auth::Auth, configuration::SessionPolicy, startup::AppState};
+
+/// Session key of the id a session is tracked by in `user_sessions`. The store's own id
+/// changes when a login renews the session, this one stays.
+const TRACKING_KEY: &str = "tracking_id";
+
+/// In seconds, how stale the last use of a session may get before it is written again. It only
+/// has to tell sessions in use from expired ones.
+const SEEN_INTERVAL_SECS: i64 = 3600;
+
+/// How many sessions a user can have at once
+#[derive(Debug, Clone, Copy)]
+pub struct SessionLimit {
+    /// 0 for no limit
+    pub max: usize,
+    pub policy: SessionPolicy,
+    /// in hours, a session unused for longer has expired
+    pub lifespan: i64,
+}
+
+/// The user is at the limit and the policy is to turn new logins away
+#[derive(Debug)]
+pub struct TooManySessions {
+    pub max: usize,
+}
+
+impl TooManySessions {
+    pub fn message(&self) -> String {
+        format!(
+            "You are signed in on {} devices already, sign out on one of them first",
+            self.max
+        )
+    }
+}
+
+/// Log the user in and track the session. Over the limit, the oldest sessions of the user are
+/// signed out or the login is turned away, by `auth.sessionpolicy`. Failing to track a session
+/// is logged but doesn't fail the login.
+pub async fn start(pool: &PgPool, auth: &Auth, user_id: Uuid, limit: SessionLimit) -> Result<(), TooManySessions> {
+    // logging in again in the same browser replaces its session rather than adding one
+    end(pool, auth).await;
+    admit(pool, user_id, limit).await?;
+
+    auth.login_user(user_id);
+    track(pool, auth, user_id, limit).await;
+
+    Ok(())
+}
+
+/// Turn the login away when the user is at the limit and the policy is to reject
+async fn admit(pool: &PgPool, user_id: Uuid, limit: SessionLimit) -> Result<(), TooManySessions> {
+    if limit.max == 0 || limit.policy != SessionPolicy::Reject {
+        return Ok(());
+    }
+
+    match count_active(pool, user_id, limit.lifespan).await {
+        Ok(active) if active >= limit.max as i64 => Err(TooManySessions { max: limit.max }),
+        Ok(_) => Ok(()),
+        Err(err) => {
+            tracing::error!(?err, "Can't count sessions: Failed to query database");
+            Ok(())
+        }
+    }
+}
+
+/// Track a signed in session under a new id, signing out the oldest ones over the limit when
+/// the policy is to evict
+async fn track(pool: &PgPool, auth: &Auth, user_id: Uuid, limit: SessionLimit) {
+    match insert(pool, user_id, limit).await {
+        Ok(tracking_id) => auth.session.set(TRACKING_KEY, tracking_id),
+        Err(err) => tracing::error!(?err, "Can't track session: Failed to insert into database"),
+    }
+}
+
+async fn insert(pool: &PgPool, user_id: Uuid, limit: SessionLimit) -> Result<Uuid, sqlx::Error> {
+    let tracking_id = Uuid::from(Ulid::new());
+    sqlx::query!(
+        "INSERT INTO user_sessions (id, user_id) VALUES ($1, $2)",
+        tracking_id,
+        user_id,
+    )
+    .execute(pool)
+    .await?;
+
+    if limit.max > 0 && limit.policy == SessionPolicy::Evict {
+        evict(pool, user_id, limit).await;
+    }
+
+    Ok(tracking_id)
+}
+
+/// Stop tracking the session, on logout
+pub async fn end(pool: &PgPool, auth: &Auth) {
+    let Some(tracking_id) = auth.session.get::<Uuid>(TRACKING_KEY) else {
+        return;
+    };
+    auth.session.remove(TRACKING_KEY);
+
+    if let Err(err) = sqlx::query!("DELETE FROM user_sessions WHERE id = $1", tracking_id)
+        .execute(pool)
+        .await
+    {
+        tracing::error!(?err, "Can't end session: Failed to delete from database");
+    }
+}
+
+async fn count_active(pool: &PgPool, user_id: Uuid, lifespan: i64) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT count(*) AS "count!"
+           FROM user_sessions
+           WHERE user_id = $1
+           AND evicted_at IS NULL
+           AND last_seen_at > now() - make_interval(hours => $2)
+        "#,
+        user_id,
+        lifespan as i32,
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Sign out the oldest sessions past the limit. They notice on their next request.
+async fn evict(pool: &PgPool, user_id: Uuid, limit: SessionLimit) {
+    let evicted = match sqlx::query_scalar!(
+        r#"UPDATE user_sessions SET evicted_at = now()
+           WHERE id IN (
+             SELECT id FROM user_sessions
+             WHERE user_id = $1
+             AND evicted_at IS NULL
+             AND last_seen_at > now() - make_interval(hours => $2)
+             ORDER BY created_at DESC
+             OFFSET $3
+           )
+           RETURNING id
+        "#,
+        user_id,
+        limit.lifespan as i32,
+        limit.max as i64,
+    )
+    .fetch_all(pool)
+    .await
+    {
+        Ok(evicted) => evicted,
+        Err(err) => {
+            tracing::error!(?err, "Can't evict sessions: Failed to query database");
+            return;
+        }
+    };
+
+    if !evicted.is_empty() {
+        audit::record(
+            pool,
+            user_id,
+            "session.evict",
+            serde_json::json!({
+                "sessions": evicted,
+                "max": limit.max,
+            }),
+        )
+        .await;
+    }
+}
+
+/// What a session's row says on one of its requests
+#[derive(Debug, PartialEq, Eq)]
+enum Seen {
+    /// there is none, the session is from before they were tracked or lost it
+    Untracked,
+    Evicted,
+    Active,
+}
+
+/// Look the session up, noting its use when the last one is older than [`SEEN_INTERVAL_SECS`]
+async fn seen(pool: &PgPool, tracking_id: Uuid) -> Result<Seen, sqlx::Error> {
+    let session = sqlx::query!(
+        r#"WITH seen AS (
+             UPDATE user_sessions SET last_seen_at = now()
+             WHERE id = $1
+             AND (evicted_at IS NOT NULL OR last_seen_at < now() - make_interval(secs => $2))
+             RETURNING evicted_at IS NOT NULL AS evicted
+           )
+           SELECT EXISTS (SELECT FROM user_sessions WHERE id = $1) AS "tracked!",
+                  COALESCE((SELECT evicted FROM seen), false) AS "evicted!"
+        "#,
+        tracking_id,
+        SEEN_INTERVAL_SECS as f64,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(match (session.tracked, session.evicted) {
+        (false, _) => Seen::Untracked,
+        (true, true) => Seen::Evicted,
+        (true, false) => Seen::Active,
+    })
+}
+
+fn evicted_response() -> Response {
+    let json = serde_json::json!({
+        "message": "You were signed out because you signed in on another device, sign in again",
+        "code": "session_evicted",
+    });
+
+    hyper::Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .header("Content-Type", "application/json")
+        .body(Body::from(json.to_string()))
+        .unwrap()
+        .into_response()
+}
+
+/// Sign out a session that was evicted since its last request, and note when a session was
+/// last used. Signed in sessions without a row, made before sessions were tracked or whose
+/// row is gone, are tracked from their first request on.
+pub async fn layer(
+    State(AppState { pool, session_limit, .. }): State<AppState>,
+    auth: Auth,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let Some(user_id) = auth.current_user.as_ref().map(|user| user.id) else {
+        return next.run(request).await;
+    };
+
+    let session = match auth.session.get::<Uuid>(TRACKING_KEY) {
+        Some(tracking_id) => seen(&pool, tracking_id).await,
+        None => Ok(Seen::Untracked),
+    };
+
+    match session {
+        Ok(Seen::Evicted) => {
+            auth.logout_user();
+            end(&pool, &auth).await;
+            evicted_response()
+        }
+        Ok(Seen::Untracked) => {
+            track(&pool, &auth, user_id, session_limit).await;
+            next.run(request).await
+        }
+        Ok(Seen::Active) => next.run(request).await,
+        Err(err) => {
+            tracing::error!(?err, "Can't check session: Failed to query database");
+            next.run(request).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(max: usize, policy: SessionPolicy) -> SessionLimit {
+        SessionLimit {
+            max,
+            policy,
+            lifespan: 24,
+        }
+    }
+
+    async fn user(pool: &PgPool) -> Uuid {
+        let user_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO users (id, username, password, name) VALUES ($1, 'alice', '', 'Alice')")
+            .bind(user_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        user_id
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn evict_policy_signs_out_the_oldest_sessions(pool: PgPool) {
+        let user_id = user(&pool).await;
+        let limit = limit(2, SessionPolicy::Evict);
+
+        let mut sessions = Vec::new();
+        for _ in 0..3 {
+            admit(&pool, user_id, limit).await.unwrap();
+            sessions.push(insert(&pool, user_id, limit).await.unwrap());
+        }
+
+        assert_eq!(seen(&pool, sessions[0]).await.unwrap(), Seen::Evicted);
+        assert_eq!(seen(&pool, sessions[1]).await.unwrap(), Seen::Active);
+        assert_eq!(seen(&pool, sessions[2]).await.unwrap(), Seen::Active);
+        assert_eq!(count_active(&pool, user_id, limit.lifespan).await.unwrap(), 2);
+
+        let audited: i64 = sqlx::query_scalar("SELECT count(*) FROM audit_log WHERE action = 'session.evict'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(audited, 1);
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn reject_policy_turns_away_logins_over_the_limit(pool: PgPool) {
+        let user_id = user(&pool).await;
+        let limit = limit(2, SessionPolicy::Reject);
+
+        let first = insert(&pool, user_id, limit).await.unwrap();
+        insert(&pool, user_id, limit).await.unwrap();
+
+        let err = admit(&pool, user_id, limit).await.unwrap_err();
+        assert_eq!(err.max, 2);
+        // nobody is signed out to make room
+        assert_eq!(seen(&pool, first).await.unwrap(), Seen::Active);
+
+        sqlx::query("DELETE FROM user_sessions WHERE id = $1")
+            .bind(first)
+            .execute(&pool)
+            .await
+            .unwrap();
+        admit(&pool, user_id, limit).await.unwrap();
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn expired_sessions_dont_count(pool: PgPool) {
+        let user_id = user(&pool).await;
+        let limit = limit(1, SessionPolicy::Reject);
+
+        insert(&pool, user_id, limit).await.unwrap();
+        sqlx::query("UPDATE user_sessions SET last_seen_at = now() - interval '2 days'")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        admit(&pool, user_id, limit).await.unwrap();
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn sessions_without_a_row_are_untracked(pool: PgPool) {
+        let user_id = user(&pool).await;
+        assert_eq!(seen(&pool, Uuid::new_v4()).await.unwrap(), Seen::Untracked);
+
+        let tracking_id = insert(&pool, user_id, limit(0, SessionPolicy::Evict)).await.unwrap();
+        sqlx::query("UPDATE user_sessions SET last_seen_at = now() - interval '2 hours'")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert_eq!(seen(&pool, tracking_id).await.unwrap(), Seen::Active);
+        let stale: bool = sqlx::query_scalar("SELECT last_seen_at < now() - interval '1 hour' FROM user_sessions")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert!(!stale);
+    }
+
+    #[tokio::test]
+    async fn evicted_sessions_get_json() {
+        let response = evicted_response();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "session_evicted");
+    }
+}
@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use data_encoding::HEXLOWER;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+/// How long a used CAS ticket is remembered, well past the few minutes CAS keeps it valid
+pub const TICKET_TTL: Duration = Duration::from_secs(3600);
+
+/// How often tickets past `TICKET_TTL` are removed
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Tickets are stored hashed, a leaked table can't be replayed either
+fn hash(ticket: &str) -> String {
+    HEXLOWER.encode(&Sha256::digest(ticket.as_bytes()))
+}
+
+/// Record `ticket` as used. False when it was already used in the last `ttl`, the callback
+/// rejects it then without asking CAS. Shared by every server process through Postgres.
+pub async fn consume(pool: &PgPool, ticket: &str, ttl: Duration) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query!(
+        r#"INSERT INTO consumed_sso_tickets (ticket_hash)
+           VALUES ($1)
+           ON CONFLICT (ticket_hash) DO UPDATE SET used_at = now()
+           WHERE consumed_sso_tickets.used_at < $2
+        "#,
+        hash(ticket),
+        Utc::now() - chrono::Duration::from_std(ttl).unwrap_or(chrono::Duration::zero()),
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(res.rows_affected() > 0)
+}
+
+/// Remove tickets used more than `ttl` ago
+pub async fn purge(pool: &PgPool, ttl: Duration) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "DELETE FROM consumed_sso_tickets WHERE used_at < $1",
+        Utc::now() - chrono::Duration::from_std(ttl).unwrap_or(chrono::Duration::zero()),
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(res.rows_affected())
+}
+
+/// Remove tickets past `TICKET_TTL`, checked every hour
+pub async fn run_purge(pool: PgPool) {
+    loop {
+        tokio::time::sleep(PURGE_INTERVAL).await;
+
+        match purge(&pool, TICKET_TTL).await {
+            Ok(0) => {}
+            Ok(removed) => tracing::info!(removed, "Purged used sso tickets"),
+            Err(err) => tracing::error!(?err, "Can't purge sso tickets: Failed to query database"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tickets_are_stored_hashed() {
+        let hashed = hash("ST-1-abc");
+
+        assert_eq!(hashed.len(), 64);
+        assert!(!hashed.contains("ST-1-abc"));
+        assert_eq!(hashed, hash("ST-1-abc"));
+        assert_ne!(hashed, hash("ST-2-abc"));
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn first_use_succeeds_and_replay_fails(pool: PgPool) {
+        assert!(consume(&pool, "ST-1-abc", TICKET_TTL).await.unwrap());
+        assert!(!consume(&pool, "ST-1-abc", TICKET_TTL).await.unwrap());
+        assert!(consume(&pool, "ST-2-abc", TICKET_TTL).await.unwrap());
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn tickets_age_out(pool: PgPool) {
+        assert!(consume(&pool, "ST-1-abc", TICKET_TTL).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // past a zero ttl the ticket is forgotten, by the purge or a later use
+        assert_eq!(purge(&pool, TICKET_TTL).await.unwrap(), 0);
+        assert!(consume(&pool, "ST-1-abc", Duration::ZERO).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(purge(&pool, Duration::ZERO).await.unwrap(), 1);
+        assert!(consume(&pool, "ST-1-abc", TICKET_TTL).await.unwrap());
+    }
+}
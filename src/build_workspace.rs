@@ -0,0 +1,182 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use uuid::Uuid;
+
+/// How often scratch directories left behind by crashed builds are looked for
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Scratch directory of one build under `build.scratchdir`, holding the Dockerfile the build
+/// uses and its ignore file, so the checkout is never written to. Removed on drop, whether the
+/// build succeeded, failed or was cancelled; `run_cleanup` removes the ones a crash left.
+#[derive(Debug)]
+pub struct BuildWorkspace {
+    dir: PathBuf,
+}
+
+impl BuildWorkspace {
+    /// A fresh directory for a build of `container_name`, unique even for concurrent builds of
+    /// the same project
+    pub fn create(scratch: &Path, container_name: &str) -> io::Result<Self> {
+        let dir = scratch.join(format!("{container_name}.{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir)?;
+
+        Ok(Self { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Write the Dockerfile and the ignore rules of the build context, returns the path to
+    /// pass to `docker build -f`. BuildKit reads `<Dockerfile>.dockerignore` next to the
+    /// Dockerfile in place of the `.dockerignore` of the context.
+    pub fn write_dockerfile(&self, dockerfile: &str, dockerignore: &str) -> io::Result<PathBuf> {
+        let path = self.dir.join("Dockerfile");
+        std::fs::write(&path, dockerfile)?;
+        std::fs::write(self.dir.join("Dockerfile.dockerignore"), dockerignore)?;
+
+        Ok(path)
+    }
+}
+
+impl Drop for BuildWorkspace {
+    fn drop(&mut self) {
+        match std::fs::remove_dir_all(&self.dir) {
+            Ok(()) => tracing::debug!("Cleaned up build workspace {:?}", self.dir),
+            Err(err) => tracing::warn!("Failed to cleanup build workspace {:?}: {}", self.dir, err),
+        }
+    }
+}
+
+/// Ignore rules for a build of the checkout at `container_src`: the project's own, with `.git`
+/// added so it is never sent to the daemon, where it would slow down every build and could be
+/// copied into the image by `COPY . .`
+pub fn dockerignore(container_src: &Path) -> io::Result<String> {
+    // a Dockerfile specific ignore file wins over the one of the context, like BuildKit reads them
+    let original = ["Dockerfile.dockerignore", ".dockerignore"]
+        .iter()
+        .map(|name| std::fs::read_to_string(container_src.join(name)))
+        .find(|result| !matches!(result, Err(err) if err.kind() == io::ErrorKind::NotFound))
+        .transpose()?;
+
+    let ignores_git = original.as_deref().map_or(false, |content| {
+        content
+            .lines()
+            .map(|line| line.trim().trim_start_matches('/').trim_end_matches('/'))
+            .any(|line| line == ".git" || line == "**/.git")
+    });
+
+    Ok(match original {
+        Some(content) if ignores_git => content,
+        Some(content) => format!("{}\n.git\n", content.trim_end()),
+        None => ".git\n".to_string(),
+    })
+}
+
+/// Create the scratch directory if it doesn't exist and make sure builds can write to it
+pub fn prepare(scratch: &Path) -> io::Result<()> {
+    std::fs::create_dir_all(scratch)?;
+
+    let probe = scratch.join(format!(".probe.{}", Uuid::new_v4()));
+    std::fs::write(&probe, b"")?;
+    std::fs::remove_file(&probe)
+}
+
+/// Remove the workspaces in `scratch` last modified over `max_age` ago, returns how many
+pub fn remove_stale(scratch: &Path, max_age: Duration) -> io::Result<usize> {
+    let cutoff = SystemTime::now() - max_age;
+    let mut removed = 0;
+
+    for entry in std::fs::read_dir(scratch)? {
+        let entry = entry?;
+        let modified = entry.metadata().and_then(|metadata| {
+            match metadata.is_dir() {
+                true => metadata.modified().map(Some),
+                false => Ok(None),
+            }
+        });
+
+        match modified {
+            Ok(Some(modified)) if modified < cutoff => match std::fs::remove_dir_all(entry.path()) {
+                Ok(()) => removed += 1,
+                Err(err) => tracing::warn!("Failed to remove stale build workspace {:?}: {}", entry.path(), err),
+            },
+            Ok(_) => {}
+            Err(err) => tracing::warn!("Failed to read build workspace {:?}: {}", entry.path(), err),
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Remove stale workspaces right away and then every hour. `max_age` has to be longer than
+/// any build runs, a workspace is left alone while its build uses it.
+pub async fn run_cleanup(scratch: PathBuf, max_age: Duration) {
+    loop {
+        let result = tokio::task::spawn_blocking({
+            let scratch = scratch.clone();
+            move || remove_stale(&scratch, max_age)
+        })
+        .await;
+
+        match result {
+            Ok(Ok(0)) => {}
+            Ok(Ok(removed)) => tracing::info!(removed, "Removed stale build workspaces"),
+            Ok(Err(err)) => tracing::error!(?err, "Can't remove stale build workspaces: Failed to read scratch directory"),
+            Err(err) => tracing::error!(?err, "Can't remove stale build workspaces"),
+        }
+
+        tokio::time::sleep(CLEANUP_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkout(files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pws-checkout-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, content) in files {
+            std::fs::write(dir.join(name), content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn git_is_ignored_without_an_ignore_file() {
+        let dir = checkout(&[]);
+        assert_eq!(dockerignore(&dir).unwrap(), ".git\n");
+    }
+
+    #[test]
+    fn git_is_added_to_the_projects_rules() {
+        let dir = checkout(&[(".dockerignore", "node_modules\n*.log\n\n")]);
+        assert_eq!(dockerignore(&dir).unwrap(), "node_modules\n*.log\n.git\n");
+
+        let dir = checkout(&[(".dockerignore", "node_modules\n/.git/\n")]);
+        assert_eq!(dockerignore(&dir).unwrap(), "node_modules\n/.git/\n");
+    }
+
+    #[test]
+    fn dockerfile_ignore_file_wins() {
+        let dir = checkout(&[(".dockerignore", "node_modules\n"), ("Dockerfile.dockerignore", "dist\n")]);
+        assert_eq!(dockerignore(&dir).unwrap(), "dist\n.git\n");
+    }
+
+    #[test]
+    fn workspace_is_removed_on_drop() {
+        let scratch = checkout(&[]);
+        let workspace = BuildWorkspace::create(&scratch, "alice-blog").unwrap();
+        let dockerfile = workspace.write_dockerfile("FROM scratch\n", ".git\n").unwrap();
+
+        assert_eq!(std::fs::read_to_string(dockerfile.with_extension("dockerignore")).unwrap(), ".git\n");
+        let dir = workspace.dir().to_path_buf();
+        drop(workspace);
+        assert!(!dir.exists());
+    }
+}
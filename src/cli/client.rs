@@ -0,0 +1,236 @@
+use std::collections::BTreeMap;
+
+use reqwest::{header, Method, RequestBuilder, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::auth::CSRF_HEADER;
+
+#[derive(Error, Debug)]
+pub enum ApiError {
+    #[error("{message} ({status})")]
+    Status { status: StatusCode, message: String },
+    #[error("Failed to reach server: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("Not logged in, run `pws login` first")]
+    NotLoggedIn,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Project {
+    pub id: String,
+    pub name: String,
+    pub owner_name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ProjectList {
+    data: Vec<Project>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Environs {
+    pub env: BTreeMap<String, String>,
+    pub version: i64,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct EnvironsUpdate {
+    pub updated: usize,
+    pub keys: Vec<String>,
+    pub version: i64,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Build {
+    pub id: String,
+    pub status: String,
+    pub created_at: String,
+    pub finished_at: Option<String>,
+    #[serde(default)]
+    pub logs: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BuildList {
+    data: Vec<Build>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CsrfToken {
+    token: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ContainerLogs {
+    logs: String,
+}
+
+/// Typed client of the PWS HTTP API, shared by the cli commands
+pub struct ApiClient {
+    base: String,
+    cookie: Option<String>,
+    http: reqwest::Client,
+    /// fetched with the first POST of the session
+    csrf: tokio::sync::OnceCell<String>,
+}
+
+impl ApiClient {
+    pub fn new(base: &str, cookie: Option<String>) -> Self {
+        Self {
+            base: base.trim_end_matches('/').to_string(),
+            cookie,
+            http: reqwest::Client::new(),
+            csrf: tokio::sync::OnceCell::new(),
+        }
+    }
+
+    fn request(&self, method: Method, path: &str) -> Result<RequestBuilder, ApiError> {
+        let cookie = self.cookie.as_ref().ok_or(ApiError::NotLoggedIn)?;
+
+        Ok(self
+            .http
+            .request(method, format!("{}{path}", self.base))
+            .header(header::COOKIE, cookie))
+    }
+
+    /// A state-changing request, carrying the CSRF token of the session
+    async fn mutation(&self, method: Method, path: &str) -> Result<RequestBuilder, ApiError> {
+        let token = self
+            .csrf
+            .get_or_try_init(|| async {
+                let csrf: CsrfToken = Self::json(self.request(Method::GET, "/api/csrf")?).await?;
+                Ok::<_, ApiError>(csrf.token)
+            })
+            .await?;
+
+        Ok(self.request(method, path)?.header(CSRF_HEADER, token))
+    }
+
+    async fn send(request: RequestBuilder) -> Result<reqwest::Response, ApiError> {
+        let res = request.send().await?;
+        if res.status().is_success() {
+            return Ok(res);
+        }
+
+        let status = res.status();
+        let body = res.text().await.unwrap_or_default();
+        let message = match serde_json::from_str::<ErrorResponse>(&body) {
+            Ok(ErrorResponse { message }) => message,
+            Err(_) if body.trim().is_empty() => status
+                .canonical_reason()
+                .unwrap_or("Request failed")
+                .to_string(),
+            Err(_) => body,
+        };
+
+        Err(ApiError::Status { status, message })
+    }
+
+    async fn json<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, ApiError> {
+        Ok(Self::send(request).await?.json::<T>().await?)
+    }
+
+    /// Log in and return the session cookie to use for the following requests
+    pub async fn login(&self, username: &str, password: &str) -> Result<String, ApiError> {
+        // keep the redirect, the session cookie is set on it
+        let http = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        let res = http
+            .post(format!("{}/api/login", self.base))
+            .json(&serde_json::json!({ "username": username, "password": password }))
+            .send()
+            .await?;
+
+        // a successful login answers with a redirect to the dashboard
+        let status = res.status();
+        if !status.is_success() && !status.is_redirection() {
+            let message = res
+                .json::<ErrorResponse>()
+                .await
+                .map(|err| err.message)
+                .unwrap_or_else(|_| "Login failed".to_string());
+            return Err(ApiError::Status { status, message });
+        }
+
+        let cookie = res
+            .headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(|value| value.split(';').next())
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        Ok(cookie)
+    }
+
+    pub async fn projects(&self) -> Result<Vec<Project>, ApiError> {
+        let list: ProjectList = Self::json(self.request(Method::GET, "/api/dashboard/project")?).await?;
+        Ok(list.data)
+    }
+
+    pub async fn environs(&self, owner: &str, project: &str) -> Result<Environs, ApiError> {
+        Self::json(self.request(Method::GET, &format!("/api/project/{owner}/{project}/env"))?).await
+    }
+
+    pub async fn set_environ(
+        &self,
+        owner: &str,
+        project: &str,
+        key: &str,
+        value: &str,
+    ) -> Result<(), ApiError> {
+        let request = self
+            .mutation(Method::POST, &format!("/api/project/{owner}/{project}/env"))
+            .await?
+            .json(&serde_json::json!({ "key": key, "value": value }));
+        Self::send(request).await.map(|_| ())
+    }
+
+    pub async fn unset_environ(&self, owner: &str, project: &str, key: &str) -> Result<(), ApiError> {
+        let request = self
+            .mutation(Method::POST, &format!("/api/project/{owner}/{project}/env/delete"))
+            .await?
+            .json(&serde_json::json!({ "key": key }));
+        Self::send(request).await.map(|_| ())
+    }
+
+    pub async fn import_environs(
+        &self,
+        owner: &str,
+        project: &str,
+        environs: &BTreeMap<String, String>,
+        version: i64,
+    ) -> Result<EnvironsUpdate, ApiError> {
+        let request = self
+            .mutation(Method::POST, &format!("/api/project/{owner}/{project}/env/bulk"))
+            .await?
+            .header(header::IF_MATCH, format!("\"{version}\""))
+            .json(&serde_json::json!({ "environs": environs }));
+        Self::json(request).await
+    }
+
+    /// Builds of a project, newest first
+    pub async fn builds(&self, owner: &str, project: &str) -> Result<Vec<Build>, ApiError> {
+        let list: BuildList =
+            Self::json(self.request(Method::GET, &format!("/api/project/{owner}/{project}/builds"))?).await?;
+        Ok(list.data)
+    }
+
+    pub async fn build(&self, owner: &str, project: &str, id: &str) -> Result<Build, ApiError> {
+        Self::json(self.request(Method::GET, &format!("/api/project/{owner}/{project}/builds/{id}"))?).await
+    }
+
+    pub async fn container_logs(&self, owner: &str, project: &str) -> Result<String, ApiError> {
+        let logs: ContainerLogs =
+            Self::json(self.request(Method::GET, &format!("/api/project/{owner}/{project}/logs"))?).await?;
+        Ok(logs.logs)
+    }
+}
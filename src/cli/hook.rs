@@ -0,0 +1,60 @@
+use std::path::Path;
+
+use clap::{ArgMatches, Command};
+
+use crate::{configuration::PushCheckPolicy, push_checks};
+
+pub fn command() -> Command {
+    Command::new("hook")
+        .about("Run by git on pushes, installed by the server in the git folder")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("pre-receive")
+                .about("Check the pushed files for secrets and files over the size limit"),
+        )
+}
+
+/// Run a hook and return the process exit code, anything but 0 rejects the push. Output goes
+/// to stderr, which the git client shows to the user.
+pub fn run(args: &ArgMatches) -> i32 {
+    match args.subcommand() {
+        Some(("pre-receive", _)) => pre_receive(),
+        _ => unreachable!("subcommand is required"),
+    }
+}
+
+fn pre_receive() -> i32 {
+    let (policy, max_file_size) = push_checks::hook_settings();
+    if policy == PushCheckPolicy::Off {
+        return 0;
+    }
+
+    let findings = match push_checks::check(Path::new("."), std::io::stdin().lock(), max_file_size) {
+        Ok(findings) => findings,
+        // a broken check shouldn't stop anyone from deploying
+        Err(err) => {
+            eprintln!("WARNING: Failed to check the pushed files: {err}");
+            return 0;
+        }
+    };
+    if findings.is_empty() {
+        return 0;
+    }
+
+    match policy {
+        PushCheckPolicy::Reject => {
+            eprintln!("Push rejected, these files can't be deployed:");
+            for finding in findings {
+                eprintln!("  {finding}");
+            }
+            eprintln!("Remove them from the pushed commits, e.g. with `git rm --cached` and `git commit --amend`, and push again");
+            1
+        }
+        _ => {
+            for finding in findings {
+                eprintln!("WARNING: {finding}");
+            }
+            0
+        }
+    }
+}
@@ -0,0 +1,407 @@
+mod client;
+mod hook;
+mod ssh;
+
+use std::{collections::BTreeMap, path::PathBuf, time::Duration};
+
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use serde::{Deserialize, Serialize};
+
+use self::client::{ApiClient, ApiError};
+
+/// Builds that are still running, as serialized by the api
+const UNFINISHED_STATES: [&str; 2] = ["PENDING", "BUILDING"];
+
+#[derive(Deserialize, Debug, Default)]
+struct CliSettings {
+    url: Option<String>,
+    token: Option<String>,
+}
+
+fn config_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/pws/config.toml"))
+}
+
+/// Read `~/.config/pws/config.toml`, `PWS_URL` and `PWS_TOKEN` take precedence
+fn read_settings() -> CliSettings {
+    let mut builder = config::Config::builder();
+    if let Some(path) = config_path() {
+        builder = builder.add_source(
+            config::File::from(path)
+                .format(config::FileFormat::Toml)
+                .required(false),
+        );
+    }
+
+    let mut settings = builder
+        .build()
+        .and_then(|config| config.try_deserialize::<CliSettings>())
+        .unwrap_or_default();
+
+    if let Ok(url) = std::env::var("PWS_URL") {
+        settings.url = Some(url);
+    }
+    if let Ok(token) = std::env::var("PWS_TOKEN") {
+        settings.token = Some(token);
+    }
+
+    settings
+}
+
+fn write_settings(url: &str, token: &str) -> std::io::Result<PathBuf> {
+    let path = config_path()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "HOME is not set"))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    // json string escapes are valid toml basic strings
+    let contents = format!(
+        "url = {}\ntoken = {}\n",
+        serde_json::to_string(url).unwrap(),
+        serde_json::to_string(token).unwrap(),
+    );
+    std::fs::write(&path, contents)?;
+
+    Ok(path)
+}
+
+fn project_args() -> [Arg; 2] {
+    [
+        Arg::new("owner").required(true).help("Owner of the project"),
+        Arg::new("project").required(true).help("Name of the project"),
+    ]
+}
+
+pub fn command() -> Command {
+    Command::new("pws")
+        .about("PWS server and command line client")
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Print responses as json"),
+        )
+        .arg(
+            Arg::new("url")
+                .long("url")
+                .global(true)
+                .help("Base url of the PWS server, defaults to PWS_URL or the config file"),
+        )
+        .subcommand(Command::new("serve").about("Run the server, the default without a subcommand"))
+        .subcommand(
+            Command::new("login")
+                .about("Log in and store the session in ~/.config/pws/config.toml")
+                .arg(Arg::new("username").required(true))
+                .arg(
+                    Arg::new("password")
+                        .long("password")
+                        .help("Read from stdin when not given"),
+                ),
+        )
+        .subcommand(
+            Command::new("projects")
+                .about("Manage projects")
+                .subcommand_required(true)
+                .subcommand(Command::new("list").about("List the projects you have access to")),
+        )
+        .subcommand(
+            Command::new("env")
+                .about("Manage the environment variables of a project")
+                .subcommand_required(true)
+                .subcommand(Command::new("get").args(project_args()))
+                .subcommand(
+                    Command::new("set")
+                        .args(project_args())
+                        .arg(Arg::new("pair").required(true).value_name("KEY=VALUE")),
+                )
+                .subcommand(
+                    Command::new("unset")
+                        .args(project_args())
+                        .arg(Arg::new("key").required(true)),
+                )
+                .subcommand(
+                    Command::new("import")
+                        .about("Replace the variables with the contents of a .env file")
+                        .args(project_args())
+                        .arg(Arg::new("file").required(true)),
+                ),
+        )
+        .subcommand(
+            Command::new("deploy")
+                .about("Show the latest deploy of a project")
+                .args(project_args())
+                .arg(
+                    Arg::new("follow")
+                        .long("follow")
+                        .short('f')
+                        .action(ArgAction::SetTrue)
+                        .help("Wait until the deploy finishes"),
+                ),
+        )
+        .subcommand(
+            Command::new("logs")
+                .about("Show the container logs of a project")
+                .args(project_args())
+                .arg(
+                    Arg::new("tail")
+                        .long("tail")
+                        .short('n')
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Only show the last N lines"),
+                ),
+        )
+        .subcommand(ssh::command())
+        .subcommand(hook::command())
+}
+
+/// Run a client subcommand and return the process exit code
+pub async fn run(matches: &ArgMatches) -> i32 {
+    // run on the server by sshd and git, they don't talk to the api
+    if let Some(("ssh", args)) = matches.subcommand() {
+        return ssh::run(args).await;
+    }
+    if let Some(("hook", args)) = matches.subcommand() {
+        return hook::run(args);
+    }
+
+    let json = matches.get_flag("json");
+    let settings = read_settings();
+    let url = matches
+        .get_one::<String>("url")
+        .cloned()
+        .or(settings.url)
+        .unwrap_or_else(|| "http://localhost:8080".to_string());
+    let client = ApiClient::new(&url, settings.token);
+
+    let result = match matches.subcommand() {
+        Some(("login", args)) => login(&client, &url, args).await,
+        Some(("projects", args)) => match args.subcommand() {
+            Some(("list", _)) => list_projects(&client, json).await,
+            _ => unreachable!("subcommand is required"),
+        },
+        Some(("env", args)) => environ(&client, args, json).await,
+        Some(("deploy", args)) => deploy(&client, args, json).await,
+        Some(("logs", args)) => logs(&client, args).await,
+        _ => unreachable!("server subcommands are handled by main"),
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("error: {err}");
+            1
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+enum CliError {
+    #[error(transparent)]
+    Api(#[from] ApiError),
+    #[error("{0}")]
+    Invalid(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+fn project(args: &ArgMatches) -> (&str, &str) {
+    (
+        args.get_one::<String>("owner").unwrap(),
+        args.get_one::<String>("project").unwrap(),
+    )
+}
+
+fn print_json(value: &impl Serialize) {
+    println!("{}", serde_json::to_string_pretty(value).unwrap());
+}
+
+/// Print rows as columns padded to the widest cell
+fn print_table(header: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths = header.iter().map(|cell| cell.len()).collect::<Vec<_>>();
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let header = header.iter().map(|cell| cell.to_string()).collect::<Vec<_>>();
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        let line = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+}
+
+async fn login(client: &ApiClient, url: &str, args: &ArgMatches) -> Result<(), CliError> {
+    let username = args.get_one::<String>("username").unwrap();
+    let password = match args.get_one::<String>("password") {
+        Some(password) => password.clone(),
+        None => {
+            eprint!("Password: ");
+            let mut password = String::new();
+            std::io::stdin().read_line(&mut password)?;
+            password.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+
+    let token = client.login(username, &password).await?;
+    if token.is_empty() {
+        return Err(CliError::Invalid("Server did not return a session".to_string()));
+    }
+
+    let path = write_settings(url, &token)?;
+    eprintln!("Logged in as {username}, session saved to {}", path.display());
+
+    Ok(())
+}
+
+async fn list_projects(client: &ApiClient, json: bool) -> Result<(), CliError> {
+    let projects = client.projects().await?;
+    if json {
+        print_json(&projects);
+        return Ok(());
+    }
+
+    let rows = projects
+        .into_iter()
+        .map(|project| vec![project.owner_name, project.name, project.id])
+        .collect();
+    print_table(&["OWNER", "PROJECT", "ID"], rows);
+
+    Ok(())
+}
+
+/// Parse a .env file, skipping comments, blank lines and an optional `export` prefix
+fn parse_dotenv(contents: &str) -> Result<BTreeMap<String, String>, CliError> {
+    let mut environs = BTreeMap::new();
+
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(CliError::Invalid(format!(
+                "Line {} is not a KEY=VALUE pair",
+                number + 1
+            )));
+        };
+
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .or_else(|| value.strip_prefix('\'').and_then(|value| value.strip_suffix('\'')))
+            .unwrap_or(value);
+        environs.insert(key.trim().to_string(), value.to_string());
+    }
+
+    Ok(environs)
+}
+
+async fn environ(client: &ApiClient, args: &ArgMatches, json: bool) -> Result<(), CliError> {
+    match args.subcommand() {
+        Some(("get", args)) => {
+            let (owner, project) = project(args);
+            let environs = client.environs(owner, project).await?;
+            if json {
+                print_json(&environs);
+            } else {
+                for (key, value) in environs.env {
+                    println!("{key}={value}");
+                }
+            }
+        }
+        Some(("set", args)) => {
+            let (owner, project) = project(args);
+            let pair = args.get_one::<String>("pair").unwrap();
+            let Some((key, value)) = pair.split_once('=') else {
+                return Err(CliError::Invalid(format!("{pair} is not a KEY=VALUE pair")));
+            };
+            client.set_environ(owner, project, key, value).await?;
+            eprintln!("Set {key}");
+        }
+        Some(("unset", args)) => {
+            let (owner, project) = project(args);
+            let key = args.get_one::<String>("key").unwrap();
+            client.unset_environ(owner, project, key).await?;
+            eprintln!("Unset {key}");
+        }
+        Some(("import", args)) => {
+            let (owner, project) = project(args);
+            let file = args.get_one::<String>("file").unwrap();
+            let environs = parse_dotenv(&std::fs::read_to_string(file)?)?;
+
+            // the bulk endpoint only accepts updates against the version we've seen
+            let current = client.environs(owner, project).await?;
+            let updated = client
+                .import_environs(owner, project, &environs, current.version)
+                .await?;
+            if json {
+                print_json(&updated);
+            } else {
+                eprintln!("Imported {} variables", updated.updated);
+            }
+        }
+        _ => unreachable!("subcommand is required"),
+    }
+
+    Ok(())
+}
+
+async fn deploy(client: &ApiClient, args: &ArgMatches, json: bool) -> Result<(), CliError> {
+    let (owner, project) = project(args);
+    let follow = args.get_flag("follow");
+
+    let Some(latest) = client.builds(owner, project).await?.into_iter().next() else {
+        return Err(CliError::Invalid(format!(
+            "{owner}/{project} has not been deployed yet, push to it first"
+        )));
+    };
+
+    let mut build = client.build(owner, project, &latest.id).await?;
+    while follow && UNFINISHED_STATES.contains(&build.status.as_str()) {
+        eprintln!("Deploy {} is {}", build.id, build.status.to_lowercase());
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        build = client.build(owner, project, &latest.id).await?;
+    }
+
+    if json {
+        print_json(&build);
+    } else {
+        if let Some(logs) = &build.logs {
+            println!("{logs}");
+        }
+        eprintln!("Deploy {} is {}", build.id, build.status.to_lowercase());
+    }
+
+    if follow && build.status == "FAILED" {
+        return Err(CliError::Invalid("Deploy failed".to_string()));
+    }
+
+    Ok(())
+}
+
+async fn logs(client: &ApiClient, args: &ArgMatches) -> Result<(), CliError> {
+    let (owner, project) = project(args);
+    let logs = client.container_logs(owner, project).await?;
+
+    let lines = logs.lines().collect::<Vec<_>>();
+    let skip = match args.get_one::<usize>("tail") {
+        Some(tail) => lines.len().saturating_sub(*tail),
+        None => 0,
+    };
+    for line in &lines[skip..] {
+        println!("{line}");
+    }
+
+    Ok(())
+}
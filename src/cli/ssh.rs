@@ -0,0 +1,233 @@
+use clap::{Arg, ArgMatches, Command};
+use sqlx::PgPool;
+
+use crate::{
+    configuration::{self, Settings},
+    git::{after_push, branch_heads, PUSH_CHANNEL},
+    push_checks::PushChecks,
+    push_message::PushMessage,
+};
+
+pub fn command() -> Command {
+    Command::new("ssh")
+        .about("Run by sshd to serve git over ssh, see the README for the setup")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("authorized-keys")
+                .about("Print the registered keys for sshd's AuthorizedKeysCommand")
+                .arg(Arg::new("key").help("Only print this base64 key, sshd's %k")),
+        )
+        .subcommand(
+            Command::new("serve")
+                .about("Run the git command in SSH_ORIGINAL_COMMAND if the key may access the project")
+                .arg(Arg::new("fingerprint").required(true)),
+        )
+}
+
+/// Run an ssh subcommand and return the process exit code. Errors go to stderr, which the git
+/// client shows to the user.
+pub async fn run(args: &ArgMatches) -> i32 {
+    match dispatch(args).await {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("error: {err}");
+            1
+        }
+    }
+}
+
+async fn dispatch(args: &ArgMatches) -> Result<i32, String> {
+    let config = configuration::get_configuration().map_err(|err| err.to_string())?;
+    let pool = config
+        .pool_options()
+        .connect_with(config.connection_options())
+        .await
+        .map_err(|err| format!("Failed to connect to database: {err}"))?;
+
+    match args.subcommand() {
+        Some(("authorized-keys", args)) => {
+            authorized_keys(&pool, args.get_one::<String>("key").map(String::as_str)).await
+        }
+        Some(("serve", args)) => {
+            let fingerprint = args.get_one::<String>("fingerprint").unwrap();
+            let push_checks = PushChecks::new(&config).map_err(|err| err.to_string())?;
+            let push_message = PushMessage::new(&config).map_err(|err| err.to_string())?;
+            serve(&pool, &config, &push_checks, &push_message, fingerprint).await
+        }
+        _ => unreachable!("subcommand is required"),
+    }
+}
+
+async fn authorized_keys(pool: &PgPool, key: Option<&str>) -> Result<i32, String> {
+    let keys = sqlx::query!(
+        r#"SELECT DISTINCT key_type, public_key, fingerprint
+           FROM ssh_keys
+           WHERE $1::TEXT IS NULL OR public_key = $1
+        "#,
+        key
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|err| format!("Failed to query database: {err}"))?;
+
+    // sshd runs the forced command from the user's home, go back to where the configuration is
+    let exe = std::env::current_exe().map_err(|err| err.to_string())?;
+    let dir = std::env::current_dir().map_err(|err| err.to_string())?;
+
+    for key in keys {
+        println!(
+            "command=\"cd '{}' && exec '{}' ssh serve '{}'\",restrict {} {}",
+            dir.display(),
+            exe.display(),
+            key.fingerprint,
+            key.key_type,
+            key.public_key
+        );
+    }
+
+    Ok(0)
+}
+
+/// Git service and repository requested by the ssh client, e.g. `git-receive-pack '/owner/app.git'`
+fn parse_command(command: &str) -> Option<(&'static str, String, String)> {
+    let (service, path) = command.split_once(' ')?;
+    let service = match service {
+        "git-upload-pack" => "upload-pack",
+        "git-receive-pack" => "receive-pack",
+        _ => return None,
+    };
+
+    let path = path.trim().trim_matches('\'').trim_start_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    let (owner, project) = path.split_once('/')?;
+
+    // no dots, so the path can't leave the git folder
+    let valid = |name: &str| {
+        !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    };
+    (valid(owner) && valid(project)).then(|| (service, owner.to_string(), project.to_string()))
+}
+
+async fn serve(
+    pool: &PgPool,
+    config: &Settings,
+    push_checks: &PushChecks,
+    push_message: &PushMessage,
+    fingerprint: &str,
+) -> Result<i32, String> {
+    let command = std::env::var("SSH_ORIGINAL_COMMAND")
+        .map_err(|_| "Interactive shells are not supported, use git to push and fetch".to_string())?;
+    let (service, owner, project) =
+        parse_command(&command).ok_or_else(|| format!("Unsupported command: {command}"))?;
+
+    let allowed = sqlx::query!(
+        r#"SELECT ssh_keys.id, projects.skip_push_checks, projects.container_name, project_owners.push_message
+           FROM ssh_keys
+           JOIN projects ON ssh_keys.project_id = projects.id
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE ssh_keys.fingerprint = $1
+           AND project_owners.name = $2
+           AND projects.name = $3
+           AND projects.deleted_at IS NULL
+        "#,
+        fingerprint,
+        owner,
+        project
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|err| format!("Failed to query database: {err}"))?;
+
+    // the same answer for a missing project, so keys can't probe for project names
+    let Some(allowed) = allowed else {
+        return Err(format!("This key has no access to {owner}/{project}"));
+    };
+
+    let path = format!("{}/{owner}/{project}.git", config.git.base);
+    let heads = branch_heads(&path);
+    let mut command = std::process::Command::new("git");
+    if service == "receive-pack" {
+        command
+            .args(push_checks.args())
+            .envs(push_checks.env(allowed.skip_push_checks));
+    }
+    let status = command
+        .args([service, &path])
+        .status()
+        .map_err(|err| format!("Failed to run git: {err}"))?;
+
+    if !status.success() || service != "receive-pack" {
+        return Ok(status.code().unwrap_or(1));
+    }
+
+    // a push the pre-receive checks rejected has nothing to deploy
+    if branch_heads(&path) != heads {
+        // told the same as over http, the server decides again before it queues the build
+        let outcome = after_push(pool, config.build.pausemode, config.build.max).await;
+        if !outcome.message.is_empty() {
+            eprintln!("{}", outcome.message);
+        }
+
+        // the server checks the push out and queues the build
+        if outcome.build {
+            if let Err(err) = sqlx::query!(
+                "SELECT pg_notify($1, $2)",
+                PUSH_CHANNEL,
+                format!("{owner}/{project}")
+            )
+            .execute(pool)
+            .await
+            {
+                eprintln!("error: Pushed, but failed to start the deploy: {err}");
+                return Ok(1);
+            }
+        }
+    }
+
+    // the build id isn't known here, the server queues the build after this process exits
+    let footer = push_message.render(
+        allowed.push_message.as_deref(),
+        &owner,
+        &project,
+        &allowed.container_name,
+        None,
+    );
+    if !footer.is_empty() {
+        eprintln!("{footer}");
+    }
+
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_git_commands() {
+        assert_eq!(
+            parse_command("git-receive-pack '/alice/blog.git'"),
+            Some(("receive-pack", "alice".to_string(), "blog".to_string()))
+        );
+        assert_eq!(
+            parse_command("git-upload-pack 'alice/my_blog'"),
+            Some(("upload-pack", "alice".to_string(), "my_blog".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_other_commands() {
+        assert_eq!(parse_command("sh -c id"), None);
+        assert_eq!(parse_command("git-upload-archive 'alice/blog.git'"), None);
+        assert_eq!(parse_command("git-receive-pack"), None);
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_git_folder() {
+        assert_eq!(parse_command("git-receive-pack '../alice/blog.git'"), None);
+        assert_eq!(parse_command("git-receive-pack 'alice/../../etc'"), None);
+        assert_eq!(parse_command("git-receive-pack 'alice/.blog'"), None);
+        assert_eq!(parse_command("git-receive-pack 'alice/blog/extra'"), None);
+        assert_eq!(parse_command("git-receive-pack 'alice/'"), None);
+    }
+}
@@ -2,15 +2,17 @@ use std::{
     io,
     net::{SocketAddr, ToSocketAddrs},
     num::NonZeroUsize,
+    path::PathBuf,
     thread::available_parallelism,
 };
 
 use axum_session::SessionConfig;
 use byte_unit::Byte;
 use chrono::Duration;
-use config::{Config, ConfigError};
+use config::{builder::DefaultState, Config, ConfigBuilder, ConfigError};
 use serde::Deserialize;
-use sqlx::postgres::PgConnectOptions;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use url::Url;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Settings {
@@ -20,12 +22,58 @@ pub struct Settings {
     pub auth: AuthSettings,
     pub build: BuilderSettings,
     pub container: ContainerSettings,
+    pub docker: DockerSettings,
+    pub quota: QuotaSettings,
+    pub scan: ScanSettings,
+    pub dockerfile: DockerfileSettings,
+    pub network: NetworkSettings,
+    pub cache: CacheSettings,
+    pub traffic: TrafficSettings,
+    pub rebuild: RebuildSettings,
+    pub deletion: DeletionSettings,
+    pub notifications: NotificationSettings,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct BuilderSettings {
+    /// builds running at once
     pub max: usize,
+    /// deploys creating networks and containers at once, the rest wait after their build
+    pub maxdeploys: usize,
     pub timeout: usize,
+    /// in seconds, how long a build waits for another deploy of the same project to finish
+    /// before it fails
+    pub locktimeout: u64,
+    /// default pip index, can be overridden per project with PIP_INDEX_URL
+    pub pipindex: Option<String>,
+    /// default extra pip index, can be overridden per project with PIP_EXTRA_INDEX_URL
+    pub pipextraindex: Option<String>,
+    /// fail builds when the preflight check finds errors instead of only logging them
+    pub preflight: bool,
+    /// serve the build queue capacity to visitors that aren't logged in
+    pub publiccapacity: bool,
+    /// images over this size, e.g. 2G, are rejected. no limit when unset
+    pub maximagesize: Option<String>,
+    /// what happens to pushes while an announcement pauses deploys
+    pub pausemode: PauseMode,
+    /// where builds write their generated Dockerfiles, see `build_scratch_dir`
+    pub scratchdir: Option<String>,
+    /// in hours, build workspaces older than this were left by a crash and are removed
+    pub scratchttl: u64,
+    /// fail builds before they start when the project's containers wouldn't fit in the host's
+    /// free memory or cpus
+    pub admission: bool,
+    /// percent of the host's memory and cpus admission keeps free
+    pub headroom: u8,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PauseMode {
+    /// queue the build, it starts once no announcement pauses deploys
+    Hold,
+    /// don't queue a build, the push is kept and deploys with the next one
+    Reject,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -33,9 +81,27 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub domain: String,
+    /// request body limit of the git smart-HTTP routes
     pub bodylimit: String,
+    /// request body limit of the JSON api routes
+    pub apibodylimit: String,
     pub ipv6: bool,
     pub secure: bool,
+    /// how project hosts are built from the owner and project name
+    pub subdomain: SubdomainScheme,
+    /// subdomains routed to platform services, projects can't be served on them
+    pub reservedsubdomains: Vec<String>,
+    /// in hours, how long the response to a request with an Idempotency-Key is replayed
+    pub idempotencyttl: u64,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SubdomainScheme {
+    /// `{owner}-{project}.{domain}`, the container name
+    Flat,
+    /// `{project}.{owner}.{domain}`, DNS has to resolve two levels below the domain
+    Owner,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -45,13 +111,61 @@ pub struct DatabaseSettings {
     pub host: String,
     pub port: u16,
     pub name: String,
+    /// acquire timeout in seconds
     pub timeout: u64,
+    pub maxconnections: u32,
+    /// in seconds
+    pub idletimeout: u64,
+    /// in milliseconds, how long a project lookup may run before it is cancelled, 0 disables it
+    pub statementtimeout: u64,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct GitSettings {
     pub base: String,
     pub auth: bool,
+    /// accept pushes and fetches over ssh with the keys registered on each project
+    pub ssh: bool,
+    /// user sshd runs `pws ssh serve` as, the user part of the clone urls
+    pub sshuser: String,
+    pub sshport: u16,
+    /// what happens to pushes holding secrets or files over `maxfilesize`
+    pub pushchecks: PushCheckPolicy,
+    /// pushed files over this size, e.g. 50M, are reported. no limit when unset
+    pub maxfilesize: Option<String>,
+    /// footer of the push output, see `push_message::VARIABLES` for what it can use
+    pub pushmessage: String,
+    /// shown as `{support_contact}` in push messages, lines using it are left out when empty
+    pub supportcontact: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PushCheckPolicy {
+    Off,
+    /// accept the push and show the findings in the push output
+    Warn,
+    /// reject the push, listing every file that has to go
+    Reject,
+}
+
+impl PushCheckPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PushCheckPolicy::Off => "off",
+            PushCheckPolicy::Warn => "warn",
+            PushCheckPolicy::Reject => "reject",
+        }
+    }
+
+    pub fn parse(policy: &str) -> Option<Self> {
+        match policy {
+            "off" => Some(PushCheckPolicy::Off),
+            "warn" => Some(PushCheckPolicy::Warn),
+            "reject" => Some(PushCheckPolicy::Reject),
+            _ => None,
+        }
+    }
 }
 
 // TODO: _ doesn't work for env vars
@@ -67,6 +181,58 @@ pub struct AuthSettings {
     pub secure: bool,
     /// in days
     pub maxlifespan: i64,
+    /// proxy that logs in to the CAS server on our behalf
+    pub ssoproxy: String,
+    pub casurl: String,
+    /// CAS service the proxy requests the ticket for. The redirect flow of `/auth/sso/login`
+    /// uses its own callback on `application.domain` instead
+    pub serviceurl: String,
+    pub casattributes: CasAttributeNames,
+    /// faculty users have to be from to sign in through SSO, anyone when empty
+    pub casfaculty: String,
+    /// sessions a user can have at once, 0 for no limit
+    pub maxsessions: usize,
+    /// what a login past `maxsessions` does
+    pub sessionpolicy: SessionPolicy,
+    /// parameters passwords and git tokens are hashed with
+    pub argon2: Argon2Settings,
+}
+
+/// Argon2id parameters. Hashes made with weaker ones are replaced on their next successful
+/// verification
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct Argon2Settings {
+    /// in KiB
+    pub memory: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionPolicy {
+    /// sign out the user's oldest sessions
+    Evict,
+    /// turn the login away
+    Reject,
+}
+
+/// Names the CAS server gives the attributes that are read, a dotted path for nested ones
+#[derive(Deserialize, Debug, Clone)]
+pub struct CasAttributeNames {
+    /// full name of the user
+    pub name: String,
+    pub faculty: String,
+}
+
+/// SSO endpoints, validated once on startup
+#[derive(Debug, Clone)]
+pub struct SsoConfig {
+    pub proxy: Url,
+    pub cas: Url,
+    pub service: Url,
+    pub attributes: CasAttributeNames,
+    pub faculty: String,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -74,24 +240,207 @@ pub struct ContainerSettings {
     pub cpu: f64,
     pub memory: String,
     pub swap: String,
+    /// seconds a new container gets to answer before the deploy fails, 0 disables the check
+    pub readytimeout: u64,
+    /// lines of the container log shown when a new container doesn't come up
+    pub loglines: usize,
+    /// start the new container next to the running one and only stop the old one once the new
+    /// one is ready, instead of stopping it first
+    pub zerodowntime: bool,
+    /// worker containers a project may run next to its web container, 0 disables workers
+    pub maxworkers: usize,
+    /// limits of each worker container
+    pub workercpu: f64,
+    pub workermemory: String,
+    /// prepended to the docker names of every project, so instances sharing a docker host
+    /// don't touch each other's containers. public hosts don't change
+    pub prefix: String,
+    /// DNS servers of every project container, docker's when empty. a project can set its own
+    pub dns: Vec<String>,
+    /// `host:ip` entries added to `/etc/hosts` of every project container, next to the ones of
+    /// the project
+    pub extrahosts: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct DockerSettings {
+    /// don't check that the daemon is reachable on startup
+    pub lazy: bool,
+    /// timeout of a single daemon call in seconds, doesn't apply to `docker build`
+    pub timeout: u64,
+    /// daemon calls at once for pages and the proxy, e.g. container logs and inspects
+    pub maxinteractive: usize,
+    /// daemon calls at once for deploys
+    pub maxlifecycle: usize,
+    /// in milliseconds, how long an interactive call waits for a slot before the request gets
+    /// a 503
+    pub maxwait: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct QuotaSettings {
+    /// size of a project's git repository, e.g. 500M. no limit when unset
+    pub reposize: Option<String>,
+    /// size of a project's image, e.g. 1G. no limit when unset
+    pub imagesize: Option<String>,
+    /// projects per owner, 0 means no limit
+    pub projects: u64,
+    /// percentage of a limit at which the owner gets warned
+    pub warnat: u8,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct NetworkSettings {
+    /// internal docker network of the `internal-only` egress policy, shared with Traefik and
+    /// the services those projects may reach
+    pub internal: String,
+    /// container name of Traefik, attached to the networks of projects denied any egress
+    pub traefik: String,
+    /// subnet of the shared network when it is created, e.g. 172.30.0.0/16, so it doesn't
+    /// overlap networks that already exist on the host. picked by the daemon when unset
+    pub subnet: Option<String>,
+    /// create the shared network with ipv6, containers on it are then reached over ipv6
+    pub ipv6: bool,
+    /// ipv6 subnet of the shared network, e.g. fd00:7773::/64. picked by the daemon when unset,
+    /// which needs ipv6 address pools in its configuration
+    pub ipv6subnet: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CacheSettings {
+    /// cache which projects a user may access instead of querying it on every request
+    pub ownership: bool,
+    /// in seconds, how long a cached lookup is used when nothing invalidates it
+    pub ownershipttl: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TrafficSettings {
+    /// Traefik access log in json format, traffic isn't counted when unset
+    pub accesslog: Option<String>,
+    /// in seconds, how often new lines of the access log are counted
+    pub interval: u64,
+}
+
+/// Defaults of the rebuild batches staff start, a batch can override them
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct RebuildSettings {
+    /// projects of a batch rebuilding at once
+    pub concurrency: usize,
+    /// in seconds, wait between rounds of a batch
+    pub pause: u64,
+}
+
+/// What happens to deleted projects
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct DeletionSettings {
+    /// in hours, how long a deleted project can be restored before it is removed for good
+    pub retention: u64,
+}
+
+/// How long notifications are kept
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct NotificationSettings {
+    /// in days, how long read notifications are kept. unread ones are kept until read
+    pub retention: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ScanSettings {
+    /// scanner run against every new image, `{image}` is replaced with the image name. it has
+    /// to print a trivy JSON report. no scan when unset
+    pub command: Option<String>,
+    pub policy: ScanPolicy,
+    /// least severe vulnerabilities that count as findings of the policy, leaked secrets always
+    /// count
+    pub threshold: ScanSeverity,
+    /// in seconds, a scan that takes longer is skipped
+    pub timeout: u64,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanPolicy {
+    Off,
+    /// add the findings to the build log
+    Warn,
+    /// fail the deploy when vulnerabilities at the threshold or secrets are found
+    Fail,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanSeverity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct DockerfileSettings {
+    /// what breaking the rules below does to a build of a project with its own Dockerfile
+    pub policy: DockerfilePolicy,
+    /// base images projects can build from, e.g. `python:3.*`. any image when empty
+    pub allowedimages: Vec<String>,
+    /// base images projects can't build from, checked before the allowed images
+    pub deniedimages: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DockerfilePolicy {
+    Off,
+    /// add the violations to the build log
+    Warn,
+    /// fail the build before it starts
+    Enforce,
 }
 
 pub fn get_configuration() -> Result<Settings, ConfigError> {
+    defaults()?
+        .add_source(config::File::with_name("configuration"))
+        .add_source(config::Environment::default().separator("_"))
+        .build()?
+        .try_deserialize::<Settings>()
+}
+
+/// Every setting that has a default, the configuration file and the environment go on top
+pub fn defaults() -> Result<ConfigBuilder<DefaultState>, ConfigError> {
     Config::builder()
         .set_default("application.port", 8080)?
         .set_default("application.host", "0.0.0.0")?
         .set_default("application.domain", "localhost:8080")?
         .set_default("application.bodylimit", "25mib")?
+        .set_default("application.apibodylimit", "256kib")?
         .set_default("application.ipv6", false)?
         .set_default("application.secure", false)?
+        .set_default("application.idempotencyttl", 24)?
+        .set_default("application.subdomain", "flat")?
+        .set_default(
+            "application.reservedsubdomains",
+            vec!["www", "api", "docs", "grafana", "portainer", "traefik"],
+        )?
         .set_default("database.user", "postgres")?
         .set_default("database.password", "postgres")?
         .set_default("database.host", "localhost")?
         .set_default("database.port", 5432)?
         .set_default("database.name", "postgres")?
         .set_default("database.timeout", 20)?
+        .set_default("database.maxconnections", 10)?
+        .set_default("database.idletimeout", 600)?
+        .set_default("database.statementtimeout", 5000)?
         .set_default("git.base", "./git-repo")?
         .set_default("git.auth", true)?
+        .set_default("git.ssh", false)?
+        .set_default("git.sshuser", "git")?
+        .set_default("git.sshport", 22)?
+        .set_default("git.pushchecks", "warn")?
+        .set_default(
+            "git.pushmessage",
+            "Project: {project_url}\nDashboard: {dashboard_url}\nDeployment: {deployment_id}\nHelp: {support_contact}",
+        )?
+        .set_default("git.supportcontact", "")?
         .set_default("auth.sso", true)?
         .set_default("auth.lifespan", 24 * 7)?
         .set_default("auth.cookiename", "session")?
@@ -99,10 +448,61 @@ pub fn get_configuration() -> Result<Settings, ConfigError> {
         .set_default("auth.httponly", true)?
         .set_default("auth.secure", false)?
         .set_default("auth.maxlifespan", 365)?
+        .set_default("auth.ssoproxy", "https://sso.mus.sh")?
+        .set_default("auth.casurl", "https://sso.ui.ac.id/cas/")?
+        .set_default("auth.serviceurl", "http://beranda.ui.ac.id/personal/")?
+        .set_default("auth.casattributes.name", "nama")?
+        .set_default("auth.casattributes.faculty", "jurusan.faculty")?
+        .set_default("auth.casfaculty", "Ilmu Komputer")?
+        .set_default("auth.maxsessions", 0)?
+        .set_default("auth.sessionpolicy", "evict")?
+        .set_default("auth.argon2.memory", 19456)?
+        .set_default("auth.argon2.iterations", 2)?
+        .set_default("auth.argon2.parallelism", 1)?
         .set_default("build.timeout", 120000)?
+        .set_default("build.locktimeout", 300)?
+        .set_default("build.maxdeploys", 2)?
+        .set_default("build.preflight", false)?
+        .set_default("build.publiccapacity", false)?
+        .set_default("build.pausemode", "hold")?
+        .set_default("build.scratchttl", 24)?
+        .set_default("build.admission", false)?
+        .set_default("build.headroom", 10)?
         .set_default("container.cpu", 0.5)?
         .set_default("container.memory", "256M")?
         .set_default("container.swap", "320M")?
+        .set_default("container.readytimeout", 0)?
+        .set_default("container.loglines", 50)?
+        .set_default("container.zerodowntime", true)?
+        .set_default("container.maxworkers", 1)?
+        .set_default("container.workercpu", 0.5)?
+        .set_default("container.workermemory", "256M")?
+        .set_default("container.prefix", "")?
+        .set_default("container.dns", Vec::<String>::new())?
+        .set_default("container.extrahosts", Vec::<String>::new())?
+        .set_default("docker.lazy", false)?
+        .set_default("docker.timeout", 30)?
+        .set_default("docker.maxinteractive", 16)?
+        .set_default("docker.maxlifecycle", 8)?
+        .set_default("docker.maxwait", 2000)?
+        .set_default("quota.projects", 0)?
+        .set_default("quota.warnat", 80)?
+        .set_default("scan.policy", "warn")?
+        .set_default("scan.threshold", "critical")?
+        .set_default("scan.timeout", 300)?
+        .set_default("dockerfile.policy", "warn")?
+        .set_default("dockerfile.allowedimages", Vec::<String>::new())?
+        .set_default("dockerfile.deniedimages", Vec::<String>::new())?
+        .set_default("network.internal", "pemasak-internal")?
+        .set_default("network.traefik", "traefik-pemasak")?
+        .set_default("network.ipv6", false)?
+        .set_default("cache.ownership", true)?
+        .set_default("cache.ownershipttl", 30)?
+        .set_default("traffic.interval", 15)?
+        .set_default("rebuild.concurrency", 2)?
+        .set_default("rebuild.pause", 30)?
+        .set_default("deletion.retention", 72)?
+        .set_default("notifications.retention", 30)?
         .set_default(
             "builder.max",
             available_parallelism()
@@ -110,11 +510,7 @@ pub fn get_configuration() -> Result<Settings, ConfigError> {
                 .get() as i32
                 - 1,
         )?
-        .set_default("builder.cpums", 100000)?
-        .add_source(config::File::with_name("configuration"))
-        .add_source(config::Environment::default().separator("_"))
-        .build()?
-        .try_deserialize::<Settings>()
+        .set_default("builder.cpums", 100000)
 }
 
 impl Settings {
@@ -127,6 +523,13 @@ impl Settings {
             .database(&self.database.name)
     }
 
+    pub fn pool_options(&self) -> PgPoolOptions {
+        PgPoolOptions::new()
+            .max_connections(self.database.maxconnections)
+            .acquire_timeout(std::time::Duration::from_secs(self.database.timeout))
+            .idle_timeout(std::time::Duration::from_secs(self.database.idletimeout))
+    }
+
     pub fn address_string(&self) -> String {
         format!("{}:{}", self.application.host, self.application.port)
     }
@@ -141,6 +544,17 @@ impl Settings {
             .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "invalid address"))
     }
 
+    /// Base of the ssh clone urls, none when ssh is disabled
+    pub fn ssh_url(&self) -> Option<String> {
+        if !self.git.ssh {
+            return None;
+        }
+
+        let domain = self.domain();
+        let host = domain.split(':').next().unwrap_or(&domain);
+        Some(format!("ssh://{}@{host}:{}", self.git.sshuser, self.git.sshport))
+    }
+
     pub fn domain(&self) -> String {
         self.application.domain.clone()
 
@@ -150,12 +564,27 @@ impl Settings {
         // }
     }
 
+    /// Directory holding the workspaces of running builds, `pws-build` in the system temp
+    /// directory when `build.scratchdir` is unset
+    pub fn build_scratch_dir(&self) -> PathBuf {
+        match &self.build.scratchdir {
+            Some(dir) => PathBuf::from(dir),
+            None => std::env::temp_dir().join("pws-build"),
+        }
+    }
+
     pub fn body_limit(&self) -> usize {
         Byte::from_str(&self.application.bodylimit)
             .unwrap_or(Byte::from_bytes(25 * 1024 * 1024))
             .get_bytes() as usize
     }
 
+    pub fn api_body_limit(&self) -> usize {
+        Byte::from_str(&self.application.apibodylimit)
+            .unwrap_or(Byte::from_bytes(256 * 1024))
+            .get_bytes() as usize
+    }
+
     pub fn session_config(&self) -> SessionConfig {
         SessionConfig::default()
             .with_lifetime(Duration::hours(self.auth.lifespan))
@@ -166,6 +595,27 @@ impl Settings {
             .with_max_lifetime(Duration::days(self.auth.maxlifespan))
     }
 
+    /// Sessions a user can have at once, 0 for no limit
+    pub fn max_sessions_per_user(&self) -> usize {
+        self.auth.maxsessions
+    }
+
+    pub fn sso_config(&self) -> Result<SsoConfig, ConfigError> {
+        let parse = |key: &str, value: &str| {
+            Url::parse(value).map_err(|err| {
+                ConfigError::Message(format!("auth.{key} is not a valid url ({value}): {err}"))
+            })
+        };
+
+        Ok(SsoConfig {
+            proxy: parse("ssoproxy", &self.auth.ssoproxy)?,
+            cas: parse("casurl", &self.auth.casurl)?,
+            service: parse("serviceurl", &self.auth.serviceurl)?,
+            attributes: self.auth.casattributes.clone(),
+            faculty: self.auth.casfaculty.clone(),
+        })
+    }
+
     pub fn container_memory_bytes(&self) -> Result<i64, ConfigError> {
         Byte::from_str(&self.container.memory)
             .map_err(|e| ConfigError::Message(format!("Invalid memory format: {}", e)))
@@ -178,12 +628,78 @@ impl Settings {
             .map(|b| b.get_bytes() as i64)
     }
 
+    pub fn quota_repo_size_bytes(&self) -> Result<Option<u64>, ConfigError> {
+        self.quota
+            .reposize
+            .as_ref()
+            .map(|size| {
+                Byte::from_str(size)
+                    .map_err(|e| ConfigError::Message(format!("Invalid repo size quota: {}", e)))
+                    .map(|b| b.get_bytes() as u64)
+            })
+            .transpose()
+    }
+
+    pub fn max_file_size_bytes(&self) -> Result<Option<u64>, ConfigError> {
+        self.git
+            .maxfilesize
+            .as_ref()
+            .map(|size| {
+                Byte::from_str(size)
+                    .map_err(|e| ConfigError::Message(format!("Invalid maximum file size: {}", e)))
+                    .map(|b| b.get_bytes() as u64)
+            })
+            .transpose()
+    }
+
+    pub fn max_image_bytes(&self) -> Result<Option<u64>, ConfigError> {
+        self.build
+            .maximagesize
+            .as_ref()
+            .map(|size| {
+                Byte::from_str(size)
+                    .map_err(|e| ConfigError::Message(format!("Invalid maximum image size: {}", e)))
+                    .map(|b| b.get_bytes() as u64)
+            })
+            .transpose()
+    }
+
+    pub fn quota_image_size_bytes(&self) -> Result<Option<u64>, ConfigError> {
+        self.quota
+            .imagesize
+            .as_ref()
+            .map(|size| {
+                Byte::from_str(size)
+                    .map_err(|e| ConfigError::Message(format!("Invalid image size quota: {}", e)))
+                    .map(|b| b.get_bytes() as u64)
+            })
+            .transpose()
+    }
+
+    /// What image scans do to a deploy, off when no scanner is configured
+    pub fn scan_policy(&self) -> ScanPolicy {
+        match &self.scan.command {
+            Some(command) if !command.trim().is_empty() => self.scan.policy,
+            _ => ScanPolicy::Off,
+        }
+    }
+
     pub fn container_cpu_quota(&self) -> i64 {
         // Convert CPU float (0.5 = 50% of one core) to quota
         // Standard period is 100000 microseconds (100ms)
         (self.container.cpu * 100000.0) as i64
     }
 
+    pub fn worker_memory_bytes(&self) -> Result<i64, ConfigError> {
+        Byte::from_str(&self.container.workermemory)
+            .map_err(|e| ConfigError::Message(format!("Invalid worker memory format: {}", e)))
+            .map(|b| b.get_bytes() as i64)
+    }
+
+    pub fn worker_cpu_quota(&self) -> i64 {
+        (self.container.workercpu * 100000.0) as i64
+    }
+
     pub fn container_cpu_period(&self) -> i64 {
         // Standard 100ms period
         100000
@@ -0,0 +1,248 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use hyper::{header, Body, Response, StatusCode};
+use serde::Serialize;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::configuration::DockerSettings;
+
+/// Used until `configure` runs, e.g. by the cli
+const DEFAULT_INTERACTIVE: usize = 16;
+const DEFAULT_LIFECYCLE: usize = 8;
+const DEFAULT_MAX_WAIT: Duration = Duration::from_secs(2);
+
+static LIMITS: OnceLock<Limits> = OnceLock::new();
+
+/// Docker calls made for someone waiting on a page, e.g. container logs or the ip lookup of
+/// the proxy, and the calls of deploys are limited apart, so neither can starve the other
+struct Limits {
+    interactive: ClassLimit,
+    lifecycle: ClassLimit,
+    /// longest an interactive call waits for a slot before it is turned away
+    max_wait: Duration,
+}
+
+struct ClassLimit {
+    semaphore: Semaphore,
+    size: usize,
+    /// calls that had to wait for a slot
+    waits: AtomicU64,
+    wait_ms_total: AtomicU64,
+    wait_ms_max: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl ClassLimit {
+    fn new(size: usize) -> Self {
+        let size = size.max(1);
+        Self {
+            semaphore: Semaphore::new(size),
+            size,
+            waits: AtomicU64::new(0),
+            wait_ms_total: AtomicU64::new(0),
+            wait_ms_max: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    fn record_wait(&self, waited: Duration) {
+        let ms = waited.as_millis() as u64;
+        self.waits.fetch_add(1, Ordering::Relaxed);
+        self.wait_ms_total.fetch_add(ms, Ordering::Relaxed);
+        self.wait_ms_max.fetch_max(ms, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> ClassStats {
+        let waits = self.waits.load(Ordering::Relaxed);
+        let wait_ms_total = self.wait_ms_total.load(Ordering::Relaxed);
+
+        ClassStats {
+            limit: self.size,
+            in_use: self.size - self.semaphore.available_permits(),
+            waits,
+            wait_ms_avg: wait_ms_total.checked_div(waits),
+            wait_ms_max: self.wait_ms_max.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn limits() -> &'static Limits {
+    LIMITS.get_or_init(|| Limits::new(DEFAULT_INTERACTIVE, DEFAULT_LIFECYCLE, DEFAULT_MAX_WAIT))
+}
+
+/// Set the limits from the configuration, only the first call counts
+pub fn configure(settings: &DockerSettings) {
+    let _ = LIMITS.set(Limits::new(
+        settings.maxinteractive,
+        settings.maxlifecycle,
+        Duration::from_millis(settings.maxwait),
+    ));
+}
+
+/// Every interactive slot stayed taken for longer than `docker.maxwait`
+#[derive(Debug)]
+pub struct Busy {
+    pub retry_after: Duration,
+}
+
+impl Busy {
+    /// 503 with a Retry-After header
+    pub fn response(&self) -> Response<Body> {
+        let json = serde_json::json!({ "message": "The docker daemon is busy, try again shortly" });
+
+        Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header(header::RETRY_AFTER, self.retry_after.as_secs().max(1).to_string())
+            .body(Body::from(json.to_string()))
+            .unwrap()
+    }
+}
+
+impl Limits {
+    fn new(interactive: usize, lifecycle: usize, max_wait: Duration) -> Self {
+        Self {
+            interactive: ClassLimit::new(interactive),
+            lifecycle: ClassLimit::new(lifecycle),
+            max_wait,
+        }
+    }
+
+    async fn interactive(&self) -> Result<SemaphorePermit<'_>, Busy> {
+        let limit = &self.interactive;
+        if let Ok(permit) = limit.semaphore.try_acquire() {
+            return Ok(permit);
+        }
+
+        let started = Instant::now();
+        match tokio::time::timeout(self.max_wait, limit.semaphore.acquire()).await {
+            Ok(permit) => {
+                limit.record_wait(started.elapsed());
+                Ok(permit.expect("daemon semaphores are never closed"))
+            }
+            Err(_) => {
+                limit.rejected.fetch_add(1, Ordering::Relaxed);
+                Err(Busy {
+                    retry_after: self.max_wait,
+                })
+            }
+        }
+    }
+
+    async fn lifecycle(&self) -> SemaphorePermit<'_> {
+        let limit = &self.lifecycle;
+        if let Ok(permit) = limit.semaphore.try_acquire() {
+            return permit;
+        }
+
+        let started = Instant::now();
+        let permit = limit.semaphore.acquire().await.expect("daemon semaphores are never closed");
+        limit.record_wait(started.elapsed());
+        permit
+    }
+
+    fn stats(&self) -> DaemonStats {
+        DaemonStats {
+            interactive: self.interactive.stats(),
+            lifecycle: self.lifecycle.stats(),
+        }
+    }
+}
+
+/// Slot for a docker call someone is waiting on. Gives up after `docker.maxwait` rather than
+/// letting requests pile up behind a busy daemon.
+pub async fn interactive() -> Result<SemaphorePermit<'static>, Busy> {
+    limits().interactive().await
+}
+
+/// Slot for a docker call of a deploy. Waits as long as it takes, every call already has its
+/// own timeout once it runs.
+pub async fn lifecycle() -> SemaphorePermit<'static> {
+    limits().lifecycle().await
+}
+
+#[derive(Serialize, Debug)]
+pub struct ClassStats {
+    pub limit: usize,
+    pub in_use: usize,
+    pub waits: u64,
+    /// none until a call had to wait
+    pub wait_ms_avg: Option<u64>,
+    pub wait_ms_max: u64,
+    /// interactive calls turned away with 503
+    pub rejected: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DaemonStats {
+    pub interactive: ClassStats,
+    pub lifecycle: ClassStats,
+}
+
+/// Counted since this server started
+pub fn stats() -> DaemonStats {
+    limits().stats()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn calls_queue_for_a_free_slot() {
+        let limits = Limits::new(1, 1, Duration::from_secs(5));
+
+        let held = limits.lifecycle().await;
+        let (waiter, ()) = tokio::join!(limits.lifecycle(), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            drop(held);
+        });
+        assert_eq!(limits.lifecycle.semaphore.available_permits(), 0);
+        drop(waiter);
+
+        let stats = limits.stats().lifecycle;
+        assert_eq!(stats.in_use, 0);
+        assert_eq!(stats.waits, 1);
+        assert!(stats.wait_ms_max >= 40, "{stats:?}");
+        assert_eq!(stats.wait_ms_avg, Some(stats.wait_ms_max));
+    }
+
+    #[tokio::test]
+    async fn full_interactive_pool_is_busy() {
+        let limits = Limits::new(2, 1, Duration::from_millis(50));
+
+        let held = (limits.interactive().await.unwrap(), limits.interactive().await.unwrap());
+        let busy = limits.interactive().await.unwrap_err();
+
+        let response = busy.response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        // never 0, which would have clients retry right away
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+
+        let stats = limits.stats().interactive;
+        assert_eq!((stats.limit, stats.in_use), (2, 2));
+        assert_eq!(stats.rejected, 1);
+        assert_eq!(stats.waits, 0);
+
+        drop(held);
+        assert!(limits.interactive().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn deploys_dont_take_interactive_slots() {
+        let limits = Limits::new(1, 1, Duration::from_millis(50));
+
+        let _deploy = limits.lifecycle().await;
+        let stats = limits.stats();
+        assert_eq!(stats.lifecycle.in_use, 1);
+        assert_eq!(stats.interactive.in_use, 0);
+        assert!(limits.interactive().await.is_ok());
+        assert_eq!(stats.interactive.wait_ms_avg, None);
+    }
+}
@@ -12,6 +12,8 @@ struct Project {
     id: Uuid,
     name: String,
     owner_name: String,
+    description: Option<String>,
+    website_url: Option<String>,
 }
 
 #[derive(Serialize, Debug)]
@@ -22,12 +24,14 @@ pub async fn get(auth: Auth, State(AppState { pool, .. }): State<AppState>) -> R
     let user = auth.current_user.unwrap();
 
     let projects = match sqlx::query!(
-        r#"SELECT projects.id AS id, projects.name AS project, project_owners.name AS owner
+        r#"SELECT projects.id AS id, projects.name AS project, project_owners.name AS owner,
+                  projects.description, projects.website_url
            FROM projects
            JOIN project_owners ON projects.owner_id = project_owners.id
            JOIN users_owners ON project_owners.id = users_owners.owner_id
            JOIN users ON users_owners.user_id = users.id
            WHERE users.id = $1
+           AND projects.deleted_at IS NULL
         "#,
         user.id
     )
@@ -55,6 +59,8 @@ pub async fn get(auth: Auth, State(AppState { pool, .. }): State<AppState>) -> R
             id: record.id,
             name: record.project,
             owner_name: record.owner,
+            description: record.description,
+            website_url: record.website_url,
         }
     }).collect::<Vec<_>>();
 
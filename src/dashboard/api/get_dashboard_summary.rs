@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use axum::extract::State;
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+
+use crate::{
+    announcements,
+    auth::Auth,
+    dashboard::summary::{DashboardSummary, DeploymentSummary, OwnerSummary, ProjectQuota, ProjectSummary},
+    docker::{NAME_LABEL, OWNER_LABEL, PROCESS_LABEL},
+    projects::api::{BuildState, error_response},
+    runtime::ContainerRuntime,
+    startup::AppState,
+};
+
+/// The user's owners, their projects with the last deployment and container state, active
+/// announcements and quota usage. It takes four queries and one container listing however many
+/// projects the user has.
+#[tracing::instrument(skip(auth, pool, docker))]
+pub async fn get(
+    auth: Auth,
+    State(AppState {
+        pool,
+        docker,
+        domain,
+        secure,
+        project_quota,
+        ..
+    }): State<AppState>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let owners = sqlx::query!(
+        r#"SELECT project_owners.id, project_owners.name
+           FROM project_owners
+           JOIN users_owners ON project_owners.id = users_owners.owner_id
+           WHERE users_owners.user_id = $1
+           ORDER BY project_owners.name
+        "#,
+        user.id
+    )
+    .fetch_all(&pool);
+
+    let projects = sqlx::query!(
+        r#"SELECT projects.id, projects.name AS project, project_owners.name AS owner,
+                  projects.description, projects.website_url, subdomain_claims.subdomain AS "subdomain?",
+                  last_build.id AS "build_id?", last_build.status AS "status?: BuildState",
+                  last_build.created_at AS "created_at?", last_build.finished_at
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           JOIN users_owners ON project_owners.id = users_owners.owner_id
+           LEFT JOIN subdomain_claims ON subdomain_claims.project_id = projects.id
+           LEFT JOIN LATERAL (
+             SELECT builds.id, builds.status, builds.created_at, builds.finished_at
+             FROM builds
+             WHERE builds.project_id = projects.id
+             ORDER BY builds.created_at DESC
+             LIMIT 1
+           ) last_build ON true
+           WHERE users_owners.user_id = $1
+           AND projects.deleted_at IS NULL
+           ORDER BY project_owners.name, projects.name
+        "#,
+        user.id
+    )
+    .fetch_all(&pool);
+
+    let notifications = sqlx::query_scalar!(
+        r#"SELECT count(*) AS "count!" FROM notifications WHERE user_id = $1 AND read_at IS NULL"#,
+        user.id
+    )
+    .fetch_one(&pool);
+
+    // every project container on the host in one call, only the user's are kept below
+    let owner_label = [OWNER_LABEL.to_string()];
+    let containers = ContainerRuntime::list_labeled_containers(&docker, &owner_label);
+
+    let (owners, projects, notifications, announcements, containers) = tokio::join!(
+        owners,
+        projects,
+        notifications,
+        announcements::active(&pool),
+        containers
+    );
+
+    let (owners, projects, notifications, announcements) = match (owners, projects, notifications, announcements) {
+        (Ok(owners), Ok(projects), Ok(notifications), Ok(announcements)) => {
+            (owners, projects, notifications, announcements)
+        }
+        (Err(err), ..) | (_, Err(err), ..) | (_, _, Err(err), _) | (.., Err(err)) => {
+            tracing::error!(?err, "Can't get dashboard: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    // web containers by owner and project, workers carry a process label
+    let containers = match containers {
+        Ok(containers) => Some(
+            containers
+                .into_iter()
+                .filter_map(|container| {
+                    let labels = container.labels?;
+                    if labels.contains_key(PROCESS_LABEL) {
+                        return None;
+                    }
+                    let owner = labels.get(OWNER_LABEL)?.clone();
+                    let name = labels.get(NAME_LABEL)?.clone();
+                    Some(((owner, name), container.state.unwrap_or_default()))
+                })
+                .collect::<HashMap<_, _>>(),
+        ),
+        Err(err) => {
+            tracing::warn!(?err, "Can't get dashboard: Failed to list containers");
+            None
+        }
+    };
+
+    let scheme = match secure {
+        true => "https",
+        false => "http",
+    };
+
+    let mut used = HashMap::<String, u64>::new();
+    let projects = projects
+        .into_iter()
+        .map(|record| {
+            *used.entry(record.owner.clone()).or_default() += 1;
+            let container = containers
+                .as_ref()
+                .and_then(|containers| containers.get(&(record.owner.clone(), record.project.clone())).cloned());
+            let last_deployment = match (record.build_id, record.status, record.created_at) {
+                (Some(build_id), Some(status), Some(created_at)) => Some(DeploymentSummary {
+                    build_id,
+                    status,
+                    created_at,
+                    finished_at: record.finished_at,
+                }),
+                _ => None,
+            };
+
+            ProjectSummary {
+                id: record.id,
+                owner: record.owner,
+                name: record.project,
+                description: record.description,
+                website_url: record.website_url,
+                url: record.subdomain.map(|subdomain| format!("{scheme}://{subdomain}.{domain}")),
+                last_deployment,
+                container,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let owners = owners
+        .into_iter()
+        .map(|record| OwnerSummary {
+            id: record.id,
+            personal: record.name == user.username,
+            quota: ProjectQuota {
+                used: used.get(&record.name).copied().unwrap_or(0),
+                limit: (project_quota > 0).then_some(project_quota),
+            },
+            name: record.name,
+        })
+        .collect::<Vec<_>>();
+
+    let json = serde_json::to_string(&DashboardSummary {
+        owners,
+        projects,
+        announcements,
+        unread_notifications: notifications,
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
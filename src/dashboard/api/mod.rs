@@ -6,9 +6,11 @@ use axum_extra::routing::RouterExt;
 use hyper::Body;
 
 mod get_dashboard_projects;
+mod get_dashboard_summary;
 
 pub async fn router(_state: AppState, _config: &Settings) -> Router<AppState, Body> {
     Router::new()
+        .route_with_tsr("/api/dashboard", get(get_dashboard_summary::get))
         .route_with_tsr("/api/dashboard/project", get(get_dashboard_projects::get))
         .route_layer(middleware::from_fn(auth))
 }
\ No newline at end of file
@@ -0,0 +1,56 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{announcements::Announcement, projects::api::BuildState};
+
+/// Everything the landing page of the dashboard shows, in one response
+#[derive(Serialize, Debug)]
+pub struct DashboardSummary {
+    pub owners: Vec<OwnerSummary>,
+    pub projects: Vec<ProjectSummary>,
+    pub announcements: Vec<Announcement>,
+    /// see `GET /api/user/notifications`
+    pub unread_notifications: i64,
+}
+
+/// The user themselves or a group they are a member of
+#[derive(Serialize, Debug)]
+pub struct OwnerSummary {
+    pub id: Uuid,
+    pub name: String,
+    /// false for groups
+    pub personal: bool,
+    pub quota: ProjectQuota,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProjectQuota {
+    pub used: u64,
+    /// none when owners can have any number of projects
+    pub limit: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProjectSummary {
+    pub id: Uuid,
+    pub owner: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub website_url: Option<String>,
+    /// where the project is served, none until it was first deployed
+    pub url: Option<String>,
+    /// none when the project was never built
+    pub last_deployment: Option<DeploymentSummary>,
+    /// state of the web container, e.g. `running` or `exited`, none when it has none or the
+    /// container runtime didn't answer
+    pub container: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DeploymentSummary {
+    pub build_id: Uuid,
+    pub status: BuildState,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+
+use bollard::container::Config;
+use data_encoding::HEXLOWER;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Characters of the value hashes kept, enough to tell values apart
+const HASH_LENGTH: usize = 12;
+
+/// Parts of Traefik label keys that name credentials, e.g. of a basicauth middleware
+const SECRET_LABEL_PARTS: [&str; 3] = ["auth", "password", "token"];
+
+/// The configuration a deployment ran with, stored on its build. Environment values are only
+/// kept as hashes, which is enough to see that one changed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub image: String,
+    /// framework the Dockerfile template was picked by, none for unknown projects
+    pub template: Option<String>,
+    /// repository or generated
+    pub dockerfile: String,
+    pub port: u16,
+    /// in bytes
+    pub memory: Option<i64>,
+    pub memory_swap: Option<i64>,
+    pub cpu_quota: Option<i64>,
+    pub cpu_period: Option<i64>,
+    pub workers: usize,
+    /// name to `sha256:<hash>` of the value
+    pub env: BTreeMap<String, String>,
+    pub labels: BTreeMap<String, String>,
+}
+
+impl Snapshot {
+    /// Snapshot of the web container a deploy is about to start
+    pub fn new(
+        container: &Config<String>,
+        template: Option<&str>,
+        dockerfile: &str,
+        port: u16,
+        workers: usize,
+    ) -> Self {
+        let env = container
+            .env
+            .iter()
+            .flatten()
+            .filter_map(|entry| entry.split_once('='))
+            .map(|(name, value)| (name.to_string(), hash_value(value)))
+            .collect();
+        let labels = container
+            .labels
+            .iter()
+            .flatten()
+            .filter(|(key, _)| !is_secret_label(key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        let host_config = container.host_config.clone().unwrap_or_default();
+
+        Self {
+            image: container.image.clone().unwrap_or_default(),
+            template: template.map(str::to_string),
+            dockerfile: dockerfile.to_string(),
+            port,
+            memory: host_config.memory,
+            memory_swap: host_config.memory_swap,
+            cpu_quota: host_config.cpu_quota,
+            cpu_period: host_config.cpu_period,
+            workers,
+            env,
+            labels,
+        }
+    }
+}
+
+fn hash_value(value: &str) -> String {
+    let hash = HEXLOWER.encode(&Sha256::digest(value.as_bytes()));
+    format!("sha256:{}", &hash[..HASH_LENGTH])
+}
+
+fn is_secret_label(key: &str) -> bool {
+    let key = key.to_lowercase();
+    SECRET_LABEL_PARTS.iter().any(|part| key.contains(part))
+}
+
+/// A setting that differs between two snapshots, by its dotted path, e.g. `env.DEBUG`. A side
+/// is none when the setting only exists on the other one.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Change {
+    pub key: String,
+    pub previous: Option<serde_json::Value>,
+    pub current: Option<serde_json::Value>,
+}
+
+/// Settings that differ between two stored snapshots, sorted by key. Works on the stored json
+/// so snapshots written before a field was added still compare.
+pub fn diff(previous: &serde_json::Value, current: &serde_json::Value) -> Vec<Change> {
+    let mut previous_values = BTreeMap::new();
+    flatten("", previous, &mut previous_values);
+    let mut current_values = BTreeMap::new();
+    flatten("", current, &mut current_values);
+
+    let mut changes = Vec::new();
+    for (key, value) in &current_values {
+        match previous_values.get(key) {
+            Some(previous) if previous == value => {}
+            previous => changes.push(Change {
+                key: key.clone(),
+                previous: previous.cloned(),
+                current: Some(value.clone()),
+            }),
+        }
+    }
+    for (key, value) in previous_values {
+        if !current_values.contains_key(&key) {
+            changes.push(Change {
+                key,
+                previous: Some(value),
+                current: None,
+            });
+        }
+    }
+
+    changes.sort_by(|a, b| a.key.cmp(&b.key));
+    changes
+}
+
+/// Leaves of a json value by their dotted path, arrays are compared as a whole
+fn flatten(prefix: &str, value: &serde_json::Value, values: &mut BTreeMap<String, serde_json::Value>) {
+    match value {
+        serde_json::Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                let path = match prefix {
+                    "" => key.clone(),
+                    prefix => format!("{prefix}.{key}"),
+                };
+                flatten(&path, value, values);
+            }
+        }
+        value => {
+            values.insert(prefix.to_string(), value.clone());
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use config::ConfigError;
+use data_encoding::HEXLOWER;
+use git2::{Oid, Repository, Tree};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{dockerfile_templates::DjangoDockerfile, get_env, git, project_config::ProjectConfig};
+
+/// Bumped whenever detection or the Dockerfile templates change, so results cached before
+/// are detected again
+pub const TEMPLATE_VERSION: u32 = 2;
+
+/// Files in the root of a repository that tell its framework, the first one found wins
+pub const MARKERS: [(&str, &str); 6] = [
+    ("manage.py", "django"),
+    ("package.json", "node"),
+    ("requirements.txt", "python"),
+    ("pyproject.toml", "python"),
+    ("go.mod", "go"),
+    ("Cargo.toml", "rust"),
+];
+
+/// Files besides the markers that the result depends on
+const BUILD_FILES: [&str; 2] = ["Dockerfile", ProjectConfig::FILE_NAME];
+
+/// What a build of the repository would use, cached on the project
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Detection {
+    /// hash of the template version and the blobs of the key files it was detected from
+    pub key: String,
+    pub framework: Option<String>,
+    /// `repository` or `generated`, like the builds record it
+    pub dockerfile: String,
+    /// Dockerfile the server would generate, without the project's environment variables.
+    /// None when the repository has its own
+    pub generated: Option<String>,
+    /// why no Dockerfile can be generated, an invalid `.pws.toml`
+    pub error: Option<String>,
+}
+
+/// Detection of HEAD of the repository at `path`, reusing the result cached on the project
+/// while the key files are the same. None when nothing was pushed yet.
+pub async fn cached(pool: &PgPool, project_id: Uuid, path: &str) -> Result<Option<Detection>, sqlx::Error> {
+    let stored = sqlx::query_scalar!("SELECT detection FROM projects WHERE id = $1", project_id)
+        .fetch_one(pool)
+        .await?
+        .and_then(|detection| serde_json::from_value::<Detection>(detection).ok());
+    let stored_key = stored.as_ref().map(|detection| detection.key.clone());
+
+    let Some(detection) = detect_at(path, stored) else {
+        return Ok(None);
+    };
+
+    if stored_key.as_ref() != Some(&detection.key) {
+        sqlx::query!(
+            "UPDATE projects SET detection = $2 WHERE id = $1",
+            project_id,
+            serde_json::to_value(&detection).unwrap(),
+        )
+        .execute(pool)
+        .await?;
+    }
+
+    Ok(Some(detection))
+}
+
+/// Detect against HEAD of the bare repository at `path`. Only the key files' blob ids are
+/// read when `cached` is still good, their contents only when they changed.
+pub fn detect_at(path: &str, cached: Option<Detection>) -> Option<Detection> {
+    let commit = git::head_commit(path)?;
+    let repo = Repository::open(path).ok()?;
+    let tree = repo.find_commit(Oid::from_str(&commit).ok()?).ok()?.tree().ok()?;
+
+    let key = cache_key(&tree);
+    if let Some(cached) = cached.filter(|cached| cached.key == key) {
+        return Some(cached);
+    }
+
+    let framework = MARKERS
+        .iter()
+        .find(|(file, _)| tree.get_name(file).is_some())
+        .map(|(_, framework)| framework.to_string());
+
+    let (dockerfile, generated, error) = match tree.get_name("Dockerfile") {
+        Some(_) => ("repository", None, None),
+        None => match project_config(&repo, &tree) {
+            Ok(project_config) => {
+                let generated = DjangoDockerfile::new()
+                    .with_base(project_config.base)
+                    .with_collectstatic(project_config.collectstatic)
+                    .with_single_stage(project_config.single_stage)
+                    .with_build_packages(project_config.build_packages.clone())
+                    .with_gunicorn_timeout(get_env::gunicorn_timeout())
+                    .with_gunicorn_graceful_timeout(get_env::gunicorn_graceful_timeout())
+                    .generate();
+                ("generated", Some(generated), None)
+            }
+            Err(err) => ("generated", None, Some(format!("Invalid {}: {}", ProjectConfig::FILE_NAME, err))),
+        },
+    };
+
+    Some(Detection {
+        key,
+        framework,
+        dockerfile: dockerfile.to_string(),
+        generated,
+        error,
+    })
+}
+
+/// Hash of the template version, the server settings baked into generated Dockerfiles and
+/// the blob id of every key file, or its absence
+fn cache_key(tree: &Tree) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!(
+        "v{TEMPLATE_VERSION} {} {}\n",
+        get_env::gunicorn_timeout(),
+        get_env::gunicorn_graceful_timeout()
+    ));
+
+    for file in MARKERS.iter().map(|(file, _)| *file).chain(BUILD_FILES) {
+        let blob = tree
+            .get_name(file)
+            .map(|entry| entry.id().to_string())
+            .unwrap_or_else(|| "-".to_string());
+        hasher.update(format!("{file} {blob}\n"));
+    }
+
+    HEXLOWER.encode(&hasher.finalize())
+}
+
+/// `.pws.toml` of the tree, the defaults when there is none
+fn project_config(repo: &Repository, tree: &Tree) -> Result<ProjectConfig, ConfigError> {
+    let Some(entry) = tree.get_name(ProjectConfig::FILE_NAME) else {
+        return Ok(ProjectConfig::default());
+    };
+    let blob = entry
+        .to_object(repo)
+        .and_then(|object| object.peel_to_blob())
+        .map_err(|err| ConfigError::Foreign(Box::new(err)))?;
+
+    ProjectConfig::parse(&String::from_utf8_lossy(blob.content()))
+}
@@ -0,0 +1,420 @@
+use std::{collections::HashMap, fmt};
+
+use crate::configuration::DockerfileSettings;
+
+/// Registry prefixes docker adds to images from Docker Hub, stripped before images are compared
+const HUB_PREFIXES: [&str; 3] = ["index.docker.io/library/", "docker.io/library/", "docker.io/"];
+
+/// Something in a user Dockerfile the server's policy doesn't allow
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// An instruction with its continuation lines joined, `line` is where it starts
+#[derive(Debug, PartialEq)]
+struct Instruction {
+    line: usize,
+    keyword: String,
+    args: String,
+}
+
+fn instructions(dockerfile: &str) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+
+    let mut push = |line: usize, text: String| {
+        let (keyword, args) = text.split_once(char::is_whitespace).unwrap_or((&text, ""));
+        instructions.push(Instruction {
+            line,
+            keyword: keyword.to_uppercase(),
+            args: args.trim().to_string(),
+        });
+    };
+
+    for (index, line) in dockerfile.lines().enumerate() {
+        let trimmed = line.trim();
+        // comments and empty lines are skipped, also between continuation lines
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        let (start, mut text) = current.take().unwrap_or((index + 1, String::new()));
+        match trimmed.strip_suffix('\\') {
+            Some(rest) => {
+                text.push_str(rest);
+                text.push(' ');
+                current = Some((start, text));
+            }
+            None => {
+                text.push_str(trimmed);
+                push(start, text);
+            }
+        }
+    }
+    if let Some((start, text)) = current {
+        push(start, text);
+    }
+
+    instructions
+}
+
+/// Replace `$NAME`, `${NAME}` and `${NAME:-default}` with the values of `args`. Fails with the
+/// name of the first variable that has no value.
+fn substitute(value: &str, args: &HashMap<String, String>) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = value;
+
+    while let Some(index) = rest.find('$') {
+        result.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+
+        let (name, default, after) = match rest.strip_prefix('{') {
+            Some(braced) => {
+                let end = braced.find('}').ok_or_else(|| braced.to_string())?;
+                let (name, default) = match braced[..end].split_once(":-") {
+                    Some((name, default)) => (name, Some(default)),
+                    None => (&braced[..end], None),
+                };
+                (name, default, &braced[end + 1..])
+            }
+            None => {
+                let end = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                (&rest[..end], None, &rest[end..])
+            }
+        };
+
+        match args.get(name).filter(|value| !value.is_empty()).map(String::as_str).or(default) {
+            Some(value) => result.push_str(value),
+            None => return Err(name.to_string()),
+        }
+        rest = after;
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+/// `*` matches any run of characters, everything else only itself
+fn glob(pattern: &str, text: &str) -> bool {
+    let (pattern, text) = (pattern.as_bytes(), text.as_bytes());
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == text[t] {
+            p += 1;
+            t += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|byte| *byte == b'*')
+}
+
+fn strip_hub(image: &str) -> &str {
+    HUB_PREFIXES
+        .iter()
+        .find_map(|prefix| image.strip_prefix(prefix))
+        .unwrap_or(image)
+}
+
+/// Whether an image names a tag or digest, a `:` before the last `/` is a registry port
+fn has_tag(image: &str) -> bool {
+    let name = image.rsplit('/').next().unwrap_or(image);
+    name.contains(':') || name.contains('@')
+}
+
+/// Image without its tag or digest
+fn repository(image: &str) -> &str {
+    let start = image.rfind('/').map(|index| index + 1).unwrap_or(0);
+    match image[start..].find([':', '@']) {
+        Some(end) => &image[..start + end],
+        None => image,
+    }
+}
+
+/// Patterns without a tag, like `python`, match every tag of the image
+fn image_matches(pattern: &str, image: &str) -> bool {
+    let pattern = strip_hub(pattern.trim());
+    match has_tag(pattern) {
+        true => glob(pattern, image),
+        false => glob(pattern, repository(image)),
+    }
+}
+
+fn check_image(image: &str, settings: &DockerfileSettings) -> Option<String> {
+    let image = strip_hub(image);
+    let image = match has_tag(image) {
+        true => image.to_string(),
+        false => format!("{image}:latest"),
+    };
+
+    if let Some(pattern) = settings.deniedimages.iter().find(|pattern| image_matches(pattern, &image)) {
+        return Some(format!("base image {image} is not allowed on this server ({pattern})"));
+    }
+    if !settings.allowedimages.is_empty()
+        && !settings.allowedimages.iter().any(|pattern| image_matches(pattern, &image))
+    {
+        return Some(format!(
+            "base image {image} is not one of the allowed images: {}",
+            settings.allowedimages.join(", ")
+        ));
+    }
+
+    None
+}
+
+/// Sources of an `ADD`, in shell or json form, without the flags
+fn add_sources(args: &str) -> Vec<String> {
+    let mut words = match args.starts_with('[') {
+        true => serde_json::from_str::<Vec<String>>(args).unwrap_or_default(),
+        false => args
+            .split_whitespace()
+            .filter(|word| !word.starts_with("--"))
+            .map(str::to_string)
+            .collect(),
+    };
+    // the last one is the destination
+    words.pop();
+    words
+}
+
+/// Check a user Dockerfile against the server's policy. `build_args` are the values the build
+/// passes with `--build-arg`, used for `ARG`s in `FROM` lines.
+pub fn check(dockerfile: &str, build_args: &HashMap<String, String>, settings: &DockerfileSettings) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    // ARGs before the first FROM are the only ones FROM lines can use
+    let mut args = HashMap::new();
+    let mut stages: Vec<String> = Vec::new();
+    let mut seen_from = false;
+    let mut final_user: Option<(usize, String)> = None;
+
+    for instruction in instructions(dockerfile) {
+        match instruction.keyword.as_str() {
+            "ARG" if !seen_from => {
+                for arg in instruction.args.split_whitespace() {
+                    let (name, default) = match arg.split_once('=') {
+                        Some((name, default)) => (name, Some(default.trim_matches('"'))),
+                        None => (arg, None),
+                    };
+                    let value = build_args.get(name).map(String::as_str).or(default);
+                    if let Some(value) = value {
+                        args.insert(name.to_string(), value.to_string());
+                    }
+                }
+            }
+            "FROM" => {
+                seen_from = true;
+                // every stage starts as the base image's user
+                final_user = None;
+
+                let mut words = instruction.args.split_whitespace().filter(|word| !word.starts_with("--"));
+                let Some(image) = words.next() else {
+                    continue;
+                };
+
+                match substitute(image, &args) {
+                    Err(name) => violations.push(Violation {
+                        line: instruction.line,
+                        message: format!("base image {image} can't be checked, ${name} has no value"),
+                    }),
+                    // stages built earlier in the same file aren't images
+                    Ok(image) if image == "scratch" || stages.contains(&image.to_lowercase()) => {}
+                    Ok(image) => {
+                        if let Some(message) = check_image(&image, settings) {
+                            violations.push(Violation {
+                                line: instruction.line,
+                                message,
+                            });
+                        }
+                    }
+                }
+
+                if let (Some(keyword), Some(name)) = (words.next(), words.next()) {
+                    if keyword.eq_ignore_ascii_case("as") {
+                        stages.push(name.to_lowercase());
+                    }
+                }
+            }
+            "USER" => final_user = Some((instruction.line, instruction.args)),
+            "ADD" => {
+                for source in add_sources(&instruction.args) {
+                    if ["http://", "https://", "git@"].iter().any(|scheme| source.starts_with(scheme)) {
+                        violations.push(Violation {
+                            line: instruction.line,
+                            message: format!(
+                                "ADD downloads {source} on every build, download it in a RUN step and verify its checksum instead"
+                            ),
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if let Some((line, user)) = final_user {
+        let name = user.split(':').next().unwrap_or(&user).trim();
+        if name == "root" || name == "0" {
+            violations.push(Violation {
+                line,
+                message: "the image runs as root, end with a USER that isn't root".to_string(),
+            });
+        }
+    }
+
+    violations.sort_by_key(|violation| violation.line);
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::DockerfilePolicy;
+
+    fn settings(allowed: &[&str], denied: &[&str]) -> DockerfileSettings {
+        DockerfileSettings {
+            policy: DockerfilePolicy::Enforce,
+            allowedimages: allowed.iter().map(|image| image.to_string()).collect(),
+            deniedimages: denied.iter().map(|image| image.to_string()).collect(),
+        }
+    }
+
+    fn violations(dockerfile: &str, build_args: &[(&str, &str)], settings: &DockerfileSettings) -> Vec<String> {
+        let build_args = build_args
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        check(dockerfile, &build_args, settings).iter().map(Violation::to_string).collect()
+    }
+
+    #[test]
+    fn continuation_lines_belong_to_their_instruction() {
+        let dockerfile = "FROM python:3.12\n\nRUN apt-get update \\\n    # comment\n    && apt-get install -y curl\nCMD [\"python\"]\n";
+
+        let instructions = instructions(dockerfile);
+        assert_eq!(instructions.len(), 3);
+        assert_eq!(instructions[1].line, 3);
+        assert_eq!(instructions[1].keyword, "RUN");
+        assert_eq!(instructions[1].args, "apt-get update  && apt-get install -y curl");
+        assert_eq!(instructions[2].line, 6);
+    }
+
+    #[test]
+    fn wildcard_tags_and_hub_prefixes() {
+        let settings = settings(&["python:3.*", "docker.io/library/node"], &["python:3.8*"]);
+
+        assert!(violations("FROM python:3.12-slim\n", &[], &settings).is_empty());
+        assert!(violations("FROM docker.io/library/python:3.11\n", &[], &settings).is_empty());
+        assert!(violations("FROM node\n", &[], &settings).is_empty());
+        assert_eq!(
+            violations("FROM python:3.8-slim\n", &[], &settings),
+            vec!["line 1: base image python:3.8-slim is not allowed on this server (python:3.8*)"]
+        );
+        assert_eq!(
+            violations("FROM python\n", &[], &settings),
+            vec!["line 1: base image python:latest is not one of the allowed images: python:3.*, docker.io/library/node"]
+        );
+        // a port isn't a tag
+        assert_eq!(
+            violations("FROM registry.local:5000/python\n", &[], &settings),
+            vec!["line 1: base image registry.local:5000/python:latest is not one of the allowed images: python:3.*, docker.io/library/node"]
+        );
+    }
+
+    #[test]
+    fn multi_stage_builds_check_every_base_but_not_the_stages() {
+        let dockerfile = "\
+FROM node:20 AS assets
+RUN npm ci && npm run build
+
+FROM ubuntu:22.04 as tools
+
+FROM python:3.12-slim
+COPY --from=assets /app/dist /app/static
+FROM Assets
+FROM scratch
+";
+        let settings = settings(&["python:*", "node:*"], &[]);
+
+        assert_eq!(
+            violations(dockerfile, &[], &settings),
+            vec!["line 4: base image ubuntu:22.04 is not one of the allowed images: python:*, node:*"]
+        );
+    }
+
+    #[test]
+    fn args_in_from_take_build_args_then_defaults() {
+        let dockerfile = "ARG VERSION=3.12\nARG VARIANT\nFROM python:${VERSION}-$VARIANT\n";
+        let settings = settings(&["python:3.12-*"], &[]);
+
+        assert!(violations(dockerfile, &[("VARIANT", "slim")], &settings).is_empty());
+        assert_eq!(
+            violations(dockerfile, &[("VERSION", "2.7"), ("VARIANT", "slim")], &settings),
+            vec!["line 3: base image python:2.7-slim is not one of the allowed images: python:3.12-*"]
+        );
+        assert_eq!(
+            violations(dockerfile, &[], &settings),
+            vec!["line 3: base image python:${VERSION}-$VARIANT can't be checked, $VARIANT has no value"]
+        );
+        assert!(violations("FROM python:${VERSION:-3.12}-slim\n", &[], &settings).is_empty());
+    }
+
+    #[test]
+    fn args_after_the_first_from_dont_reach_from_lines() {
+        let dockerfile = "FROM python:3.12-slim\nARG BASE=python:3.12-slim\nFROM $BASE\n";
+
+        assert_eq!(
+            violations(dockerfile, &[], &settings(&[], &[])),
+            vec!["line 3: base image $BASE can't be checked, $BASE has no value"]
+        );
+    }
+
+    #[test]
+    fn only_the_final_user_of_the_last_stage_counts() {
+        let settings = settings(&[], &[]);
+
+        assert!(violations("FROM python\nUSER root\nRUN pip install x\nUSER app\n", &[], &settings).is_empty());
+        assert!(violations("FROM node AS build\nUSER root\nFROM python\n", &[], &settings).is_empty());
+        assert_eq!(
+            violations("FROM python\nUSER app\nUSER 0:0\n", &[], &settings),
+            vec!["line 3: the image runs as root, end with a USER that isn't root"]
+        );
+    }
+
+    #[test]
+    fn remote_adds_are_reported() {
+        let dockerfile = "\
+FROM python
+ADD requirements.txt /app/
+ADD --chown=app https://example.com/tool.tar.gz /opt/
+ADD [\"git@github.com:org/repo.git\", \"/src\"]
+";
+
+        assert_eq!(
+            violations(dockerfile, &[], &settings(&[], &[])),
+            vec![
+                "line 3: ADD downloads https://example.com/tool.tar.gz on every build, download it in a RUN step and verify its checksum instead",
+                "line 4: ADD downloads git@github.com:org/repo.git on every build, download it in a RUN step and verify its checksum instead",
+            ]
+        );
+    }
+}
@@ -1,35 +1,166 @@
+use serde::Deserialize;
+
+/// Distribution the generated Python images are based on
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BaseImage {
+    /// smallest image, but packages without musl wheels (numpy, pandas, ...) have to be built
+    /// from source or fail to install
+    #[default]
+    Alpine,
+    /// Debian based, larger but installs the glibc wheels from PyPI
+    Slim,
+}
+
+impl BaseImage {
+    pub fn image(&self) -> &'static str {
+        match self {
+            BaseImage::Alpine => "python:3.11-alpine",
+            BaseImage::Slim => "python:3.11-slim",
+        }
+    }
+
+    /// Command installing the compilers needed by packages built from source, and `extra`
+    /// packages of the distribution
+    pub fn build_deps(&self, extra: &[String]) -> String {
+        let compilers = match self {
+            BaseImage::Alpine => ["gcc", "musl-dev"],
+            BaseImage::Slim => ["gcc", "libc6-dev"],
+        };
+        let packages = compilers
+            .into_iter()
+            .chain(extra.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        match self {
+            BaseImage::Alpine => format!("apk add --no-cache {packages}"),
+            BaseImage::Slim => format!(
+                "apt-get update && apt-get install -y --no-install-recommends {packages} && rm -rf /var/lib/apt/lists/*"
+            ),
+        }
+    }
+}
+
 pub struct DjangoDockerfile {
+    pub base: BaseImage,
     pub environment_vars: Vec<String>,
+    pub package_index: bool,
+    pub collectstatic: bool,
+    pub gunicorn_timeout: u64,
+    pub gunicorn_graceful_timeout: u64,
+    pub single_stage: bool,
+    pub build_packages: Vec<String>,
+}
+
+impl Default for DjangoDockerfile {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl DjangoDockerfile {
     pub fn new() -> Self {
         Self {
+            base: BaseImage::default(),
             environment_vars: Vec::new(),
+            package_index: false,
+            collectstatic: false,
+            gunicorn_timeout: 30,
+            gunicorn_graceful_timeout: 30,
+            single_stage: false,
+            build_packages: Vec::new(),
         }
     }
     
+    pub fn with_base(mut self, base: BaseImage) -> Self {
+        self.base = base;
+        self
+    }
+
     pub fn with_environment(mut self, env_vars: Vec<String>) -> Self {
         self.environment_vars = env_vars;
         self
     }
 
+    /// Read the pip index urls from the `pip_index_url` and `pip_extra_index_url` build
+    /// secrets so private registry credentials never end up in an image layer
+    pub fn with_package_index(mut self, enabled: bool) -> Self {
+        self.package_index = enabled;
+        self
+    }
+
+    /// Run `collectstatic` while building the image. Gunicorn doesn't serve static files, so
+    /// the project needs whitenoise (`whitenoise.middleware.WhiteNoiseMiddleware`) and a
+    /// `STATIC_ROOT` for the collected files to be reachable
+    pub fn with_collectstatic(mut self, enabled: bool) -> Self {
+        self.collectstatic = enabled;
+        self
+    }
+
+    /// Seconds a worker may spend on a request before gunicorn kills it. The project can
+    /// override it at runtime with the `GUNICORN_TIMEOUT` environment variable
+    pub fn with_gunicorn_timeout(mut self, secs: u64) -> Self {
+        self.gunicorn_timeout = secs;
+        self
+    }
+
+    /// Seconds workers get to finish in-flight requests after SIGTERM. The project can override
+    /// it at runtime with the `GUNICORN_GRACEFUL_TIMEOUT` environment variable
+    pub fn with_gunicorn_graceful_timeout(mut self, secs: u64) -> Self {
+        self.gunicorn_graceful_timeout = secs;
+        self
+    }
+
+    /// Build in a single stage that keeps the compilers and headers, for debugging packages
+    /// that need them at runtime. The image is larger
+    pub fn with_single_stage(mut self, enabled: bool) -> Self {
+        self.single_stage = enabled;
+        self
+    }
+
+    /// Extra distribution packages installed next to the compilers, e.g. `libpq-dev` for
+    /// headers a Python package builds against. They stay in the builder stage, only a single
+    /// stage image keeps them
+    pub fn with_build_packages(mut self, packages: Vec<String>) -> Self {
+        self.build_packages = packages;
+        self
+    }
+
     pub fn generate(&self) -> String {
-        let mut dockerfile = String::from(r#"
-# Multi-stage build for smaller image
-FROM python:3.11-alpine AS builder
+        let header = match self.single_stage {
+            true => format!("# Single-stage build, keeps the build dependencies for debugging\nFROM {}", self.base.image()),
+            false => format!("# Multi-stage build for smaller image\nFROM {} AS builder", self.base.image()),
+        };
+
+        let mut dockerfile = format!(r#"
+{header}
 
 WORKDIR /app
 
 # Install build dependencies
-RUN apk add --no-cache gcc musl-dev
+RUN {}
 
 # Install Python packages
 COPY requirements.txt .
-RUN pip install --no-cache-dir -r requirements.txt
+"#, self.base.build_deps(&self.build_packages));
+
+        if self.package_index {
+            dockerfile.push_str(r#"RUN --mount=type=secret,id=pip_index_url --mount=type=secret,id=pip_extra_index_url \
+    if [ -f /run/secrets/pip_index_url ]; then export PIP_INDEX_URL="$(cat /run/secrets/pip_index_url)"; fi; \
+    if [ -f /run/secrets/pip_extra_index_url ]; then export PIP_EXTRA_INDEX_URL="$(cat /run/secrets/pip_extra_index_url)"; fi; \
+    pip install --no-cache-dir -r requirements.txt
+"#);
+        } else {
+            dockerfile.push_str("RUN pip install --no-cache-dir -r requirements.txt\n");
+        }
 
+        if self.single_stage {
+            dockerfile.push_str("\n# Copy app\nCOPY . .\n");
+        } else {
+            dockerfile.push_str(&format!(r#"
 # Runtime stage
-FROM python:3.11-alpine AS runtime
+FROM {} AS runtime
 
 WORKDIR /app
 
@@ -39,7 +170,8 @@ COPY --from=builder /usr/local/bin /usr/local/bin
 
 # Copy app
 COPY . .
-"#);
+"#, self.base.image()));
+        }
 
         // Add environment variables
         if !self.environment_vars.is_empty() {
@@ -49,7 +181,11 @@ COPY . .
             }
         }
 
-        dockerfile.push_str(r#"
+        if self.collectstatic {
+            dockerfile.push_str("\n# Collect static files\nRUN python manage.py collectstatic --noinput\n");
+        }
+
+        dockerfile.push_str(&format!(r#"
 # Production setup
 EXPOSE 80
 
@@ -57,10 +193,97 @@ EXPOSE 80
 CMD ["sh", "-c", "\
     python manage.py migrate --noinput 2>/dev/null || true; \
     WSGI_MODULE=$(python -c \"import glob; files = glob.glob('*/wsgi.py'); print(files[0].split('/')[0] if files else 'wsgi')\"); \
-    gunicorn --bind 0.0.0.0:80 --workers 2 $WSGI_MODULE.wsgi:application"]
-"#);
+    exec gunicorn --bind 0.0.0.0:80 --workers 2 \
+    --timeout ${{GUNICORN_TIMEOUT:-{}}} \
+    --graceful-timeout ${{GUNICORN_GRACEFUL_TIMEOUT:-{}}} \
+    $WSGI_MODULE.wsgi:application"]
+"#, self.gunicorn_timeout, self.gunicorn_graceful_timeout));
         
         dockerfile
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The shell command gunicorn runs under
+    fn cmd(dockerfile: &str) -> &str {
+        &dockerfile[dockerfile.find("CMD [").unwrap()..]
+    }
+
+    #[test]
+    fn gunicorn_gets_the_configured_timeouts() {
+        let dockerfile = DjangoDockerfile::new()
+            .with_gunicorn_timeout(120)
+            .with_gunicorn_graceful_timeout(45)
+            .generate();
+
+        let cmd = cmd(&dockerfile);
+        assert!(cmd.contains("exec gunicorn --bind 0.0.0.0:80"), "{cmd}");
+        assert!(cmd.contains("--timeout ${GUNICORN_TIMEOUT:-120}"), "{cmd}");
+        assert!(cmd.contains("--graceful-timeout ${GUNICORN_GRACEFUL_TIMEOUT:-45}"), "{cmd}");
+    }
+
+    #[test]
+    fn gunicorn_timeouts_default_to_gunicorns_own() {
+        let dockerfile = DjangoDockerfile::new().generate();
+
+        let cmd = cmd(&dockerfile);
+        assert!(cmd.contains("--timeout ${GUNICORN_TIMEOUT:-30}"), "{cmd}");
+        assert!(cmd.contains("--graceful-timeout ${GUNICORN_GRACEFUL_TIMEOUT:-30}"), "{cmd}");
+    }
+
+    #[test]
+    fn package_index_is_read_from_secrets_by_the_install() {
+        let dockerfile = DjangoDockerfile::new().with_package_index(true).generate();
+
+        let (builder, runtime) = dockerfile.split_once("FROM python:3.11-alpine AS runtime").unwrap();
+        assert!(
+            builder.contains("RUN --mount=type=secret,id=pip_index_url --mount=type=secret,id=pip_extra_index_url \\\n"),
+            "{builder}"
+        );
+        assert!(builder.contains("pip install --no-cache-dir -r requirements.txt\n"), "{builder}");
+        assert!(!runtime.contains("PIP_INDEX_URL"), "{runtime}");
+
+        let dockerfile = DjangoDockerfile::new().generate();
+        assert!(!dockerfile.contains("--mount=type=secret"), "{dockerfile}");
+    }
+
+    #[test]
+    fn build_packages_stay_in_the_builder_stage() {
+        let dockerfile = DjangoDockerfile::new()
+            .with_build_packages(vec!["postgresql-dev".to_string(), "jpeg-dev".to_string()])
+            .generate();
+
+        let (builder, runtime) = dockerfile.split_once("FROM python:3.11-alpine AS runtime").unwrap();
+        assert!(builder.contains("RUN apk add --no-cache gcc musl-dev postgresql-dev jpeg-dev\n"), "{builder}");
+        assert!(!runtime.contains("postgresql-dev"), "{runtime}");
+        assert!(!runtime.contains("apk add"), "{runtime}");
+    }
+
+    #[test]
+    fn build_packages_use_the_package_manager_of_the_base() {
+        let dockerfile = DjangoDockerfile::new()
+            .with_base(BaseImage::Slim)
+            .with_build_packages(vec!["libpq-dev".to_string()])
+            .generate();
+
+        assert!(
+            dockerfile.contains("apt-get install -y --no-install-recommends gcc libc6-dev libpq-dev &&"),
+            "{dockerfile}"
+        );
+    }
+
+    #[test]
+    fn single_stage_keeps_the_build_packages() {
+        let dockerfile = DjangoDockerfile::new()
+            .with_single_stage(true)
+            .with_build_packages(vec!["libpq-dev".to_string()])
+            .generate();
+
+        assert!(!dockerfile.contains(" AS runtime"), "{dockerfile}");
+        assert!(dockerfile.contains("apk add --no-cache gcc musl-dev libpq-dev"), "{dockerfile}");
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+
+use bollard::errors::Error;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    configuration::NetworkSettings,
+    docker::PROJECT_LABEL,
+    runtime::{Addressing, ContainerRuntime},
+};
+
+/// Network Traefik and projects that may reach the internet share
+pub const SHARED_NETWORK: &str = "pemasak";
+
+/// What a project's containers can reach. Traefik reaches them under every policy.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, sqlx::Type)]
+#[sqlx(type_name = "egress_policy", rename_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum EgressPolicy {
+    /// the internet and the other projects on the shared network
+    #[default]
+    Allow,
+    /// nothing, the project gets a network of its own with only Traefik on it
+    Deny,
+    /// the services on the internal network, e.g. addon databases, but not the internet
+    InternalOnly,
+}
+
+fn isolated_network(container_name: &str) -> String {
+    format!("{container_name}-isolated")
+}
+
+/// Name of the network a project's containers are attached to under a policy, and whether
+/// it is internal, i.e. has no route out of the host
+pub fn network_for(policy: EgressPolicy, container_name: &str, settings: &NetworkSettings) -> (String, bool) {
+    match policy {
+        EgressPolicy::Allow => (SHARED_NETWORK.to_string(), false),
+        EgressPolicy::Deny => (isolated_network(container_name), true),
+        EgressPolicy::InternalOnly => (settings.internal.clone(), true),
+    }
+}
+
+/// Create the network of a policy when it doesn't exist yet and return its name. Traefik is
+/// attached to internal networks, and the network of a denied project is labelled with the
+/// project so it is cleaned up with the project's other orphans.
+pub async fn ensure_network(
+    docker: &dyn ContainerRuntime,
+    policy: EgressPolicy,
+    container_name: &str,
+    settings: &NetworkSettings,
+) -> Result<String, Error> {
+    let (name, internal) = network_for(policy, container_name, settings);
+
+    // projects get their own networks from the daemon's pools, only the shared one may be
+    // pinned to a subnet and get ipv6
+    let addressing = match name == SHARED_NETWORK {
+        true => Addressing {
+            subnet: settings.subnet.as_deref(),
+            ipv6: settings.ipv6,
+            ipv6_subnet: settings.ipv6subnet.as_deref(),
+        },
+        false => Addressing::default(),
+    };
+
+    // the name filter of the daemon matches substrings
+    let existing = docker
+        .list_networks(&name)
+        .await?
+        .into_iter()
+        .find(|network| network.name.as_deref() == Some(name.as_str()));
+
+    match existing {
+        // networks can't be changed once created, containers keep using ipv4 on this one
+        Some(network) if addressing.ipv6 && network.enable_ipv6 != Some(true) => {
+            tracing::warn!("Network {} was created without ipv6, remove it to have it recreated with ipv6", name);
+        }
+        Some(_) => {}
+        None => {
+            let labels = match policy {
+                EgressPolicy::Deny => HashMap::from([(PROJECT_LABEL.to_string(), container_name.to_string())]),
+                _ => HashMap::new(),
+            };
+            docker.create_network(&name, internal, addressing, labels).await?;
+        }
+    }
+
+    if internal {
+        let attached = docker
+            .inspect_network(&name)
+            .await?
+            .containers
+            .unwrap_or_default()
+            .values()
+            .any(|container| container.name.as_deref() == Some(settings.traefik.as_str()));
+
+        if !attached {
+            docker.connect_network(&name, &settings.traefik).await?;
+        }
+    }
+
+    Ok(name)
+}
+
+/// Move the running containers of a project to the network of a policy, without restarting
+/// them. Containers join the new network before they leave the old one so Traefik keeps a
+/// route to them. Returns the number of containers moved.
+pub async fn apply(
+    docker: &dyn ContainerRuntime,
+    policy: EgressPolicy,
+    container_name: &str,
+    settings: &NetworkSettings,
+) -> Result<usize, Error> {
+    let network = ensure_network(docker, policy, container_name, settings).await?;
+    let policy_networks = [
+        SHARED_NETWORK.to_string(),
+        settings.internal.clone(),
+        isolated_network(container_name),
+    ];
+
+    let containers = docker
+        .list_labeled_containers(&[format!("{PROJECT_LABEL}={container_name}")])
+        .await?;
+
+    for container in &containers {
+        let Some(id) = &container.id else {
+            continue;
+        };
+
+        let attached = container
+            .network_settings
+            .as_ref()
+            .and_then(|settings| settings.networks.as_ref())
+            .map(|networks| networks.keys().cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        if !attached.contains(&network) {
+            docker.connect_network(&network, id).await?;
+        }
+
+        for old in attached
+            .iter()
+            .filter(|name| **name != network && policy_networks.contains(name))
+        {
+            docker.disconnect_network(old, id).await?;
+        }
+    }
+
+    Ok(containers.len())
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum InterpolateError {
+    #[error("{name} references ${{{reference}}}, which is not set")]
+    Missing { name: String, reference: String },
+    #[error("variables reference each other in a loop: {}", cycle.join(" -> "))]
+    Cycle { cycle: Vec<String> },
+    #[error("{name} has a `${{` without a closing `}}`")]
+    Unclosed { name: String },
+}
+
+/// Resolve `${VAR}` references between the variables of a project, e.g. a `DATABASE_URL` built
+/// from `DB_HOST` and `DB_PORT`. References resolve against the same map and may be nested,
+/// `$$` is a literal `$` and a `$` that doesn't start a reference is kept as is.
+pub fn interpolate_env(map: &HashMap<String, String>) -> Result<HashMap<String, String>, InterpolateError> {
+    let mut resolved = HashMap::with_capacity(map.len());
+    let mut stack = Vec::new();
+
+    for name in map.keys() {
+        resolve(name, map, &mut resolved, &mut stack)?;
+    }
+
+    Ok(resolved)
+}
+
+fn resolve(
+    name: &str,
+    map: &HashMap<String, String>,
+    resolved: &mut HashMap<String, String>,
+    stack: &mut Vec<String>,
+) -> Result<String, InterpolateError> {
+    if let Some(value) = resolved.get(name) {
+        return Ok(value.clone());
+    }
+    if let Some(start) = stack.iter().position(|entry| entry == name) {
+        let mut cycle = stack[start..].to_vec();
+        cycle.push(name.to_string());
+        return Err(InterpolateError::Cycle { cycle });
+    }
+
+    stack.push(name.to_string());
+
+    let mut value = String::new();
+    let mut rest = map[name].as_str();
+    while let Some(index) = rest.find('$') {
+        value.push_str(&rest[..index]);
+        rest = &rest[index..];
+
+        if let Some(after) = rest.strip_prefix("$$") {
+            value.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let end = after.find('}').ok_or_else(|| InterpolateError::Unclosed {
+                name: name.to_string(),
+            })?;
+            let reference = &after[..end];
+            if !map.contains_key(reference) {
+                return Err(InterpolateError::Missing {
+                    name: name.to_string(),
+                    reference: reference.to_string(),
+                });
+            }
+
+            value.push_str(&resolve(reference, map, resolved, stack)?);
+            rest = &after[end + 1..];
+        } else {
+            value.push('$');
+            rest = &rest[1..];
+        }
+    }
+    value.push_str(rest);
+
+    stack.pop();
+    resolved.insert(name.to_string(), value.clone());
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn resolves_references() {
+        let resolved = interpolate_env(&env(&[
+            ("DATABASE_URL", "postgres://${DB_USER}@${DB_HOST}:${DB_PORT}/app"),
+            ("DB_HOST", "db.internal"),
+            ("DB_PORT", "5432"),
+            ("DB_USER", "app"),
+        ]))
+        .unwrap();
+
+        assert_eq!(resolved["DATABASE_URL"], "postgres://app@db.internal:5432/app");
+        assert_eq!(resolved["DB_HOST"], "db.internal");
+    }
+
+    #[test]
+    fn resolves_nested_references() {
+        let resolved = interpolate_env(&env(&[
+            ("URL", "https://${HOST}/"),
+            ("HOST", "${NAME}.example.com"),
+            ("NAME", "blog"),
+        ]))
+        .unwrap();
+
+        assert_eq!(resolved["URL"], "https://blog.example.com/");
+    }
+
+    #[test]
+    fn keeps_dollars_that_arent_references() {
+        let resolved = interpolate_env(&env(&[("PRICE", "$5 or $$HOME"), ("TRAILING", "a$")])).unwrap();
+
+        assert_eq!(resolved["PRICE"], "$5 or $HOME");
+        assert_eq!(resolved["TRAILING"], "a$");
+    }
+
+    #[test]
+    fn missing_reference() {
+        let err = interpolate_env(&env(&[("URL", "https://${HOST}/")])).unwrap_err();
+
+        assert_eq!(
+            err,
+            InterpolateError::Missing {
+                name: "URL".to_string(),
+                reference: "HOST".to_string(),
+            }
+        );
+        assert_eq!(err.to_string(), "URL references ${HOST}, which is not set");
+    }
+
+    #[test]
+    fn unclosed_reference() {
+        let err = interpolate_env(&env(&[("URL", "https://${HOST")])).unwrap_err();
+
+        assert_eq!(err, InterpolateError::Unclosed { name: "URL".to_string() });
+    }
+
+    #[test]
+    fn references_in_a_loop() {
+        let err = interpolate_env(&env(&[("A", "${B}"), ("B", "${A}")])).unwrap_err();
+
+        // which variable the loop is found from depends on the map's order
+        match err {
+            InterpolateError::Cycle { cycle } => {
+                assert_eq!(cycle.len(), 3);
+                assert_eq!(cycle.first(), cycle.last());
+            }
+            err => panic!("unexpected error {err}"),
+        }
+        assert!(matches!(
+            interpolate_env(&env(&[("SELF", "x${SELF}")])),
+            Err(InterpolateError::Cycle { .. })
+        ));
+    }
+}
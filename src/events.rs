@@ -0,0 +1,146 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// Events a subscriber can fall behind by before it misses some
+const CHANNEL_CAPACITY: usize = 64;
+
+lazy_static! {
+    /// Builds that are queued or running, by build id
+    static ref CHANNELS: Mutex<HashMap<Uuid, Channel>> = Mutex::new(HashMap::new());
+}
+
+struct Channel {
+    sender: broadcast::Sender<BuildEvent>,
+    /// replayed to subscribers that join halfway
+    last: BuildEvent,
+}
+
+/// Phases of a build in the order they happen. Not every build goes through all of them, a
+/// build without a release command skips releasing and a cached build may never pull.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum BuildStep {
+    Queued,
+    Preparing,
+    Building,
+    /// read from the BuildKit output
+    PullingBaseImage,
+    /// read from the BuildKit output
+    InstallingDependencies,
+    /// read from the BuildKit output
+    ExportingImage,
+    Scanning,
+    Releasing,
+    StartingContainer,
+    StartingWorkers,
+    Finished,
+}
+
+impl BuildStep {
+    /// Step a line of `docker build --progress=plain` output starts, if any
+    pub fn from_buildkit(line: &str) -> Option<Self> {
+        // step lines look like `#5 [2/6] RUN pip install -r requirements.txt`
+        let line = line.trim_start();
+        if !line.starts_with('#') {
+            return None;
+        }
+
+        if line.contains("[internal] load metadata for") || line.contains(" FROM ") {
+            Some(BuildStep::PullingBaseImage)
+        } else if ["pip install", "npm install", "npm ci", "yarn install", "poetry install", "uv sync"]
+            .iter()
+            .any(|command| line.contains(command))
+        {
+            Some(BuildStep::InstallingDependencies)
+        } else if line.contains("exporting to image") {
+            Some(BuildStep::ExportingImage)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct BuildEvent {
+    pub build_id: Uuid,
+    pub step: BuildStep,
+    pub at: DateTime<Utc>,
+    /// how the build ended, only set on the finished event
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<&'static str>,
+}
+
+impl BuildEvent {
+    fn new(build_id: Uuid, step: BuildStep, status: Option<&'static str>) -> Self {
+        Self {
+            build_id,
+            step,
+            at: Utc::now(),
+            status,
+        }
+    }
+}
+
+/// Start publishing the events of a build, called when it is queued
+pub fn open(build_id: Uuid) {
+    let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+    let last = BuildEvent::new(build_id, BuildStep::Queued, None);
+    CHANNELS.lock().unwrap().insert(build_id, Channel { sender, last });
+}
+
+/// Publish the end of a build and drop its channel, which ends every subscription
+pub fn close(build_id: Uuid, status: &'static str) {
+    if let Some(channel) = CHANNELS.lock().unwrap().remove(&build_id) {
+        let _ = channel
+            .sender
+            .send(BuildEvent::new(build_id, BuildStep::Finished, Some(status)));
+    }
+}
+
+/// Follow a queued or running build. The first event is the step the build is at. None when
+/// the build isn't queued or running on this server.
+pub fn subscribe(build_id: Uuid) -> Option<(BuildEvent, broadcast::Receiver<BuildEvent>)> {
+    CHANNELS
+        .lock()
+        .unwrap()
+        .get(&build_id)
+        .map(|channel| (channel.last.clone(), channel.sender.subscribe()))
+}
+
+/// Publishes the steps of one build. Steps only move forward, a step that is reported again
+/// or after a later one is ignored.
+pub struct BuildEvents {
+    build_id: Uuid,
+    current: Mutex<BuildStep>,
+}
+
+impl BuildEvents {
+    pub fn new(build_id: Uuid) -> Self {
+        Self {
+            build_id,
+            current: Mutex::new(BuildStep::Queued),
+        }
+    }
+
+    pub fn step(&self, step: BuildStep) {
+        {
+            let mut current = self.current.lock().unwrap();
+            if step <= *current {
+                return;
+            }
+            *current = step;
+        }
+
+        let event = BuildEvent::new(self.build_id, step, None);
+        if let Some(channel) = CHANNELS.lock().unwrap().get_mut(&self.build_id) {
+            // nobody listening is fine, the event is still replayed to the next subscriber
+            let _ = channel.sender.send(event.clone());
+            channel.last = event;
+        }
+    }
+}
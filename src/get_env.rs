@@ -52,3 +52,13 @@ pub fn grafana_user() -> String {
 pub fn grafana_password() -> String {
     get_env_or_default("GF_SECURITY_ADMIN_PASSWORD", "password")
 }
+
+/// Get the default gunicorn worker timeout of generated Django images, in seconds
+pub fn gunicorn_timeout() -> u64 {
+    get_env_or_default("GUNICORN_TIMEOUT", "60").parse().unwrap_or(60)
+}
+
+/// Get the default gunicorn graceful shutdown timeout of generated Django images, in seconds
+pub fn gunicorn_graceful_timeout() -> u64 {
+    get_env_or_default("GUNICORN_GRACEFUL_TIMEOUT", "30").parse().unwrap_or(30)
+}
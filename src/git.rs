@@ -4,12 +4,9 @@ use std::{
     io::Read,
     path::Path as StdPath,
     process::{Output, Stdio},
+    time::Duration,
 };
 
-use argon2::{
-    password_hash::{PasswordHash, PasswordVerifier},
-    Argon2,
-};
 use axum::{
     extract::{DefaultBodyLimit, Path, Query, State},
     middleware::{self, Next},
@@ -18,23 +15,141 @@ use axum::{
     Router,
 };
 use axum_extra::routing::RouterExt;
-use git2::Repository;
-use http_body::combinators::UnsyncBoxBody;
+use git2::{Oid, Repository};
+use http_body::{combinators::UnsyncBoxBody, Limited};
 use hyper::{
     body::Bytes, http::response::Builder as ResponseBuilder, Body, HeaderMap, Request, StatusCode,
 };
 
 use anyhow::Result;
 use serde::Deserialize;
-use tokio::{io::AsyncWriteExt, process::Command};
+use sqlx::{postgres::PgListener, PgPool};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    process::{Child, Command},
+    sync::{mpsc::Sender, oneshot},
+};
 use tower_http::limit::RequestBodyLimitLayer;
 
-use crate::{configuration::Settings, queue::BuildQueueItem, startup::AppState};
+use crate::{announcements, auth::hashing::{self, Hasher, Verification}, configuration::{PauseMode, Settings}, queue::{BuildQueueItem, BuildTrigger}, startup::AppState, system::capacity};
 
 use data_encoding::BASE64;
+use uuid::Uuid;
+
+/// Whether a git request pushes to or fetches from the repository
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum GitOperation {
+    Push,
+    Fetch,
+}
+
+impl GitOperation {
+    fn of<B>(request: &Request<B>) -> Self {
+        let uri = request.uri();
+        let pushes = uri.path().trim_end_matches('/').ends_with("git-receive-pack")
+            || uri
+                .query()
+                .map(|query| query.contains("service=git-receive-pack"))
+                .unwrap_or(false);
+
+        match pushes {
+            true => GitOperation::Push,
+            false => GitOperation::Fetch,
+        }
+    }
+}
+
+/// Record when a token was last used. A clone makes several requests, so the row is only
+/// written when the last recorded use is more than a minute old.
+async fn record_token_use(pool: &PgPool, token_id: Uuid, operation: GitOperation) {
+    let result = match operation {
+        GitOperation::Push => {
+            sqlx::query!(
+                r#"UPDATE api_token SET last_push_at = now()
+                   WHERE id = $1
+                   AND (last_push_at IS NULL OR last_push_at < now() - interval '1 minute')
+                "#,
+                token_id
+            )
+            .execute(pool)
+            .await
+        }
+        GitOperation::Fetch => {
+            sqlx::query!(
+                r#"UPDATE api_token SET last_fetch_at = now()
+                   WHERE id = $1
+                   AND (last_fetch_at IS NULL OR last_fetch_at < now() - interval '1 minute')
+                "#,
+                token_id
+            )
+            .execute(pool)
+            .await
+        }
+    };
+
+    if let Err(err) = result {
+        tracing::warn!(?err, ?operation, "Can't record git token use");
+    }
+}
+
+/// Id of the git token of `owner`/`repo` that `token` is the plaintext of, if any. Tokens of
+/// soft-deleted projects aren't found. Its hash is replaced when it was made with weaker
+/// parameters than the configured ones.
+pub async fn find_token(
+    pool: &PgPool,
+    hasher: &Hasher,
+    owner: &str,
+    repo: &str,
+    token: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let tokens = sqlx::query!(
+        r#"SELECT api_token.id, api_token.token AS token
+            FROM project_owners
+            JOIN projects ON project_owners.id = projects.owner_id
+            JOIN api_token ON projects.id = api_token.project_id
+            WHERE project_owners.name = $1
+            AND projects.name = $2
+            AND projects.deleted_at IS NULL
+        "#,
+        owner,
+        repo
+    )
+    .fetch_all(pool)
+    .await?;
+
+    // Argon2 is slow on purpose, it mustn't hold up the other requests on this worker
+    let verified = tokio::task::spawn_blocking({
+        let (hasher, token) = (hasher.clone(), token.to_string());
+        move || {
+            tokens
+                .into_iter()
+                .map(|record| {
+                    let verification = hasher.verify(token.as_bytes(), &record.token);
+                    (record, verification)
+                })
+                .find(|(_, verification)| verification.is_valid())
+        }
+    })
+    .await;
+
+    let (record, verification) = match verified {
+        Ok(Some(verified)) => verified,
+        Ok(None) => return Ok(None),
+        Err(err) => {
+            tracing::error!(?err, "Can't check git token: Failed to verify");
+            return Ok(None);
+        }
+    };
+
+    if let Verification::Rehashed(rehashed) = &verification {
+        hashing::store_token(pool, record.id, &record.token, rehashed).await;
+    }
+
+    Ok(Some(record.id))
+}
 
 async fn basic_auth<B>(
-    State(AppState { pool, git_auth, .. }): State<AppState>,
+    State(AppState { pool, git_auth, hasher, .. }): State<AppState>,
     Path((_owner, repo)): Path<(String, String)>,
     headers: HeaderMap,
     request: Request<B>,
@@ -60,7 +175,7 @@ async fn basic_auth<B>(
         true => {
             repo.split(".git").next().unwrap_or("")
         }.to_owned(),
-        false => format!("{repo}"),
+        false => repo.to_string(),
     };
 
     match headers.get("Authorization").and_then(|v| v.to_str().ok()) {
@@ -80,37 +195,15 @@ async fn basic_auth<B>(
             let owner_name = parts.next().unwrap_or("");
             let token = parts.next().unwrap_or("");
 
-            let tokens = match sqlx::query!(
-                r#"SELECT projects.name AS project_name, api_token.token AS token, project_owners.name AS project_owner
-                    FROM project_owners
-                    JOIN projects ON project_owners.id = projects.owner_id
-                    JOIN api_token ON projects.id = api_token.project_id
-                    WHERE project_owners.name = $1
-                "#,
-                owner_name
-            )
-            .fetch_all(&pool)
-            .await
-            {
-                Ok(tokens) => tokens,
-                Err(sqlx::Error::RowNotFound) => return Err(auth_failed),
+            let token_id = match find_token(&pool, &hasher, owner_name, &repo, token).await {
+                Ok(Some(token_id)) => token_id,
+                Ok(None) | Err(sqlx::Error::RowNotFound) => return Err(auth_failed),
                 Err(_) => return Err(auth_err),
             };
 
-            let hasher = Argon2::default();
-            let authenticated = tokens.iter().any(|rec| {
-                let hash_match = PasswordHash::new(&rec.token)
-                    .and_then(|hash| hasher.verify_password(token.as_bytes(), &hash))
-                    .is_ok();
-
-                let authorization_match = rec.project_name == repo && rec.project_owner == owner_name;
-
-                hash_match && authorization_match
-            });
-            
-            if !authenticated {
-                return Err(auth_failed);
-            }
+            // recorded in the background so the git client isn't kept waiting on the write
+            let operation = GitOperation::of(&request);
+            tokio::spawn(async move { record_token_use(&pool, token_id, operation).await });
 
             Ok(next.run(request).await)
         }
@@ -118,7 +211,7 @@ async fn basic_auth<B>(
 }
 
 pub fn router(state: AppState, config: &Settings) -> Router<AppState, Body> {
-    Router::new()
+    let router = Router::new()
         .route_with_tsr("/:owner/:repo/git-upload-pack", post(upload_pack_rpc))
         .route_with_tsr("/:owner/:repo/git-receive-pack", post(receive_pack_rpc))
         .route_with_tsr("/:owner/:repo/info/refs", get(get_info_refs))
@@ -164,13 +257,45 @@ pub fn router(state: AppState, config: &Settings) -> Router<AppState, Body> {
             "/:owner/:repo/objects/packs/:file",
             get(get_pack_or_idx_file),
         )
-        .route_layer(middleware::from_fn_with_state(state, basic_auth))
-        // not git server related
-        .layer(DefaultBodyLimit::disable())
-        .layer(RequestBodyLimitLayer::new(config.body_limit()))
+        .route_layer(middleware::from_fn_with_state(state, basic_auth));
+
+    // not git server related
+    limit_bodies(router, config.body_limit())
     // .with_state(state)
 }
 
+/// Packs can be much larger than api bodies, so git routes get their own limit in place of
+/// axum's default one
+fn limit_bodies<S>(router: Router<S, Limited<Body>>, limit: usize) -> Router<S, Body>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    router
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(limit))
+}
+
+/// Passed to every upload-pack and receive-pack, lets clients ask for partial clones like
+/// `--filter=blob:none`. Shallow clones need nothing extra.
+const SERVER_CONFIG: [&str; 2] = ["-c", "uploadpack.allowFilter=true"];
+
+/// Bytes read from git at a time when streaming its output
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// How long a push waits for the queue to tell the id of its build
+const BUILD_ID_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Hand the protocol the client asked for in `Git-Protocol`, e.g. `version=2`, to git. git
+/// ignores the parameters it doesn't know, so the header is passed on as is.
+fn protocol_env(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .get("Git-Protocol")
+        .and_then(|v| v.to_str().ok())
+        .map(|protocol| ("GIT_PROTOCOL".to_string(), protocol.to_string()))
+        .into_iter()
+        .collect()
+}
+
 async fn git_command<P, IA, S, IE, K, V>(dir: P, args: IA, envs: IE) -> Result<Output>
 where
     P: AsRef<StdPath>,
@@ -391,11 +516,313 @@ fn normal_merge(
     Ok(())
 }
 
+/// Branches of a repository and the commit each points to, empty when it can't be read
+pub fn branch_heads(path: &str) -> Vec<(String, Oid)> {
+    let Ok(repo) = Repository::open(path) else {
+        return Vec::new();
+    };
+    let Ok(references) = repo.references_glob("refs/heads/*") else {
+        return Vec::new();
+    };
+
+    references
+        .filter_map(|reference| reference.ok())
+        .filter_map(|reference| Some((reference.name()?.to_string(), reference.target()?)))
+        .collect()
+}
+
+/// Bring the checkout images are built from up to date with the pushed branch, cloning the
+/// repository on the first push
+pub fn sync_checkout(path: &str, container_src: &str) -> Result<(), git2::Error> {
+    // get first file in branch folder
+    let branch = std::fs::read_dir(format!("{path}/refs/heads"))
+        .ok()
+        .and_then(|mut dir| {
+            dir.find_map(|entry| entry.ok().and_then(|e| e.file_name().into_string().ok()))
+        })
+        .ok_or_else(|| git2::Error::from_str("no branch found"))?;
+    tracing::info!(branch, "git branch name");
+
+    if git2::Repository::clone(path, container_src).is_ok() {
+        return Ok(());
+    }
+
+    tracing::info!("repo already cloned");
+    // try to pull
+    let repo = git2::Repository::open(container_src)?;
+    let mut fo = git2::FetchOptions::new();
+    fo.download_tags(git2::AutotagOption::All);
+
+    let mut remote = repo.find_remote("origin")?;
+    remote.fetch(&[&branch], Some(&mut fo), None)?;
+
+    let fetch_head = repo.find_reference("FETCH_HEAD")?;
+    let fetch_commit = repo.reference_to_annotated_commit(&fetch_head)?;
+
+    let analysis = repo.merge_analysis(&[&fetch_commit])?;
+
+    if analysis.0.is_fast_forward() {
+        tracing::info!("fast forward");
+        let refname = format!("refs/heads/{branch}");
+        match repo.find_reference(&refname) {
+            Ok(mut r) => {
+                fast_forward(&repo, &mut r, &fetch_commit)?;
+            }
+            Err(_) => {
+                // The branch doesn't exist so just set the reference to the
+                // commit directly. Usually this is because you are pulling
+                // into an empty repository.
+                repo.reference(
+                    &refname,
+                    fetch_commit.id(),
+                    true,
+                    &format!("Setting {} to {}", fetch_commit.id(), &branch),
+                )?;
+                repo.set_head(&refname)?;
+                repo.checkout_head(Some(
+                    git2::build::CheckoutBuilder::default()
+                        .allow_conflicts(true)
+                        .conflict_style_merge(true)
+                        .force(),
+                ))?;
+            }
+        };
+    } else {
+        tracing::info!("merge");
+        let head_commit = repo.reference_to_annotated_commit(&repo.head()?)?;
+        normal_merge(&repo, &head_commit, &fetch_commit)?;
+    };
+
+    Ok(())
+}
+
+/// Commit at the head of a repository or checkout. A bare repository whose HEAD names a branch
+/// that was never pushed falls back to its first branch, the one builds are made from.
+pub fn head_commit(path: &str) -> Option<String> {
+    let repo = Repository::open(path).ok()?;
+    let head = repo.head().ok().or_else(|| {
+        repo.branches(Some(git2::BranchType::Local))
+            .ok()?
+            .find_map(|branch| branch.ok())
+            .map(|(branch, _)| branch.into_reference())
+    })?;
+
+    head.peel_to_commit().ok().map(|commit| commit.id().to_string())
+}
+
+/// README names looked for at the root of a repository, in order, and the format of each.
+/// Names are matched without case.
+const README_FORMATS: [(&str, &str); 4] = [
+    ("readme.md", "markdown"),
+    ("readme.rst", "rst"),
+    ("readme.txt", "text"),
+    ("readme", "text"),
+];
+
+/// A README read from a commit
+#[derive(Debug, Clone)]
+pub struct Readme {
+    /// file name as committed
+    pub name: String,
+    pub format: &'static str,
+    pub content: String,
+}
+
+/// README at the root of a commit of a bare repository, none when there is none
+pub fn read_readme(path: &str, commit: &str) -> Option<Readme> {
+    let repo = Repository::open(path).ok()?;
+    let tree = repo.find_commit(Oid::from_str(commit).ok()?).ok()?.tree().ok()?;
+
+    README_FORMATS.iter().find_map(|(candidate, format)| {
+        let entry = tree.iter().find(|entry| {
+            entry.kind() == Some(git2::ObjectType::Blob)
+                && entry.name().is_some_and(|name| name.eq_ignore_ascii_case(candidate))
+        })?;
+        let blob = entry.to_object(&repo).ok()?.into_blob().ok()?;
+
+        Some(Readme {
+            name: entry.name()?.to_string(),
+            format,
+            content: String::from_utf8_lossy(blob.content()).into_owned(),
+        })
+    })
+}
+
+/// Commits reachable from `head` but not from `base`, none when either is unknown to the
+/// repository, e.g. after a force push
+pub fn commits_behind(path: &str, base: &str, head: &str) -> Option<usize> {
+    let repo = Repository::open(path).ok()?;
+    let base = git2::Oid::from_str(base).ok()?;
+    let head = git2::Oid::from_str(head).ok()?;
+
+    repo.graph_ahead_behind(head, base).ok().map(|(ahead, _)| ahead)
+}
+
+/// Postgres channel `pws ssh serve` notifies with `{owner}/{repo}` after a push over ssh
+pub const PUSH_CHANNEL: &str = "git_push";
+
+/// What a saved push is answered with, the same over http and ssh
+#[derive(Debug)]
+pub struct PushOutcome {
+    /// announcements, the queue and the pause, shown to the pusher above the push message
+    pub message: String,
+    /// false while deploys are paused with `build.pausemode: reject`
+    pub build: bool,
+}
+
+/// Decide whether a push that changed a branch is built and what the pusher is told about it
+pub async fn after_push(pool: &PgPool, pause_mode: PauseMode, build_max: usize) -> PushOutcome {
+    let announcements = announcements::active(pool).await.unwrap_or_else(|err| {
+        tracing::error!(?err, "Can't get announcements: Failed to query database");
+        Vec::new()
+    });
+    let mut message = announcements::push_message(&announcements);
+    let paused = announcements::pausing(&announcements).is_some();
+
+    if paused && pause_mode == PauseMode::Reject {
+        message.push_str("\nDeploys are paused, this push was saved but won't be built. Push again once they resume");
+        return PushOutcome {
+            message: message.trim().to_string(),
+            build: false,
+        };
+    }
+
+    // counted before the build is queued, so `queued` is the builds ahead of this one
+    match capacity::current(pool, build_max).await {
+        Ok(capacity) => {
+            message.push('\n');
+            message.push_str(&capacity.push_message());
+        }
+        Err(err) => tracing::error!(?err, "Can't get capacity: Failed to query database"),
+    }
+    if paused {
+        message.push_str("\nDeploys are paused, your build starts once they resume");
+    }
+
+    PushOutcome {
+        message: message.trim().to_string(),
+        build: true,
+    }
+}
+
+/// Queue a build for every push made over ssh. Those pushes are handled by a process sshd
+/// starts, which can't reach the build queue of the server. That process shows the pusher
+/// the outcome, it is decided again here with the server's settings.
+pub async fn listen_for_pushes(
+    pool: PgPool,
+    base: String,
+    build_channel: Sender<BuildQueueItem>,
+    pause_mode: PauseMode,
+    build_max: usize,
+) {
+    let mut listener = match PgListener::connect_with(&pool).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            tracing::error!(?err, "Can't listen for ssh pushes: Failed to connect to database");
+            return;
+        }
+    };
+    if let Err(err) = listener.listen(PUSH_CHANNEL).await {
+        tracing::error!(?err, "Can't listen for ssh pushes: Failed to listen on channel");
+        return;
+    }
+
+    loop {
+        // the listener reconnects on its own, pushes made while it was down are missed
+        let notification = match listener.recv().await {
+            Ok(notification) => notification,
+            Err(err) => {
+                tracing::error!(?err, "Can't receive ssh pushes: Lost database connection");
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        let Some((owner, repo)) = notification.payload().split_once('/') else {
+            tracing::warn!(payload = notification.payload(), "Ignoring malformed push notification");
+            continue;
+        };
+
+        let path = format!("{base}/{owner}/{repo}.git");
+        let container_src = format!("{path}/master");
+        if let Err(err) = sync_checkout(&path, &container_src) {
+            tracing::error!(?err, "Can't update the checkout of {owner}/{repo}");
+            continue;
+        }
+        if !after_push(&pool, pause_mode, build_max).await.build {
+            tracing::info!(%owner, %repo, "Not building ssh push: Deploys are paused");
+            continue;
+        }
+
+        let item = BuildQueueItem {
+            container_src,
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            trigger: BuildTrigger::Push,
+            batch_id: None,
+            reply: None,
+        };
+        if build_channel.send(item).await.is_err() {
+            tracing::error!("Can't queue ssh push: Build queue is closed");
+            return;
+        }
+    }
+}
+
+/// Put a message at the top and a footer at the end of a receive-pack response. git prints
+/// sideband 2 packets as `remote:` lines; when the client didn't negotiate a sideband the
+/// response is left as is.
+async fn with_progress_message(res: Response<Body>, message: &str, footer: &str) -> Response<Body> {
+    let (parts, body) = res.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(err) => {
+            tracing::error!(?err, "Can't read receive-pack response");
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+
+    let sideband = body.len() > 4
+        && std::str::from_utf8(&body[..4])
+            .ok()
+            .and_then(|len| u16::from_str_radix(len, 16).ok())
+            .is_some()
+        && matches!(body[4], 1..=3);
+    if !sideband {
+        return Response::from_parts(parts, Body::from(body));
+    }
+
+    let progress = |text: &str| -> Vec<u8> {
+        text.lines()
+            // 4 length bytes, the band and the newline
+            .flat_map(|line| format!("{:04x}\x02{line}\n", line.len() + 6).into_bytes())
+            .collect()
+    };
+
+    // the footer goes before the flush packet that ends the response
+    let (content, flush) = match body.ends_with(b"0000") {
+        true => body.split_at(body.len() - 4),
+        false => (&body[..], &b""[..]),
+    };
+    let mut framed = Vec::with_capacity(message.len() + body.len() + footer.len() + 32);
+    framed.extend_from_slice(&progress(message));
+    framed.extend_from_slice(content);
+    framed.extend_from_slice(&progress(footer));
+    framed.extend_from_slice(flush);
+
+    Response::from_parts(parts, Body::from(framed))
+}
+
 pub async fn receive_pack_rpc(
     Path((owner, repo)): Path<(String, String)>,
     State(AppState {
         base,
         build_channel,
+        build_max,
+        pool,
+        pause_mode,
+        push_checks,
+        push_message,
         ..
     }): State<AppState>,
     headers: HeaderMap,
@@ -405,9 +832,58 @@ pub async fn receive_pack_rpc(
         true => format!("{base}/{owner}/{repo}"),
         false => format!("{base}/{owner}/{repo}.git"),
     };
-    let head_dir = format!("{path}/refs/heads");
+    let project_name = repo.trim_end_matches(".git");
+
+    let project = match sqlx::query!(
+        r#"SELECT projects.skip_push_checks, projects.container_name, project_owners.push_message
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.name = $1 AND project_owners.name = $2
+           AND projects.deleted_at IS NULL
+        "#,
+        project_name,
+        owner,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(project)) => project,
+        // deleted projects keep their repository until they are purged, it takes no pushes
+        Ok(None) => {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty())
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't receive push: Failed to query database");
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::empty())
+                .unwrap();
+        }
+    };
+
+    let footer = |deployment_id: Option<Uuid>| {
+        push_message.render(
+            project.push_message.as_deref(),
+            &owner,
+            project_name,
+            &project.container_name,
+            deployment_id,
+        )
+    };
 
-    let res = service_rpc("receive-pack", &path, headers, body).await;
+    let heads = branch_heads(&path);
+    let res = service_rpc(
+        "receive-pack",
+        &path,
+        headers,
+        body,
+        &push_checks.args(),
+        push_checks.env(project.skip_push_checks),
+    )
+    .await;
     if res.status() != StatusCode::OK {
         return res;
     }
@@ -420,113 +896,44 @@ pub async fn receive_pack_rpc(
     {
         return res;
     }
+    // a push the pre-receive checks rejected, or one that changed nothing, has nothing to deploy
+    if branch_heads(&path) == heads {
+        return with_progress_message(res, "", &footer(None)).await;
+    }
 
     let container_src = format!("{path}/master");
-    let container_name = format!("{owner}-{}", repo.trim_end_matches(".git")).replace('.', "-");
-
-    // get first file in branch folder
-    let branch = match std::fs::read_dir(&head_dir) {
-        Ok(mut dir) => dir.find_map(|entry| {
-            entry.ok().and_then(|e| {
-                e.file_name().into_string().ok()
-                // .and_then(|s| s.strip_suffix(".lock").map(|s| s.to_string()))
-            })
-        }),
-        Err(_) => None,
-    };
-
-    let branch = match branch {
-        Some(branch) => branch,
-        None => {
-            tracing::error!("no branch found");
-            return Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Body::empty())
-                .unwrap();
-        }
-    };
-    tracing::info!(branch, "git branch name");
-
-    // TODO: clean up this mess
-    if let Err(_e) = git2::Repository::clone(&path, &container_src) {
-        tracing::info!("repo already cloned");
-        // try to pull
-        let repo = git2::Repository::open(&container_src).unwrap();
-        let mut fo = git2::FetchOptions::new();
-        fo.download_tags(git2::AutotagOption::All);
-
-        let mut remote = repo.find_remote("origin").unwrap();
-        remote.fetch(&[&branch], Some(&mut fo), None).unwrap();
-
-        let fetch_head = repo.find_reference("FETCH_HEAD").unwrap();
-        let fetch_commit = repo.reference_to_annotated_commit(&fetch_head).unwrap();
 
-        let analysis = repo.merge_analysis(&[&fetch_commit]).unwrap();
+    if let Err(err) = sync_checkout(&path, &container_src) {
+        tracing::error!(?err, "Can't update the checkout of {owner}/{repo}");
+        return Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::empty())
+            .unwrap();
+    }
 
-        if analysis.0.is_fast_forward() {
-            tracing::info!("fast forward");
-            let refname = format!("refs/heads/{branch}");
-            match repo.find_reference(&refname) {
-                Ok(mut r) => {
-                    fast_forward(&repo, &mut r, &fetch_commit).unwrap();
-                }
-                Err(_) => {
-                    // The branch doesn't exist so just set the reference to the
-                    // commit directly. Usually this is because you are pulling
-                    // into an empty repository.
-                    repo.reference(
-                        &refname,
-                        fetch_commit.id(),
-                        true,
-                        &format!("Setting {} to {}", fetch_commit.id(), &branch),
-                    )
-                    .unwrap();
-                    repo.set_head(&refname).unwrap();
-                    repo.checkout_head(Some(
-                        git2::build::CheckoutBuilder::default()
-                            .allow_conflicts(true)
-                            .conflict_style_merge(true)
-                            .force(),
-                    ))
-                    .unwrap();
-                }
-            };
-        } else {
-            tracing::info!("merge");
-            let head_commit = repo
-                .reference_to_annotated_commit(&repo.head().unwrap())
-                .unwrap();
-            normal_merge(&repo, &head_commit, &fetch_commit).unwrap();
-        };
+    let outcome = after_push(&pool, pause_mode, build_max).await;
+    if !outcome.build {
+        return with_progress_message(res, &outcome.message, &footer(None)).await;
+    }
 
-        if false {
-            // try to delete the folder and clone again
-            // tracing::error!("can't fetch repo -> {:#?}", e);
-            std::fs::remove_dir_all(&container_src).unwrap();
-
-            if let Err(e) = git2::Repository::clone(&path, &container_src) {
-                // if this doesnt work then something is wrong
-                println!("error -> {:#?}", e);
-                return Response::builder()
-                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body(Body::empty())
-                    .unwrap();
-            };
-        };
+    let (reply, build_id) = oneshot::channel();
+    let item = BuildQueueItem {
+        container_src,
+        owner: owner.clone(),
+        repo: repo.clone(),
+        trigger: BuildTrigger::Push,
+        batch_id: None,
+        reply: Some(reply),
     };
+    tokio::spawn(async move { build_channel.send(item).await });
 
-    tokio::spawn(async move {
-        build_channel
-            .send(BuildQueueItem {
-                container_name,
-                container_src,
-                owner,
-                repo,
-            })
-            .await
-    });
+    // the queue answers once the build is stored, the push output goes without an id otherwise
+    let build_id = tokio::time::timeout(BUILD_ID_TIMEOUT, build_id)
+        .await
+        .ok()
+        .and_then(|build_id| build_id.ok());
 
-    res
+    with_progress_message(res, &outcome.message, &footer(build_id)).await
 }
 
 pub async fn upload_pack_rpc(
@@ -540,10 +947,19 @@ pub async fn upload_pack_rpc(
         false => format!("{base}/{owner}/{repo}.git"),
     };
 
-    service_rpc("upload-pack", &path, headers, body).await
+    service_rpc("upload-pack", &path, headers, body, &[], Vec::new()).await
 }
 
-pub async fn service_rpc(rpc: &str, path: &str, headers: HeaderMap, body: Bytes) -> Response<Body> {
+/// Run a git service over the request body. `args` and `envs` are added to those every
+/// service gets.
+pub async fn service_rpc(
+    rpc: &str,
+    path: &str,
+    headers: HeaderMap,
+    body: Bytes,
+    args: &[String],
+    envs: Vec<(String, String)>,
+) -> Response<Body> {
     let mut response = Response::builder()
         .header("Content-Type", format!("application/x-git-{rpc}-result"))
         .body(Body::empty())
@@ -577,19 +993,16 @@ pub async fn service_rpc(rpc: &str, path: &str, headers: HeaderMap, body: Bytes)
         return response;
     }
 
-    let env = match headers.get("Git-Protocol").and_then(|v| v.to_str().ok()) {
-        Some("version=2") => ("GIT_PROTOCOL".to_string(), "version=2".to_string()),
-        _ => ("".to_string(), "".to_string()),
-    };
-
-    let envs = std::env::vars().chain([env]).collect::<Vec<_>>();
-
     let mut cmd = Command::new("git");
-    cmd.args([rpc, "--stateless-rpc", path])
+    cmd.args(SERVER_CONFIG)
+        .args(args)
+        .args([rpc, "--stateless-rpc", path])
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
-        .envs(envs);
+        .envs(protocol_env(&headers))
+        .envs(envs)
+        .kill_on_drop(true);
 
     let mut child = cmd.spawn().expect("failed to spawn command");
     let mut stdin = child.stdin.take().expect("failed to get stdin");
@@ -601,6 +1014,12 @@ pub async fn service_rpc(rpc: &str, path: &str, headers: HeaderMap, body: Bytes)
     }
     drop(stdin);
 
+    // packs of large repositories are sent as git writes them instead of held in memory
+    if rpc == "upload-pack" {
+        *response.body_mut() = stream_output(child);
+        return response;
+    }
+
     let output = child
         .wait_with_output()
         .await
@@ -620,6 +1039,56 @@ pub async fn service_rpc(rpc: &str, path: &str, headers: HeaderMap, body: Bytes)
     response
 }
 
+/// Send the output of a git process as it is written. A process that fails midway aborts the
+/// body, so the client sees a broken transfer rather than a truncated pack.
+fn stream_output(mut child: Child) -> Body {
+    let (mut sender, body) = Body::channel();
+    let mut stdout = child.stdout.take().expect("failed to get stdout");
+    let mut stderr = child.stderr.take().expect("failed to get stderr");
+
+    tokio::spawn(async move {
+        // drained next to stdout so a chatty git can't block on a full stderr pipe
+        let stderr = tokio::spawn(async move {
+            let mut buf = Vec::new();
+            let _ = stderr.read_to_end(&mut buf).await;
+            buf
+        });
+
+        let mut buf = vec![0; STREAM_CHUNK_SIZE];
+        loop {
+            match stdout.read(&mut buf).await {
+                Ok(0) => break,
+                Ok(len) => {
+                    // the client went away, dropping the child kills git
+                    if sender.send_data(Bytes::copy_from_slice(&buf[..len])).await.is_err() {
+                        return;
+                    }
+                }
+                Err(err) => {
+                    tracing::error!(?err, "Failed to read git output");
+                    sender.abort();
+                    return;
+                }
+            }
+        }
+
+        match child.wait().await {
+            Ok(status) if status.success() => {}
+            Ok(status) => {
+                let stderr = stderr.await.unwrap_or_default();
+                tracing::error!(?status, stderr = %String::from_utf8_lossy(&stderr), "Command failed");
+                sender.abort();
+            }
+            Err(err) => {
+                tracing::error!(?err, "Failed to wait for git");
+                sender.abort();
+            }
+        }
+    });
+
+    body
+}
+
 #[derive(Deserialize, Debug)]
 pub struct GitQuery {
     service: String,
@@ -661,17 +1130,21 @@ pub async fn get_info_refs(
             .unwrap();
     }
 
-    let env = match headers.get("Git-Protocol").and_then(|v| v.to_str().ok()) {
-        Some("version=2") => ("GIT_PROTOCOL".to_string(), "version=2".to_string()),
-        _ => ("".to_string(), "".to_string()),
-    };
+    advertise_refs(&path, service, &headers).await
+}
 
-    let envs = std::env::vars().chain([env]).collect::<Vec<_>>();
+/// Refs of the repository at `path` for an upload-pack or receive-pack client, in the protocol
+/// version it asked for
+async fn advertise_refs(path: &str, service: &str, headers: &HeaderMap) -> Response<Body> {
+    let version_2 = headers
+        .get("Git-Protocol")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|protocol| protocol.split(':').any(|param| param == "version=2"));
 
     let out = match git_command(
-        &path,
-        &[service, "--stateless-rpc", "--advertise-refs", "."],
-        envs,
+        path,
+        SERVER_CONFIG.iter().copied().chain([service, "--stateless-rpc", "--advertise-refs", "."]),
+        protocol_env(headers),
     )
     .await
     {
@@ -685,8 +1158,14 @@ pub async fn get_info_refs(
         }
     };
 
-    let body = packet_write(&format!("# service=git-{}\n", service));
-    let body = [body, packet_flush(), out.stdout].concat();
+    // a version 2 advertisement starts with the capabilities, without the service line
+    let body = match version_2 {
+        true => out.stdout,
+        false => {
+            let body = packet_write(&format!("# service=git-{}\n", service));
+            [body, packet_flush(), out.stdout].concat()
+        }
+    };
 
     Response::builder()
         .no_cache()
@@ -699,3 +1178,138 @@ pub async fn get_info_refs(
         .body(Body::from(body))
         .unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use tower::ServiceExt;
+
+    use super::*;
+
+    /// A push endpoint reading the whole pack, behind the limits of the git routes
+    fn pushes(limit: usize) -> Router {
+        let router = Router::new().route("/", post(|body: Bytes| async move { body.len().to_string() }));
+        limit_bodies(router, limit)
+    }
+
+    async fn push(body: Body, length: Option<usize>) -> Response {
+        let mut request = Request::post("/");
+        if let Some(length) = length {
+            request = request.header(hyper::header::CONTENT_LENGTH, length);
+        }
+        pushes(1024).oneshot(request.body(body).unwrap()).await.unwrap()
+    }
+
+    fn chunked(size: usize) -> Body {
+        let chunks = (0..2).map(move |_| Ok::<_, std::io::Error>(Bytes::from(vec![0; size / 2])));
+        Body::wrap_stream(futures::stream::iter(chunks))
+    }
+
+    #[tokio::test]
+    async fn pushes_within_the_limit_are_read() {
+        let response = push(Body::from(vec![0; 1000]), Some(1000)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(hyper::body::to_bytes(response.into_body()).await.unwrap(), "1000");
+    }
+
+    /// A bare repository with a few commits of a file that doesn't compress
+    fn fixture(dir: &StdPath) -> String {
+        let work = dir.join("work");
+        let bare = dir.join("repo.git");
+        std::fs::create_dir_all(&work).unwrap();
+
+        let git = |dir: &StdPath, args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .current_dir(dir)
+                .args(["-c", "user.name=pws", "-c", "user.email=pws@localhost"])
+                .args(args)
+                .status()
+                .unwrap();
+            assert!(status.success(), "git {args:?} failed");
+        };
+
+        git(&work, &["init", "-q"]);
+        let mut seed = 1u64;
+        for commit in 0..4 {
+            let data: Vec<u8> = (0..64 * 1024)
+                .map(|_| {
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                    (seed >> 56) as u8
+                })
+                .collect();
+            std::fs::write(work.join("data.bin"), data).unwrap();
+            git(&work, &["add", "."]);
+            git(&work, &["commit", "-q", "-m", &format!("commit {commit}")]);
+        }
+        git(dir, &["clone", "-q", "--bare", "work", "repo.git"]);
+
+        bare.to_str().unwrap().to_string()
+    }
+
+    /// The fetch routes of the git server for the repository at `path`
+    async fn serve(path: String) -> String {
+        let fetch = path.clone();
+        let router = Router::new()
+            .route(
+                "/repo.git/info/refs",
+                get(|Query(GitQuery { service }): Query<GitQuery>, headers: HeaderMap| async move {
+                    advertise_refs(&fetch, get_git_service(&service), &headers).await
+                }),
+            )
+            .route(
+                "/repo.git/git-upload-pack",
+                post(|headers: HeaderMap, body: Bytes| async move {
+                    service_rpc("upload-pack", &path, headers, body, &[], Vec::new()).await
+                }),
+            );
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/repo.git", listener.local_addr().unwrap());
+        let server = axum::Server::from_tcp(listener).unwrap().serve(router.into_make_service());
+        tokio::spawn(server);
+        url
+    }
+
+    /// Clone over protocol version 2 and tell how many bytes of packs were received
+    async fn clone(url: &str, dir: &StdPath, args: &[&str]) -> u64 {
+        let status = Command::new("git")
+            .args(["-c", "protocol.version=2", "clone", "-q", "--no-checkout"])
+            .args(args)
+            .arg(url)
+            .arg(dir)
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success(), "git clone {args:?} failed");
+
+        std::fs::read_dir(dir.join(".git/objects/pack"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "pack"))
+            .map(|path| std::fs::metadata(path).unwrap().len())
+            .sum()
+    }
+
+    #[tokio::test]
+    async fn shallow_and_filtered_clones_get_smaller_packs() {
+        let dir = std::env::temp_dir().join(format!("pws-clone-{}", Uuid::new_v4()));
+        let url = serve(fixture(&dir)).await;
+
+        let full = clone(&url, &dir.join("full"), &[]).await;
+        let shallow = clone(&url, &dir.join("shallow"), &["--depth", "1"]).await;
+        let filtered = clone(&url, &dir.join("filtered"), &["--filter=blob:none"]).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(full > 4 * 64 * 1024, "full clone got {full} bytes");
+        assert!(shallow < full / 2, "shallow clone got {shallow} of {full} bytes");
+        assert!(filtered < full / 10, "filtered clone got {filtered} of {full} bytes");
+    }
+
+    #[tokio::test]
+    async fn oversized_pushes_are_refused_with_or_without_a_length() {
+        let response = push(Body::from(vec![0; 4096]), Some(4096)).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let response = push(chunked(4096), None).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::docker::DeployError;
+
+/// Lines at the end of a failed build log that are searched for known failures
+const TAIL_LINES: usize = 50;
+
+const PIP_NOT_FOUND: [&str; 2] = [
+    "No matching distribution found for ",
+    "Could not find a version that satisfies the requirement ",
+];
+
+/// Short advice for the user on why a deploy failed, when the failure is a known one. The log
+/// itself is never changed, the hint is shown below it.
+pub fn hint(err: &anyhow::Error) -> Option<String> {
+    // the log names the actual problem, e.g. a crashed container's output
+    if let Some(hint) = log_hint(&err.to_string()) {
+        return Some(hint);
+    }
+
+    err.downcast_ref::<DeployError>()
+        .and_then(deploy_error_hint)
+        .map(|hint| hint.to_string())
+}
+
+fn deploy_error_hint(err: &DeployError) -> Option<&'static str> {
+    match err {
+        DeployError::DeployFailed { cause, .. } => deploy_error_hint(cause),
+        DeployError::ReleaseTimeout { .. } => {
+            Some("The release command took too long, move slow tasks out of it or make them faster")
+        }
+        DeployError::NotReady { .. } => {
+            Some("Your app didn't start in time, check the log above and make sure it listens on port 80")
+        }
+        DeployError::SubdomainUnavailable { .. } => {
+            Some("Another project or a platform service already uses this address, create the project under a different name")
+        }
+        DeployError::ScanFailed { .. } => {
+            Some("Update the packages with critical vulnerabilities and remove credentials from the repository, use environment variables for secrets instead")
+        }
+        DeployError::DockerfilePolicy { .. } => {
+            Some("Fix the Dockerfile lines listed above, the server only builds from the base images its administrators allow")
+        }
+        DeployError::ImageTooLarge { .. } => {
+            Some("Add a .dockerignore, drop unused packages from requirements.txt and keep datasets out of the image")
+        }
+        DeployError::InvalidHost { .. } => {
+            Some("The project's address is too long, create the project again with a shorter name")
+        }
+        DeployError::Network { .. } => {
+            Some("The server couldn't set up the network of your project, this is not caused by your code. Let the administrators know")
+        }
+        DeployError::Daemon { .. } | DeployError::DaemonTimeout { .. } => {
+            Some("The server had trouble talking to docker, this is not caused by your code. Try pushing again")
+        }
+        _ => None,
+    }
+}
+
+/// Match the tail of a build log against known failures
+pub fn log_hint(log: &str) -> Option<String> {
+    let lines = log.lines().collect::<Vec<_>>();
+    let tail = &lines[lines.len().saturating_sub(TAIL_LINES)..];
+
+    // the package pip choked on is named at the end of its error line
+    for line in tail.iter().rev() {
+        for pattern in PIP_NOT_FOUND {
+            if let Some((_, rest)) = line.split_once(pattern) {
+                let package = rest.split_whitespace().next().unwrap_or(rest);
+                return Some(format!(
+                    "pip could not install `{package}`, check its line in requirements.txt for typos or a version that doesn't exist"
+                ));
+            }
+        }
+    }
+
+    let contains = |patterns: &[&str]| {
+        tail.iter()
+            .any(|line| patterns.iter().any(|pattern| line.contains(pattern)))
+    };
+
+    if contains(&["\"/requirements.txt\": not found", "requirements.txt: no such file or directory"]) {
+        return Some("Add a requirements.txt or a Dockerfile to the root of the repository".to_string());
+    }
+
+    if contains(&["exit code: 137", "signal: killed", "MemoryError", "Cannot allocate memory"]) {
+        return Some("The build exceeded its memory limit, install fewer or lighter packages".to_string());
+    }
+
+    if contains(&["Listening at: http://127.0.0.1:", "server at http://127.0.0.1:", "Address already in use"]) {
+        return Some("Your app must listen on port 80 on all interfaces (0.0.0.0), not only on localhost".to_string());
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_the_package_pip_couldnt_find() {
+        let log = "#9 [builder 4/6] RUN pip install -r requirements.txt\n\
+                   #9 2.31 ERROR: Could not find a version that satisfies the requirement djangoo==4.2 (from versions: none)\n\
+                   #9 2.32 ERROR: No matching distribution found for djangoo==4.2\n\
+                   ERROR: failed to solve: process did not complete successfully: exit code: 1";
+
+        assert_eq!(
+            log_hint(log).unwrap(),
+            "pip could not install `djangoo==4.2`, check its line in requirements.txt for typos or a version that doesn't exist"
+        );
+    }
+
+    #[test]
+    fn missing_requirements() {
+        let log = "ERROR: failed to solve: failed to compute cache key: \"/requirements.txt\": not found";
+
+        assert_eq!(
+            log_hint(log).unwrap(),
+            "Add a requirements.txt or a Dockerfile to the root of the repository"
+        );
+    }
+
+    #[test]
+    fn build_out_of_memory() {
+        let log = "#10 41.2 Building wheel for numpy (pyproject.toml)\n\
+                   ERROR: failed to solve: process \"/bin/sh -c pip install -r requirements.txt\" did not complete successfully: exit code: 137";
+
+        assert_eq!(
+            log_hint(log).unwrap(),
+            "The build exceeded its memory limit, install fewer or lighter packages"
+        );
+    }
+
+    #[test]
+    fn app_listening_on_localhost() {
+        let log = "[2024-03-01 10:00:00 +0000] [1] [INFO] Starting gunicorn 21.2.0\n\
+                   [2024-03-01 10:00:00 +0000] [1] [INFO] Listening at: http://127.0.0.1:8000 (1)";
+
+        assert_eq!(
+            log_hint(log).unwrap(),
+            "Your app must listen on port 80 on all interfaces (0.0.0.0), not only on localhost"
+        );
+    }
+
+    #[test]
+    fn only_the_tail_of_the_log_is_searched() {
+        let mut log = "ERROR: No matching distribution found for left-pad\n".to_string();
+        log.push_str(&"step\n".repeat(TAIL_LINES));
+
+        assert_eq!(log_hint(&log), None);
+        assert_eq!(log_hint("#5 DONE 0.1s\nnaming to docker.io/library/alice-blog:latest done"), None);
+    }
+
+    #[test]
+    fn hints_at_deploy_errors() {
+        let err = anyhow::Error::from(DeployError::DeployFailed {
+            cause: Box::new(DeployError::ImageTooLarge { size: 2, limit: 1 }),
+            recovery: crate::docker::Recovery::Kept,
+        });
+
+        assert_eq!(
+            hint(&err).unwrap(),
+            "Add a .dockerignore, drop unused packages from requirements.txt and keep datasets out of the image"
+        );
+        assert_eq!(hint(&anyhow::Error::from(DeployError::Cancelled)), None);
+    }
+
+    #[test]
+    fn log_hints_come_before_deploy_error_hints() {
+        let err = anyhow::Error::from(DeployError::NotReady {
+            container: "alice-blog".to_string(),
+            reason: "exited with code 1".to_string(),
+            crash_loop: false,
+            log: "Listening at: http://127.0.0.1:8000 (1)".to_string(),
+        });
+
+        assert_eq!(
+            hint(&err).unwrap(),
+            "Your app must listen on port 80 on all interfaces (0.0.0.0), not only on localhost"
+        );
+    }
+}
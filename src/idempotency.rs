@@ -0,0 +1,447 @@
+use std::future::Future;
+
+use axum::{
+    extract::State,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use chrono::Utc;
+use data_encoding::HEXLOWER;
+use http_body::{Body as HttpBody, LengthLimitError, Limited};
+use hyper::{Body, Method, Request, StatusCode};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{auth::Auth, startup::AppState};
+
+/// Header clients set to make a POST safe to retry
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Set on responses replayed from an earlier request with the same key
+const REPLAYED_HEADER: &str = "idempotent-replayed";
+
+const MAX_KEY_LENGTH: usize = 255;
+
+/// A request still running after this long died with the server, its key can be used again
+const PENDING_TIMEOUT_SECS: i64 = 600;
+
+/// Response extension of handlers whose response holds credentials or environment values,
+/// like the git password of a new project. Only its status is stored, a retry is told the
+/// request was handled instead of getting the secret again from the database. A project's
+/// password can't be recovered that way, the project has to be made again.
+#[derive(Debug, Clone, Copy)]
+pub struct Sensitive;
+
+fn error_response(status: StatusCode, code: &str, message: &str) -> Response {
+    let json = serde_json::json!({ "message": message, "code": code });
+
+    hyper::Response::builder()
+        .status(status)
+        .body(Body::from(json.to_string()))
+        .unwrap()
+        .into_response()
+}
+
+fn database_error(err: sqlx::Error) -> Response {
+    tracing::error!(?err, "Can't check idempotency key: Failed to query database");
+    let json = serde_json::json!({ "message": format!("Failed to query database: {err}") });
+
+    hyper::Response::builder()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .body(Body::from(json.to_string()))
+        .unwrap()
+        .into_response()
+}
+
+/// Run a POST with an `Idempotency-Key` header at most once per user and key. A retry with the
+/// same key and request gets the stored response of the first one, a retry while the first is
+/// still running gets 409. Server errors aren't stored, the request can be retried with the same
+/// key. Bodies of [`Sensitive`] responses aren't stored either, a retry of one gets 409. Keys are
+/// kept for `application.idempotencyttl` hours.
+pub async fn layer(
+    State(AppState {
+        pool,
+        idempotency_ttl,
+        api_body_limit,
+        ..
+    }): State<AppState>,
+    auth: Auth,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let key = request
+        .headers()
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .map(|key| key.to_str().map(str::to_string));
+    let is_post = request.method() == Method::POST;
+    // the auth layer turns away requests without a user
+    let (Some(key), Some(user), true) = (key, auth.current_user.as_ref(), is_post) else {
+        return next.run(request).await;
+    };
+    let key = match key {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => key,
+        _ => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "idempotency_key_invalid",
+                &format!("Idempotency-Key has to be between 1 and {MAX_KEY_LENGTH} visible ASCII characters"),
+            )
+        }
+    };
+
+    let limits = Limits {
+        ttl_hours: idempotency_ttl,
+        body: api_body_limit,
+    };
+    run_once(&pool, limits, user.id, &key, request, |request| next.run(request)).await
+}
+
+/// How long keys are kept and how much of a body is read
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    ttl_hours: u64,
+    body: usize,
+}
+
+/// Read a whole body of at most `limit` bytes
+async fn read_body<B>(body: B, limit: usize) -> Result<Bytes, Response>
+where
+    B: HttpBody,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    hyper::body::to_bytes(Limited::new(body, limit))
+        .await
+        .map_err(|err| match err.downcast_ref::<LengthLimitError>() {
+            Some(_) => error_response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "body_too_large",
+                &format!("Body is larger than the limit of {limit} bytes"),
+            ),
+            None => error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid_body",
+                &format!("Failed to read body: {err}"),
+            ),
+        })
+}
+
+/// The body of [`layer`], with `handler` in place of the rest of the stack
+async fn run_once<F, Fut>(
+    pool: &PgPool,
+    limits: Limits,
+    user_id: Uuid,
+    key: &str,
+    request: Request<Body>,
+    handler: F,
+) -> Response
+where
+    F: FnOnce(Request<Body>) -> Fut,
+    Fut: Future<Output = Response>,
+{
+    // the body is hashed so a key reused for a different request is caught
+    let (parts, body) = request.into_parts();
+    let body = match read_body(body, limits.body).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{} {}\n", parts.method, parts.uri.path()));
+    hasher.update(&body);
+    let request_hash = HEXLOWER.encode(&hasher.finalize());
+    let request = Request::from_parts(parts, Body::from(body));
+
+    let now = Utc::now();
+    if let Err(err) = sqlx::query!(
+        r#"DELETE FROM idempotency_keys
+           WHERE created_at < $1
+              OR (user_id = $2 AND key = $3 AND response_status IS NULL AND created_at < $4)
+        "#,
+        now - chrono::Duration::hours(limits.ttl_hours as i64),
+        user_id,
+        key,
+        now - chrono::Duration::seconds(PENDING_TIMEOUT_SECS),
+    )
+    .execute(pool)
+    .await
+    {
+        return database_error(err);
+    }
+
+    // only one of concurrent requests with the same key gets to insert it and run
+    let inserted = match sqlx::query!(
+        r#"INSERT INTO idempotency_keys (user_id, key, request_hash)
+           VALUES ($1, $2, $3)
+           ON CONFLICT (user_id, key) DO NOTHING
+        "#,
+        user_id,
+        key,
+        request_hash,
+    )
+    .execute(pool)
+    .await
+    {
+        Ok(res) => res.rows_affected() == 1,
+        Err(err) => return database_error(err),
+    };
+
+    if !inserted {
+        let stored = match sqlx::query!(
+            r#"SELECT request_hash, response_status, response_body
+               FROM idempotency_keys
+               WHERE user_id = $1 AND key = $2
+            "#,
+            user_id,
+            key,
+        )
+        .fetch_optional(pool)
+        .await
+        {
+            Ok(stored) => stored,
+            Err(err) => return database_error(err),
+        };
+
+        return match stored {
+            Some(stored) if stored.request_hash != request_hash => error_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "idempotency_key_reused",
+                "Idempotency-Key was already used for a different request",
+            ),
+            Some(stored) => match (stored.response_status, stored.response_body) {
+                (Some(status), Some(body)) => hyper::Response::builder()
+                    .status(status as u16)
+                    .header(REPLAYED_HEADER, "true")
+                    .body(Body::from(body))
+                    .unwrap()
+                    .into_response(),
+                (Some(_), None) => error_response(
+                    StatusCode::CONFLICT,
+                    "idempotency_response_withheld",
+                    "A request with this Idempotency-Key was already handled, its response held credentials and isn't kept",
+                ),
+                (None, _) => error_response(
+                    StatusCode::CONFLICT,
+                    "idempotency_key_in_progress",
+                    "A request with this Idempotency-Key is still running, retry once it has finished",
+                ),
+            },
+            // the first request failed and freed the key in between
+            None => error_response(
+                StatusCode::CONFLICT,
+                "idempotency_key_in_progress",
+                "A request with this Idempotency-Key just finished, retry it",
+            ),
+        };
+    }
+
+    let response = handler(request).await;
+    let (parts, body) = response.into_parts();
+    let body = match read_body(body, limits.body).await {
+        Ok(body) => body,
+        Err(response) => {
+            tracing::error!(status = %response.status(), "Can't store idempotent response: Failed to read response body");
+            // the request ran, keep the key so a retry doesn't run it again
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "response_failed",
+                "Failed to read the response, the request was handled",
+            );
+        }
+    };
+
+    let sensitive = parts.extensions.get::<Sensitive>().is_some();
+    let stored = match parts.status.is_server_error() {
+        true => {
+            sqlx::query!(
+                "DELETE FROM idempotency_keys WHERE user_id = $1 AND key = $2",
+                user_id,
+                key,
+            )
+            .execute(pool)
+            .await
+        }
+        false => {
+            sqlx::query!(
+                r#"UPDATE idempotency_keys SET response_status = $1, response_body = $2
+                   WHERE user_id = $3 AND key = $4
+                "#,
+                parts.status.as_u16() as i16,
+                (!sensitive).then_some(body.as_ref()),
+                user_id,
+                key,
+            )
+            .execute(pool)
+            .await
+        }
+    };
+    if let Err(err) = stored {
+        tracing::error!(?err, "Can't store idempotent response: Failed to query database");
+    }
+
+    Response::from_parts(parts, axum::body::boxed(Body::from(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use tokio::sync::oneshot;
+
+    use super::*;
+
+    const LIMITS: Limits = Limits {
+        ttl_hours: 24,
+        body: 1024,
+    };
+
+    async fn user(pool: &PgPool) -> Uuid {
+        let user_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO users (id, username, password, name) VALUES ($1, 'alice', '', 'Alice')")
+            .bind(user_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        user_id
+    }
+
+    fn request(body: &str) -> Request<Body> {
+        Request::post("/api/project/new")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    /// Answers with the number of requests it has handled
+    fn counted(calls: &Arc<AtomicUsize>) -> impl FnOnce(Request<Body>) -> std::future::Ready<Response> {
+        let calls = calls.clone();
+        move |_| {
+            let n = calls.fetch_add(1, Ordering::SeqCst) + 1;
+            std::future::ready((StatusCode::CREATED, n.to_string()).into_response())
+        }
+    }
+
+    async fn text(response: Response) -> String {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    async fn code(response: Response) -> String {
+        let body: serde_json::Value = serde_json::from_str(&text(response).await).unwrap();
+        body["code"].as_str().unwrap().to_string()
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "..", scripts("schema")))]
+    async fn retry_after_success_gets_the_stored_response(pool: PgPool) {
+        let user_id = user(&pool).await;
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let first = run_once(&pool, LIMITS, user_id, "key", request("{}"), counted(&calls)).await;
+        assert_eq!(first.status(), StatusCode::CREATED);
+        assert!(first.headers().get(REPLAYED_HEADER).is_none());
+        assert_eq!(text(first).await, "1");
+
+        let retry = run_once(&pool, LIMITS, user_id, "key", request("{}"), counted(&calls)).await;
+        assert_eq!(retry.status(), StatusCode::CREATED);
+        assert_eq!(retry.headers()[REPLAYED_HEADER], "true");
+        assert_eq!(text(retry).await, "1");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let reused = run_once(&pool, LIMITS, user_id, "key", request("{\"a\":1}"), counted(&calls)).await;
+        assert_eq!(reused.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(code(reused).await, "idempotency_key_reused");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "..", scripts("schema")))]
+    async fn retry_while_running_is_turned_away(pool: PgPool) {
+        let user_id = user(&pool).await;
+        let (started_tx, started) = oneshot::channel();
+        let (finish, finish_rx) = oneshot::channel::<()>();
+
+        let first = tokio::spawn({
+            let pool = pool.clone();
+            async move {
+                run_once(&pool, LIMITS, user_id, "key", request("{}"), move |_| async move {
+                    started_tx.send(()).unwrap();
+                    finish_rx.await.unwrap();
+                    (StatusCode::CREATED, "first").into_response()
+                })
+                .await
+            }
+        });
+        started.await.unwrap();
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let retry = run_once(&pool, LIMITS, user_id, "key", request("{}"), counted(&calls)).await;
+        assert_eq!(retry.status(), StatusCode::CONFLICT);
+        assert_eq!(code(retry).await, "idempotency_key_in_progress");
+
+        finish.send(()).unwrap();
+        assert_eq!(text(first.await.unwrap()).await, "first");
+        let retry = run_once(&pool, LIMITS, user_id, "key", request("{}"), counted(&calls)).await;
+        assert_eq!(text(retry).await, "first");
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "..", scripts("schema")))]
+    async fn sensitive_responses_arent_replayed(pool: PgPool) {
+        let user_id = user(&pool).await;
+
+        let first = run_once(&pool, LIMITS, user_id, "key", request("{}"), |_| async {
+            let mut response = (StatusCode::OK, "password").into_response();
+            response.extensions_mut().insert(Sensitive);
+            response
+        })
+        .await;
+        assert_eq!(text(first).await, "password");
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let retry = run_once(&pool, LIMITS, user_id, "key", request("{}"), counted(&calls)).await;
+        assert_eq!(retry.status(), StatusCode::CONFLICT);
+        assert_eq!(code(retry).await, "idempotency_response_withheld");
+        let stored: Option<Vec<u8>> = sqlx::query_scalar("SELECT response_body FROM idempotency_keys")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, None);
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "..", scripts("schema")))]
+    async fn server_errors_free_the_key(pool: PgPool) {
+        let user_id = user(&pool).await;
+
+        let first = run_once(&pool, LIMITS, user_id, "key", request("{}"), |_| async {
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        })
+        .await;
+        assert_eq!(first.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let retry = run_once(&pool, LIMITS, user_id, "key", request("{}"), counted(&calls)).await;
+        assert_eq!(retry.status(), StatusCode::CREATED);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "..", scripts("schema")))]
+    async fn oversized_bodies_are_refused_unread(pool: PgPool) {
+        let user_id = user(&pool).await;
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        // no content-length, the size is only known once the body is read
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            for _ in 0..4 {
+                let _ = sender.send_data(Bytes::from(vec![b'a'; 512])).await;
+            }
+        });
+        let request = Request::post("/api/project/new").body(body).unwrap();
+
+        let response = run_once(&pool, LIMITS, user_id, "key", request, counted(&calls)).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(code(response).await, "body_too_large");
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+}
@@ -1,12 +1,42 @@
+pub mod admin;
+pub mod announcements;
 pub mod auth;
+pub mod build_workspace;
+pub mod cli;
 pub mod configuration;
+pub mod daemon_limits;
+pub mod detection;
 pub mod docker;
+pub mod dockerfile_policy;
 pub mod dockerfile_templates;
+pub mod egress;
+pub mod environ;
+pub mod events;
 pub mod get_env;
 pub mod git;
+pub mod hints;
+pub mod idempotency;
+pub mod notifications;
 pub mod owner;
+pub mod placeholder;
+pub mod preflight;
+pub mod project_config;
 pub mod projects;
+pub mod push_checks;
+pub mod push_message;
 pub mod queue;
+pub mod quota;
+pub mod rebuild;
+pub mod redact;
+pub mod routes;
+pub mod scan;
+pub mod schedule;
+pub mod ssh_keys;
+pub mod runtime;
 pub mod startup;
+pub mod system;
 pub mod telemetry;
+pub mod traffic;
+pub mod validation;
 pub mod dashboard;
+pub mod deploy_snapshot;
@@ -1,10 +1,15 @@
+use bollard::Docker;
 use hyper::{client::HttpConnector, Body};
 use pemasak_infra::{
-    configuration,
+    auth::{self, hashing::Hasher, sessions::SessionLimit},
+    build_workspace, cli, configuration, daemon_limits, git, notifications, projects,
+    push_checks::PushChecks,
+    push_message::PushMessage,
     queue::{build_queue_handler, BuildQueue},
-    startup, telemetry,
+    rebuild, routes, schedule, startup,
+    system::admission::Admission,
+    telemetry, traffic,
 };
-use sqlx::postgres::PgPoolOptions;
 use std::{net::TcpListener, path::Path, process};
 use tokio::fs::OpenOptions;
 
@@ -12,6 +17,12 @@ type Client = hyper::client::Client<HttpConnector, Body>;
 
 #[tokio::main]
 async fn main() {
+    let matches = cli::command().get_matches();
+    match matches.subcommand() {
+        None | Some(("serve", _)) => {}
+        _ => process::exit(cli::run(&matches).await),
+    }
+
     telemetry::init_tracing();
     let config = match configuration::get_configuration() {
         Ok(config) => config,
@@ -21,8 +32,16 @@ async fn main() {
         }
     };
 
-    let pool = match PgPoolOptions::new()
-        .acquire_timeout(std::time::Duration::from_secs(config.database.timeout))
+    let sso_config = match config.sso_config() {
+        Ok(sso_config) => sso_config,
+        Err(err) => {
+            tracing::error!(?err, "Invalid sso configuration");
+            process::exit(1);
+        }
+    };
+
+    let pool = match config
+        .pool_options()
         .connect_with(config.connection_options())
         .await
     {
@@ -41,12 +60,99 @@ async fn main() {
 
     // Atlas migration check removed - using schema.sql initialization instead
 
+    // owners created before their names were unique
+    match projects::repo::unique_owner_names(&pool).await {
+        Ok(duplicates) if duplicates.is_empty() => {}
+        Ok(duplicates) => {
+            for duplicate in duplicates {
+                tracing::error!(
+                    name = %duplicate.name,
+                    owner_ids = ?duplicate.owner_ids,
+                    "Owners share a name, merge or rename them"
+                );
+            }
+            process::exit(1);
+        }
+        Err(err) => {
+            tracing::error!(?err, "Failed to check owner names");
+            process::exit(1);
+        }
+    }
+
+    // projects created before container names were stored, the subdomain claims below use them
+    match projects::repo::backfill_container_names(&pool).await {
+        Ok(conflicts) if conflicts.is_empty() => {}
+        Ok(conflicts) => {
+            for conflict in conflicts {
+                tracing::error!(
+                    project_id = %conflict.project_id,
+                    container_name = %conflict.container_name,
+                    reason = %conflict.reason,
+                    "Project can't keep its container name, rename or delete it"
+                );
+            }
+            process::exit(1);
+        }
+        Err(err) => {
+            tracing::error!(?err, "Failed to backfill container names");
+            process::exit(1);
+        }
+    }
+
+    // projects created before subdomains were claimed, and changes to the reserved list
+    match routes::backfill(&pool, &config).await {
+        Ok(conflicts) => {
+            for conflict in conflicts {
+                tracing::warn!(
+                    project_id = %conflict.project_id,
+                    subdomain = %conflict.subdomain,
+                    reason = %conflict.reason,
+                    "Project can't claim its subdomain, its deploys will fail"
+                );
+            }
+        }
+        Err(err) => {
+            tracing::error!(?err, "Failed to backfill subdomain claims");
+            process::exit(1);
+        }
+    }
+
+    if config.cache.ownership {
+        projects::repo::configure_cache(config.cache.ownershipttl);
+    }
+    projects::repo::configure_timeout(config.database.statementtimeout);
+    daemon_limits::configure(&config.docker);
+
     // check docker permissions
     if let Err(err) = tokio::fs::metadata("/var/run/docker.sock").await {
         tracing::error!(?err, "Failed to access docker socket");
         process::exit(1);
     }
 
+    // one client shared by the build queue and the handlers
+    let docker = match Docker::connect_with_local_defaults() {
+        Ok(docker) => docker,
+        Err(err) => {
+            tracing::error!(?err, "Failed to connect to docker");
+            process::exit(1);
+        }
+    };
+
+    if !config.docker.lazy {
+        let timeout = std::time::Duration::from_secs(config.docker.timeout);
+        match tokio::time::timeout(timeout, docker.ping()).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(err)) => {
+                tracing::error!(?err, "Failed to ping docker daemon");
+                process::exit(1);
+            }
+            Err(_) => {
+                tracing::error!("Docker daemon did not answer ping in time");
+                process::exit(1);
+            }
+        }
+    }
+
     // check if git folder exists
     match tokio::fs::metadata(&config.git.base).await {
         Err(err) => {
@@ -85,21 +191,128 @@ async fn main() {
         }
     }
 
-    let (build_queue, build_channel) = BuildQueue::new(config.build.max, pool.clone(), config.clone());
+    let scratch_dir = config.build_scratch_dir();
+    if let Err(err) = build_workspace::prepare(&scratch_dir) {
+        tracing::error!(?err, ?scratch_dir, "Cannot write to the build scratch directory");
+        process::exit(1);
+    }
+
+    let hasher = match Hasher::new(&config.auth.argon2) {
+        Ok(hasher) => hasher,
+        Err(err) => {
+            tracing::error!(?err, "Invalid auth.argon2 configuration");
+            process::exit(1);
+        }
+    };
+
+    let admission = match Admission::new(&config) {
+        Ok(admission) => admission,
+        Err(err) => {
+            tracing::error!(?err, "Invalid admission configuration");
+            process::exit(1);
+        }
+    };
+
+    let push_checks = match PushChecks::new(&config) {
+        Ok(push_checks) => push_checks,
+        Err(err) => {
+            tracing::error!(?err, "Invalid push check configuration");
+            process::exit(1);
+        }
+    };
+    if let Err(err) = push_checks.install_hook() {
+        tracing::error!(?err, "Failed to install the pre-receive hook");
+        process::exit(1);
+    }
+
+    let push_message = match PushMessage::new(&config) {
+        Ok(push_message) => push_message,
+        Err(err) => {
+            tracing::error!(?err, "Invalid push message configuration");
+            process::exit(1);
+        }
+    };
+
+    let (build_queue, build_channel) = BuildQueue::new(config.build.max, pool.clone(), docker.clone(), config.clone(), admission);
 
     tokio::spawn(async move {
         build_queue_handler(build_queue).await;
     });
 
+    tokio::spawn(schedule::run_scheduled_rebuilds(
+        pool.clone(),
+        config.git.base.clone(),
+        build_channel.clone(),
+    ));
+
+    rebuild::resume(pool.clone(), config.git.base.clone(), build_channel.clone()).await;
+
+    tokio::spawn(build_workspace::run_cleanup(
+        scratch_dir,
+        std::time::Duration::from_secs(config.build.scratchttl * 3600),
+    ));
+
+    tokio::spawn(notifications::run_prune(pool.clone(), config.notifications.retention));
+
+    tokio::spawn(auth::sso_tickets::run_purge(pool.clone()));
+
+    tokio::spawn(projects::deletion::run_purge(
+        pool.clone(),
+        docker.clone(),
+        config.git.base.clone(),
+        config.container.prefix.clone(),
+        config.deletion.retention,
+    ));
+
+    if let Some(access_log) = &config.traffic.accesslog {
+        tokio::spawn(traffic::run_ingest(
+            pool.clone(),
+            access_log.clone(),
+            config.container.prefix.clone(),
+            config.traffic.interval,
+        ));
+    }
+
+    if config.git.ssh {
+        tokio::spawn(git::listen_for_pushes(
+            pool.clone(),
+            config.git.base.clone(),
+            build_channel.clone(),
+            config.build.pausemode,
+            config.build.max,
+        ));
+    }
+
     let state = startup::AppState {
         base: config.git.base.clone(),
         git_auth: config.git.auth,
-        sso: config.auth.sso.clone(),
+        sso: config.auth.sso,
+        sso_config,
         client: Client::new(),
         domain: config.domain(),
+        subdomain: config.application.subdomain,
         build_channel,
+        build_max: config.build.max,
+        ssh_url: config.ssh_url(),
+        network: config.network.clone(),
         pool,
+        docker,
         secure: config.application.secure,
+        pause_mode: config.build.pausemode,
+        container_prefix: config.container.prefix.clone(),
+        push_checks,
+        push_message,
+        idempotency_ttl: config.application.idempotencyttl,
+        api_body_limit: config.api_body_limit(),
+        rebuild: config.rebuild,
+        deletion_retention: config.deletion.retention,
+        session_limit: SessionLimit {
+            max: config.max_sessions_per_user(),
+            policy: config.auth.sessionpolicy,
+            lifespan: config.auth.lifespan,
+        },
+        project_quota: config.quota.projects,
+        hasher,
     };
 
     let addr_string = config.address_string();
@@ -0,0 +1,85 @@
+use axum::extract::{Query, State};
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{auth::Auth, notifications::NotificationType, projects::api::error_response, startup::AppState};
+
+/// Most notifications returned at once, the newest ones
+const LIST_LIMIT: i64 = 100;
+
+#[derive(Deserialize, Debug)]
+pub struct NotificationsQuery {
+    /// leave out the ones already read
+    #[serde(default)]
+    unread: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct NotificationItem {
+    id: Uuid,
+    #[serde(rename = "type")]
+    kind: NotificationType,
+    payload: serde_json::Value,
+    created_at: DateTime<Utc>,
+    /// none while unread
+    read_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug)]
+struct NotificationsResponse {
+    /// of all the user's notifications, not only the ones returned
+    unread: i64,
+    notifications: Vec<NotificationItem>,
+}
+
+/// The user's notifications, newest first
+#[tracing::instrument(skip(auth, pool))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Query(NotificationsQuery { unread }): Query<NotificationsQuery>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let notifications = sqlx::query_as!(
+        NotificationItem,
+        r#"SELECT id, type AS "kind: NotificationType", payload, created_at, read_at
+           FROM notifications
+           WHERE user_id = $1
+           AND (NOT $2 OR read_at IS NULL)
+           ORDER BY created_at DESC, id DESC
+           LIMIT $3
+        "#,
+        user.id,
+        unread,
+        LIST_LIMIT,
+    )
+    .fetch_all(&pool);
+
+    let unread_count = sqlx::query_scalar!(
+        r#"SELECT count(*) AS "count!" FROM notifications WHERE user_id = $1 AND read_at IS NULL"#,
+        user.id
+    )
+    .fetch_one(&pool);
+
+    let (notifications, unread) = match tokio::join!(notifications, unread_count) {
+        (Ok(notifications), Ok(unread)) => (notifications, unread),
+        (Err(err), _) | (_, Err(err)) => {
+            tracing::error!(?err, "Can't list notifications: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    let json = serde_json::to_string(&NotificationsResponse { unread, notifications }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
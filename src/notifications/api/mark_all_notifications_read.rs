@@ -0,0 +1,42 @@
+use axum::extract::State;
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::{auth::Auth, projects::api::error_response, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct MarkAllReadResponse {
+    /// notifications that were unread until now
+    marked: u64,
+}
+
+/// Mark every unread notification of the user read
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let marked = match sqlx::query!(
+        "UPDATE notifications SET read_at = now() WHERE user_id = $1 AND read_at IS NULL",
+        user.id,
+    )
+    .execute(&pool)
+    .await
+    {
+        Ok(res) => res.rows_affected(),
+        Err(err) => {
+            tracing::error!(?err, "Can't mark notifications read: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to query database: {}", err));
+        }
+    };
+
+    let json = serde_json::to_string(&MarkAllReadResponse { marked }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,43 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use uuid::Uuid;
+
+use crate::{auth::Auth, projects::api::error_response, startup::AppState};
+
+/// Mark one of the user's notifications read. One read before keeps its first read time.
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path(notification_id): Path<Uuid>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    match sqlx::query!(
+        r#"UPDATE notifications SET read_at = COALESCE(read_at, now())
+           WHERE id = $1 AND user_id = $2
+           RETURNING id
+        "#,
+        notification_id,
+        user.id,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "Notification does not exist".to_string()),
+        Err(err) => {
+            tracing::error!(?err, "Can't mark notification read: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    }
+
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap()
+}
@@ -0,0 +1,17 @@
+use axum::{middleware, Router, routing::{get, post}};
+use axum_extra::routing::RouterExt;
+use hyper::Body;
+
+use crate::{auth::auth, startup::AppState, configuration::Settings};
+
+mod list_notifications;
+mod mark_notification_read;
+mod mark_all_notifications_read;
+
+pub async fn router(_state: AppState, _config: &Settings) -> Router<AppState, Body> {
+    Router::new()
+        .route_with_tsr("/api/user/notifications", get(list_notifications::get))
+        .route_with_tsr("/api/user/notifications/read-all", post(mark_all_notifications_read::post))
+        .route_with_tsr("/api/user/notifications/:notification_id/read", post(mark_notification_read::post))
+        .route_layer(middleware::from_fn(auth))
+}
@@ -0,0 +1,229 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use ulid::Ulid;
+use uuid::Uuid;
+
+pub mod api;
+
+/// How often read notifications past the retention are removed
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// What a notification is about, its payload is shaped by it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "notification_type", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationType {
+    /// `owner`, `project`, `build_id`
+    DeployFailed,
+    /// the new container kept exiting, `owner`, `project`, `build_id`
+    CrashLoop,
+    /// `owner`, `project`, `quota`, `current`, `limit`, `suggestion`
+    QuotaWarning,
+    /// the user was added to a group, `owner`, `added_by`
+    CollaboratorAdded,
+}
+
+/// Notify every member of the owner of a project. Failing to write is logged, the event that
+/// caused it carries on.
+pub async fn notify_project(pool: &PgPool, project_id: Uuid, kind: NotificationType, payload: serde_json::Value) {
+    let members = match sqlx::query_scalar!(
+        r#"SELECT users_owners.user_id
+           FROM projects
+           JOIN users_owners ON projects.owner_id = users_owners.owner_id
+           WHERE projects.id = $1
+        "#,
+        project_id
+    )
+    .fetch_all(pool)
+    .await
+    {
+        Ok(members) => members,
+        Err(err) => {
+            tracing::error!(?err, ?kind, "Can't notify project members: Failed to query database");
+            return;
+        }
+    };
+
+    insert(pool, &members, kind, payload).await;
+}
+
+/// Notify one user, like `notify_project`
+pub async fn notify_user(pool: &PgPool, user_id: Uuid, kind: NotificationType, payload: serde_json::Value) {
+    insert(pool, &[user_id], kind, payload).await;
+}
+
+async fn insert(pool: &PgPool, users: &[Uuid], kind: NotificationType, payload: serde_json::Value) {
+    if users.is_empty() {
+        return;
+    }
+    let ids = users.iter().map(|_| Uuid::from(Ulid::new())).collect::<Vec<_>>();
+
+    if let Err(err) = sqlx::query!(
+        r#"INSERT INTO notifications (id, user_id, type, payload)
+           SELECT id, user_id, $3, $4
+           FROM UNNEST($1::uuid[], $2::uuid[]) AS recipients (id, user_id)
+        "#,
+        &ids,
+        users,
+        kind as NotificationType,
+        payload,
+    )
+    .execute(pool)
+    .await
+    {
+        tracing::error!(?err, ?kind, "Can't notify users: Failed to insert into database");
+    }
+}
+
+/// Remove notifications read more than `retention` days ago, checked every hour
+pub async fn run_prune(pool: PgPool, retention: u64) {
+    loop {
+        tokio::time::sleep(PRUNE_INTERVAL).await;
+
+        match prune(&pool, retention).await {
+            Ok(removed) if removed > 0 => tracing::info!(removed, "Pruned read notifications"),
+            Ok(_) => {}
+            Err(err) => tracing::error!(?err, "Can't prune notifications: Failed to query database"),
+        }
+    }
+}
+
+/// Remove notifications read more than `retention` days ago. Unread ones are kept however old
+/// they are. Returns how many were removed.
+pub async fn prune(pool: &PgPool, retention: u64) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "DELETE FROM notifications WHERE read_at < $1",
+        Utc::now() - chrono::Duration::days(retention as i64),
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(res.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    async fn user(pool: &PgPool, username: &str) -> Uuid {
+        let id = Uuid::new_v4();
+        sqlx::query("INSERT INTO users (id, username, password, name) VALUES ($1, $2, '', $2)")
+            .bind(id)
+            .bind(username)
+            .execute(pool)
+            .await
+            .unwrap();
+        id
+    }
+
+    /// An owner with `members` and a project of it
+    async fn project(pool: &PgPool, owner: &str, members: &[Uuid]) -> Uuid {
+        let owner_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO project_owners (id, name) VALUES ($1, $2)")
+            .bind(owner_id)
+            .bind(owner)
+            .execute(pool)
+            .await
+            .unwrap();
+        for member in members {
+            sqlx::query("INSERT INTO users_owners (user_id, owner_id) VALUES ($1, $2)")
+                .bind(member)
+                .bind(owner_id)
+                .execute(pool)
+                .await
+                .unwrap();
+        }
+
+        let id = Uuid::new_v4();
+        sqlx::query("INSERT INTO projects (id, owner_id, name, container_name) VALUES ($1, $2, 'blog', $3)")
+            .bind(id)
+            .bind(owner_id)
+            .bind(format!("{owner}-blog"))
+            .execute(pool)
+            .await
+            .unwrap();
+        id
+    }
+
+    async fn received(pool: &PgPool, user_id: Uuid) -> Vec<(NotificationType, serde_json::Value)> {
+        sqlx::query_as("SELECT type, payload FROM notifications WHERE user_id = $1 ORDER BY id")
+            .bind(user_id)
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn every_member_of_the_owner_is_notified(pool: PgPool) {
+        let alice = user(&pool, "alice").await;
+        let bob = user(&pool, "bob").await;
+        let carol = user(&pool, "carol").await;
+        let blog = project(&pool, "team", &[alice, bob]).await;
+        project(&pool, "carol", &[carol]).await;
+        let payload = json!({ "owner": "team", "project": "blog" });
+
+        notify_project(&pool, blog, NotificationType::DeployFailed, payload.clone()).await;
+
+        assert_eq!(received(&pool, alice).await, vec![(NotificationType::DeployFailed, payload.clone())]);
+        assert_eq!(received(&pool, bob).await, vec![(NotificationType::DeployFailed, payload)]);
+        assert!(received(&pool, carol).await.is_empty());
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn projects_without_members_notify_nobody(pool: PgPool) {
+        let blog = project(&pool, "alice", &[]).await;
+
+        notify_project(&pool, blog, NotificationType::CrashLoop, json!({})).await;
+
+        let count: i64 = sqlx::query_scalar("SELECT count(*) FROM notifications").fetch_one(&pool).await.unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn a_user_is_notified_alone(pool: PgPool) {
+        let alice = user(&pool, "alice").await;
+        let bob = user(&pool, "bob").await;
+        let payload = json!({ "owner": "team", "added_by": "bob" });
+
+        notify_user(&pool, alice, NotificationType::CollaboratorAdded, payload.clone()).await;
+
+        assert_eq!(received(&pool, alice).await, vec![(NotificationType::CollaboratorAdded, payload)]);
+        assert!(received(&pool, bob).await.is_empty());
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn prune_keeps_unread_and_recently_read_notifications(pool: PgPool) {
+        let alice = user(&pool, "alice").await;
+        for (kind, days_ago, read_days_ago) in [
+            ("deploy_failed", 60, None),
+            ("crash_loop", 60, Some(31)),
+            ("quota_warning", 60, Some(29)),
+        ] {
+            sqlx::query(
+                r#"INSERT INTO notifications (id, user_id, type, payload, created_at, read_at)
+                   VALUES ($1, $2, $3::notification_type, '{}',
+                           now() - make_interval(days => $4), now() - make_interval(days => $5))"#,
+            )
+            .bind(Uuid::new_v4())
+            .bind(alice)
+            .bind(kind)
+            .bind(days_ago)
+            .bind(read_days_ago)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(prune(&pool, 30).await.unwrap(), 1);
+
+        let kinds = received(&pool, alice).await.into_iter().map(|(kind, _)| kind).collect::<Vec<_>>();
+        assert_eq!(kinds.len(), 2);
+        assert!(kinds.contains(&NotificationType::DeployFailed));
+        assert!(kinds.contains(&NotificationType::QuotaWarning));
+    }
+}
@@ -7,6 +7,7 @@ use uuid::Uuid;
 
 use crate::{
     auth::Auth,
+    notifications::{self, NotificationType},
     startup::AppState,
 };
 
@@ -200,6 +201,17 @@ pub async fn post(
             .unwrap();
     }
 
+    notifications::notify_user(
+        &pool,
+        invited_user,
+        NotificationType::CollaboratorAdded,
+        serde_json::json!({
+            "owner_id": owner_id,
+            "added_by": auth.current_user.as_ref().map(|user| user.username.clone()),
+        }),
+    )
+    .await;
+
     Response::builder()
         .status(StatusCode::NO_CONTENT)
         .body(Body::empty())
@@ -3,7 +3,7 @@ use hyper::{Body, StatusCode};
 use leptos::{ssr::render_to_string, view};
 use uuid::Uuid;
 
-use crate::{auth::Auth, startup::AppState};
+use crate::{auth::Auth, projects::repo, startup::AppState};
 
 #[tracing::instrument(skip(auth, pool))]
 pub async fn post(
@@ -77,6 +77,7 @@ pub async fn post(
             .body(Body::from(html))
             .unwrap();
     };
+    repo::invalidate_user(user_id);
 
     Response::builder()
         .status(StatusCode::NO_CONTENT)
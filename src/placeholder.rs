@@ -0,0 +1,292 @@
+use axum::extract::{Host, State};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use sqlx::PgPool;
+
+use crate::{configuration::SubdomainScheme, projects::api::BuildState, startup::AppState};
+
+/// Seconds between reloads of the placeholder page
+const REFRESH_SECS: u32 = 15;
+
+struct PlaceholderProject {
+    owner: String,
+    project: String,
+    status: Option<BuildState>,
+}
+
+/// Find the project served on a subdomain. Under the flat scheme the subdomain is the
+/// container name, with or without `container.prefix` in front. Projects deployed before
+/// container names were stored are found through their domain row.
+async fn project_for(
+    pool: &PgPool,
+    subdomain: &str,
+    scheme: SubdomainScheme,
+    prefix: &str,
+) -> Result<Option<PlaceholderProject>, sqlx::Error> {
+    let project = match scheme {
+        SubdomainScheme::Flat => {
+            sqlx::query!(
+                r#"SELECT projects.id, projects.name AS project, project_owners.name AS owner
+                   FROM projects
+                   JOIN project_owners ON projects.owner_id = project_owners.id
+                   WHERE projects.container_name = $1
+                   OR $2 || projects.container_name = $1
+                   OR projects.id IN (SELECT project_id FROM domains WHERE name = $1)
+                   LIMIT 1
+                "#,
+                subdomain,
+                prefix
+            )
+            .fetch_optional(pool)
+            .await?
+            .map(|record| (record.id, record.owner, record.project))
+        }
+        SubdomainScheme::Owner => {
+            let Some((project, owner)) = subdomain.split_once('.') else {
+                return Ok(None);
+            };
+
+            sqlx::query!(
+                r#"SELECT projects.id, projects.name AS project, project_owners.name AS owner
+                   FROM projects
+                   JOIN project_owners ON projects.owner_id = project_owners.id
+                   WHERE lower(projects.name) = $1
+                   AND lower(project_owners.name) = $2
+                "#,
+                project,
+                owner
+            )
+            .fetch_optional(pool)
+            .await?
+            .map(|record| (record.id, record.owner, record.project))
+        }
+    };
+
+    let Some((id, owner, project)) = project else {
+        return Ok(None);
+    };
+
+    let status = sqlx::query!(
+        r#"SELECT status AS "status: BuildState"
+           FROM builds WHERE project_id = $1
+           ORDER BY created_at DESC
+           LIMIT 1
+        "#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?
+    .map(|record| record.status);
+
+    Ok(Some(PlaceholderProject {
+        owner,
+        project,
+        status,
+    }))
+}
+
+pub(crate) fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn render(project: &PlaceholderProject) -> String {
+    let owner = escape_html(&project.owner);
+    let name = escape_html(&project.project);
+    let message = match project.status {
+        None => "This project hasn't been deployed yet. Push to its repository to deploy it.",
+        Some(BuildState::PENDING) | Some(BuildState::BUILDING) => {
+            "This project is being deployed, the page reloads once it is up."
+        }
+        Some(BuildState::FAILED) => "The last deploy of this project failed, check the build log on the dashboard.",
+        Some(BuildState::CANCELLED) => "The last deploy of this project was cancelled. Push to its repository to deploy it.",
+        Some(BuildState::SUCCESSFUL) => "This project is starting or has been stopped.",
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta http-equiv="refresh" content="{REFRESH_SECS}">
+<title>{owner}/{name}</title>
+</head>
+<body style="font-family: sans-serif; text-align: center; margin-top: 15vh">
+<h1>{owner}/{name}</h1>
+<p>{message}</p>
+<img src="/api/project/{owner}/{name}/badge/status" alt="build status">
+</body>
+</html>
+"#
+    )
+}
+
+/// Served by Traefik's catch-all router for project hosts that have no running container, so
+/// new and failed projects show their status instead of a bad gateway
+pub async fn get(
+    State(AppState {
+        pool,
+        domain,
+        subdomain,
+        container_prefix,
+        ..
+    }): State<AppState>,
+    Host(hostname): Host,
+) -> Response<Body> {
+    let host = hostname.split(':').next().unwrap_or(&hostname);
+    let domain = domain.split(':').next().unwrap_or(&domain);
+
+    let project = match host.strip_suffix(domain).and_then(|host| host.strip_suffix('.')) {
+        Some(name) if !name.is_empty() => project_for(&pool, name, subdomain, &container_prefix).await,
+        _ => Ok(None),
+    };
+
+    let project = match project {
+        Ok(Some(project)) => project,
+        Ok(None) => {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty())
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't render placeholder: Failed to query database");
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::empty())
+                .unwrap();
+        }
+    };
+
+    Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .header("Retry-After", REFRESH_SECS.to_string())
+        .body(Body::from(render(&project)))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn project(status: Option<BuildState>) -> PlaceholderProject {
+        PlaceholderProject {
+            owner: "alice".to_string(),
+            project: "blog".to_string(),
+            status,
+        }
+    }
+
+    #[test]
+    fn render_explains_every_state() {
+        let pages = [
+            (None, "hasn't been deployed yet"),
+            (Some(BuildState::PENDING), "is being deployed"),
+            (Some(BuildState::BUILDING), "is being deployed"),
+            (Some(BuildState::FAILED), "failed"),
+            (Some(BuildState::CANCELLED), "was cancelled"),
+            (Some(BuildState::SUCCESSFUL), "starting or has been stopped"),
+        ];
+
+        for (status, message) in pages {
+            let page = render(&project(status));
+            assert!(page.contains(message), "{page}");
+            assert!(page.contains("<h1>alice/blog</h1>"), "{page}");
+            assert!(page.contains(&format!("content=\"{REFRESH_SECS}\"")), "{page}");
+        }
+    }
+
+    #[test]
+    fn render_escapes_names() {
+        let page = render(&PlaceholderProject {
+            owner: "<script>".to_string(),
+            project: "a\"&b".to_string(),
+            status: None,
+        });
+
+        assert!(!page.contains("<script>"), "{page}");
+        assert!(page.contains("<h1>&lt;script&gt;/a&quot;&amp;b</h1>"), "{page}");
+    }
+
+    /// `alice/blog` under the container name `alice-blog`, with a failed build after a successful one
+    async fn blog(pool: &PgPool) -> Uuid {
+        let (owner_id, project_id) = (Uuid::new_v4(), Uuid::new_v4());
+        sqlx::query("INSERT INTO project_owners (id, name) VALUES ($1, 'alice')")
+            .bind(owner_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO projects (id, owner_id, name, container_name) VALUES ($1, $2, 'blog', 'alice-blog')")
+            .bind(project_id)
+            .bind(owner_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        for (status, age) in [("successful", 2), ("failed", 1)] {
+            sqlx::query(
+                r#"INSERT INTO builds (id, project_id, status, created_at)
+                   VALUES ($1, $2, $3::build_state, now() - make_interval(hours => $4))
+                "#,
+            )
+            .bind(Uuid::new_v4())
+            .bind(project_id)
+            .bind(status)
+            .bind(age)
+            .execute(pool)
+            .await
+            .unwrap();
+        }
+
+        project_id
+    }
+
+    async fn found(pool: &PgPool, subdomain: &str, scheme: SubdomainScheme, prefix: &str) -> Option<String> {
+        project_for(pool, subdomain, scheme, prefix)
+            .await
+            .unwrap()
+            .map(|project| format!("{}/{} {:?}", project.owner, project.project, project.status))
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "..", scripts("schema")))]
+    async fn flat_hosts_are_container_names(pool: PgPool) {
+        blog(&pool).await;
+        let blog = Some("alice/blog Some(FAILED)".to_string());
+
+        assert_eq!(found(&pool, "alice-blog", SubdomainScheme::Flat, "").await, blog);
+        assert_eq!(found(&pool, "alice-blog", SubdomainScheme::Flat, "pws-").await, blog);
+        assert_eq!(found(&pool, "pws-alice-blog", SubdomainScheme::Flat, "pws-").await, blog);
+        assert_eq!(found(&pool, "pws-alice-blog", SubdomainScheme::Flat, "").await, None);
+        assert_eq!(found(&pool, "alice", SubdomainScheme::Flat, "").await, None);
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "..", scripts("schema")))]
+    async fn flat_hosts_of_legacy_domains(pool: PgPool) {
+        let project_id = blog(&pool).await;
+        sqlx::query("INSERT INTO domains (id, project_id, name, port, docker_ip) VALUES ($1, $2, 'old-blog', 80, '')")
+            .bind(Uuid::new_v4())
+            .bind(project_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert!(found(&pool, "old-blog", SubdomainScheme::Flat, "").await.is_some());
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "..", scripts("schema")))]
+    async fn owner_hosts_are_project_then_owner(pool: PgPool) {
+        blog(&pool).await;
+
+        assert_eq!(
+            found(&pool, "blog.alice", SubdomainScheme::Owner, "").await,
+            Some("alice/blog Some(FAILED)".to_string())
+        );
+        assert_eq!(found(&pool, "alice.blog", SubdomainScheme::Owner, "").await, None);
+        assert_eq!(found(&pool, "alice-blog", SubdomainScheme::Owner, "").await, None);
+    }
+}
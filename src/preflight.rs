@@ -0,0 +1,114 @@
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::docker::detect_framework;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Finding {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// Result of checking a checkout for common misconfigurations before spending minutes on a
+/// build that is bound to fail
+#[derive(Serialize, Debug, Default)]
+pub struct Preflight {
+    pub framework: Option<&'static str>,
+    pub findings: Vec<Finding>,
+}
+
+impl Preflight {
+    pub fn has_errors(&self) -> bool {
+        self.findings
+            .iter()
+            .any(|finding| finding.severity == Severity::Error)
+    }
+
+    fn push(&mut self, severity: Severity, code: &'static str, message: impl Into<String>) {
+        self.findings.push(Finding {
+            severity,
+            code,
+            message: message.into(),
+        });
+    }
+}
+
+impl std::fmt::Display for Preflight {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for finding in &self.findings {
+            let severity = match finding.severity {
+                Severity::Error => "ERROR",
+                Severity::Warning => "WARNING",
+            };
+            writeln!(f, "{severity}: {}", finding.message)?;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn check(container_src: &str) -> Preflight {
+    let root = Path::new(container_src);
+    let mut preflight = Preflight {
+        framework: detect_framework(container_src),
+        ..Default::default()
+    };
+
+    let is_empty = match std::fs::read_dir(root) {
+        Ok(entries) => !entries
+            .filter_map(|entry| entry.ok())
+            .any(|entry| entry.file_name() != ".git"),
+        Err(_) => true,
+    };
+    if is_empty {
+        preflight.push(Severity::Error, "empty", "Repository is empty, push some code first");
+        return preflight;
+    }
+
+    // the project brings its own build, nothing we can check
+    if root.join("Dockerfile").exists() {
+        return preflight;
+    }
+
+    // without a Dockerfile the django template is used
+    if !root.join("manage.py").exists() {
+        preflight.push(
+            Severity::Error,
+            "missing_manage_py",
+            "manage.py not found, add a Dockerfile if this is not a Django project",
+        );
+    }
+
+    match std::fs::read_to_string(root.join("requirements.txt")) {
+        Ok(requirements) => {
+            let has_gunicorn = requirements.lines().any(|line| {
+                line.trim()
+                    .to_ascii_lowercase()
+                    .starts_with("gunicorn")
+            });
+            if !has_gunicorn {
+                preflight.push(
+                    Severity::Warning,
+                    "missing_gunicorn",
+                    "gunicorn is not listed in requirements.txt",
+                );
+            }
+        }
+        Err(_) => preflight.push(
+            Severity::Error,
+            "missing_requirements",
+            "requirements.txt not found",
+        ),
+    }
+
+    preflight
+}
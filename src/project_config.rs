@@ -0,0 +1,121 @@
+use std::{net::IpAddr, path::Path};
+
+use config::{Config, ConfigError, FileFormat};
+use serde::Deserialize;
+
+use crate::dockerfile_templates::BaseImage;
+
+/// Per project build options read from `.pws.toml` in the root of the repository
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ProjectConfig {
+    /// command that runs once in a throwaway container after the image is built and before the
+    /// new container serves traffic. A non zero exit code aborts the deploy
+    pub release: Option<String>,
+    /// run `collectstatic` while building the generated Django image
+    #[serde(default)]
+    pub collectstatic: bool,
+    /// keep the generated Dockerfile next to the checkout instead of deleting it after the
+    /// build, for debugging
+    #[serde(default)]
+    pub keep_dockerfile: bool,
+    /// base of the generated Python image, `alpine` or `slim`
+    #[serde(default)]
+    pub base: BaseImage,
+    /// generate a single stage image that keeps the build dependencies, to debug packages that
+    /// need them at runtime
+    #[serde(default)]
+    pub single_stage: bool,
+    /// distribution packages installed only in the builder stage of the generated image,
+    /// e.g. `libpq-dev` for the headers psycopg2 builds against
+    #[serde(default)]
+    pub build_packages: Vec<String>,
+    /// command of a long running process started next to the web container from the same
+    /// image, e.g. a celery worker. Defaults to the `worker` entry of the Procfile
+    pub worker: Option<String>,
+    /// number of worker containers, capped by the server
+    pub workers: Option<usize>,
+    /// resolve `${VAR}` references between the project's environment variables before they
+    /// are passed to the build and the containers
+    #[serde(default)]
+    pub interpolate_env: bool,
+    /// DNS servers of the project's containers, replacing the server's
+    #[serde(default)]
+    pub dns: Vec<String>,
+    /// `host:ip` entries added to `/etc/hosts` of the project's containers
+    #[serde(default)]
+    pub extra_hosts: Vec<String>,
+}
+
+impl ProjectConfig {
+    pub const FILE_NAME: &'static str = ".pws.toml";
+    /// Kept Dockerfile, written to the directory holding the checkout so it isn't part of the
+    /// build context
+    pub const KEPT_DOCKERFILE_NAME: &'static str = "Dockerfile.generated";
+
+    pub fn load(container_src: &str) -> Result<Self, ConfigError> {
+        let path = Path::new(container_src).join(Self::FILE_NAME);
+        let mut project_config = match path.is_file() {
+            true => {
+                let contents = std::fs::read_to_string(&path).map_err(|err| ConfigError::Foreign(Box::new(err)))?;
+                Self::parse(&contents)?
+            }
+            false => Self::default(),
+        };
+
+        if project_config.worker.is_none() {
+            project_config.worker = procfile_entry(container_src, "worker");
+        }
+
+        Ok(project_config)
+    }
+
+    /// Options from the contents of a `.pws.toml`, e.g. one read from a commit rather than the
+    /// checkout. The Procfile is left to `load`
+    pub fn parse(contents: &str) -> Result<Self, ConfigError> {
+        let project_config = Config::builder()
+            .add_source(config::File::from_str(contents, FileFormat::Toml))
+            .build()?
+            .try_deserialize::<ProjectConfig>()?;
+
+        if let Some(server) = project_config.dns.iter().find(|server| server.parse::<IpAddr>().is_err()) {
+            return Err(ConfigError::Message(format!("dns: {server} is not an ip address")));
+        }
+        if let Some(host) = project_config.extra_hosts.iter().find(|host| !is_extra_host(host)) {
+            return Err(ConfigError::Message(format!("extra_hosts: {host} is not like host:ip")));
+        }
+        if let Some(package) = project_config.build_packages.iter().find(|package| !is_package_name(package)) {
+            return Err(ConfigError::Message(format!("build_packages: {package} is not a package name")));
+        }
+
+        Ok(project_config)
+    }
+}
+
+/// `host:ip` as docker takes it, the ip may be v6 so only the first colon separates
+fn is_extra_host(entry: &str) -> bool {
+    entry
+        .split_once(':')
+        .is_some_and(|(host, ip)| !host.is_empty() && ip.parse::<IpAddr>().is_ok())
+}
+
+/// Name of an apk or apt package, optionally with a version. It ends up in a shell command of
+/// the generated Dockerfile, so nothing else is let through
+pub fn is_package_name(package: &str) -> bool {
+    package.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && package
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '+' | '-' | '=' | ':' | '~'))
+}
+
+/// Command of a process in the `Procfile`, whose lines look like `name: command`
+fn procfile_entry(container_src: &str, name: &str) -> Option<String> {
+    let contents = std::fs::read_to_string(Path::new(container_src).join("Procfile")).ok()?;
+
+    contents
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .filter_map(|line| line.split_once(':'))
+        .find(|(process, _)| process.trim() == name)
+        .map(|(_, command)| command.trim().to_string())
+        .filter(|command| !command.is_empty())
+}
@@ -0,0 +1,108 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use garde::Validate;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{
+    auth::Auth,
+    projects::{api::{error_response, lookup_error}, repo},
+    ssh_keys::{self, PublicKey},
+    startup::AppState,
+    validation::ValidJson,
+};
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct AddSshKeyRequest {
+    /// defaults to the comment of the key
+    #[garde(length(max = 100))]
+    pub name: Option<String>,
+    /// a line of `~/.ssh/id_ed25519.pub` or similar
+    #[garde(length(min = 1))]
+    pub public_key: String,
+}
+
+#[derive(Serialize, Debug)]
+struct AddSshKeyResponse {
+    id: Uuid,
+    name: String,
+    fingerprint: String,
+    clone_url: Option<String>,
+}
+
+/// Register a public key that can push to and fetch the project over ssh
+#[tracing::instrument(skip(auth, pool, req))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, ssh_url, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+    ValidJson(req): ValidJson<AddSshKeyRequest>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let key = match PublicKey::parse(&req.public_key) {
+        Ok(key) => key,
+        Err(err) => return error_response(StatusCode::BAD_REQUEST, err.to_string()),
+    };
+    let fingerprint = key.fingerprint();
+    let name = req
+        .name
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .or(key.comment.clone())
+        .unwrap_or_else(|| fingerprint.clone());
+
+    let project_record = match repo::find_owned(&pool, user.id, &owner, &project).await {
+        Ok(Some(record)) => record,
+        Ok(None) => {
+            return error_response(StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE.to_string())
+        }
+        Err(err) => return lookup_error(err),
+    };
+
+    let id = Uuid::from(Ulid::new());
+    match sqlx::query!(
+        r#"INSERT INTO ssh_keys (id, project_id, name, key_type, public_key, fingerprint)
+           VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        id,
+        project_record.id,
+        name,
+        key.kind,
+        key.data,
+        fingerprint,
+    )
+    .execute(&pool)
+    .await
+    {
+        Ok(_) => {}
+        Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {
+            return error_response(
+                StatusCode::CONFLICT,
+                "Key is already registered on this project".to_string(),
+            );
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't add ssh key: Failed to insert into database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to insert into database: {}", err),
+            );
+        }
+    }
+
+    let json = serde_json::to_string(&AddSshKeyResponse {
+        id,
+        name,
+        fingerprint,
+        clone_url: ssh_url.map(|base| ssh_keys::clone_url(&base, &owner, &project)),
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
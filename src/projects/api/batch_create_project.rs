@@ -0,0 +1,441 @@
+use std::collections::{HashMap, HashSet};
+
+use axum::{extract::State, response::Response};
+use garde::Validate;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use super::create_project::{clone_command, generate_token};
+use crate::{
+    auth::{hashing::Hasher, Auth},
+    configuration::SubdomainScheme,
+    docker::{container_name_for, subdomain_for},
+    idempotency,
+    projects::{api::error_response, deletion},
+    routes::{self, ClaimError},
+    startup::AppState,
+    validation::ValidJson,
+};
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct BatchCreateProjectRequest {
+    #[garde(length(min = 1))]
+    pub owner: String,
+    /// names are checked one by one, an invalid one fails only itself
+    #[garde(length(min = 1))]
+    pub projects: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "lowercase")]
+enum ProjectStatus {
+    Created,
+    Conflict,
+    Invalid,
+}
+
+#[derive(Serialize, Debug)]
+struct ProjectResult {
+    project_name: String,
+    status: ProjectStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    domain: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    git_password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clone_command: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct BatchCreateProjectResponse {
+    owner_name: String,
+    git_username: String,
+    created: usize,
+    results: Vec<ProjectResult>,
+}
+
+impl ProjectResult {
+    fn rejected(project_name: String, status: ProjectStatus, message: &str) -> Self {
+        Self {
+            project_name,
+            status,
+            message: Some(message.to_string()),
+            id: None,
+            domain: None,
+            git_password: None,
+            clone_command: None,
+        }
+    }
+}
+
+/// Create several projects under one owner at once. Invalid and already existing names are
+/// reported per project, every other project is created in a single transaction.
+#[tracing::instrument(skip(auth, pool, base, domain))]
+pub async fn post(
+    auth: Auth,
+    State(AppState {
+        pool, base, domain, secure, subdomain, deletion_retention, hasher, ..
+    }): State<AppState>,
+    ValidJson(BatchCreateProjectRequest { owner, projects }): ValidJson<BatchCreateProjectRequest>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    // check if the caller is a member of the owner
+    let owner_id = match sqlx::query!(
+        r#"SELECT project_owners.id
+           FROM project_owners
+           JOIN users_owners ON project_owners.id = users_owners.owner_id
+           WHERE project_owners.name = $1
+           AND users_owners.user_id = $2
+           AND project_owners.deleted_at IS NULL
+        "#,
+        owner,
+        user.id,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(data)) => data.id,
+        Ok(None) => {
+            return error_response(StatusCode::BAD_REQUEST, "Owner does not exist".to_string());
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get project_owners: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database {}", err),
+            );
+        }
+    };
+
+    let mut results = match create(&pool, &hasher, owner_id, &owner, projects, subdomain, deletion_retention).await {
+        Ok(results) => results,
+        Err(response) => return response,
+    };
+
+    let protocol = match secure {
+        true => "https",
+        false => "http",
+    };
+
+    // the rows are committed at this point, a missing repo only affects that one project
+    for result in results.iter_mut() {
+        if !matches!(result.status, ProjectStatus::Created) {
+            continue;
+        }
+
+        let project = &result.project_name;
+        result.domain = Some(format!("{protocol}://{domain}/{owner}/{project}"));
+        if let Some(token) = &result.git_password {
+            result.clone_command = Some(clone_command(secure, &domain, &owner, project, token));
+        }
+
+        let path = format!("{base}/{owner}/{project}.git");
+        if let Err(err) = git2::Repository::init_bare(path) {
+            tracing::error!(?err, "Can't create project: Failed to create repo");
+            result.message = Some(format!("Failed to create repository: {}", err));
+        }
+    }
+
+    let json = serde_json::to_string(&BatchCreateProjectResponse {
+        owner_name: owner,
+        git_username: user.username,
+        created: results
+            .iter()
+            .filter(|result| matches!(result.status, ProjectStatus::Created))
+            .count(),
+        results,
+    })
+    .unwrap();
+
+    // holds the git passwords, see idempotency::Sensitive
+    Response::builder()
+        .status(StatusCode::OK)
+        .extension(idempotency::Sensitive)
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Create the valid and free names of `projects` under the owner in one transaction, each
+/// with its git password. The others are reported as invalid or conflicting.
+async fn create(
+    pool: &PgPool,
+    hasher: &Hasher,
+    owner_id: Uuid,
+    owner: &str,
+    projects: Vec<String>,
+    subdomain: SubdomainScheme,
+    deletion_retention: u64,
+) -> Result<Vec<ProjectResult>, Response<Body>> {
+    let existing = match sqlx::query!(
+        r#"SELECT name, deleted_at FROM projects WHERE owner_id = $1 AND name = ANY($2)"#,
+        owner_id,
+        &projects,
+    )
+    .fetch_all(pool)
+    .await
+    {
+        Ok(rows) => rows.into_iter().map(|row| (row.name, row.deleted_at)).collect::<HashMap<_, _>>(),
+        Err(err) => {
+            tracing::error!(?err, "Can't get projects: Failed to query database");
+            return Err(error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database {}", err),
+            ));
+        }
+    };
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(err) => {
+            tracing::error!(?err, "Can't create projects: Failed to begin transaction");
+            return Err(error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to begin transaction {}", err),
+            ));
+        }
+    };
+
+    let mut seen = HashSet::new();
+    let mut results = Vec::with_capacity(projects.len());
+
+    for project in projects {
+        // same rule as garde's alphanumeric on the single create request
+        if project.is_empty() || !project.chars().all(char::is_alphanumeric) {
+            results.push(ProjectResult::rejected(
+                project,
+                ProjectStatus::Invalid,
+                "Project name can only contain alphanumeric characters",
+            ));
+            continue;
+        }
+
+        if let Some(Some(deleted_at)) = existing.get(&project) {
+            let message = deletion::name_held_message(*deleted_at, deletion_retention);
+            results.push(ProjectResult::rejected(project, ProjectStatus::Conflict, &message));
+            continue;
+        }
+        if existing.contains_key(&project) || !seen.insert(project.clone()) {
+            results.push(ProjectResult::rejected(
+                project,
+                ProjectStatus::Conflict,
+                "Project already exists",
+            ));
+            continue;
+        }
+
+        let container_name = container_name_for(owner, &project);
+        let project_subdomain = subdomain_for(owner, &project, &container_name, subdomain);
+        match routes::check_subdomain(&mut tx, None, &project_subdomain).await {
+            Ok(()) => {}
+            Err(ClaimError::Database(err)) => {
+                tracing::error!(?err, "Can't create projects: Failed to check subdomain");
+                if let Err(err) = tx.rollback().await {
+                    tracing::error!(?err, "Can't create projects: Failed to rollback transaction");
+                }
+
+                return Err(error_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to query database {}", err),
+                ));
+            }
+            Err(err) => {
+                results.push(ProjectResult::rejected(
+                    project,
+                    ProjectStatus::Conflict,
+                    &format!("Can't use the project's address: {err}"),
+                ));
+                continue;
+            }
+        }
+
+        let project_id = match sqlx::query!(
+            r#"INSERT INTO projects (id, name, owner_id, container_name) VALUES ($1, $2, $3, $4) RETURNING id"#,
+            Uuid::from(Ulid::new()),
+            project,
+            owner_id,
+            container_name,
+        )
+        .fetch_one(&mut *tx)
+        .await
+        {
+            Ok(data) => data.id,
+            Err(err) => {
+                tracing::error!(?err, "Can't insert projects: Failed to insert into database");
+                if let Err(err) = tx.rollback().await {
+                    tracing::error!(?err, "Can't insert projects: Failed to rollback transaction");
+                }
+
+                return Err(error_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to create project {project}"),
+                ));
+            }
+        };
+
+        // checked above, only fails when another request claimed it in the meantime
+        if let Err(err) = routes::claim_subdomain(&mut tx, project_id, &project_subdomain).await {
+            tracing::error!(?err, "Can't create projects: Failed to claim subdomain");
+            if let Err(err) = tx.rollback().await {
+                tracing::error!(?err, "Can't create projects: Failed to rollback transaction");
+            }
+
+            return Err(error_response(
+                StatusCode::CONFLICT,
+                format!("Can't use the address of project {project}: {err}"),
+            ));
+        }
+
+        let (token, hash) = match generate_token(hasher) {
+            Ok(token) => token,
+            Err(err) => {
+                tracing::error!(?err, "Can't create project: Failed to hash token");
+                if let Err(err) = tx.rollback().await {
+                    tracing::error!(?err, "Can't create projects: Failed to rollback transaction");
+                }
+
+                return Err(error_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to generate token {}", err),
+                ));
+            }
+        };
+
+        if let Err(err) = sqlx::query!(
+            "INSERT INTO api_token (id, project_id, token) VALUES ($1, $2, $3)",
+            Uuid::from(Ulid::new()),
+            project_id,
+            hash,
+        )
+        .execute(&mut *tx)
+        .await
+        {
+            tracing::error!(?err, "Can't insert api_token: Failed to insert into database");
+            if let Err(err) = tx.rollback().await {
+                tracing::error!(?err, "Can't insert api_token: Failed to rollback transaction");
+            }
+
+            return Err(error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to insert into database {}", err),
+            ));
+        }
+
+        results.push(ProjectResult {
+            project_name: project,
+            status: ProjectStatus::Created,
+            message: None,
+            id: Some(project_id),
+            domain: None,
+            git_password: Some(token),
+            clone_command: None,
+        });
+    }
+
+    if let Err(err) = tx.commit().await {
+        tracing::error!(?err, "Can't create projects: Failed to commit transaction");
+        return Err(error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to commit transaction: {}", err),
+        ));
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::Argon2Settings;
+
+    fn hasher() -> Hasher {
+        Hasher::new(&Argon2Settings {
+            memory: 16,
+            iterations: 2,
+            parallelism: 1,
+        })
+        .unwrap()
+    }
+
+    async fn owner(pool: &PgPool) -> Uuid {
+        let owner_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO project_owners (id, name) VALUES ($1, 'alice')")
+            .bind(owner_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO projects (id, owner_id, name, container_name) VALUES ($1, $2, 'blog', 'alice-blog')")
+            .bind(Uuid::new_v4())
+            .bind(owner_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        owner_id
+    }
+
+    async fn create_batch(pool: &PgPool, hasher: &Hasher, owner_id: Uuid, projects: &[&str]) -> Vec<ProjectResult> {
+        let projects = projects.iter().map(|project| project.to_string()).collect();
+        create(pool, hasher, owner_id, "alice", projects, SubdomainScheme::Flat, 24)
+            .await
+            .map_err(|response| response.status())
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../../..", scripts("schema")))]
+    async fn mixed_batch_creates_only_the_valid_new_names(pool: PgPool) {
+        let (owner_id, hasher) = (owner(&pool).await, hasher());
+
+        let results = create_batch(&pool, &hasher, owner_id, &["shop", "blog", "my-site", "wiki", "shop"]).await;
+
+        let statuses = results
+            .iter()
+            .map(|result| (result.project_name.as_str(), serde_json::to_value(&result.status).unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            vec![
+                ("shop", "created".into()),
+                ("blog", "conflict".into()),
+                ("my-site", "invalid".into()),
+                ("wiki", "created".into()),
+                ("shop", "conflict".into()),
+            ]
+        );
+        assert_eq!(results[1].message.as_deref(), Some("Project already exists"));
+        assert!(results[2].id.is_none() && results[2].git_password.is_none());
+
+        let names: Vec<String> = sqlx::query_scalar("SELECT name FROM projects ORDER BY name")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(names, vec!["blog", "shop", "wiki"]);
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../../..", scripts("schema")))]
+    async fn created_projects_get_their_git_password(pool: PgPool) {
+        let (owner_id, hasher) = (owner(&pool).await, hasher());
+
+        let results = create_batch(&pool, &hasher, owner_id, &["shop"]).await;
+
+        let stored: String = sqlx::query_scalar("SELECT token FROM api_token WHERE project_id = $1")
+            .bind(results[0].id.unwrap())
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        let password = results[0].git_password.as_deref().unwrap();
+        assert!(hasher.verify(password.as_bytes(), &stored).is_valid());
+
+        let container_name: String = sqlx::query_scalar("SELECT container_name FROM projects WHERE name = 'shop'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(container_name, "alice-shop");
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+
+use axum::extract::{State, Path};
+use axum::http::HeaderMap;
+use axum::response::Response;
+use garde::Validate;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{auth::Auth, projects::{api::{error_response, lookup_error}, repo}, startup::AppState, validation::{self, ValidJson}};
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct BulkUpdateProjectEnvironRequest {
+    #[garde(custom(validation::environs))]
+    pub environs: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Debug)]
+struct BulkUpdateProjectEnvironResponse {
+    updated: usize,
+    keys: Vec<String>,
+    version: i64,
+}
+
+/// Parse the environs version from an `If-Match: "<version>"` header
+fn if_match_version(headers: &HeaderMap) -> Option<Result<i64, ()>> {
+    headers.get("If-Match").map(|value| {
+        value
+            .to_str()
+            .map_err(|_| ())?
+            .trim()
+            .trim_start_matches("W/")
+            .trim_matches('"')
+            .parse::<i64>()
+            .map_err(|_| ())
+    })
+}
+
+#[tracing::instrument(skip(auth, pool, req))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+    headers: HeaderMap,
+    ValidJson(req): ValidJson<BulkUpdateProjectEnvironRequest>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    // the version read from the env endpoint, so concurrent edits don't overwrite each other
+    let version = match if_match_version(&headers) {
+        Some(Ok(version)) => version,
+        Some(Err(_)) => {
+            return error_response(StatusCode::BAD_REQUEST, "If-Match must be the version of the environment variables".to_string());
+        }
+        None => {
+            return error_response(StatusCode::PRECONDITION_REQUIRED, "If-Match header is required".to_string());
+        }
+    };
+
+    // check if project exist
+    let project = match repo::find_owned(&pool, user.id, &owner, &project).await {
+        Ok(Some(record)) => record,
+        Ok(None) => {
+            return error_response(StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE.to_string());
+        }
+        Err(err) => return lookup_error(err),
+    };
+
+    let version = match merge(&pool, project.id, version, &req.environs).await {
+        Ok(Some(version)) => version,
+        Ok(None) => {
+            return error_response(StatusCode::CONFLICT, "Environment variables were changed in the meantime, reload and try again".to_string());
+        }
+        Err(err) => {
+            tracing::error!(
+                ?err,
+                "Can't update project environs: Failed to insert into database"
+            );
+
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to insert into database".to_string());
+        }
+    };
+
+    updated_response(&req.environs, version)
+}
+
+/// Write every variable or none. A single statement, so the version check can't race with
+/// another write. None when the environs aren't at `version` anymore.
+async fn merge(
+    pool: &PgPool,
+    project_id: Uuid,
+    version: i64,
+    environs: &BTreeMap<String, String>,
+) -> Result<Option<i64>, sqlx::Error> {
+    let environs = serde_json::to_value(environs).unwrap();
+
+    let record = sqlx::query!(
+        r#"UPDATE projects
+            SET environs = projects.environs || $1,
+                environs_version = projects.environs_version + 1
+            WHERE id = $2
+            AND environs_version = $3
+            RETURNING environs_version
+        "#,
+        environs,
+        project_id,
+        version,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record.map(|record| record.environs_version))
+}
+
+/// The keys written and the new version, which is also the ETag for the next update
+fn updated_response(environs: &BTreeMap<String, String>, version: i64) -> Response<Body> {
+    let keys = environs.keys().cloned().collect::<Vec<_>>();
+    let json = serde_json::to_string(&BulkUpdateProjectEnvironResponse {
+        updated: keys.len(),
+        keys,
+        version,
+    }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("ETag", format!("\"{version}\""))
+        .body(Body::from(json))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn json<B>(response: Response<B>) -> serde_json::Value
+    where
+        B: hyper::body::HttpBody,
+        B::Error: std::fmt::Debug,
+    {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// Validates like the endpoint and answers with the summary of version 1
+    async fn submit(body: serde_json::Value) -> axum::response::Response {
+        let app = Router::new().route(
+            "/",
+            routing::post(|ValidJson(req): ValidJson<BulkUpdateProjectEnvironRequest>| async move {
+                updated_response(&req.environs, 1)
+            }),
+        );
+        let request = hyper::Request::post("/")
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        app.oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn summary_lists_the_updated_keys() {
+        let response = submit(serde_json::json!({ "environs": { "DEBUG": "false", "ALLOWED_HOSTS": "*" } })).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["ETag"], "\"1\"");
+        assert_eq!(
+            json(response).await,
+            serde_json::json!({ "updated": 2, "keys": ["ALLOWED_HOSTS", "DEBUG"], "version": 1 })
+        );
+    }
+
+    #[tokio::test]
+    async fn every_invalid_key_is_named() {
+        let response = submit(serde_json::json!({ "environs": { "1ST": "x", "DEBUG": "false", "EMPTY": "" } })).await;
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = json(response).await;
+        assert_eq!(body["errors"][0]["field"], "environs");
+        let message = body["errors"][0]["message"].as_str().unwrap();
+        let errors = message.split("; ").collect::<Vec<_>>();
+        assert_eq!(errors.len(), 2, "{message}");
+        assert!(errors[0].starts_with("1ST: "), "{message}");
+        assert_eq!(errors[1], "EMPTY: Value cannot be empty");
+    }
+
+    async fn project(pool: &PgPool) -> Uuid {
+        let (owner_id, project_id) = (Uuid::new_v4(), Uuid::new_v4());
+        sqlx::query("INSERT INTO project_owners (id, name) VALUES ($1, 'alice')")
+            .bind(owner_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query(
+            r#"INSERT INTO projects (id, owner_id, name, container_name, environs)
+               VALUES ($1, $2, 'blog', 'alice-blog', '{"SECRET_KEY": "secret", "DEBUG": "true"}')"#,
+        )
+        .bind(project_id)
+        .bind(owner_id)
+        .execute(pool)
+        .await
+        .unwrap();
+        project_id
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../../..", scripts("schema")))]
+    async fn merge_keeps_the_other_keys(pool: PgPool) {
+        let project_id = project(&pool).await;
+        let environs = BTreeMap::from([("DEBUG".to_string(), "false".to_string())]);
+
+        assert_eq!(merge(&pool, project_id, 0, &environs).await.unwrap(), Some(1));
+
+        let stored: serde_json::Value = sqlx::query_scalar("SELECT environs FROM projects")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, serde_json::json!({ "SECRET_KEY": "secret", "DEBUG": "false" }));
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../../..", scripts("schema")))]
+    async fn merge_at_an_old_version_writes_nothing(pool: PgPool) {
+        let project_id = project(&pool).await;
+        let environs = BTreeMap::from([("DEBUG".to_string(), "false".to_string())]);
+        merge(&pool, project_id, 0, &environs).await.unwrap();
+
+        let environs = BTreeMap::from([("DEBUG".to_string(), "maybe".to_string())]);
+        assert_eq!(merge(&pool, project_id, 0, &environs).await.unwrap(), None);
+
+        let debug: String = sqlx::query_scalar("SELECT environs->>'DEBUG' FROM projects")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(debug, "false");
+    }
+}
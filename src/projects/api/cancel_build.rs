@@ -0,0 +1,97 @@
+use axum::extract::{State, Path};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{auth::Auth, events, projects::{api::{error_response, lookup_error}, repo}, queue, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct CancelBuildResponse {
+    message: String,
+}
+
+/// Cancel a build that is queued or still building its image. A queued build is marked
+/// cancelled right away, a running one once its `docker build` has been killed.
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project, build_id)): Path<(String, String, Uuid)>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let project_record = match repo::find_owned(&pool, user.id, &owner, &project).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE.to_string()),
+        Err(err) => return lookup_error(err),
+    };
+
+    // the builder skips builds that are no longer pending when it picks them up
+    let queued = sqlx::query!(
+        r#"UPDATE builds SET status = 'cancelled', finished_at = now(), log = 'Build cancelled'
+           WHERE id = $1 AND project_id = $2 AND status = 'pending'
+           RETURNING id
+        "#,
+        build_id,
+        project_record.id,
+    )
+    .fetch_optional(&pool)
+    .await;
+
+    let message = match queued {
+        Ok(Some(_)) => {
+            events::close(build_id, "cancelled");
+            "Build cancelled"
+        }
+        Ok(None) => {
+            let running = sqlx::query!(
+                "SELECT id FROM builds WHERE id = $1 AND project_id = $2 AND status = 'building'",
+                build_id,
+                project_record.id,
+            )
+            .fetch_optional(&pool)
+            .await;
+
+            match running {
+                Ok(Some(_)) if queue::cancel(build_id) => "Build is being cancelled",
+                Ok(Some(_)) => {
+                    return error_response(
+                        StatusCode::CONFLICT,
+                        "The image is already built, the deploy can no longer be cancelled".to_string(),
+                    );
+                }
+                Ok(None) => {
+                    return error_response(
+                        StatusCode::CONFLICT,
+                        "Build does not exist or has already finished".to_string(),
+                    );
+                }
+                Err(err) => {
+                    tracing::error!(?err, "Can't cancel build: Failed to query database");
+                    return error_response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("Failed to query database: {}", err),
+                    );
+                }
+            }
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't cancel build: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    let json = serde_json::to_string(&CancelBuildResponse {
+        message: message.to_string(),
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::ACCEPTED)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,109 @@
+use axum::extract::{Path, Query, State};
+use axum::response::Response;
+use garde::Validate;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{auth::Auth, idempotency, projects::{api::{error_response, lookup_error}, repo}, startup::AppState, validation::ValidJson};
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CopyMode {
+    /// keep the target's variables, source values win on conflicts
+    #[default]
+    Merge,
+    /// drop the target's variables
+    Replace,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CopyProjectEnvironQuery {
+    #[serde(default)]
+    pub mode: CopyMode,
+}
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct CopyProjectEnvironRequest {
+    #[garde(length(min = 1))]
+    pub source_owner: String,
+    #[garde(length(min = 1))]
+    pub source_project: String,
+}
+
+#[derive(Serialize, Debug)]
+struct CopyProjectEnvironResponse {
+    environs: serde_json::Value,
+}
+
+/// Copy the environment variables of another project the user has access to.
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+    Query(CopyProjectEnvironQuery { mode }): Query<CopyProjectEnvironQuery>,
+    ValidJson(req): ValidJson<CopyProjectEnvironRequest>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let mut ids = Vec::with_capacity(2);
+    for (owner, project) in [(&owner, &project), (&req.source_owner, &req.source_project)] {
+        match repo::find_owned(&pool, user.id, owner, project).await {
+            Ok(Some(record)) => ids.push(record.id),
+            Ok(None) => {
+                return error_response(
+                    StatusCode::NOT_FOUND,
+                    format!("Project {owner}/{project} does not exist"),
+                );
+            }
+            Err(err) => return lookup_error(err),
+        }
+    }
+    let (target_id, source_id) = (ids[0], ids[1]);
+
+    if target_id == source_id {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "Can't copy environment variables from a project to itself".to_string(),
+        );
+    }
+
+    // read and write in one statement so a concurrent update of the source can't be half applied
+    let environs = match sqlx::query!(
+        r#"UPDATE projects
+            SET environs = CASE WHEN $3 THEN source.environs ELSE projects.environs || source.environs END,
+                environs_version = projects.environs_version + 1
+            FROM projects AS source
+            WHERE projects.id = $1
+            AND source.id = $2
+            RETURNING projects.environs
+        "#,
+        target_id,
+        source_id,
+        mode == CopyMode::Replace,
+    )
+    .fetch_one(&pool)
+    .await
+    {
+        Ok(record) => record.environs,
+        Err(err) => {
+            tracing::error!(
+                ?err,
+                "Can't update project environs: Failed to insert into database"
+            );
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to insert into database".to_string(),
+            );
+        }
+    };
+
+    let json = serde_json::to_string(&CopyProjectEnvironResponse { environs }).unwrap();
+
+    // holds the environment values, see idempotency::Sensitive
+    Response::builder()
+        .status(StatusCode::OK)
+        .extension(idempotency::Sensitive)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -1,23 +1,23 @@
 use axum::{
     extract::State,
     response::Response,
-    Json,
 };
-use garde::{Unvalidated, Validate};
+use garde::Validate;
 use hyper::{Body, StatusCode};
 use serde::{Deserialize, Serialize};
 use ulid::Ulid;
 use uuid::Uuid;
 
-use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
-    Argon2,
-};
 use rand::{Rng, SeedableRng};
 
 use crate::{
-    auth::Auth,
+    auth::{hashing::Hasher, Auth},
+    docker::{container_name_for, subdomain_for},
+    idempotency,
+    projects::deletion,
+    routes::{self, ClaimError},
     startup::AppState,
+    validation::ValidJson,
 };
 
 // Base64 url safe
@@ -45,30 +45,46 @@ struct CreateProjectResponse {
     domain: String,
     git_username: String,
     git_password: String,
+    clone_command: String,
+}
+
+/// Placeholder shown in place of a token that is only stored hashed
+pub(super) const TOKEN_PLACEHOLDER: &str = "<token>";
+
+/// `git clone` command with the credentials in the url. git authenticates pushes with the owner
+/// name as the username.
+pub(super) fn clone_command(secure: bool, domain: &str, owner: &str, project: &str, token: &str) -> String {
+    let protocol = match secure {
+        true => "https",
+        false => "http",
+    };
+
+    format!("git clone {protocol}://{owner}:{token}@{domain}/{owner}/{project}")
+}
+
+/// Generate a git password for a project, returns the token and its argon2 hash
+pub(super) fn generate_token(hasher: &Hasher) -> Result<(String, String), argon2::password_hash::Error> {
+    let mut rng = rand::rngs::StdRng::from_entropy();
+    let token = (0..TOKEN_LENGTH)
+        .map(|_| {
+            let idx = rng.gen_range(0..CHARSET.len());
+            CHARSET[idx] as char
+        })
+        .collect::<String>();
+
+    let hash = hasher.hash(token.as_bytes())?;
+
+    Ok((token, hash))
 }
 
 #[tracing::instrument(skip(pool, base, domain))]
 pub async fn post(
     auth: Auth,
     State(AppState {
-        pool, base, domain, secure, ..
+        pool, base, domain, secure, subdomain, deletion_retention, hasher, ..
     }): State<AppState>,
-    Json(req): Json<Unvalidated<CreateProjectRequest>>,
-) -> Response<Body> {    
-    let CreateProjectRequest { owner, project } = match req.validate(&()) {
-        Ok(valid) => valid.into_inner(),
-        Err(err) => {
-            let json = serde_json::to_string(&ErrorResponse {
-                message: err.to_string()
-            }).unwrap();
-
-            return Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(Body::from(json))
-                .unwrap();
-        }
-    };
-
+    ValidJson(CreateProjectRequest { owner, project }): ValidJson<CreateProjectRequest>,
+) -> Response<Body> {
     let path = match project.ends_with(".git") {
         true => format!("{base}/{owner}/{project}"),
         false => format!("{base}/{owner}/{project}.git"),
@@ -97,7 +113,7 @@ pub async fn post(
             tracing::error!(?err, "Can't get project_owners: Failed to query database");
 
             let json = serde_json::to_string(&ErrorResponse {
-                message: format!("Failed to query database {}", err.to_string())
+                message: format!("Failed to query database {}", err)
             }).unwrap();
 
             return Response::builder()
@@ -107,9 +123,9 @@ pub async fn post(
         }
     };
 
-    // check if project already exist
+    // check if project already exist, a deleted one keeps its name until it is purged
     match sqlx::query!(
-        r#"SELECT id FROM projects WHERE name = $1 AND owner_id = $2"#,
+        r#"SELECT deleted_at FROM projects WHERE name = $1 AND owner_id = $2"#,
         project,
         owner_id,
     )
@@ -117,10 +133,12 @@ pub async fn post(
     .await
     {
         Ok(None) => {}
-        Ok(_) => {
-            let json = serde_json::to_string(&ErrorResponse {
-                message: "Project already exists".to_string(),
-            }).unwrap();
+        Ok(Some(record)) => {
+            let message = match record.deleted_at {
+                Some(deleted_at) => deletion::name_held_message(deleted_at, deletion_retention),
+                None => "Project already exists".to_string(),
+            };
+            let json = serde_json::to_string(&ErrorResponse { message }).unwrap();
 
             return Response::builder()
                 .status(StatusCode::CONFLICT)
@@ -130,7 +148,7 @@ pub async fn post(
         Err(err) => {
             tracing::error!(?err, "Can't get projects: Failed to query database");
             let json = serde_json::to_string(&ErrorResponse {
-                message: format!("Failed to query database {}", err.to_string())
+                message: format!("Failed to query database {}", err)
             }).unwrap();
 
             return Response::builder()
@@ -147,7 +165,7 @@ pub async fn post(
             tracing::error!(?err, "Can't insert user: Failed to begin transaction");
 
             let json = serde_json::to_string(&ErrorResponse {
-                message: format!("Failed to begin transaction {}", err.to_string())
+                message: format!("Failed to begin transaction {}", err)
             }).unwrap();
 
             return Response::builder()
@@ -159,11 +177,13 @@ pub async fn post(
     };
 
     // create project
+    let container_name = container_name_for(&owner, &project);
     let project_id = match sqlx::query!(
-        r#"INSERT INTO projects (id, name, owner_id) VALUES ($1, $2, $3) RETURNING id"#,
+        r#"INSERT INTO projects (id, name, owner_id, container_name) VALUES ($1, $2, $3, $4) RETURNING id"#,
         Uuid::from(Ulid::new()),
         project,
         owner_id,
+        container_name,
     )
     .fetch_one(&mut *tx)
     .await
@@ -192,10 +212,41 @@ pub async fn post(
         }
     };
 
+    match routes::claim_subdomain(
+        &mut tx,
+        project_id,
+        &subdomain_for(&owner, &project, &container_name, subdomain),
+    )
+    .await
+    {
+        Ok(()) => {}
+        Err(err) => {
+            let status = match err {
+                ClaimError::Database(_) => {
+                    tracing::error!(?err, "Can't create project: Failed to claim subdomain");
+                    StatusCode::INTERNAL_SERVER_ERROR
+                }
+                _ => StatusCode::CONFLICT,
+            };
+            if let Err(err) = tx.rollback().await {
+                tracing::error!(?err, "Can't create project: Failed to rollback transaction");
+            }
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Can't use the project's address: {}", err)
+            }).unwrap();
+
+            return Response::builder()
+                .status(status)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    }
+
     if let Err(err) = git2::Repository::init_bare(path) {
         tracing::error!(?err, "Can't create project: Failed to create repo");
         let json = serde_json::to_string(&ErrorResponse {
-            message: format!("Failed to create project: {}", err.to_string())
+            message: format!("Failed to create project: {}", err)
         }).unwrap();
 
         return Response::builder()
@@ -204,24 +255,13 @@ pub async fn post(
             .unwrap();
     }
 
-    // generate token
-    let mut rng = rand::rngs::StdRng::from_entropy();
-    let token = (0..TOKEN_LENGTH)
-        .map(|_| {
-            let idx = rng.gen_range(0..CHARSET.len());
-            CHARSET[idx] as char
-        })
-        .collect::<String>();
-
-    let salt = SaltString::generate(&mut OsRng);
-    let hasher = Argon2::default();
-    let hash = match hasher.hash_password(token.as_bytes(), &salt) {
-        Ok(hash) => hash,
+    let (token, hash) = match generate_token(&hasher) {
+        Ok(token) => token,
         Err(err) => {
             tracing::error!(?err, "Can't create project: Failed to hash token");
 
             let json = serde_json::to_string(&ErrorResponse {
-                message: format!("Failed to generate token {}", err.to_string())
+                message: format!("Failed to generate token {}", err)
             }).unwrap();
             
             return Response::builder()
@@ -235,7 +275,7 @@ pub async fn post(
         "INSERT INTO api_token (id, project_id, token) VALUES ($1, $2, $3)",
         Uuid::from(Ulid::new()),
         project_id,
-        hash.to_string(),
+        hash,
     )
     .execute(&mut *tx)
     .await
@@ -246,7 +286,7 @@ pub async fn post(
         );
 
         let json = serde_json::to_string(&ErrorResponse {
-            message: format!("Failed to insert into database {}", err.to_string())
+            message: format!("Failed to insert into database {}", err)
         }).unwrap();
 
         return Response::builder()
@@ -259,7 +299,7 @@ pub async fn post(
         tracing::error!(?err, "Can't create project: Failed to commit transaction");
 
         let json = serde_json::to_string(&ErrorResponse {
-            message: format!("Failed to commit transaction: {}", err.to_string())
+            message: format!("Failed to commit transaction: {}", err)
         }).unwrap();
 
 
@@ -283,12 +323,15 @@ pub async fn post(
             project_name: project.clone(),
             domain: format!("{protocol}://{domain}/{owner}/{project}"),
             git_username: username,
+            clone_command: clone_command(secure, &domain, &owner, &project, &token),
             git_password: token,
         }
     ).unwrap();
 
+    // holds the git password, see idempotency::Sensitive
     Response::builder()
         .status(StatusCode::OK)
+        .extension(idempotency::Sensitive)
         .body(Body::from(json))
         .unwrap()
 }
@@ -0,0 +1,101 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use garde::Validate;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{auth::Auth, idempotency, projects::{api::{error_response, lookup_error}, repo, shares}, startup::AppState, validation::ValidJson};
+
+/// Longest a share link can stay open, links that never expire are made without `expires_in_days`
+const MAX_EXPIRES_IN_DAYS: i64 = 365;
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct CreateShareRequest {
+    /// the link never expires when not given
+    #[garde(custom(valid_expiry))]
+    pub expires_in_days: Option<i64>,
+}
+
+fn valid_expiry(value: &Option<i64>, _ctx: &()) -> garde::Result {
+    match value {
+        Some(days) if !(1..=MAX_EXPIRES_IN_DAYS).contains(days) => Err(garde::Error::new(format!(
+            "Has to be between 1 and {MAX_EXPIRES_IN_DAYS}"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct CreateShareResponse {
+    id: Uuid,
+    /// only shown here, the server keeps a hash of it
+    token: String,
+    url: String,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+/// Make a link that shows the status and build logs of the project to anyone who has it, with
+/// the values of the project's environment variables hidden
+#[tracing::instrument(skip(auth, pool, domain))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, domain, secure, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+    ValidJson(req): ValidJson<CreateShareRequest>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let expires_at = req
+        .expires_in_days
+        .map(|days| Utc::now() + chrono::Duration::days(days));
+
+    let project_record = match repo::find_owned(&pool, user.id, &owner, &project).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE.to_string()),
+        Err(err) => return lookup_error(err),
+    };
+
+    let id = Uuid::from(Ulid::new());
+    let (token, token_hash) = shares::generate_token();
+    if let Err(err) = sqlx::query!(
+        r#"INSERT INTO project_shares (id, project_id, token_hash, created_by, expires_at)
+           VALUES ($1, $2, $3, $4, $5)
+        "#,
+        id,
+        project_record.id,
+        token_hash,
+        user.id,
+        expires_at,
+    )
+    .execute(&pool)
+    .await
+    {
+        tracing::error!(?err, "Can't create share: Failed to query database");
+        return error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to query database: {}", err),
+        );
+    }
+
+    let protocol = match secure {
+        true => "https",
+        false => "http",
+    };
+    let json = serde_json::to_string(&CreateShareResponse {
+        id,
+        url: format!("{protocol}://{domain}/share/{token}/status"),
+        token,
+        expires_at,
+    })
+    .unwrap();
+
+    // holds the share token, see idempotency::Sensitive
+    Response::builder()
+        .status(StatusCode::CREATED)
+        .extension(idempotency::Sensitive)
+        .body(Body::from(json))
+        .unwrap()
+}
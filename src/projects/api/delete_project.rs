@@ -1,32 +1,62 @@
 use std::collections::HashMap;
-use std::fs::File;
 
-use axum::extract::{State, Path};
+use axum::extract::{Path, Query, State};
 use axum::response::Response;
-use bollard::Docker;
-use bollard::container::{RemoveContainerOptions, StopContainerOptions};
+use chrono::{DateTime, Utc};
 use hyper::{Body, StatusCode};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use crate::auth::Auth;
+use crate::admin::audit;
+use crate::auth::{Auth, ADMIN_PERMISSION};
+use crate::projects::{deletion, repo};
 use crate::startup::AppState;
 
+#[derive(Deserialize, Debug)]
+pub struct DeleteProjectQuery {
+    /// remove everything right away instead of after the retention, admins only
+    #[serde(default)]
+    hard: bool,
+}
+
 #[derive(Serialize)]
 struct DeleteProjectSuccessResponse {
     message: String
 }
 
+#[derive(Serialize)]
+struct SoftDeleteProjectResponse {
+    message: String,
+    /// the project is purged after this, until then it can be restored
+    restorable_until: DateTime<Utc>,
+}
+
 #[derive(Serialize)]
 struct DeleteProjectErrorResponse {
     message: String,
     details: Vec<String>
 }
 
-#[tracing::instrument(skip(pool, base, auth))]
+fn error_response(status: StatusCode, message: String) -> Response<Body> {
+    let json = serde_json::to_string(&DeleteProjectErrorResponse {
+        message,
+        details: vec!(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Delete a project. It is only stopped and marked deleted, and purged once
+/// `deletion.retention` is over unless it is restored first. With `?hard=true` an admin purges
+/// it right away.
+#[tracing::instrument(skip(pool, base, auth, docker))]
 pub async fn post(
     auth: Auth,
     Path((owner, project)): Path<(String, String)>,
-    State(AppState { pool, base, .. }): State<AppState>,
+    Query(query): Query<DeleteProjectQuery>,
+    State(AppState { pool, base, docker, container_prefix, deletion_retention, .. }): State<AppState>,
 ) -> Response<Body> {
     fn to_response(status: HashMap<&'static str, &'static str>) -> Response<Body> {
         let success = status.iter().all(|(_, v)| *v == "successfully deleted");
@@ -39,7 +69,7 @@ pub async fn post(
             false => serde_json::to_string(
                 &DeleteProjectErrorResponse {
                     message: "Failed to delete project".to_string(),
-                    details: status.into_iter().map(|(k, v)|{ format!("{}: {}", k.to_string(), v.to_string()) }).collect::<Vec<_>>()
+                    details: status.into_iter().map(|(k, v)|{ format!("{}: {}", k, v) }).collect::<Vec<_>>()
                 }
             )
         }.unwrap();
@@ -50,166 +80,83 @@ pub async fn post(
             .unwrap()
     }
 
-    let path = match project.ends_with(".git") {
-        true => format!("{base}/{owner}/{project}"),
-        false => format!("{base}/{owner}/{project}.git"),
-    };
+    let user = auth.current_user.unwrap();
+    let project = project.trim_end_matches(".git");
+    let is_admin = user.permissions.contains(ADMIN_PERMISSION);
 
-    match auth.current_user {
-        Some(user) => {
-            if user.username != owner {
-                let json = serde_json::to_string(&DeleteProjectErrorResponse {
-                    message: format!("You are not allowed to delete this project"),
-                    details: vec!(),
-                }).unwrap();
-    
-                return Response::builder()
-                    .status(StatusCode::BAD_REQUEST)
-                    .body(Body::from(json))
-                    .unwrap();
-            }
-        },
-        None => ()
-    }
-
-    //TODO: better error log
-    let mut status: HashMap<&'static str, &'static str> = HashMap::new();
-
-    // check if owner exist
-    match sqlx::query!(
-        r#"SELECT id FROM project_owners WHERE name = $1 AND deleted_at IS NULL"#,
-        owner,
-    )
-    .fetch_optional(&pool)
-    .await
-    {
-        Ok(Some(data)) => {
-            // check if project exist
-            match sqlx::query!(
-                r#"SELECT id FROM projects WHERE name = $1 AND owner_id = $2"#,
-                project,
-                data.id,
-            )
-            .fetch_optional(&pool)
-            .await
-            {
-                Ok(Some(_)) => {
-                    match sqlx::query!(
-                        "DELETE FROM projects WHERE name = $1 AND owner_id = $2",
-                        project,
-                        data.id
-                    )
-                    .execute(&pool)
-                    .await
-                    {
-                        Ok(_) => {
-                            status.insert("project", "successfully deleted");
-                        }
-                        Err(err) => {
-                            tracing::error!(?err, "Can't delete project: Failed to delete project");
-                            status.insert("project", "failed to delete: database error");
-                        }
-                    }
-                }
-                Err(err) => {
-                    tracing::error!(?err, "Can't delete project: Failed to query database");
-                    status.insert("project", "failed to delete: database error");
-                }
-                _ => {
-                    status.insert("project", "failed to delete: project does not exist");
-                }
-            };
-        }
-        Ok(None) => {
-            tracing::debug!("Can't delete project: Owner does not exist");
-        }
-        Err(err) => {
-            tracing::error!(?err, "Can't get project_owners: Failed to query database");
-        }
+    if query.hard && !is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Only admins can hard delete a project".to_string());
     }
 
-    // check if repo exists
-    match File::open(&path) {
-        Err(err) => {
-            tracing::debug!(?err, "Can't delete project: Repo does not exist");
-            status.insert("repo", "failed to delete: repo does not exist");
-        }
-        Ok(_) => match std::fs::remove_dir_all(&path) {
-            Ok(_) => {
-                status.insert("repo", "successfully deleted");
-            }
+    // members of a group owner can use the project but only its owner can delete it
+    if user.username != owner && !query.hard {
+        let (status, message) = match repo::find_owned(&pool, user.id, &owner, project).await {
+            Ok(Some(_)) => (StatusCode::FORBIDDEN, format!("Only {owner} can delete this project")),
+            Ok(None) => (StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE.to_string()),
             Err(err) => {
-                tracing::error!(?err, "Can't delete project: Failed to delete repo");
-                status.insert("repo", "failed to delete: repo error");
+                tracing::error!(?err, "Can't delete project: Failed to query database");
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to query database: {}", err))
             }
-        },
-    };
-
-    let container_name = format!("{owner}-{}", project.trim_end_matches(".git")).replace('.', "-");
+        };
+        return error_response(status, message);
+    }
 
-    let docker = match Docker::connect_with_local_defaults() {
+    let record = match deletion::find(&pool, &owner, project).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE.to_string()),
         Err(err) => {
-            tracing::error!(?err, "Can't delete project: Failed to connect to docker");
-            status.insert("container", "failed to delete: docker error");
-            return to_response(status);
+            tracing::error!(?err, "Can't delete project: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
         }
-        Ok(docker) => docker,
     };
 
-    // remove container
-    match docker.inspect_container(&container_name, None).await {
-        Ok(_) => {
-            match docker
-                .stop_container(&container_name, None::<StopContainerOptions>)
-                .await
-            {
-                Ok(_) => {
-                    match docker
-                        .remove_container(&container_name, None::<RemoveContainerOptions>)
-                        .await
-                    {
-                        Ok(_) => {
-                            status.insert("container", "successfully deleted");
-                        }
-                        Err(err) => {
-                            tracing::error!(
-                                ?err,
-                                "Can't delete project: Failed to delete container"
-                            );
-                            status.insert("container", "failed to delete: container error");
-                        }
-                    }
-                }
-                Err(err) => {
-                    tracing::error!(?err, "Can't delete project: Failed to stop container");
-                    status.insert("container", "failed to delete: container error");
-                }
-            };
-        }
-        Err(err) => {
-            tracing::debug!(?err, "Can't delete project: Container does not exist");
-            status.insert("container", "failed to delete: container does not exist");
-        }
-    };
+    if query.hard {
+        let status = deletion::purge(&pool, &docker, &base, &container_prefix, &record).await;
+        audit::record(
+            &pool,
+            user.id,
+            "project.purge",
+            serde_json::json!({
+                "project_id": record.id,
+                "owner": record.owner,
+                "project": record.name,
+                "status": status,
+            }),
+        )
+        .await;
+
+        return to_response(status);
+    }
 
-    // remove image
-    match docker.inspect_image(&container_name).await {
-        Ok(_) => match docker.remove_image(&container_name, None, None).await {
-            Ok(_) => {
-                status.insert("image", "successfully deleted");
-            }
-            Err(err) => {
-                tracing::error!(?err, "Can't delete project: Failed to delete image");
-                status.insert("image", "failed to delete: image error");
-            }
-        },
+    if record.deleted_at.is_some() {
+        return error_response(StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE.to_string());
+    }
+
+    let deleted_at = match deletion::soft_delete(&pool, &docker, &container_prefix, &record).await {
+        Ok(Some(deleted_at)) => deleted_at,
+        // deleted by a request racing this one
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE.to_string()),
         Err(err) => {
-            tracing::debug!(?err, "Can't delete project: Image does not exist");
-            status.insert("image", "failed to delete: image does not exist");
+            tracing::error!(?err, "Can't delete project: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
         }
     };
 
-
-
-    to_response(status)
+    let restorable_until = deletion::purge_at(deleted_at, deletion_retention);
+    let json = serde_json::to_string(&SoftDeleteProjectResponse {
+        message: format!("Project deleted, it can be restored until {restorable_until}"),
+        restorable_until,
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
 }
@@ -1,11 +1,10 @@
 use axum::extract::{State, Path};
 use axum::response::Response;
-use axum::Json;
-use garde::{Unvalidated, Validate};
+use garde::Validate;
 use hyper::{Body, StatusCode};
 use serde::{Deserialize, Serialize};
 
-use crate::{auth::Auth, startup::AppState};
+use crate::{auth::Auth, projects::repo, startup::AppState, validation::ValidJson};
 
 #[derive(Deserialize, Validate, Debug)]
 pub struct DeleteProjectEnvironRequest {
@@ -23,23 +22,9 @@ pub async fn post(
     auth: Auth,
     State(AppState { pool, domain, secure, .. }): State<AppState>,
     Path((owner, project)): Path<(String, String)>,
-    Json(req): Json<Unvalidated<DeleteProjectEnvironRequest>>
+    ValidJson(DeleteProjectEnvironRequest { key }): ValidJson<DeleteProjectEnvironRequest>
 ) -> Response<Body> {
-    let _user = auth.current_user.unwrap();
-
-    let DeleteProjectEnvironRequest { key } = match req.validate(&()) {
-        Ok(valid) => valid.into_inner(),
-        Err(err) => {
-            let json = serde_json::to_string(&ErrorResponse {
-                message: err.to_string()
-            }).unwrap();
-
-            return Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(Body::from(json))
-                .unwrap();
-        }
-    };
+    let user = auth.current_user.unwrap();
 
     // check if project exist
     let project = match sqlx::query!(
@@ -49,9 +34,12 @@ pub async fn post(
            JOIN users_owners ON project_owners.id = users_owners.owner_id
            AND projects.name = $1
            AND project_owners.name = $2
+           AND users_owners.user_id = $3
+           AND projects.deleted_at IS NULL
         "#,
         project,
         owner,
+        user.id,
     )
     .fetch_optional(&pool)
     .await
@@ -59,11 +47,11 @@ pub async fn post(
         Ok(Some(record)) => record,
         Ok(None) => {
             let json = serde_json::to_string(&ErrorResponse {
-                message: "Project does not exist".to_string()
+                message: repo::NOT_FOUND_MESSAGE.to_string()
             }).unwrap();
 
             return Response::builder()
-                .status(StatusCode::BAD_REQUEST)
+                .status(StatusCode::NOT_FOUND)
                 .body(Body::from(json))
                 .unwrap();
         }
@@ -71,7 +59,7 @@ pub async fn post(
             tracing::error!(?err, "Can't get projects: Failed to query database");
 
             let json = serde_json::to_string(&ErrorResponse {
-                message: format!("Failed to query database: {}", err.to_string())
+                message: format!("Failed to query database: {}", err)
             }).unwrap();
 
             return Response::builder()
@@ -84,7 +72,8 @@ pub async fn post(
 
     match sqlx::query!(
         r#"UPDATE projects
-            SET environs = environs - $1
+            SET environs = environs - $1,
+                environs_version = environs_version + 1
             WHERE id = $2
         "#,
         key,
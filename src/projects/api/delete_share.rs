@@ -0,0 +1,59 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{auth::Auth, projects::{api::{error_response, lookup_error}, repo}, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct DeleteShareResponse {
+    message: String,
+}
+
+/// Revoke a share link, it stops working right away
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project, share_id)): Path<(String, String, Uuid)>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let project_record = match repo::find_owned(&pool, user.id, &owner, &project).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE.to_string()),
+        Err(err) => return lookup_error(err),
+    };
+
+    match sqlx::query!(
+        "DELETE FROM project_shares WHERE id = $1 AND project_id = $2",
+        share_id,
+        project_record.id
+    )
+    .execute(&pool)
+    .await
+    {
+        Ok(result) if result.rows_affected() == 0 => {
+            return error_response(StatusCode::NOT_FOUND, "Share link does not exist".to_string());
+        }
+        Ok(_) => {}
+        Err(err) => {
+            tracing::error!(?err, "Can't delete share: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    }
+
+    let json = serde_json::to_string(&DeleteShareResponse {
+        message: "Share link revoked".to_string(),
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -1,10 +1,12 @@
-use axum::extract::Path;
+use axum::extract::{Path, State};
 use axum::response::Response;
-use bollard::Docker;
 use bollard::container::{StopContainerOptions, StartContainerOptions};
 use hyper::{Body, StatusCode};
 use serde::Serialize;
 use crate::auth::Auth;
+use crate::projects::repo;
+use crate::docker::docker_name;
+use crate::startup::AppState;
 
 #[derive(Serialize)]
 struct DeleteVolumeSuccessResponse {
@@ -17,41 +19,43 @@ struct DeleteVolumeErrorResponse {
     details: Vec<String>
 }
 
-#[tracing::instrument(skip(auth))]
-pub async fn post(auth: Auth, Path((owner, project)): Path<(String, String)>) -> Response<Body> {
-    let container_name = format!("{owner}-{}", project.trim_end_matches(".git")).replace('.', "-");
-    let db_name = format!("{}-db", container_name);
-    let volume_name = format!("{}-volume", container_name);
+#[tracing::instrument(skip(auth, pool, docker))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, docker, container_prefix, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
 
-    match auth.current_user {
-        Some(user) => {
-            if user.username != owner {
-                let json = serde_json::to_string(&DeleteVolumeErrorResponse {
-                    message: format!("You are not allowed to delete this project"),
-                    details: vec!(),
-                }).unwrap();
-    
-                return Response::builder()
-                    .status(StatusCode::BAD_REQUEST)
-                    .body(Body::from(json))
-                    .unwrap();
-            }
-        },
-        None => ()
-    }
+    let error = |status: StatusCode, message: String| {
+        let json = serde_json::to_string(&DeleteVolumeErrorResponse {
+            message,
+            details: vec!(),
+        }).unwrap();
 
-    let docker = match Docker::connect_with_local_defaults() {
-        Ok(docker) => docker,
+        Response::builder()
+            .status(status)
+            .body(Body::from(json))
+            .unwrap()
+    };
+
+    let project = match repo::find_owned(&pool, user.id, &owner, project.trim_end_matches(".git")).await {
+        // members of a group owner can use the project but only its owner can delete it
+        Ok(Some(_)) if user.username != owner => {
+            return error(StatusCode::FORBIDDEN, format!("Only {owner} can delete this volume"));
+        }
+        Ok(Some(project)) => project,
+        Ok(None) => return error(StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE.to_string()),
         Err(err) => {
-            tracing::error!(?err, "Can't delete volume: Failed to connect to docker");
-            // TODO: better message
-            return Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Body::from(""))
-                .unwrap();
+            tracing::error!(?err, "Can't delete volume: Failed to query database");
+            return error(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to query database: {}", err));
         }
     };
 
+    let container_name = docker_name(&container_prefix, &project.container_name);
+    let db_name = format!("{}-db", container_name);
+    let volume_name = format!("{}-volume", container_name);
+
     let turned_on = match docker.inspect_container(&db_name, None).await {
         Ok(_) => {
             match docker
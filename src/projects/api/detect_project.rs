@@ -0,0 +1,46 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+
+use crate::{auth::Auth, detection, projects::{api::{error_response, lookup_error}, repo}, startup::AppState};
+
+/// Framework and Dockerfile a deploy of the last pushed commit would use, without deploying
+#[tracing::instrument(skip(auth, pool, base))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, base, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let project_record = match repo::find_owned(&pool, user.id, &owner, &project).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE.to_string()),
+        Err(err) => return lookup_error(err),
+    };
+
+    let path = format!("{base}/{owner}/{project}.git");
+    let detection = match detection::cached(&pool, project_record.id, &path).await {
+        Ok(Some(detection)) => detection,
+        Ok(None) => {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                "Nothing was pushed to this project yet".to_string(),
+            )
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't detect project: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    let json = serde_json::to_string(&detection).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+
+use axum::extract::{Path, State};
+use axum::response::Response;
+use garde::Validate;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{auth::Auth, idempotency, projects::{api::{error_response, lookup_error}, repo}, startup::AppState, validation::{self, ValidJson}};
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct DiffProjectEnvironRequest {
+    #[garde(custom(validation::environs))]
+    pub environs: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Debug)]
+struct ChangedValue {
+    old: String,
+    new: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+struct DiffProjectEnvironResponse {
+    added: BTreeMap<String, String>,
+    removed: BTreeMap<String, String>,
+    changed: BTreeMap<String, ChangedValue>,
+}
+
+fn diff_environs(
+    current: BTreeMap<String, String>,
+    proposed: BTreeMap<String, String>,
+) -> DiffProjectEnvironResponse {
+    let mut diff = DiffProjectEnvironResponse::default();
+
+    for (key, old) in current.iter() {
+        if !proposed.contains_key(key) {
+            diff.removed.insert(key.clone(), old.clone());
+        }
+    }
+
+    for (key, new) in proposed {
+        match current.get(&key) {
+            None => {
+                diff.added.insert(key, new);
+            }
+            Some(old) if *old != new => {
+                diff.changed.insert(key, ChangedValue { old: old.clone(), new });
+            }
+            Some(_) => {}
+        }
+    }
+
+    diff
+}
+
+/// Preview what replacing the environment variables with the given map would change
+#[tracing::instrument(skip(auth, pool, req))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+    ValidJson(req): ValidJson<DiffProjectEnvironRequest>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let project = match repo::find_owned(&pool, user.id, &owner, &project).await {
+        Ok(Some(record)) => record,
+        Ok(None) => {
+            return error_response(StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE.to_string());
+        }
+        Err(err) => return lookup_error(err),
+    };
+
+    let environs = match sqlx::query!(
+        r#"SELECT environs FROM projects WHERE id = $1"#,
+        project.id
+    )
+    .fetch_one(&pool)
+    .await
+    {
+        Ok(record) => record.environs,
+        Err(err) => {
+            tracing::error!(?err, "Can't get project environs: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    let current = match environs.as_object() {
+        Some(map) => map
+            .iter()
+            .map(|(key, value)| (key.clone(), value.as_str().unwrap_or("").to_string()))
+            .collect::<BTreeMap<_, _>>(),
+        None => BTreeMap::new(),
+    };
+
+    let json = serde_json::to_string(&diff_environs(current, req.environs)).unwrap();
+
+    // holds the environment values, see idempotency::Sensitive
+    Response::builder()
+        .status(StatusCode::OK)
+        .extension(idempotency::Sensitive)
+        .body(Body::from(json))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn environs(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    fn diff(current: &[(&str, &str)], proposed: &[(&str, &str)]) -> serde_json::Value {
+        serde_json::to_value(diff_environs(environs(current), environs(proposed))).unwrap()
+    }
+
+    #[test]
+    fn new_keys_are_added() {
+        assert_eq!(
+            diff(&[("DEBUG", "false")], &[("DEBUG", "false"), ("SENTRY_DSN", "https://sentry.example.com/1")]),
+            serde_json::json!({
+                "added": { "SENTRY_DSN": "https://sentry.example.com/1" },
+                "removed": {},
+                "changed": {},
+            })
+        );
+    }
+
+    #[test]
+    fn missing_keys_are_removed() {
+        assert_eq!(
+            diff(&[("DEBUG", "false"), ("SECRET_KEY", "secret")], &[("DEBUG", "false")]),
+            serde_json::json!({ "added": {}, "removed": { "SECRET_KEY": "secret" }, "changed": {} })
+        );
+    }
+
+    #[test]
+    fn changed_values_show_both_sides() {
+        assert_eq!(
+            diff(&[("DEBUG", "true"), ("ALLOWED_HOSTS", "*")], &[("DEBUG", "false"), ("ALLOWED_HOSTS", "*")]),
+            serde_json::json!({
+                "added": {},
+                "removed": {},
+                "changed": { "DEBUG": { "old": "true", "new": "false" } },
+            })
+        );
+    }
+
+    #[test]
+    fn same_environs_have_no_diff() {
+        let same = [("DEBUG", "false"), ("SECRET_KEY", "secret")];
+        assert_eq!(diff(&same, &same), serde_json::json!({ "added": {}, "removed": {}, "changed": {} }));
+    }
+}
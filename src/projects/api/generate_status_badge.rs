@@ -5,15 +5,17 @@ use axum::response::Response;
 use hyper::{Body, StatusCode};
 use serde::{Serialize, Deserialize};
 
-use crate::{auth::Auth, startup::AppState};
+use crate::{auth::Auth, projects::repo, startup::AppState};
 
 #[derive(Serialize, Deserialize, Debug, sqlx::Type)]
 #[sqlx(type_name = "build_state", rename_all = "lowercase")] 
+#[allow(clippy::upper_case_acronyms)]
 pub enum BuildState {
     PENDING,
     BUILDING,
     SUCCESSFUL,
-    FAILED
+    FAILED,
+    CANCELLED,
 }
 
 impl fmt::Display for BuildState {
@@ -23,6 +25,7 @@ impl fmt::Display for BuildState {
             BuildState::BUILDING => write!(f, "Building"),
             BuildState::SUCCESSFUL => write!(f, "Successful"),
             BuildState::FAILED => write!(f, "Failed"),
+            BuildState::CANCELLED => write!(f, "Cancelled"),
         }
     }
 }
@@ -32,9 +35,9 @@ struct ErrorResponse {
     message: String,
 }
 
-#[tracing::instrument(skip(auth, pool))]
+#[tracing::instrument(skip(_auth, pool))]
 pub async fn get(
-    auth: Auth,
+    _auth: Auth,
     State(AppState { pool, domain, secure, .. }): State<AppState>,
     Path((owner, project)): Path<(String, String)>,
 ) -> Response<Body> {
@@ -46,6 +49,7 @@ pub async fn get(
            JOIN users_owners ON project_owners.id = users_owners.owner_id
            AND projects.name = $1
            AND project_owners.name = $2
+           AND projects.deleted_at IS NULL
         "#,
         project,
         owner,
@@ -56,11 +60,11 @@ pub async fn get(
         Ok(Some(record)) => record,
         Ok(None) => {
             let json = serde_json::to_string(&ErrorResponse {
-                message: "Project does not exist".to_string()
+                message: repo::NOT_FOUND_MESSAGE.to_string()
             }).unwrap();
 
             return Response::builder()
-                .status(StatusCode::BAD_REQUEST)
+                .status(StatusCode::NOT_FOUND)
                 .body(Body::from(json))
                 .unwrap();
         }
@@ -68,7 +72,7 @@ pub async fn get(
             tracing::error!(?err, "Can't get projects: Failed to query database");
 
             let json = serde_json::to_string(&ErrorResponse {
-                message: format!("Failed to query database: {}", err.to_string())
+                message: format!("Failed to query database: {}", err)
             }).unwrap();
 
             return Response::builder()
@@ -90,7 +94,7 @@ pub async fn get(
         Ok(record) => record,
         Err(err) => {
             let json = serde_json::to_string(&ErrorResponse {
-                message: format!("Failed to query database: {}", err.to_string())
+                message: format!("Failed to query database: {}", err)
             }).unwrap();
 
             return Response::builder()
@@ -107,6 +111,7 @@ pub async fn get(
         BuildState::FAILED => badgen::Color::Red,
         BuildState::SUCCESSFUL => badgen::Color::Green,
         BuildState::BUILDING => badgen::Color::Yellow,
+        BuildState::CANCELLED => badgen::Color::Grey,
     };
 
     let badge = badgen::badge(
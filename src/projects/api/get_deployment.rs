@@ -0,0 +1,104 @@
+use axum::extract::{State, Path};
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{auth::Auth, egress::EgressPolicy, git, projects::{api::{error_response, lookup_error}, repo}, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct DeploymentResponse {
+    /// last successful build, none when the project was never deployed
+    build_id: Option<Uuid>,
+    deployed_sha: Option<String>,
+    deployed_at: Option<DateTime<Utc>>,
+    /// head of the pushed branch
+    pushed_sha: Option<String>,
+    /// pushed commits that aren't deployed yet, none when the two can't be compared
+    commits_behind: Option<usize>,
+    up_to_date: bool,
+    egress_policy: EgressPolicy,
+}
+
+/// Commit the running container was built from next to the head of the pushed branch, to
+/// tell whether the latest push is live
+#[tracing::instrument(skip(auth, pool, base))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, base, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let project_record = match repo::find_owned(&pool, user.id, &owner, &project).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE.to_string()),
+        Err(err) => return lookup_error(err),
+    };
+
+    let deployed = match sqlx::query!(
+        r#"SELECT id, commit_sha, finished_at
+           FROM builds
+           WHERE project_id = $1 AND status = 'successful'
+           ORDER BY created_at DESC
+           LIMIT 1
+        "#,
+        project_record.id
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(record) => record,
+        Err(err) => {
+            tracing::error!(?err, "Can't get deployment: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    let egress_policy = match sqlx::query!(
+        r#"SELECT egress_policy AS "egress_policy: EgressPolicy" FROM projects WHERE id = $1"#,
+        project_record.id
+    )
+    .fetch_one(&pool)
+    .await
+    {
+        Ok(record) => record.egress_policy,
+        Err(err) => {
+            tracing::error!(?err, "Can't get deployment: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    let path = format!("{base}/{owner}/{project}.git");
+    let pushed_sha = git::head_commit(&path);
+    let deployed_sha = deployed.as_ref().and_then(|record| record.commit_sha.clone());
+
+    let commits_behind = match (&deployed_sha, &pushed_sha) {
+        (Some(deployed_sha), Some(pushed_sha)) => git::commits_behind(&path, deployed_sha, pushed_sha),
+        _ => None,
+    };
+    let up_to_date = deployed_sha.is_some() && (deployed_sha == pushed_sha || commits_behind == Some(0));
+
+    let json = serde_json::to_string(&DeploymentResponse {
+        build_id: deployed.as_ref().map(|record| record.id),
+        deployed_at: deployed.and_then(|record| record.finished_at),
+        deployed_sha,
+        pushed_sha,
+        commits_behind,
+        up_to_date,
+        egress_policy,
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,134 @@
+use axum::extract::{Path, Query, State};
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    auth::Auth,
+    deploy_snapshot::{self, Change},
+    projects::{api::{error_response, lookup_error}, repo},
+    startup::AppState,
+};
+
+#[derive(Deserialize, Debug)]
+pub struct DeploymentConfigQuery {
+    /// `prev` to compare with the deployment before this one
+    diff: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct ConfigDiff {
+    /// none when this is the first deployment with a stored configuration
+    previous_build_id: Option<Uuid>,
+    changes: Vec<Change>,
+}
+
+#[derive(Serialize, Debug)]
+struct DeploymentConfigResponse {
+    build_id: Uuid,
+    created_at: DateTime<Utc>,
+    /// none for builds that failed or were deployed before configurations were stored
+    config: Option<serde_json::Value>,
+    /// only with `?diff=prev`
+    diff: Option<ConfigDiff>,
+}
+
+/// Configuration a deployment ran with: environment variables with hashed values, resource
+/// limits, template, port and Traefik labels. `?diff=prev` adds what changed since the
+/// deployment before it.
+#[tracing::instrument(skip(auth, pool))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project, build_id)): Path<(String, String, Uuid)>,
+    Query(DeploymentConfigQuery { diff }): Query<DeploymentConfigQuery>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let with_diff = match diff.as_deref() {
+        None => false,
+        Some("prev") => true,
+        Some(other) => {
+            return error_response(StatusCode::BAD_REQUEST, format!("Unknown diff {other}, only prev is supported"))
+        }
+    };
+
+    let project_record = match repo::find_owned(&pool, user.id, &owner, &project).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE.to_string()),
+        Err(err) => return lookup_error(err),
+    };
+
+    let build = match sqlx::query!(
+        "SELECT id, created_at, runtime_config FROM builds WHERE id = $1 AND project_id = $2",
+        build_id,
+        project_record.id,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(build)) => build,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "Build does not exist".to_string()),
+        Err(err) => {
+            tracing::error!(?err, "Can't get deployment config: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    let diff = match (with_diff, &build.runtime_config) {
+        (true, Some(current)) => {
+            let previous = match sqlx::query!(
+                r#"SELECT id, runtime_config AS "runtime_config!"
+                   FROM builds
+                   WHERE project_id = $1 AND created_at < $2 AND runtime_config IS NOT NULL
+                   ORDER BY created_at DESC
+                   LIMIT 1
+                "#,
+                project_record.id,
+                build.created_at,
+            )
+            .fetch_optional(&pool)
+            .await
+            {
+                Ok(previous) => previous,
+                Err(err) => {
+                    tracing::error!(?err, "Can't get deployment config: Failed to query database");
+                    return error_response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("Failed to query database: {}", err),
+                    );
+                }
+            };
+
+            Some(match previous {
+                Some(previous) => ConfigDiff {
+                    previous_build_id: Some(previous.id),
+                    changes: deploy_snapshot::diff(&previous.runtime_config, current),
+                },
+                None => ConfigDiff {
+                    previous_build_id: None,
+                    changes: Vec::new(),
+                },
+            })
+        }
+        _ => None,
+    };
+
+    let json = serde_json::to_string(&DeploymentConfigResponse {
+        build_id: build.id,
+        created_at: build.created_at,
+        config: build.runtime_config,
+        diff,
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
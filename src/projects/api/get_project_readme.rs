@@ -0,0 +1,118 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use axum::extract::{Path, State};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    auth::Auth,
+    git::{self, Readme},
+    projects::{api::lookup_error, repo},
+    startup::AppState,
+};
+
+lazy_static! {
+    /// README of the last commit each project was asked for, a commit never changes so the
+    /// entry is good until the next push
+    static ref READMES: Mutex<HashMap<Uuid, (String, Option<Readme>)>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Serialize, Debug)]
+struct ReadmeResponse {
+    commit: String,
+    name: String,
+    /// `markdown`, `rst` or `text`
+    format: &'static str,
+    content: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+    /// set when the project has no README, so the page can show how to add one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+}
+
+fn error_response(status: StatusCode, message: String) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse { message, code: None }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .body(Body::from(json))
+        .unwrap()
+}
+
+fn readme_not_found() -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: "Project has no README".to_string(),
+        code: Some("readme_not_found"),
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// README at the root of the last pushed commit, `README.md`, `README.rst` or `README.txt`
+/// in any case. The content is returned as is, rendering it is up to the page.
+#[tracing::instrument(skip(auth, pool, base))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, base, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let project_record = match repo::find_owned(&pool, user.id, &owner, &project).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE.to_string()),
+        Err(err) => return lookup_error(err),
+    };
+
+    // nothing pushed yet
+    let path = format!("{base}/{owner}/{project}.git");
+    let Some(commit) = git::head_commit(&path) else {
+        return readme_not_found();
+    };
+
+    let cached = READMES
+        .lock()
+        .unwrap()
+        .get(&project_record.id)
+        .filter(|(cached_commit, _)| *cached_commit == commit)
+        .map(|(_, readme)| readme.clone());
+    let readme = match cached {
+        Some(readme) => readme,
+        None => {
+            let readme = git::read_readme(&path, &commit);
+            READMES
+                .lock()
+                .unwrap()
+                .insert(project_record.id, (commit.clone(), readme.clone()));
+            readme
+        }
+    };
+
+    let Some(readme) = readme else {
+        return readme_not_found();
+    };
+
+    let json = serde_json::to_string(&ReadmeResponse {
+        commit,
+        name: readme.name,
+        format: readme.format,
+        content: readme.content,
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
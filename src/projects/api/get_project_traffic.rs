@@ -0,0 +1,104 @@
+use axum::extract::{Path, Query, State};
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{auth::Auth, projects::{api::{error_response, lookup_error}, repo}, startup::AppState};
+
+/// Longest range served, in hours
+const MAX_RANGE_HOURS: i64 = 90 * 24;
+
+#[derive(Deserialize, Debug)]
+pub struct TrafficQuery {
+    /// `24h`, `7d` and the like, 7 days when not given
+    range: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct TrafficPoint {
+    /// start of the hour, hours without requests are left out
+    hour: DateTime<Utc>,
+    requests: i64,
+    status_2xx: i64,
+    status_3xx: i64,
+    status_4xx: i64,
+    status_5xx: i64,
+}
+
+#[derive(Serialize, Debug)]
+struct TrafficResponse {
+    since: DateTime<Utc>,
+    points: Vec<TrafficPoint>,
+}
+
+/// Hours in a range like `24h` or `7d`
+fn parse_range(range: &str) -> Option<i64> {
+    let (number, unit) = range.split_at(range.len().checked_sub(1)?);
+    let number = number.parse::<i64>().ok().filter(|number| *number > 0)?;
+    match unit {
+        "h" => Some(number),
+        "d" => number.checked_mul(24),
+        _ => None,
+    }
+}
+
+/// Requests served to the project per hour, read from the Traefik access log when it is
+/// configured
+#[tracing::instrument(skip(auth, pool))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+    Query(TrafficQuery { range }): Query<TrafficQuery>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let hours = match range.as_deref().map(parse_range) {
+        None => 7 * 24,
+        Some(Some(hours)) if hours <= MAX_RANGE_HOURS => hours,
+        Some(_) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "Range has to be like 24h or 7d, and at most 90d".to_string(),
+            )
+        }
+    };
+
+    let project_record = match repo::find_owned(&pool, user.id, &owner, &project).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE.to_string()),
+        Err(err) => return lookup_error(err),
+    };
+
+    let since = Utc::now() - chrono::Duration::hours(hours);
+    let points = match sqlx::query_as!(
+        TrafficPoint,
+        r#"SELECT hour, requests, status_2xx, status_3xx, status_4xx, status_5xx
+           FROM project_traffic
+           WHERE project_id = $1 AND hour > $2::timestamptz - interval '1 hour'
+           ORDER BY hour
+        "#,
+        project_record.id,
+        since,
+    )
+    .fetch_all(&pool)
+    .await
+    {
+        Ok(points) => points,
+        Err(err) => {
+            tracing::error!(?err, "Can't get traffic: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    let json = serde_json::to_string(&TrafficResponse { since, points }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
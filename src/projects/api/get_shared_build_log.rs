@@ -0,0 +1,72 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{projects::{api::{BuildState, error_response}, shares}, redact, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct SharedBuildLogResponse {
+    id: Uuid,
+    status: BuildState,
+    created_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+    logs: String,
+}
+
+/// Log of a build of the project of a share link, with secrets hidden
+#[tracing::instrument(skip(pool, token))]
+pub async fn get(
+    State(AppState { pool, .. }): State<AppState>,
+    Path((token, build_id)): Path<(String, Uuid)>,
+) -> Response<Body> {
+    let shared = match shares::find(&pool, &token).await {
+        Ok(Some(shared)) => shared,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, shares::NOT_FOUND_MESSAGE.to_string()),
+        Err(err) => {
+            tracing::error!(?err, "Can't get shared build log: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    let build = match sqlx::query!(
+        r#"SELECT id, status AS "status: BuildState", created_at, finished_at, log
+           FROM builds
+           WHERE id = $1 AND project_id = $2
+        "#,
+        build_id,
+        shared.id,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(build)) => build,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "Build does not exist".to_string()),
+        Err(err) => {
+            tracing::error!(?err, "Can't get shared build log: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    let json = serde_json::to_string(&SharedBuildLogResponse {
+        id: build.id,
+        status: build.status,
+        created_at: build.created_at,
+        finished_at: build.finished_at,
+        logs: redact::build_log(&build.log, &shared.environs),
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,114 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{projects::{api::{BuildState, error_response}, shares}, startup::AppState};
+
+/// Builds shown on a shared page
+const MAX_BUILDS: i64 = 10;
+
+#[derive(Serialize, Debug)]
+struct SharedBuild {
+    id: Uuid,
+    status: BuildState,
+    trigger: String,
+    commit_sha: Option<String>,
+    created_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug)]
+struct SharedStatusResponse {
+    owner: String,
+    project: String,
+    /// last successful build, none when the project was never deployed
+    deployed_build_id: Option<Uuid>,
+    deployed_sha: Option<String>,
+    builds: Vec<SharedBuild>,
+}
+
+/// Read-only status of the project of a share link, for people without an account
+#[tracing::instrument(skip(pool, token))]
+pub async fn get(
+    State(AppState { pool, .. }): State<AppState>,
+    Path(token): Path<String>,
+) -> Response<Body> {
+    let shared = match shares::find(&pool, &token).await {
+        Ok(Some(shared)) => shared,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, shares::NOT_FOUND_MESSAGE.to_string()),
+        Err(err) => {
+            tracing::error!(?err, "Can't get shared status: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    let builds = match sqlx::query_as!(
+        SharedBuild,
+        r#"SELECT id, status AS "status: BuildState", trigger, commit_sha, created_at, finished_at
+           FROM builds
+           WHERE project_id = $1
+           ORDER BY created_at DESC
+           LIMIT $2
+        "#,
+        shared.id,
+        MAX_BUILDS,
+    )
+    .fetch_all(&pool)
+    .await
+    {
+        Ok(builds) => builds,
+        Err(err) => {
+            tracing::error!(?err, "Can't get shared status: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    let deployed = match sqlx::query!(
+        r#"SELECT id, commit_sha
+           FROM builds
+           WHERE project_id = $1 AND status = 'successful'
+           ORDER BY created_at DESC
+           LIMIT 1
+        "#,
+        shared.id
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(record) => record,
+        Err(err) => {
+            tracing::error!(?err, "Can't get shared status: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    let (deployed_build_id, deployed_sha) = match deployed {
+        Some(deployed) => (Some(deployed.id), deployed.commit_sha),
+        None => (None, None),
+    };
+    let json = serde_json::to_string(&SharedStatusResponse {
+        owner: shared.owner,
+        project: shared.project,
+        deployed_build_id,
+        deployed_sha,
+        builds,
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
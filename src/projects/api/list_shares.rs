@@ -0,0 +1,79 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{auth::Auth, projects::{api::{error_response, lookup_error}, repo}, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct Share {
+    id: Uuid,
+    /// none when the user who made it was deleted
+    created_by: Option<String>,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    expired: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct ListSharesResponse {
+    data: Vec<Share>,
+}
+
+/// Share links of the project, without their tokens
+#[tracing::instrument(skip(auth, pool))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let project_record = match repo::find_owned(&pool, user.id, &owner, &project).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE.to_string()),
+        Err(err) => return lookup_error(err),
+    };
+
+    let shares = match sqlx::query!(
+        r#"SELECT project_shares.id, users.username AS "created_by?", project_shares.created_at, project_shares.expires_at
+           FROM project_shares
+           LEFT JOIN users ON project_shares.created_by = users.id
+           WHERE project_shares.project_id = $1
+           ORDER BY project_shares.created_at
+        "#,
+        project_record.id
+    )
+    .fetch_all(&pool)
+    .await
+    {
+        Ok(shares) => shares,
+        Err(err) => {
+            tracing::error!(?err, "Can't list shares: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    let now = Utc::now();
+    let data = shares
+        .into_iter()
+        .map(|share| Share {
+            id: share.id,
+            created_by: share.created_by,
+            created_at: share.created_at,
+            expired: share.expires_at.map_or(false, |expires_at| expires_at <= now),
+            expires_at: share.expires_at,
+        })
+        .collect();
+    let json = serde_json::to_string(&ListSharesResponse { data }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,74 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{auth::Auth, projects::{api::{error_response, lookup_error}, repo}, ssh_keys, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct SshKey {
+    id: Uuid,
+    name: String,
+    key_type: String,
+    fingerprint: String,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+struct ListSshKeysResponse {
+    /// none when the server doesn't accept ssh
+    clone_url: Option<String>,
+    data: Vec<SshKey>,
+}
+
+#[tracing::instrument(skip(auth, pool))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, ssh_url, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let project_record = match repo::find_owned(&pool, user.id, &owner, &project).await {
+        Ok(Some(record)) => record,
+        Ok(None) => {
+            return error_response(StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE.to_string())
+        }
+        Err(err) => return lookup_error(err),
+    };
+
+    let keys = match sqlx::query_as!(
+        SshKey,
+        r#"SELECT id, name, key_type, fingerprint, created_at
+           FROM ssh_keys
+           WHERE project_id = $1
+           ORDER BY created_at
+        "#,
+        project_record.id
+    )
+    .fetch_all(&pool)
+    .await
+    {
+        Ok(keys) => keys,
+        Err(err) => {
+            tracing::error!(?err, "Can't list ssh keys: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    let json = serde_json::to_string(&ListSshKeysResponse {
+        clone_url: ssh_url.map(|base| ssh_keys::clone_url(&base, &owner, &project)),
+        data: keys,
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
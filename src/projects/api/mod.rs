@@ -1,32 +1,117 @@
 use axum::{middleware, Router, routing::{get, post}};
+use axum::response::Response;
 use axum_extra::routing::RouterExt;
-use hyper::Body;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
 
-use crate::{auth::auth, startup::AppState, configuration::Settings};
+use crate::{auth::auth, idempotency, projects::repo, startup::AppState, configuration::Settings};
 
 mod create_project;
+mod batch_create_project;
 mod project_dashboard;
+mod get_deployment;
+mod get_deployment_config;
+mod update_auto_rebuild;
 mod web_terminal;
 mod delete_project;
+mod restore_project;
 mod delete_volume;
 mod view_build_log;
+mod cancel_build;
+mod stream_build_events;
 mod view_container_log;
+mod get_project_traffic;
+mod get_project_readme;
+mod update_project_details;
 mod view_project_environ;
 mod update_project_environ;
+mod bulk_update_project_environ;
+mod copy_project_environ;
+mod diff_project_environ;
 mod delete_project_environ;
 mod generate_status_badge;
+mod preflight_project;
+mod detect_project;
+mod validate_git_credentials;
+mod list_ssh_keys;
+mod add_ssh_key;
+mod delete_ssh_key;
+mod create_share;
+mod list_shares;
+mod delete_share;
+mod get_shared_status;
+mod get_shared_build_log;
 
-pub async fn router(_state: AppState, _config: &Settings) -> Router<AppState, Body> {
+pub use project_dashboard::BuildState;
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+/// `{"message": ...}` with `status`, how the api answers errors
+pub fn error_response(status: StatusCode, message: String) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse { message }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Answer to a failed project lookup, see [`repo::find_owned`]. One cancelled by the statement
+/// timeout is a 503, so clients know to try again.
+pub fn lookup_error(err: sqlx::Error) -> Response<Body> {
+    if repo::is_statement_timeout(&err) {
+        tracing::error!(?err, "Can't get projects: Query timed out");
+        return error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Database is busy, please try again later".to_string(),
+        );
+    }
+
+    tracing::error!(?err, "Can't get projects: Failed to query database");
+    error_response(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("Failed to query database: {err}"),
+    )
+}
+
+pub async fn router(state: AppState, _config: &Settings) -> Router<AppState, Body> {
     Router::new()
         .route_with_tsr("/api/project/new", post(create_project::post))
+        .route_with_tsr("/api/project/new/batch", post(batch_create_project::post))
         .route_with_tsr("/api/project/:owner/:project/builds", get(project_dashboard::get))
+        .route_with_tsr("/api/project/:owner/:project/deployment", get(get_deployment::get))
+        .route_with_tsr("/api/project/:owner/:project/deployments/:build_id/config", get(get_deployment_config::get))
+        .route_with_tsr("/api/project/:owner/:project/auto-rebuild", post(update_auto_rebuild::post))
         .route_with_tsr("/api/project/:owner/:project/logs", get(view_container_log::get))
+        .route_with_tsr("/api/project/:owner/:project/traffic", get(get_project_traffic::get))
+        .route_with_tsr("/api/project/:owner/:project/readme", get(get_project_readme::get))
+        .route_with_tsr("/api/project/:owner/:project/details", post(update_project_details::post))
         .route_with_tsr("/api/project/:owner/:project/env", get(view_project_environ::get).post(update_project_environ::post))
+        .route_with_tsr("/api/project/:owner/:project/env/bulk", post(bulk_update_project_environ::post))
+        .route_with_tsr("/api/project/:owner/:project/env/copy", post(copy_project_environ::post))
+        .route_with_tsr("/api/project/:owner/:project/env/diff", post(diff_project_environ::post))
         .route_with_tsr("/api/project/:owner/:project/env/delete", post(delete_project_environ::post))
         .route_with_tsr("/api/project/:owner/:project/builds/:build_id", get(view_build_log::get))
+        .route_with_tsr("/api/project/:owner/:project/builds/:build_id/cancel", post(cancel_build::post))
+        .route_with_tsr("/api/project/:owner/:project/builds/:build_id/events", get(stream_build_events::get))
+        .route_with_tsr("/api/project/:owner/:project/preflight", get(preflight_project::get))
+        .route_with_tsr("/api/project/:owner/:project/detect", get(detect_project::get))
         .route_with_tsr("/api/project/:owner/:project/delete", post(delete_project::post))
+        .route_with_tsr("/api/project/:owner/:project/restore", post(restore_project::post))
         .route_with_tsr("/api/project/:owner/:project/volume/delete", post(delete_volume::post))
         .route_with_tsr("/api/project/:owner/:project/terminal/ws", get(web_terminal::ws))
+        .route_with_tsr("/api/project/:owner/:project/ssh-keys", get(list_ssh_keys::get).post(add_ssh_key::post))
+        .route_with_tsr("/api/project/:owner/:project/ssh-keys/:key_id/delete", post(delete_ssh_key::post))
+        .route_with_tsr("/api/project/:owner/:project/share", get(list_shares::get).post(create_share::post))
+        .route_with_tsr("/api/project/:owner/:project/share/:share_id/delete", post(delete_share::post))
+        // runs after auth, which is the outer layer
+        .route_layer(middleware::from_fn_with_state(state, idempotency::layer))
         .route_layer(middleware::from_fn(auth))
         .route_with_tsr("/api/project/:owner/:project/badge/status", get(generate_status_badge::get))
+        .route_with_tsr("/api/project/:owner/:project/git/validate", post(validate_git_credentials::post))
+        .route_with_tsr("/share/:token/status", get(get_shared_status::get))
+        .route_with_tsr("/share/:token/deployments/:build_id/log", get(get_shared_build_log::get))
 }
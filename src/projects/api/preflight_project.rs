@@ -0,0 +1,32 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+
+use crate::{auth::Auth, preflight, projects::{api::{error_response, lookup_error}, repo}, startup::AppState};
+
+/// Check the last pushed source of a project for common misconfigurations
+#[tracing::instrument(skip(auth, pool, base))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, base, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    match repo::find_owned(&pool, user.id, &owner, &project).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return error_response(StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE.to_string());
+        }
+        Err(err) => return lookup_error(err),
+    };
+
+    // same checkout the build uses, see git::receive_pack_rpc
+    let container_src = format!("{base}/{owner}/{project}.git/master");
+    let json = serde_json::to_string(&preflight::check(&container_src)).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -7,7 +7,8 @@ use hyper::{Body, StatusCode};
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
 
-use crate::{auth::Auth, startup::AppState};
+use super::create_project::{clone_command, TOKEN_PLACEHOLDER};
+use crate::{auth::Auth, projects::{api::lookup_error, repo}, ssh_keys, startup::AppState};
 
 #[derive(Serialize, Deserialize, Debug, sqlx::Type)]
 #[sqlx(type_name = "build_state", rename_all = "lowercase")] 
@@ -15,7 +16,8 @@ pub enum BuildState {
     PENDING,
     BUILDING,
     SUCCESSFUL,
-    FAILED
+    FAILED,
+    CANCELLED,
 }
 
 impl fmt::Display for BuildState {
@@ -25,6 +27,7 @@ impl fmt::Display for BuildState {
             BuildState::BUILDING => write!(f, "Building"),
             BuildState::SUCCESSFUL => write!(f, "Successful"),
             BuildState::FAILED => write!(f, "Failed"),
+            BuildState::CANCELLED => write!(f, "Cancelled"),
         }
     }
 }
@@ -40,63 +43,52 @@ struct Build {
     status: BuildState,
     created_at: DateTime<Utc>,
     finished_at: Option<DateTime<Utc>>,
+    framework: Option<String>,
+    dockerfile: Option<String>,
+    /// push, scheduled or manual
+    trigger: String,
 }
 
 #[derive(Serialize, Debug)]
 struct ProjectBuildListResponse {
-    data: Vec<Build>
+    data: Vec<Build>,
+    /// last time any of the project's git credentials pushed or fetched
+    last_push_at: Option<DateTime<Utc>>,
+    last_fetch_at: Option<DateTime<Utc>>,
+    /// with a placeholder, the token is only shown when the project is created
+    clone_command: String,
+    /// none when the server doesn't accept ssh
+    ssh_clone_url: Option<String>,
+    /// findings of the most recent image scan
+    latest_scan: Option<serde_json::Value>,
 }
 
 #[tracing::instrument(skip(auth, pool))]
 pub async fn get(
     auth: Auth,
-    State(AppState { pool, domain, secure, .. }): State<AppState>,
+    State(AppState { pool, domain, secure, ssh_url, .. }): State<AppState>,
     Path((owner, project)): Path<(String, String)>,
 ) -> Response<Body> {
-    let _user = auth.current_user.unwrap();
+    let user = auth.current_user.unwrap();
 
     // check if project exist
-    let project_record = match sqlx::query!(
-        r#"SELECT projects.id, projects.name AS project, project_owners.name AS owner
-           FROM projects
-           JOIN project_owners ON projects.owner_id = project_owners.id
-           JOIN users_owners ON project_owners.id = users_owners.owner_id
-           AND projects.name = $1
-           AND project_owners.name = $2
-        "#,
-        project,
-        owner,
-    )
-    .fetch_optional(&pool)
-    .await
-    {
+    let project_record = match repo::find_owned(&pool, user.id, &owner, &project).await {
         Ok(Some(record)) => record,
         Ok(None) => {
             let json = serde_json::to_string(&ErrorResponse {
-                message: "Project does not exist".to_string(),
-            }).unwrap();
-
-            return Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(Body::from(json))
-                .unwrap();
-        }
-        Err(err) => {
-            tracing::error!(?err, "Can't get projects: Failed to query database");
-
-            let json = serde_json::to_string(&ErrorResponse {
-                message: format!("Failed to query database: {}", err.to_string()),
+                message: repo::NOT_FOUND_MESSAGE.to_string(),
             }).unwrap();
 
             return Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .status(StatusCode::NOT_FOUND)
                 .body(Body::from(json))
                 .unwrap();
         }
+        Err(err) => return lookup_error(err),
     };
 
     let build_records = match sqlx::query!(
-        r#"SELECT id, project_id, status AS "status: BuildState", created_at, finished_at 
+        r#"SELECT id, project_id, status AS "status: BuildState", created_at, finished_at, framework, dockerfile, trigger
         FROM builds WHERE project_id = $1
         ORDER BY created_at DESC"#,
         project_record.id
@@ -107,7 +99,7 @@ pub async fn get(
         Ok(records) => records,
         Err(err) => {
             let json = serde_json::to_string(&ErrorResponse {
-                message: format!("Failed to query database: {}", err.to_string()),
+                message: format!("Failed to query database: {}", err),
             }).unwrap();
 
             return Response::builder()
@@ -123,11 +115,63 @@ pub async fn get(
             status: record.status,
             created_at: record.created_at,
             finished_at: record.finished_at,
+            framework: record.framework,
+            dockerfile: record.dockerfile,
+            trigger: record.trigger,
         }
     }).collect::<Vec<_>>();
 
+    let token_usage = match sqlx::query!(
+        r#"SELECT MAX(last_push_at) AS last_push_at, MAX(last_fetch_at) AS last_fetch_at
+        FROM api_token WHERE project_id = $1 AND deleted_at IS NULL"#,
+        project_record.id
+    )
+    .fetch_one(&pool)
+    .await
+    {
+        Ok(record) => record,
+        Err(err) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Failed to query database: {}", err),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        },
+    };
+
+    let latest_scan = match sqlx::query!(
+        r#"SELECT scan_summary FROM builds
+        WHERE project_id = $1 AND scan_summary IS NOT NULL
+        ORDER BY created_at DESC
+        LIMIT 1"#,
+        project_record.id
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(record) => record.and_then(|record| record.scan_summary),
+        Err(err) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: format!("Failed to query database: {}", err),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        },
+    };
+
     let json = serde_json::to_string(&ProjectBuildListResponse {
-        data: builds
+        data: builds,
+        last_push_at: token_usage.last_push_at,
+        last_fetch_at: token_usage.last_fetch_at,
+        clone_command: clone_command(secure, &domain, &owner, &project, TOKEN_PLACEHOLDER),
+        ssh_clone_url: ssh_url.map(|base| ssh_keys::clone_url(&base, &owner, &project)),
+        latest_scan,
     }).unwrap();
 
     Response::builder()
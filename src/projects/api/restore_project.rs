@@ -0,0 +1,107 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use chrono::Utc;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::{
+    auth::{Auth, ADMIN_PERMISSION},
+    projects::{api::error_response, deletion::{self, Redeploy}, repo},
+    startup::AppState,
+};
+
+#[derive(Serialize, Debug)]
+struct RestoreProjectResponse {
+    message: String,
+    /// `started` when the kept containers run again, `queued` when the project is rebuilt
+    redeploy: &'static str,
+}
+
+/// Bring back a deleted project before it is purged. Like deleting, it is left to the owner
+/// whose name matches the user's, and to admins.
+#[tracing::instrument(skip(auth, pool, docker, build_channel))]
+pub async fn post(
+    auth: Auth,
+    State(AppState {
+        pool,
+        base,
+        docker,
+        container_prefix,
+        build_channel,
+        deletion_retention,
+        ..
+    }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    if user.username != owner && !user.permissions.contains(ADMIN_PERMISSION) {
+        return error_response(StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE.to_string());
+    }
+
+    let record = match deletion::find(&pool, &owner, &project).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE.to_string()),
+        Err(err) => {
+            tracing::error!(?err, "Can't restore project: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    let Some(deleted_at) = record.deleted_at else {
+        return error_response(StatusCode::CONFLICT, "Project is not deleted".to_string());
+    };
+    if deletion::purge_at(deleted_at, deletion_retention) <= Utc::now() {
+        return error_response(
+            StatusCode::GONE,
+            format!("Project was deleted more than {deletion_retention} hours ago, it is about to be purged"),
+        );
+    }
+
+    let redeploy = match deletion::restore(
+        &pool,
+        &docker,
+        &container_prefix,
+        &base,
+        &build_channel,
+        deletion_retention,
+        &record,
+    )
+    .await
+    {
+        Ok(Some(redeploy)) => redeploy,
+        Ok(None) => {
+            return error_response(
+                StatusCode::CONFLICT,
+                "Project was restored or purged in the meantime".to_string(),
+            )
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't restore project: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    };
+
+    tracing::info!(%owner, %project, ?redeploy, "Restored deleted project");
+
+    let (message, redeploy) = match redeploy {
+        Redeploy::Started => ("Project restored", "started"),
+        Redeploy::Queued => ("Project restored, it is being rebuilt from its repository", "queued"),
+    };
+    let json = serde_json::to_string(&RestoreProjectResponse {
+        message: message.to_string(),
+        redeploy,
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
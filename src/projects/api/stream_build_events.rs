@@ -0,0 +1,81 @@
+use std::convert::Infallible;
+
+use axum::extract::{Path, State};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use futures::StreamExt;
+use hyper::StatusCode;
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use crate::{auth::Auth, events, projects::{api::{self, lookup_error}, repo}, startup::AppState};
+
+/// The api's error answer, as the response type of an sse handler
+fn error_response(status: StatusCode, message: String) -> Response {
+    api::error_response(status, message).into_response()
+}
+
+/// Follow the steps of a queued or running build as server-sent events. Every event is a
+/// `step` event holding a JSON build event, starting with the step the build is at and
+/// ending with `finished` and the status the build ended with.
+#[tracing::instrument(skip(auth, pool))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project, build_id)): Path<(String, String, Uuid)>,
+) -> Response {
+    let user = auth.current_user.unwrap();
+
+    let project_record = match repo::find_owned(&pool, user.id, &owner, &project).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE.to_string()),
+        Err(err) => return lookup_error(err).into_response(),
+    };
+
+    match sqlx::query!(
+        "SELECT id FROM builds WHERE id = $1 AND project_id = $2",
+        build_id,
+        project_record.id,
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "Build does not exist".to_string()),
+        Err(err) => {
+            tracing::error!(?err, "Can't stream build events: Failed to query database");
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to query database: {}", err),
+            );
+        }
+    }
+
+    let Some((current, receiver)) = events::subscribe(build_id) else {
+        return error_response(
+            StatusCode::CONFLICT,
+            "Build has already finished, its status is in the build details".to_string(),
+        );
+    };
+
+    let stream = futures::stream::unfold((Some(current), receiver), |(current, mut receiver)| async move {
+        if let Some(event) = current {
+            return Some((event, (None, receiver)));
+        }
+
+        loop {
+            match receiver.recv().await {
+                Ok(event) => return Some((event, (None, receiver))),
+                // a slow client skips the steps it missed, the next one still says where the build is
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+    .map(|event| {
+        let data = serde_json::to_string(&event).unwrap();
+        Ok::<_, Infallible>(Event::default().event("step").data(data))
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default()).into_response()
+}
@@ -0,0 +1,74 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use axum::Json;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::Auth,
+    projects::{api::{error_response, lookup_error}, repo},
+    schedule::{Schedule, DEFAULT_SCHEDULE},
+    startup::AppState,
+};
+
+#[derive(Deserialize, Debug)]
+pub struct UpdateAutoRebuildRequest {
+    pub enabled: bool,
+    /// cron expression in UTC, weekly when not given
+    pub schedule: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct UpdateAutoRebuildResponse {
+    /// none when automatic rebuilds are off
+    schedule: Option<String>,
+}
+
+/// Turn automatic rebuilds of the project on or off. Rebuilds pull a fresh base image and
+/// only replace the running container once the new one is ready.
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+    Json(req): Json<UpdateAutoRebuildRequest>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let schedule = match (req.enabled, req.schedule) {
+        (false, _) => None,
+        (true, None) => Some(DEFAULT_SCHEDULE.to_string()),
+        (true, Some(schedule)) => match Schedule::parse(&schedule) {
+            Ok(_) => Some(schedule.trim().to_string()),
+            Err(err) => return error_response(StatusCode::BAD_REQUEST, format!("Invalid schedule: {err}")),
+        },
+    };
+
+    let project_record = match repo::find_owned(&pool, user.id, &owner, &project).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE.to_string()),
+        Err(err) => return lookup_error(err),
+    };
+
+    if let Err(err) = sqlx::query!(
+        "UPDATE projects SET auto_rebuild = $1, updated_at = now() WHERE id = $2",
+        schedule,
+        project_record.id,
+    )
+    .execute(&pool)
+    .await
+    {
+        tracing::error!(?err, "Can't update auto rebuild: Failed to query database");
+        return error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to query database: {}", err),
+        );
+    }
+
+    let json = serde_json::to_string(&UpdateAutoRebuildResponse { schedule }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
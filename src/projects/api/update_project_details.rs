@@ -0,0 +1,90 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use garde::Validate;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{auth::Auth, projects::{api::{error_response, lookup_error}, repo}, startup::AppState, validation::{self, ValidJson}};
+
+/// Longest description, in characters
+const MAX_DESCRIPTION_LENGTH: usize = 500;
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct UpdateProjectDetailsRequest {
+    /// cleared when empty or not given
+    #[garde(custom(description_length))]
+    pub description: Option<String>,
+    /// http or https url, cleared when empty or not given
+    #[garde(custom(validation::http_url))]
+    pub website_url: Option<String>,
+}
+
+/// Counted in characters once trimmed, like it is stored
+fn description_length(value: &Option<String>, _ctx: &()) -> garde::Result {
+    match value {
+        Some(description) if description.trim().chars().count() > MAX_DESCRIPTION_LENGTH => Err(garde::Error::new(
+            format!("Description can't be longer than {MAX_DESCRIPTION_LENGTH} characters"),
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct UpdateProjectDetailsResponse {
+    description: Option<String>,
+    website_url: Option<String>,
+}
+
+/// Set the description and website shown on the project page and in the project list
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+    ValidJson(req): ValidJson<UpdateProjectDetailsRequest>,
+) -> Response<Body> {
+    let user = auth.current_user.unwrap();
+
+    let description = req
+        .description
+        .map(|description| description.trim().to_string())
+        .filter(|description| !description.is_empty());
+
+    let website_url = req
+        .website_url
+        .map(|website_url| website_url.trim().to_string())
+        .filter(|website_url| !website_url.is_empty());
+
+    let project_record = match repo::find_owned(&pool, user.id, &owner, &project).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE.to_string()),
+        Err(err) => return lookup_error(err),
+    };
+
+    if let Err(err) = sqlx::query!(
+        "UPDATE projects SET description = $1, website_url = $2, updated_at = now() WHERE id = $3",
+        description,
+        website_url,
+        project_record.id,
+    )
+    .execute(&pool)
+    .await
+    {
+        tracing::error!(?err, "Can't update project details: Failed to query database");
+        return error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to query database: {}", err),
+        );
+    }
+
+    let json = serde_json::to_string(&UpdateProjectDetailsResponse {
+        description,
+        website_url,
+    })
+    .unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
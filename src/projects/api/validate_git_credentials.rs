@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::extract::{Path, State};
+use axum::response::Response;
+use axum::Json;
+use hyper::{header, header::HeaderValue, Body, StatusCode};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use crate::{git, projects::api::error_response, startup::AppState};
+
+/// Attempts allowed per project in each window
+const MAX_ATTEMPTS: u32 = 10;
+const WINDOW: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    /// Start of the current window and the attempts made in it, per project
+    static ref ATTEMPTS: Mutex<HashMap<String, (Instant, u32)>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ValidateGitCredentialsRequest {
+    pub username: String,
+    pub token: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ValidateGitCredentialsResponse {
+    valid: bool,
+}
+
+/// Count an attempt against a project. Returns how long to wait when it is over the limit.
+fn throttle(key: String) -> Option<Duration> {
+    let now = Instant::now();
+    let mut attempts = ATTEMPTS.lock().unwrap();
+    attempts.retain(|_, (start, _)| now.duration_since(*start) < WINDOW);
+
+    let (start, count) = attempts.entry(key).or_insert((now, 0));
+    if *count >= MAX_ATTEMPTS {
+        return Some(WINDOW - now.duration_since(*start));
+    }
+
+    *count += 1;
+    None
+}
+
+/// Check a git username and token against a project without running any git operation, so CI
+/// can verify its credentials before pushing. Unknown projects are reported as invalid
+/// credentials, and attempts are limited per project so the endpoint can't be used to guess
+/// tokens.
+#[tracing::instrument(skip(pool, req), fields(username = %req.username))]
+pub async fn post(
+    State(AppState { pool, hasher, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+    Json(req): Json<ValidateGitCredentialsRequest>,
+) -> Response<Body> {
+    if let Some(retry_after) = throttle(format!("{owner}/{project}")) {
+        let mut res = error_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many attempts, please try again later".to_string(),
+        );
+        res.headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(retry_after.as_secs().max(1)));
+        return res;
+    }
+
+    // git authenticates with the owner name as the username
+    let valid = req.username == owner
+        && match git::find_token(&pool, &hasher, &owner, &project, &req.token).await {
+            Ok(token) => token.is_some(),
+            Err(err) => {
+                tracing::error!(?err, "Can't validate git credentials: Failed to query database");
+                return error_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to query database: {}", err),
+                );
+            }
+        };
+
+    let json = serde_json::to_string(&ValidateGitCredentialsResponse { valid }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -7,15 +7,21 @@ use hyper::{Body, StatusCode};
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
 
-use crate::{auth::Auth, startup::AppState};
+use crate::{auth::Auth, projects::{api::lookup_error, repo}, startup::AppState, system::capacity::{self, QueuePosition}};
+
+/// Bounds of the `Retry-After` of a pending build, the estimated wait when there is one
+const POLL_MIN_SECS: u64 = 2;
+const POLL_MAX_SECS: u64 = 30;
 
 #[derive(Serialize, Deserialize, Debug, sqlx::Type)]
 #[sqlx(type_name = "build_state", rename_all = "lowercase")] 
+#[allow(clippy::upper_case_acronyms)]
 pub enum BuildState {
     PENDING,
     BUILDING,
     SUCCESSFUL,
-    FAILED
+    FAILED,
+    CANCELLED,
 }
 
 impl fmt::Display for BuildState {
@@ -25,6 +31,7 @@ impl fmt::Display for BuildState {
             BuildState::BUILDING => write!(f, "Building"),
             BuildState::SUCCESSFUL => write!(f, "Successful"),
             BuildState::FAILED => write!(f, "Failed"),
+            BuildState::CANCELLED => write!(f, "Cancelled"),
         }
     }
 }
@@ -35,7 +42,13 @@ struct BuildDetailResponse {
     status: BuildState,
     created_at: DateTime<Utc>,
     finished_at: Option<DateTime<Utc>>,
-    logs: String
+    framework: Option<String>,
+    dockerfile: Option<String>,
+    /// push, scheduled or manual
+    trigger: String,
+    logs: String,
+    /// where the build stands in the queue while it is pending
+    queue: Option<QueuePosition>,
 }
 
 #[derive(Serialize, Debug)]
@@ -46,53 +59,29 @@ struct ErrorResponse {
 #[tracing::instrument(skip(auth, pool))]
 pub async fn get(
     auth: Auth,
-    State(AppState { pool, domain, secure, .. }): State<AppState>,
+    State(AppState { pool, domain, secure, build_max, .. }): State<AppState>,
     Path((owner, project, build_id)): Path<(String, String, Uuid)>,
 ) -> Response<Body> {
-    let _user = auth.current_user.unwrap();
+    let user = auth.current_user.unwrap();
 
     // check if project exist
-    let _project_record = match sqlx::query!(
-        r#"SELECT projects.id, projects.name AS project, project_owners.name AS owner
-           FROM projects
-           JOIN project_owners ON projects.owner_id = project_owners.id
-           JOIN users_owners ON project_owners.id = users_owners.owner_id
-           AND projects.name = $1
-           AND project_owners.name = $2
-        "#,
-        project,
-        owner,
-    )
-    .fetch_optional(&pool)
-    .await
-    {
+    let _project_record = match repo::find_owned(&pool, user.id, &owner, &project).await {
         Ok(Some(record)) => record,
         Ok(None) => {
             let json = serde_json::to_string(&ErrorResponse {
-                message: "Project does not exist".to_string()
+                message: repo::NOT_FOUND_MESSAGE.to_string()
             }).unwrap();
 
             return Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(Body::from(json))
-                .unwrap();
-        }
-        Err(err) => {
-            tracing::error!(?err, "Can't get projects: Failed to query database");
-
-            let json = serde_json::to_string(&ErrorResponse {
-                message: format!("Failed to query database: {}", err.to_string())
-            }).unwrap();
-
-            return Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .status(StatusCode::NOT_FOUND)
                 .body(Body::from(json))
                 .unwrap();
         }
+        Err(err) => return lookup_error(err),
     };
 
     let build = match sqlx::query!(
-        r#"SELECT id, project_id, status AS "status: BuildState", created_at, finished_at, log 
+        r#"SELECT id, project_id, status AS "status: BuildState", created_at, finished_at, framework, dockerfile, trigger, log 
         FROM builds WHERE id = $1
         ORDER BY created_at DESC"#,
         build_id
@@ -103,7 +92,7 @@ pub async fn get(
         Ok(record) => record,
         Err(err) => {
             let json = serde_json::to_string(&ErrorResponse {
-                message: format!("Failed to query database: {}", err.to_string())
+                message: format!("Failed to query database: {}", err)
             }).unwrap();
 
             return Response::builder()
@@ -113,16 +102,37 @@ pub async fn get(
         }, 
     };
 
+    let queue = match build.status {
+        BuildState::PENDING => capacity::position(&pool, build.id, build_max).await.unwrap_or_else(|err| {
+            tracing::error!(?err, "Can't get queue position: Failed to query database");
+            None
+        }),
+        _ => None,
+    };
+    // tells pollers of a queued build when checking again is worth it
+    let retry_after = queue.as_ref().map(|queue| {
+        queue
+            .estimated_wait_secs
+            .unwrap_or(POLL_MAX_SECS)
+            .clamp(POLL_MIN_SECS, POLL_MAX_SECS)
+    });
+
     let json = serde_json::to_string(&BuildDetailResponse {
         id: build.id,
         status: build.status,
         created_at: build.created_at,
         finished_at: build.finished_at,
+        framework: build.framework,
+        dockerfile: build.dockerfile,
+        trigger: build.trigger,
         logs: build.log,
+        queue,
     }).unwrap();
 
-    Response::builder()
-        .status(StatusCode::OK)
-        .body(Body::from(json))
-        .unwrap()
+    let mut response = Response::builder().status(StatusCode::OK);
+    if let Some(retry_after) = retry_after {
+        response = response.header("Retry-After", retry_after.to_string());
+    }
+
+    response.body(Body::from(json)).unwrap()
 }
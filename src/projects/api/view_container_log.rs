@@ -1,13 +1,18 @@
-use axum::extract::{State, Path};
+use axum::extract::{State, Path, Query};
 use axum::response::Response;
 use bollard::container::{LogsOptions, LogOutput};
-use bollard::Docker;
 use futures::StreamExt;
 use hyper::{Body, StatusCode};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::{auth::Auth, startup::AppState};
+use crate::{auth::Auth, daemon_limits, docker::{docker_name, find_process_container, Process}, projects::repo, startup::AppState};
+
+#[derive(Deserialize, Debug)]
+pub struct LogQuery {
+    /// `web` (the default), `worker` or `worker-N`
+    process: Option<String>,
+}
 
 #[derive(Serialize, Debug)]
 struct LogResponse {
@@ -20,13 +25,14 @@ struct ErrorResponse {
     message: String,
 }
 
-#[tracing::instrument(skip(auth, pool))]
+#[tracing::instrument(skip(auth, pool, docker))]
 pub async fn get(
     auth: Auth,
-    State(AppState { pool, domain, secure, .. }): State<AppState>,
-    Path((owner, project)): Path<(String, String)>,
+    State(AppState { pool, domain, secure, docker, container_prefix, .. }): State<AppState>,
+    Path((owner, project_name)): Path<(String, String)>,
+    Query(LogQuery { process }): Query<LogQuery>,
 ) -> Response<Body> {
-    let _user = auth.current_user.unwrap();
+    let user = auth.current_user.unwrap();
 
     // check if project exist
     let project = match sqlx::query!(
@@ -37,9 +43,12 @@ pub async fn get(
            JOIN domains ON domains.project_id = projects.id
            AND projects.name = $1
            AND project_owners.name = $2
+           AND users_owners.user_id = $3
+           AND projects.deleted_at IS NULL
         "#,
-        project,
+        project_name,
         owner,
+        user.id,
     )
     .fetch_optional(&pool)
     .await
@@ -47,11 +56,11 @@ pub async fn get(
         Ok(Some(record)) => record,
         Ok(None) => {
             let json = serde_json::to_string(&ErrorResponse {
-                message: "Project does not exist".to_string()
+                message: repo::NOT_FOUND_MESSAGE.to_string()
             }).unwrap();
 
             return Response::builder()
-                .status(StatusCode::BAD_REQUEST)
+                .status(StatusCode::NOT_FOUND)
                 .body(Body::from(json))
                 .unwrap();
         }
@@ -59,7 +68,7 @@ pub async fn get(
             tracing::error!(?err, "Can't get projects: Failed to query database");
 
             let json = serde_json::to_string(&ErrorResponse {
-                message: format!("Failed to query database: {}", err.to_string())
+                message: format!("Failed to query database: {}", err)
             }).unwrap();
 
             return Response::builder()
@@ -69,16 +78,31 @@ pub async fn get(
         }
     };
 
-    let docker = match Docker::connect_with_local_defaults().map_err(|err| {
-        tracing::error!("Failed to connect to docker: {}", err);
-        err
-    }) {
-        Ok(docker) => docker,
+    let process = process.as_deref().unwrap_or("web");
+    let Some(process) = Process::parse(process) else {
+        let json = serde_json::to_string(&ErrorResponse {
+            message: format!("Unknown process {process}, expected web, worker or worker-N")
+        }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from(json))
+            .unwrap();
+    };
+
+    let _permit = match daemon_limits::interactive().await {
+        Ok(permit) => permit,
+        Err(busy) => return busy.response(),
+    };
+
+    let container_name = docker_name(&container_prefix, &project.container_name);
+    let container = match find_process_container(&docker, &owner, &project_name, &container_name, process).await {
+        Ok(container) => container,
         Err(err) => {
-            tracing::error!(?err, "Failed to connect to docker");
+            tracing::error!(?err, "Can't get logs: Failed to list containers");
 
             let json = serde_json::to_string(&ErrorResponse {
-                message: format!("Failed to connect to docker: {}", err.to_string())
+                message: format!("Failed to list containers: {}", err)
             }).unwrap();
 
             return Response::builder()
@@ -88,7 +112,7 @@ pub async fn get(
         }
     };
 
-    let log_stream = &mut docker.logs(&project.container_name, Some(LogsOptions {
+    let log_stream = &mut docker.logs(&container, Some(LogsOptions {
         tail: "100",
         stdout: true,
         stderr: true,
@@ -110,7 +134,7 @@ pub async fn get(
 
     let json = serde_json::to_string(&LogResponse {
         id: project.id,
-        logs: logs,
+        logs,
     }).unwrap();
 
     Response::builder()
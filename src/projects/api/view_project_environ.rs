@@ -5,12 +5,13 @@ use serde::Serialize;
 use serde_json::Value;
 use uuid::Uuid;
 
-use crate::{auth::Auth, startup::AppState};
+use crate::{auth::Auth, projects::repo, startup::AppState};
 
 #[derive(Serialize, Debug)]
 struct EnvironResponse {
     id: Uuid,
     env: Value,
+    version: i64,
 }
 
 #[derive(Serialize, Debug)]
@@ -24,19 +25,22 @@ pub async fn get(
     State(AppState { pool, domain, secure, .. }): State<AppState>,
     Path((owner, project)): Path<(String, String)>,
 ) -> Response<Body> {
-    let _user = auth.current_user.unwrap();
+    let user = auth.current_user.unwrap();
 
     // check if project exist
     let project = match sqlx::query!(
-        r#"SELECT projects.id AS id, projects.name AS project, projects.environs AS env
+        r#"SELECT projects.id AS id, projects.name AS project, projects.environs AS env, projects.environs_version AS version
            FROM projects
            JOIN project_owners ON projects.owner_id = project_owners.id
            JOIN users_owners ON project_owners.id = users_owners.owner_id
            AND projects.name = $1
            AND project_owners.name = $2
+           AND users_owners.user_id = $3
+           AND projects.deleted_at IS NULL
         "#,
         project,
         owner,
+        user.id,
     )
     .fetch_optional(&pool)
     .await
@@ -44,11 +48,11 @@ pub async fn get(
         Ok(Some(record)) => record,
         Ok(None) => {
             let json = serde_json::to_string(&ErrorResponse {
-                message: "Project does not exist".to_string()
+                message: repo::NOT_FOUND_MESSAGE.to_string()
             }).unwrap();
 
             return Response::builder()
-                .status(StatusCode::BAD_REQUEST)
+                .status(StatusCode::NOT_FOUND)
                 .body(Body::from(json))
                 .unwrap();
         }
@@ -56,7 +60,7 @@ pub async fn get(
             tracing::error!(?err, "Can't get projects: Failed to query database");
 
             let json = serde_json::to_string(&ErrorResponse {
-                message: format!("Failed to query database: {}", err.to_string())
+                message: format!("Failed to query database: {}", err)
             }).unwrap();
 
             return Response::builder()
@@ -69,10 +73,12 @@ pub async fn get(
     let json = serde_json::to_string(&EnvironResponse {
         id: project.id,
         env: project.env,
+        version: project.version,
     }).unwrap();
 
     Response::builder()
         .status(StatusCode::OK)
+        .header("ETag", format!("\"{}\"", project.version))
         .body(Body::from(json))
         .unwrap()
 }
@@ -1,21 +1,25 @@
 use std::{net::SocketAddr, time::Duration, borrow::Cow};
 
-use axum::{extract::{WebSocketUpgrade, Path, ConnectInfo, ws::{Message, CloseFrame}}, TypedHeader, headers, response::IntoResponse};
+use axum::{extract::{WebSocketUpgrade, Path, State, ConnectInfo, ws::{Message, CloseFrame}}, TypedHeader, headers, response::IntoResponse};
+use hyper::StatusCode;
 use bollard::{Docker, exec::{CreateExecOptions, StartExecResults}};
 use futures_util::{StreamExt, SinkExt};
 use tokio::io::AsyncWriteExt;
 use serde::{Deserialize, Serialize};
 
+use crate::{auth::Auth, daemon_limits, docker::{docker_name, find_process_container, Process}, projects::repo, startup::AppState};
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct WsRequest {
     pub message: String,
 }
 
-#[tracing::instrument]
+#[tracing::instrument(skip(auth, pool))]
 pub async fn ws(
+    auth: Auth,
     Path((owner, project)): Path<(String, String)>,
-    // State(AppState { pool, base, .. }): State<AppState>,
+    State(AppState { pool, container_prefix, .. }): State<AppState>,
     ws: WebSocketUpgrade,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
@@ -31,6 +35,22 @@ pub async fn ws(
 
     tracing::info!(user_agent, "New websocket connection");
 
+    let user = auth.current_user.unwrap();
+    let container_name = match repo::find_owned(&pool, user.id, &owner, project.trim_end_matches(".git")).await {
+        Ok(Some(project)) => docker_name(&container_prefix, &project.container_name),
+        Ok(None) => return (StatusCode::NOT_FOUND, repo::NOT_FOUND_MESSAGE).into_response(),
+        Err(err) => {
+            tracing::error!(?err, "Can't start terminal: Failed to query database");
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to query database: {}", err)).into_response();
+        }
+    };
+
+    // held until the shell runs, the session itself doesn't take a slot
+    let permit = match daemon_limits::interactive().await {
+        Ok(permit) => permit,
+        Err(busy) => return busy.response().into_response(),
+    };
+
     ws.on_upgrade(move |mut socket| {
         async move {
             //send a ping (unsupported by some browsers) just to kick things off and get a response
@@ -68,10 +88,16 @@ pub async fn ws(
                 }
             };
 
-            let container_name = format!("{owner}-{}", project.trim_end_matches(".git")).replace('.', "-");
+            let container = match find_process_container(&docker, &owner, &project, &container_name, Process::Web).await {
+                Ok(container) => container,
+                Err(err) => {
+                    tracing::error!(?err, "Can't start terminal: Failed to list containers");
+                    return;
+                }
+            };
             let exec = match docker
                 .create_exec(
-                    &container_name,
+                    &container,
                     CreateExecOptions::<&str> {
                         attach_stdout: Some(true),
                         attach_stderr: Some(true),
@@ -101,6 +127,7 @@ pub async fn ws(
                     return;
                 }
             };
+            drop(permit);
 
             // By splitting socket we can send and receive at the same time. In this example we will send
             let (mut sender, mut receiver) = socket.split();
@@ -173,7 +200,7 @@ pub async fn ws(
                                     },
                                     Ok(msg) => {
                                         let mut msg = msg.message;
-                                        msg.push_str("\n");
+                                        msg.push('\n');
                                         match input.write_all(msg.as_bytes()).await {
                                             Err(err) => {
                                                 tracing::error!(?err, "Can't write to terminal");
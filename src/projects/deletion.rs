@@ -0,0 +1,439 @@
+use std::{collections::HashMap, path::Path, time::Duration};
+
+use bollard::Docker;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use tokio::sync::mpsc::Sender;
+use uuid::Uuid;
+
+use crate::{
+    docker::{docker_name, NAME_LABEL, OWNER_LABEL, PROCESS_LABEL, PROJECT_LABEL},
+    projects::repo,
+    queue::{BuildQueueItem, BuildTrigger},
+    runtime::ContainerRuntime,
+};
+
+/// How often projects past their retention are looked for
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Seconds a container gets to exit when a project is deleted
+const STOP_TIMEOUT_SECS: i64 = 10;
+
+/// A project found by owner and name, deleted or not
+#[derive(Debug)]
+pub struct Project {
+    pub id: Uuid,
+    pub owner: String,
+    pub name: String,
+    pub container_name: String,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+/// The project whatever its state, deletion has to see soft-deleted ones
+pub async fn find(pool: &PgPool, owner: &str, project: &str) -> Result<Option<Project>, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"SELECT projects.id, projects.container_name, projects.deleted_at
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.name = $1 AND project_owners.name = $2
+        "#,
+        project,
+        owner,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record.map(|record| Project {
+        id: record.id,
+        owner: owner.to_string(),
+        name: project.to_string(),
+        container_name: record.container_name,
+        deleted_at: record.deleted_at,
+    }))
+}
+
+/// When a project deleted at `deleted_at` is purged, after `retention` hours
+pub fn purge_at(deleted_at: DateTime<Utc>, retention: u64) -> DateTime<Utc> {
+    deleted_at + chrono::Duration::hours(retention as i64)
+}
+
+/// Why a name can't be used while the deleted project holding it isn't purged yet
+pub fn name_held_message(deleted_at: DateTime<Utc>, retention: u64) -> String {
+    format!(
+        "A deleted project holds this name until {}, restore it or ask an admin to delete it for good with ?hard=true",
+        purge_at(deleted_at, retention)
+    )
+}
+
+/// Mark the project deleted and stop its containers. The repository, image, containers and
+/// rows are kept until the project is purged, so it can be restored. Returns none when the
+/// project was already deleted.
+pub async fn soft_delete(
+    pool: &PgPool,
+    docker: &dyn ContainerRuntime,
+    container_prefix: &str,
+    project: &Project,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    let deleted_at = sqlx::query_scalar!(
+        r#"UPDATE projects SET deleted_at = now(), updated_at = now()
+           WHERE id = $1 AND deleted_at IS NULL
+           RETURNING deleted_at AS "deleted_at!""#,
+        project.id,
+    )
+    .fetch_optional(pool)
+    .await?;
+    if deleted_at.is_none() {
+        return Ok(None);
+    }
+    repo::invalidate_project(&project.owner, &project.name);
+
+    // a stopped container drops out of Traefik and the proxy, which is all routing needs
+    for container in project_containers(docker, container_prefix, project).await {
+        if let Err(err) = docker.stop_container(&container, STOP_TIMEOUT_SECS).await {
+            tracing::warn!(?err, %container, "Can't stop container of deleted project");
+        }
+    }
+
+    Ok(deleted_at)
+}
+
+/// How a restored project comes back
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Redeploy {
+    /// its kept containers were started again
+    Started,
+    /// its containers were gone, the build queue deploys it again from its repository
+    Queued,
+}
+
+/// Undo a soft delete and bring the project's containers back. Returns none when the project
+/// isn't deleted, or was deleted more than `retention` hours ago and is about to be purged.
+pub async fn restore(
+    pool: &PgPool,
+    docker: &dyn ContainerRuntime,
+    container_prefix: &str,
+    base: &str,
+    build_channel: &Sender<BuildQueueItem>,
+    retention: u64,
+    project: &Project,
+) -> Result<Option<Redeploy>, sqlx::Error> {
+    let restored = sqlx::query!(
+        r#"UPDATE projects SET deleted_at = NULL, updated_at = now()
+           WHERE id = $1 AND deleted_at > now() - make_interval(hours => $2)
+        "#,
+        project.id,
+        retention as i32,
+    )
+    .execute(pool)
+    .await?;
+    if restored.rows_affected() == 0 {
+        return Ok(None);
+    }
+
+    let containers = project_containers(docker, container_prefix, project).await;
+    let mut started = !containers.is_empty();
+    for container in containers {
+        if let Err(err) = docker.start_container(&container).await {
+            tracing::warn!(?err, %container, "Can't start container of restored project");
+            started = false;
+        }
+    }
+    if started {
+        return Ok(Some(Redeploy::Started));
+    }
+
+    let item = BuildQueueItem {
+        container_src: format!("{base}/{}/{}.git/master", project.owner, project.name),
+        owner: project.owner.clone(),
+        repo: project.name.clone(),
+        trigger: BuildTrigger::Manual,
+        batch_id: None,
+        reply: None,
+    };
+    if build_channel.send(item).await.is_err() {
+        tracing::error!("Can't redeploy restored project: Build queue is closed");
+    }
+
+    Ok(Some(Redeploy::Queued))
+}
+
+/// Containers of the project, web and workers, running or not
+async fn project_containers(docker: &dyn ContainerRuntime, container_prefix: &str, project: &Project) -> Vec<String> {
+    let container_name = docker_name(container_prefix, &project.container_name);
+    let label_sets = [
+        [
+            format!("{OWNER_LABEL}={}", project.owner),
+            format!("{NAME_LABEL}={}", project.name),
+        ],
+        // workers are labeled with the web container's name as well
+        [format!("{PROJECT_LABEL}={container_name}"), format!("{PROCESS_LABEL}=worker")],
+    ];
+
+    let mut containers = Vec::new();
+    for labels in label_sets {
+        match docker.list_labeled_containers(&labels).await {
+            Ok(found) => containers.extend(found.into_iter().filter_map(|container| container.id)),
+            Err(err) => tracing::error!(?err, "Can't list containers of project"),
+        }
+    }
+    // containers from before the labels only go by their name
+    if containers.is_empty() && docker.inspect_container(&container_name).await.is_ok() {
+        containers.push(container_name);
+    }
+
+    containers.sort();
+    containers.dedup();
+    containers
+}
+
+/// Remove everything of a project for good: its row, repository, containers and image. The
+/// status of every part is `successfully deleted` or why it failed.
+pub async fn purge(
+    pool: &PgPool,
+    docker: &Docker,
+    base: &str,
+    container_prefix: &str,
+    project: &Project,
+) -> HashMap<&'static str, &'static str> {
+    let mut status = HashMap::new();
+
+    match sqlx::query!("DELETE FROM projects WHERE id = $1", project.id)
+        .execute(pool)
+        .await
+    {
+        Ok(_) => {
+            repo::invalidate_project(&project.owner, &project.name);
+            status.insert("project", "successfully deleted");
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't delete project: Failed to delete project");
+            status.insert("project", "failed to delete: database error");
+        }
+    }
+
+    let path = format!("{base}/{}/{}.git", project.owner, project.name);
+    match Path::new(&path).exists() {
+        false => {
+            tracing::debug!("Can't delete project: Repo does not exist");
+            status.insert("repo", "failed to delete: repo does not exist");
+        }
+        true => match std::fs::remove_dir_all(&path) {
+            Ok(_) => {
+                status.insert("repo", "successfully deleted");
+            }
+            Err(err) => {
+                tracing::error!(?err, "Can't delete project: Failed to delete repo");
+                status.insert("repo", "failed to delete: repo error");
+            }
+        },
+    };
+
+    // stopped ones of a soft-deleted project as well as running ones
+    let containers = project_containers(docker, container_prefix, project).await;
+    match containers.is_empty() {
+        true => {
+            status.insert("container", "failed to delete: container does not exist");
+        }
+        false => {
+            status.insert("container", "successfully deleted");
+            for container in containers {
+                if let Err(err) = ContainerRuntime::remove_container(docker, &container, true).await {
+                    tracing::error!(?err, "Can't delete project: Failed to delete container");
+                    status.insert("container", "failed to delete: container error");
+                }
+            }
+        }
+    }
+
+    let container_name = docker_name(container_prefix, &project.container_name);
+    match ContainerRuntime::inspect_image(docker, &container_name).await {
+        Ok(_) => match ContainerRuntime::remove_image(docker, &container_name).await {
+            Ok(_) => {
+                status.insert("image", "successfully deleted");
+            }
+            Err(err) => {
+                tracing::error!(?err, "Can't delete project: Failed to delete image");
+                status.insert("image", "failed to delete: image error");
+            }
+        },
+        Err(err) => {
+            tracing::debug!(?err, "Can't delete project: Image does not exist");
+            status.insert("image", "failed to delete: image does not exist");
+        }
+    };
+
+    status
+}
+
+/// Purge the projects that were deleted more than `retention` hours ago, checked every hour
+pub async fn run_purge(pool: PgPool, docker: Docker, base: String, container_prefix: String, retention: u64) {
+    loop {
+        tokio::time::sleep(PURGE_INTERVAL).await;
+
+        let expired = match sqlx::query!(
+            r#"SELECT projects.name AS project, project_owners.name AS owner
+               FROM projects
+               JOIN project_owners ON projects.owner_id = project_owners.id
+               WHERE projects.deleted_at < $1
+            "#,
+            Utc::now() - chrono::Duration::hours(retention as i64),
+        )
+        .fetch_all(&pool)
+        .await
+        {
+            Ok(expired) => expired,
+            Err(err) => {
+                tracing::error!(?err, "Can't purge deleted projects: Failed to query database");
+                continue;
+            }
+        };
+
+        for expired in expired {
+            let project = match find(&pool, &expired.owner, &expired.project).await {
+                Ok(Some(project)) => project,
+                Ok(None) => continue,
+                Err(err) => {
+                    tracing::error!(?err, "Can't purge deleted project: Failed to query database");
+                    continue;
+                }
+            };
+
+            let status = purge(&pool, &docker, &base, &container_prefix, &project).await;
+            tracing::info!(owner = %project.owner, project = %project.name, ?status, "Purged deleted project");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::{auth::hashing::Hasher, configuration::Argon2Settings, git, runtime::fake::FakeRuntime};
+
+    const RETENTION: u64 = 24;
+
+    /// `alice/blog`, its member and its git token `secret`
+    async fn blog(pool: &PgPool, hasher: &Hasher) -> (Uuid, Project) {
+        let (user_id, owner_id, project_id) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        sqlx::query("INSERT INTO users (id, username, password, name) VALUES ($1, 'alice', '', 'Alice')")
+            .bind(user_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO project_owners (id, name) VALUES ($1, 'alice')")
+            .bind(owner_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO users_owners (user_id, owner_id) VALUES ($1, $2)")
+            .bind(user_id)
+            .bind(owner_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO projects (id, owner_id, name, container_name) VALUES ($1, $2, 'blog', 'alice-blog')")
+            .bind(project_id)
+            .bind(owner_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO api_token (id, project_id, token) VALUES ($1, $2, $3)")
+            .bind(Uuid::new_v4())
+            .bind(project_id)
+            .bind(hasher.hash(b"secret").unwrap())
+            .execute(pool)
+            .await
+            .unwrap();
+
+        (user_id, find(pool, "alice", "blog").await.unwrap().unwrap())
+    }
+
+    fn hasher() -> Hasher {
+        Hasher::new(&Argon2Settings {
+            memory: 8,
+            iterations: 1,
+            parallelism: 1,
+        })
+        .unwrap()
+    }
+
+    async fn deleted_at(pool: &PgPool, project: &Project) -> Option<DateTime<Utc>> {
+        find(pool, &project.owner, &project.name).await.unwrap().unwrap().deleted_at
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn restore_within_retention_starts_the_kept_containers(pool: PgPool) {
+        let (_, project) = blog(&pool, &hasher()).await;
+        let docker = FakeRuntime::default();
+        docker.add_container("alice-blog", true);
+        let (build_channel, mut builds) = mpsc::channel(1);
+
+        assert!(soft_delete(&pool, &docker, "", &project).await.unwrap().is_some());
+        assert_eq!(docker.containers(), vec![("alice-blog".to_string(), false)]);
+        // deleting twice does nothing
+        assert!(soft_delete(&pool, &docker, "", &project).await.unwrap().is_none());
+
+        let redeploy = restore(&pool, &docker, "", "/git", &build_channel, RETENTION, &project).await.unwrap();
+        assert_eq!(redeploy, Some(Redeploy::Started));
+        assert_eq!(docker.containers(), vec![("alice-blog".to_string(), true)]);
+        assert_eq!(deleted_at(&pool, &project).await, None);
+        assert!(builds.try_recv().is_err());
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn restore_without_containers_rebuilds(pool: PgPool) {
+        let (_, project) = blog(&pool, &hasher()).await;
+        let docker = FakeRuntime::default();
+        let (build_channel, mut builds) = mpsc::channel(1);
+
+        soft_delete(&pool, &docker, "", &project).await.unwrap();
+        let redeploy = restore(&pool, &docker, "", "/git", &build_channel, RETENTION, &project).await.unwrap();
+        assert_eq!(redeploy, Some(Redeploy::Queued));
+
+        let item = builds.try_recv().unwrap();
+        assert_eq!((item.owner.as_str(), item.repo.as_str()), ("alice", "blog"));
+        assert_eq!(item.container_src, "/git/alice/blog.git/master");
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn restore_past_retention_is_refused(pool: PgPool) {
+        let (_, project) = blog(&pool, &hasher()).await;
+        let docker = FakeRuntime::default();
+        docker.add_container("alice-blog", true);
+        let (build_channel, _builds) = mpsc::channel(1);
+
+        soft_delete(&pool, &docker, "", &project).await.unwrap();
+        sqlx::query("UPDATE projects SET deleted_at = now() - make_interval(hours => $1)")
+            .bind(RETENTION as i32 + 1)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let redeploy = restore(&pool, &docker, "", "/git", &build_channel, RETENTION, &project).await.unwrap();
+        assert_eq!(redeploy, None);
+        assert!(deleted_at(&pool, &project).await.is_some());
+        assert_eq!(docker.containers(), vec![("alice-blog".to_string(), false)]);
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn deleted_projects_are_hidden(pool: PgPool) {
+        let hasher = hasher();
+        let (user_id, project) = blog(&pool, &hasher).await;
+        let docker = FakeRuntime::default();
+        let (build_channel, _builds) = mpsc::channel(1);
+        repo::configure_cache(60);
+
+        // cached before the delete, which has to drop it
+        assert!(repo::find_owned(&pool, user_id, "alice", "blog").await.unwrap().is_some());
+        assert!(git::find_token(&pool, &hasher, "alice", "blog", "secret").await.unwrap().is_some());
+
+        soft_delete(&pool, &docker, "", &project).await.unwrap();
+        assert!(repo::find_owned(&pool, user_id, "alice", "blog").await.unwrap().is_none());
+        assert!(git::find_token(&pool, &hasher, "alice", "blog", "secret").await.unwrap().is_none());
+
+        restore(&pool, &docker, "", "/git", &build_channel, RETENTION, &project).await.unwrap();
+        assert!(repo::find_owned(&pool, user_id, "alice", "blog").await.unwrap().is_some());
+        assert!(git::find_token(&pool, &hasher, "alice", "blog", "secret").await.unwrap().is_some());
+    }
+}
@@ -1 +1,4 @@
 pub mod api;
+pub mod deletion;
+pub mod repo;
+pub mod shares;
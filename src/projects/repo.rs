@@ -0,0 +1,570 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use lazy_static::lazy_static;
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::docker::legacy_container_name;
+
+/// Entries past which expired ones are dropped on the next insert
+const CACHE_PRUNE_AT: usize = 10_000;
+
+type CacheKey = (Uuid, String, String);
+
+lazy_static! {
+    /// Successful `find_owned` lookups by user, owner and project. Only hits are cached, so
+    /// access that is granted shows up right away and only revoking it needs an invalidation.
+    static ref OWNED: Mutex<HashMap<CacheKey, (Instant, ProjectRow)>> = Mutex::new(HashMap::new());
+}
+
+/// In seconds, 0 until the cache is turned on
+static CACHE_TTL: AtomicU64 = AtomicU64::new(0);
+/// In milliseconds, 0 lets lookups run as long as they take
+static STATEMENT_TIMEOUT: AtomicU64 = AtomicU64::new(0);
+static CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+static CACHE_MISSES: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Serialize, Debug)]
+pub struct CacheStats {
+    pub enabled: bool,
+    pub ttl_secs: u64,
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// none until the first lookup
+    pub hit_rate: Option<f64>,
+}
+
+/// Turn on caching of `find_owned`, a ttl of 0 leaves it off
+pub fn configure_cache(ttl_secs: u64) {
+    CACHE_TTL.store(ttl_secs, Ordering::Relaxed);
+}
+
+/// Cancel lookups running longer than `timeout_ms`, 0 turns it off
+pub fn configure_timeout(timeout_ms: u64) {
+    STATEMENT_TIMEOUT.store(timeout_ms, Ordering::Relaxed);
+}
+
+pub fn cache_stats() -> CacheStats {
+    let hits = CACHE_HITS.load(Ordering::Relaxed);
+    let misses = CACHE_MISSES.load(Ordering::Relaxed);
+    let ttl_secs = CACHE_TTL.load(Ordering::Relaxed);
+
+    CacheStats {
+        enabled: ttl_secs > 0,
+        ttl_secs,
+        entries: OWNED.lock().unwrap().len(),
+        hits,
+        misses,
+        hit_rate: (hits + misses > 0).then_some(hits as f64 / (hits + misses) as f64),
+    }
+}
+
+/// Forget the cached access of a user, after they were added to or removed from an owner
+pub fn invalidate_user(user_id: Uuid) {
+    OWNED.lock().unwrap().retain(|(user, _, _), _| *user != user_id);
+}
+
+/// Forget the cached access to a project, after it was deleted or renamed
+pub fn invalidate_project(owner: &str, project: &str) {
+    OWNED
+        .lock()
+        .unwrap()
+        .retain(|(_, cached_owner, cached_project), _| cached_owner != owner || cached_project != project);
+}
+
+#[derive(Debug, Clone)]
+pub struct ProjectRow {
+    pub id: Uuid,
+    pub project: String,
+    pub owner: String,
+    /// name of the project's containers, unprefixed
+    pub container_name: String,
+}
+
+/// Message of the 404 every project endpoint answers when the user isn't a member of the
+/// project's owner. It is the same answer as for a project that doesn't exist, so the api
+/// doesn't reveal which projects exist. Members that aren't allowed an action get a 403 naming
+/// who is, the only such action is deleting a project or its volume, which is left to the
+/// owner whose name matches the user's.
+pub const NOT_FOUND_MESSAGE: &str = "Project does not exist";
+
+/// Find a project by owner and project name, only if `user_id` is a member of the owner.
+/// Soft-deleted projects aren't found, see [`crate::projects::deletion`].
+/// Found projects are cached for the configured ttl, see [`configure_cache`].
+pub async fn find_owned(
+    pool: &PgPool,
+    user_id: Uuid,
+    owner: &str,
+    project: &str,
+) -> Result<Option<ProjectRow>, sqlx::Error> {
+    let ttl = Duration::from_secs(CACHE_TTL.load(Ordering::Relaxed));
+    let timeout = Duration::from_millis(STATEMENT_TIMEOUT.load(Ordering::Relaxed));
+    if ttl.is_zero() {
+        return query_owned(pool, user_id, owner, project, timeout).await;
+    }
+
+    let key = (user_id, owner.to_string(), project.to_string());
+    if let Some((cached_at, row)) = OWNED.lock().unwrap().get(&key) {
+        if cached_at.elapsed() < ttl {
+            CACHE_HITS.fetch_add(1, Ordering::Relaxed);
+            return Ok(Some(row.clone()));
+        }
+    }
+    CACHE_MISSES.fetch_add(1, Ordering::Relaxed);
+
+    let row = query_owned(pool, user_id, owner, project, timeout).await?;
+    if let Some(row) = &row {
+        let mut cache = OWNED.lock().unwrap();
+        if cache.len() >= CACHE_PRUNE_AT {
+            cache.retain(|_, (cached_at, _)| cached_at.elapsed() < ttl);
+        }
+        cache.insert(key, (Instant::now(), row.clone()));
+    }
+
+    Ok(row)
+}
+
+/// The timeout is set for the lookup's own transaction, the pooled connection goes back
+/// without it
+async fn query_owned(
+    pool: &PgPool,
+    user_id: Uuid,
+    owner: &str,
+    project: &str,
+    timeout: Duration,
+) -> Result<Option<ProjectRow>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    if !timeout.is_zero() {
+        sqlx::query(&format!("SET LOCAL statement_timeout = {}", timeout.as_millis()))
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    let row = sqlx::query_as!(
+        ProjectRow,
+        r#"SELECT projects.id, projects.name AS project, project_owners.name AS owner, projects.container_name
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           JOIN users_owners ON project_owners.id = users_owners.owner_id
+           WHERE projects.name = $1
+           AND project_owners.name = $2
+           AND users_owners.user_id = $3
+           AND projects.deleted_at IS NULL
+        "#,
+        project,
+        owner,
+        user_id,
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(row)
+}
+
+/// Owners sharing a name, from before owner names were unique
+#[derive(Debug)]
+pub struct DuplicateOwner {
+    pub name: String,
+    /// oldest first
+    pub owner_ids: Vec<Uuid>,
+}
+
+/// Make owner names unique, which lookups by owner name rely on. The constraint is only added
+/// when no two owners share a name, the duplicates are returned instead and have to be merged
+/// or renamed by hand.
+pub async fn unique_owner_names(pool: &PgPool) -> Result<Vec<DuplicateOwner>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    // no owner can be added under a taken name while they are checked
+    sqlx::query("LOCK TABLE project_owners IN SHARE MODE")
+        .execute(&mut *tx)
+        .await?;
+
+    let duplicates = sqlx::query_as!(
+        DuplicateOwner,
+        r#"SELECT name, array_agg(id ORDER BY created_at) AS "owner_ids!"
+           FROM project_owners
+           GROUP BY name
+           HAVING count(*) > 1
+           ORDER BY name
+        "#
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    if duplicates.is_empty() {
+        sqlx::query(
+            r#"DO $$ BEGIN
+                 IF NOT EXISTS (SELECT FROM pg_constraint WHERE conname = 'unique_owner_name') THEN
+                   ALTER TABLE project_owners ADD CONSTRAINT unique_owner_name UNIQUE (name);
+                 END IF;
+               END $$
+            "#,
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+
+    Ok(duplicates)
+}
+
+/// A project made before container names were stored whose old name is already another
+/// project's, the old scheme gave `a-b`/`c` and `a`/`b-c` the same one
+#[derive(Debug)]
+pub struct Conflict {
+    pub project_id: Uuid,
+    pub container_name: String,
+    pub reason: String,
+}
+
+/// Store the container name of every project made before names were stored: the name its
+/// containers already run under. The column is added without its constraints first, they
+/// only follow once every project has a name of its own. A project whose old name is taken
+/// keeps none and is returned, it has to be renamed or deleted by hand.
+pub async fn backfill_container_names(pool: &PgPool) -> Result<Vec<Conflict>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("ALTER TABLE projects ADD COLUMN IF NOT EXISTS container_name TEXT")
+        .execute(&mut *tx)
+        .await?;
+
+    let stored = sqlx::query!(
+        "SELECT id, container_name FROM projects WHERE container_name IS NOT NULL"
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let mut taken = stored
+        .into_iter()
+        .map(|project| (project.container_name, project.id))
+        .collect::<HashMap<_, _>>();
+
+    let projects = sqlx::query!(
+        r#"SELECT projects.id, projects.name AS project, project_owners.name AS owner
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.container_name IS NULL
+           ORDER BY projects.created_at
+        "#
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut conflicts = Vec::new();
+    for project in projects {
+        let container_name = legacy_container_name(&project.owner, &project.project);
+        if let Some(other) = taken.get(&container_name) {
+            conflicts.push(Conflict {
+                project_id: project.id,
+                reason: format!("already the container name of project {other}"),
+                container_name,
+            });
+            continue;
+        }
+
+        sqlx::query!(
+            "UPDATE projects SET container_name = $1 WHERE id = $2",
+            container_name,
+            project.id,
+        )
+        .execute(&mut *tx)
+        .await?;
+        taken.insert(container_name, project.id);
+    }
+
+    if conflicts.is_empty() {
+        sqlx::query("ALTER TABLE projects ALTER COLUMN container_name SET NOT NULL")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"DO $$ BEGIN
+                 IF NOT EXISTS (SELECT FROM pg_constraint WHERE conname = 'unique_container_name') THEN
+                   ALTER TABLE projects ADD CONSTRAINT unique_container_name UNIQUE (container_name);
+                 END IF;
+               END $$
+            "#,
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+
+    Ok(conflicts)
+}
+
+/// Whether the query was cancelled by its `statement_timeout`, see [`configure_timeout`].
+pub fn is_statement_timeout(err: &sqlx::Error) -> bool {
+    err.as_database_error()
+        .and_then(|err| err.code())
+        .map(|code| code == "57014")
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A user who is a member of `alice`, which owns `alice/blog`
+    async fn member(pool: &PgPool) -> Uuid {
+        let (user_id, owner_id, project_id) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        sqlx::query("INSERT INTO users (id, username, password, name) VALUES ($1, 'alice', '', 'Alice')")
+            .bind(user_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO project_owners (id, name) VALUES ($1, 'alice')")
+            .bind(owner_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO users_owners (user_id, owner_id) VALUES ($1, $2)")
+            .bind(user_id)
+            .bind(owner_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO projects (id, owner_id, name, container_name) VALUES ($1, $2, 'blog', 'alice-blog')")
+            .bind(project_id)
+            .bind(owner_id)
+            .execute(pool)
+            .await
+            .unwrap();
+
+        user_id
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn invalidated_access_is_looked_up_again(pool: PgPool) {
+        let user_id = member(&pool).await;
+        configure_cache(60);
+
+        let row = find_owned(&pool, user_id, "alice", "blog").await.unwrap().unwrap();
+        assert_eq!(row.container_name, "alice-blog");
+
+        // removed from the owner, the cached access outlives it until it is invalidated
+        sqlx::query("DELETE FROM users_owners WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let misses = CACHE_MISSES.load(Ordering::Relaxed);
+        assert!(find_owned(&pool, user_id, "alice", "blog").await.unwrap().is_some());
+        assert_eq!(CACHE_MISSES.load(Ordering::Relaxed), misses);
+
+        invalidate_user(user_id);
+        assert!(find_owned(&pool, user_id, "alice", "blog").await.unwrap().is_none());
+        assert_eq!(CACHE_MISSES.load(Ordering::Relaxed), misses + 1);
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn blocked_lookup_times_out(pool: PgPool) {
+        let user_id = member(&pool).await;
+
+        let mut blocker = pool.begin().await.unwrap();
+        sqlx::query("LOCK TABLE projects IN ACCESS EXCLUSIVE MODE")
+            .execute(&mut *blocker)
+            .await
+            .unwrap();
+
+        let err = query_owned(&pool, user_id, "alice", "blog", Duration::from_millis(100))
+            .await
+            .unwrap_err();
+        assert!(is_statement_timeout(&err), "{err:?}");
+
+        blocker.rollback().await.unwrap();
+        let row = query_owned(&pool, user_id, "alice", "blog", Duration::from_millis(100))
+            .await
+            .unwrap();
+        assert!(row.is_some());
+    }
+
+    /// Drop the container names, as in a database made before they were stored
+    async fn legacy_projects(pool: &PgPool, projects: &[(&str, &str)]) -> Vec<Uuid> {
+        sqlx::query("ALTER TABLE projects DROP COLUMN container_name")
+            .execute(pool)
+            .await
+            .unwrap();
+
+        let mut ids = Vec::new();
+        for (age, (owner, project)) in projects.iter().enumerate() {
+            let (owner_id, project_id) = (Uuid::new_v4(), Uuid::new_v4());
+            sqlx::query("INSERT INTO project_owners (id, name) VALUES ($1, $2) ON CONFLICT (name) DO NOTHING")
+                .bind(owner_id)
+                .bind(owner)
+                .execute(pool)
+                .await
+                .unwrap();
+            sqlx::query(
+                r#"INSERT INTO projects (id, owner_id, name, created_at)
+                   SELECT $1, id, $3, now() - make_interval(days => $4) FROM project_owners WHERE name = $2
+                "#,
+            )
+            .bind(project_id)
+            .bind(owner)
+            .bind(project)
+            .bind((projects.len() - age) as i32)
+            .execute(pool)
+            .await
+            .unwrap();
+            ids.push(project_id);
+        }
+
+        ids
+    }
+
+    async fn container_name(pool: &PgPool, project_id: Uuid) -> Option<String> {
+        sqlx::query_scalar("SELECT container_name FROM projects WHERE id = $1")
+            .bind(project_id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    async fn constrained(pool: &PgPool) -> bool {
+        sqlx::query_scalar(
+            r#"SELECT attnotnull AND EXISTS (SELECT FROM pg_constraint WHERE conname = 'unique_container_name')
+               FROM pg_attribute
+               WHERE attrelid = 'projects'::regclass AND attname = 'container_name'
+            "#,
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn legacy_projects_keep_their_container_names(pool: PgPool) {
+        let ids = legacy_projects(&pool, &[("alice", "my.site.git"), ("bob", "blog")]).await;
+
+        let conflicts = backfill_container_names(&pool).await.unwrap();
+        assert!(conflicts.is_empty(), "{conflicts:?}");
+        assert_eq!(container_name(&pool, ids[0]).await.as_deref(), Some("alice-my-site"));
+        assert_eq!(container_name(&pool, ids[1]).await.as_deref(), Some("bob-blog"));
+        assert!(constrained(&pool).await);
+
+        // later startups find nothing left to do
+        assert!(backfill_container_names(&pool).await.unwrap().is_empty());
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn colliding_legacy_names_are_conflicts(pool: PgPool) {
+        let ids = legacy_projects(&pool, &[("a-b", "c"), ("a", "b-c")]).await;
+
+        let conflicts = backfill_container_names(&pool).await.unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].project_id, ids[1]);
+        assert_eq!(conflicts[0].container_name, "a-b-c");
+        // the older project keeps the name, the other one isn't renamed behind its back
+        assert_eq!(container_name(&pool, ids[0]).await.as_deref(), Some("a-b-c"));
+        assert_eq!(container_name(&pool, ids[1]).await, None);
+        assert!(!constrained(&pool).await);
+
+        sqlx::query("DELETE FROM projects WHERE id = $1")
+            .bind(ids[1])
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(backfill_container_names(&pool).await.unwrap().is_empty());
+        assert!(constrained(&pool).await);
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn duplicate_owners_are_reported(pool: PgPool) {
+        sqlx::query("ALTER TABLE project_owners DROP CONSTRAINT unique_owner_name")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let mut alices = Vec::new();
+        for (name, age) in [("alice", 2), ("alice", 1), ("bob", 1)] {
+            let owner_id = Uuid::new_v4();
+            sqlx::query("INSERT INTO project_owners (id, name, created_at) VALUES ($1, $2, now() - make_interval(days => $3))")
+                .bind(owner_id)
+                .bind(name)
+                .bind(age)
+                .execute(&pool)
+                .await
+                .unwrap();
+            if name == "alice" {
+                alices.push(owner_id);
+            }
+        }
+
+        let duplicates = unique_owner_names(&pool).await.unwrap();
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].name, "alice");
+        assert_eq!(duplicates[0].owner_ids, alices);
+
+        sqlx::query("UPDATE project_owners SET name = 'alice2' WHERE id = $1")
+            .bind(alices[1])
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(unique_owner_names(&pool).await.unwrap().is_empty());
+        let err = sqlx::query("INSERT INTO project_owners (id, name) VALUES ($1, 'bob')")
+            .bind(Uuid::new_v4())
+            .execute(&pool)
+            .await
+            .unwrap_err();
+        assert!(err.as_database_error().unwrap().is_unique_violation(), "{err:?}");
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn ownership_lookup_uses_the_indexes(pool: PgPool) {
+        // enough rows that scanning a table costs more than its index
+        sqlx::query(
+            r#"WITH users AS (
+                 INSERT INTO users (id, username, password, name)
+                 SELECT gen_random_uuid(), 'user' || n, '', 'User' FROM generate_series(1, 2000) n
+                 RETURNING id, username
+               ), owners AS (
+                 INSERT INTO project_owners (id, name)
+                 SELECT gen_random_uuid(), 'owner' || n FROM generate_series(1, 2000) n
+                 RETURNING id, name
+               ), members AS (
+                 INSERT INTO users_owners (user_id, owner_id)
+                 SELECT users.id, owners.id FROM users JOIN owners ON substr(users.username, 5) = substr(owners.name, 6)
+               )
+               INSERT INTO projects (id, owner_id, name, container_name)
+               SELECT gen_random_uuid(), owners.id, 'blog' || n, owners.name || '-blog' || n
+               FROM owners, generate_series(1, 5) n
+            "#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query("ANALYZE").execute(&pool).await.unwrap();
+
+        // the query of query_owned
+        let plan: Vec<String> = sqlx::query_scalar(
+            r#"EXPLAIN SELECT projects.id, projects.name AS project, project_owners.name AS owner, projects.container_name
+               FROM projects
+               JOIN project_owners ON projects.owner_id = project_owners.id
+               JOIN users_owners ON project_owners.id = users_owners.owner_id
+               WHERE projects.name = $1
+               AND project_owners.name = $2
+               AND users_owners.user_id = $3
+               AND projects.deleted_at IS NULL
+            "#,
+        )
+        .bind("blog1")
+        .bind("owner7")
+        .bind(Uuid::new_v4())
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+
+        let plan = plan.join("\n");
+        for index in ["unique_owner_name", "projects_owner_id_name_idx"] {
+            assert!(plan.contains(&format!("using {index}")), "{plan}");
+        }
+        assert!(!plan.contains("Seq Scan"), "{plan}");
+    }
+}
@@ -0,0 +1,140 @@
+use data_encoding::HEXLOWER;
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const TOKEN_LENGTH: usize = 32;
+
+/// Answer of the share routes for tokens that don't exist, were revoked or expired alike
+pub const NOT_FOUND_MESSAGE: &str = "Share link does not exist or has expired";
+
+/// A project opened through a share token
+#[derive(Debug, Clone)]
+pub struct SharedProject {
+    pub id: Uuid,
+    pub project: String,
+    pub owner: String,
+    pub environs: serde_json::Value,
+}
+
+/// A new random token and the hash it is stored as
+pub fn generate_token() -> (String, String) {
+    let token = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect::<String>();
+    let hash = hash_token(&token);
+
+    (token, hash)
+}
+
+/// Tokens are stored hashed, a leaked database doesn't open the shared pages
+fn hash_token(token: &str) -> String {
+    HEXLOWER.encode(&Sha256::digest(token.as_bytes()))
+}
+
+/// Project of a share token that hasn't expired. Revoking a share deletes its row, so revoked
+/// tokens aren't found either.
+pub async fn find(pool: &PgPool, token: &str) -> Result<Option<SharedProject>, sqlx::Error> {
+    sqlx::query_as!(
+        SharedProject,
+        r#"SELECT projects.id, projects.name AS project, project_owners.name AS owner, projects.environs
+           FROM project_shares
+           JOIN projects ON project_shares.project_id = projects.id
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE project_shares.token_hash = $1
+           AND (project_shares.expires_at IS NULL OR project_shares.expires_at > now())
+           AND projects.deleted_at IS NULL
+        "#,
+        hash_token(token),
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_stored_as_their_hash() {
+        let (token, hash) = generate_token();
+        assert_eq!(token.len(), TOKEN_LENGTH);
+        assert!(token.chars().all(|c| c.is_ascii_alphanumeric()), "{token}");
+        assert_eq!(hash, hash_token(&token));
+        assert_eq!(hash.len(), 64);
+        assert_ne!(hash, token);
+
+        let (other, other_hash) = generate_token();
+        assert_ne!(token, other);
+        assert_ne!(hash, other_hash);
+    }
+
+    /// A share of `alice/blog` expiring `expires_in` hours from now, returns its token
+    async fn share(pool: &PgPool, expires_in: Option<i32>) -> String {
+        let (owner_id, project_id) = (Uuid::new_v4(), Uuid::new_v4());
+        sqlx::query("INSERT INTO project_owners (id, name) VALUES ($1, 'alice') ON CONFLICT (name) DO NOTHING")
+            .bind(owner_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query(
+            r#"INSERT INTO projects (id, owner_id, name, container_name)
+               SELECT $1, id, 'blog-' || $1, 'alice-blog-' || $1 FROM project_owners WHERE name = 'alice'
+            "#,
+        )
+        .bind(project_id)
+        .execute(pool)
+        .await
+        .unwrap();
+
+        let (token, hash) = generate_token();
+        sqlx::query(
+            r#"INSERT INTO project_shares (id, project_id, token_hash, expires_at)
+               VALUES ($1, $2, $3, now() + make_interval(hours => $4))
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(project_id)
+        .bind(hash)
+        .bind(expires_in)
+        .execute(pool)
+        .await
+        .unwrap();
+
+        token
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn find_opens_shares_until_they_expire(pool: PgPool) {
+        let forever = share(&pool, None).await;
+        let open = share(&pool, Some(1)).await;
+        let expired = share(&pool, Some(-1)).await;
+
+        let found = find(&pool, &forever).await.unwrap().unwrap();
+        assert_eq!(found.owner, "alice");
+        assert!(find(&pool, &open).await.unwrap().is_some());
+        assert!(find(&pool, &expired).await.unwrap().is_none());
+        assert!(find(&pool, "not-a-token").await.unwrap().is_none());
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn find_skips_revoked_shares_and_deleted_projects(pool: PgPool) {
+        let revoked = share(&pool, None).await;
+        sqlx::query("DELETE FROM project_shares WHERE token_hash = $1")
+            .bind(hash_token(&revoked))
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(find(&pool, &revoked).await.unwrap().is_none());
+
+        let deleted = share(&pool, None).await;
+        sqlx::query("UPDATE projects SET deleted_at = now()")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(find(&pool, &deleted).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,232 @@
+use config::ConfigError;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::{
+    configuration::{Settings, SubdomainScheme},
+    docker::host_for,
+};
+
+/// Variables a push message can use, as `{name}`
+pub const VARIABLES: [&str; 4] = ["project_url", "dashboard_url", "deployment_id", "support_contact"];
+
+#[derive(Error, Debug, PartialEq)]
+pub enum TemplateError {
+    #[error("unknown variable {{{0}}}, expected one of {}", VARIABLES.join(", "))]
+    UnknownVariable(String),
+    #[error("unclosed {{ at character {0}, write {{{{ for a literal brace")]
+    Unclosed(usize),
+    #[error("unmatched }} at character {0}, write }}}} for a literal brace")]
+    Unmatched(usize),
+}
+
+#[derive(Debug, PartialEq)]
+enum Part<'a> {
+    Text(&'a str),
+    /// a literal brace, from `{{` or `}}`
+    Brace(char),
+    Variable(&'a str),
+}
+
+/// Split a template into text and variables. Only the names in `VARIABLES` are allowed, there
+/// are no expressions.
+fn parse(template: &str) -> Result<Vec<Part>, TemplateError> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    let mut offset = 0;
+
+    while let Some(index) = rest.find(['{', '}']) {
+        if index > 0 {
+            parts.push(Part::Text(&rest[..index]));
+        }
+        let brace = rest.as_bytes()[index] as char;
+        let after = &rest[index + 1..];
+
+        let consumed = if after.starts_with(brace) {
+            parts.push(Part::Brace(brace));
+            index + 2
+        } else if brace == '}' {
+            return Err(TemplateError::Unmatched(offset + index));
+        } else {
+            let end = after.find('}').ok_or(TemplateError::Unclosed(offset + index))?;
+            let name = after[..end].trim();
+            if !VARIABLES.contains(&name) {
+                return Err(TemplateError::UnknownVariable(name.to_string()));
+            }
+            parts.push(Part::Variable(name));
+            index + end + 2
+        };
+
+        rest = &rest[consumed..];
+        offset += consumed;
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest));
+    }
+
+    Ok(parts)
+}
+
+/// Check a template before it is stored or used
+pub fn validate(template: &str) -> Result<(), TemplateError> {
+    parse(template).map(|_| ())
+}
+
+/// What the variables of a push message stand for
+#[derive(Debug, Default)]
+pub struct Values {
+    pub project_url: String,
+    pub dashboard_url: String,
+    /// the build that takes the push, empty when the push isn't built
+    pub deployment_id: String,
+    pub support_contact: String,
+}
+
+impl Values {
+    fn get(&self, name: &str) -> &str {
+        match name {
+            "project_url" => &self.project_url,
+            "dashboard_url" => &self.dashboard_url,
+            "deployment_id" => &self.deployment_id,
+            "support_contact" => &self.support_contact,
+            _ => "",
+        }
+    }
+}
+
+/// Render a template line by line. A line using a variable that has no value is left out, so
+/// e.g. a support line disappears when no contact is set.
+pub fn render(template: &str, values: &Values) -> Result<String, TemplateError> {
+    let mut lines = Vec::new();
+
+    for line in template.lines() {
+        let mut rendered = String::new();
+        let mut missing = false;
+        for part in parse(line)? {
+            match part {
+                Part::Text(text) => rendered.push_str(text),
+                Part::Brace(brace) => rendered.push(brace),
+                Part::Variable(name) => {
+                    let value = values.get(name);
+                    missing |= value.is_empty();
+                    rendered.push_str(value);
+                }
+            }
+        }
+        if !missing {
+            lines.push(rendered);
+        }
+    }
+
+    Ok(lines.join("\n"))
+}
+
+/// The footer of the push output, from `git.pushmessage` or an owner's own template
+#[derive(Debug, Clone)]
+pub struct PushMessage {
+    pub template: String,
+    pub support_contact: String,
+    domain: String,
+    secure: bool,
+    subdomain: SubdomainScheme,
+}
+
+impl PushMessage {
+    pub fn new(config: &Settings) -> Result<Self, ConfigError> {
+        validate(&config.git.pushmessage)
+            .map_err(|err| ConfigError::Message(format!("git.pushmessage is not a valid template: {err}")))?;
+
+        Ok(Self {
+            template: config.git.pushmessage.clone(),
+            support_contact: config.git.supportcontact.clone(),
+            domain: config.domain(),
+            secure: config.application.secure,
+            subdomain: config.application.subdomain,
+        })
+    }
+
+    /// Footer of a push to a project, `owner_template` replaces the server's template
+    pub fn render(
+        &self,
+        owner_template: Option<&str>,
+        owner: &str,
+        project: &str,
+        container_name: &str,
+        deployment_id: Option<Uuid>,
+    ) -> String {
+        let scheme = match self.secure {
+            true => "https",
+            false => "http",
+        };
+        let host = host_for(owner, project, container_name, self.subdomain, &self.domain);
+        let values = Values {
+            project_url: format!("{scheme}://{host}"),
+            dashboard_url: format!("{scheme}://{}/web/project/{owner}/{project}", self.domain),
+            deployment_id: deployment_id.map(|id| id.to_string()).unwrap_or_default(),
+            support_contact: self.support_contact.clone(),
+        };
+
+        // owner templates are checked when they are set, the server's one on startup
+        render(owner_template.unwrap_or(&self.template), &values).unwrap_or_else(|err| {
+            tracing::error!(%err, owner, "Can't render push message");
+            String::new()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration;
+
+    fn push_message(overrides: &[(&str, &str)]) -> PushMessage {
+        let mut builder = configuration::defaults().unwrap().set_override("build.max", 1).unwrap();
+        for (key, value) in overrides {
+            builder = builder.set_override(*key, *value).unwrap();
+        }
+
+        PushMessage::new(&builder.build().unwrap().try_deserialize().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn renders_the_default_template() {
+        let push_message = push_message(&[("application.domain", "pws.example.com"), ("git.supportcontact", "help@example.com")]);
+        let deployment_id = Uuid::nil();
+
+        assert_eq!(
+            push_message.render(None, "alice", "blog", "alice-blog", Some(deployment_id)),
+            "Project: http://alice-blog.pws.example.com\n\
+             Dashboard: http://pws.example.com/web/project/alice/blog\n\
+             Deployment: 00000000-0000-0000-0000-000000000000\n\
+             Help: help@example.com"
+        );
+    }
+
+    #[test]
+    fn default_template_leaves_out_lines_without_a_value() {
+        let push_message = push_message(&[("application.domain", "pws.example.com"), ("application.secure", "true")]);
+
+        assert_eq!(
+            push_message.render(None, "alice", "blog", "alice-blog", None),
+            "Project: https://alice-blog.pws.example.com\nDashboard: https://pws.example.com/web/project/alice/blog"
+        );
+    }
+
+    #[test]
+    fn owner_template_replaces_the_servers() {
+        let push_message = push_message(&[]);
+
+        assert_eq!(
+            push_message.render(Some("{{course}} {project_url}"), "alice", "blog", "alice-blog", None),
+            "{course} http://alice-blog.localhost:8080"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        assert_eq!(validate("{project}"), Err(TemplateError::UnknownVariable("project".to_string())));
+        assert_eq!(validate("Project: {project_url"), Err(TemplateError::Unclosed(9)));
+        assert_eq!(validate("a } b"), Err(TemplateError::Unmatched(2)));
+        assert_eq!(validate("{{ {project_url} }}"), Ok(()));
+    }
+}
@@ -1,36 +1,164 @@
 use std::{
-    collections::{HashSet, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     hash::Hash,
     sync::{
         atomic::{AtomicUsize, Ordering},
         Arc,
     },
+    time::{Duration, Instant},
 };
 
 use anyhow::Result;
+use bollard::Docker;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use thiserror::Error;
 use tokio::sync::mpsc::{self, Receiver, Sender};
-use tokio::sync::Mutex;
+use tokio::sync::{oneshot, Mutex, Semaphore};
 use ulid::Ulid;
 use uuid::Uuid;
 
-use crate::{docker::{build_docker, DockerContainer}, configuration::Settings};
+use crate::{announcements, detection, projects::api::BuildState, events::{self, BuildEvents}, docker::{build_docker, BuildOptions, detect_framework, dockerfile_source, DeployError, DockerContainer, Recovery}, configuration::Settings, git, hints, notifications::{self, NotificationType}, quota, system::admission::Admission};
 
 type ConcurrentMutex<T> = Arc<Mutex<T>>;
 
+/// How often a build waiting for the deploy lock of its project checks it again
+const DEPLOY_LOCK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time a deploy gets past the build timeout for everything after the image build, a lock held
+/// longer than that belongs to a stuck build
+const DEPLOY_LOCK_GRACE: Duration = Duration::from_secs(300);
+
+lazy_static! {
+    /// Running builds that can still be cancelled, by build id
+    static ref CANCELLATIONS: std::sync::Mutex<HashMap<Uuid, oneshot::Sender<()>>> =
+        std::sync::Mutex::new(HashMap::new());
+    /// Builds deploying a project, by container name, with when they took it
+    static ref DEPLOY_LOCKS: std::sync::Mutex<HashMap<String, (Uuid, Instant)>> =
+        std::sync::Mutex::new(HashMap::new());
+}
+
+/// Keeps a build cancellable until it finishes, however it finishes
+struct Cancellable(Uuid);
+
+impl Cancellable {
+    fn register(build_id: Uuid) -> (Self, oneshot::Receiver<()>) {
+        let (tx, rx) = oneshot::channel();
+        CANCELLATIONS.lock().unwrap().insert(build_id, tx);
+        (Self(build_id), rx)
+    }
+}
+
+impl Drop for Cancellable {
+    fn drop(&mut self) {
+        CANCELLATIONS.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// Keeps other builds of the same project from deploying until the build holding it finishes,
+/// so two builds don't replace each other's containers
+struct DeployLock {
+    container_name: String,
+    build_id: Uuid,
+}
+
+impl DeployLock {
+    /// Wait up to `timeout` for the project to be free. A lock held past `stale_after` is
+    /// released and taken over, the build holding it is stuck.
+    async fn acquire(container_name: &str, build_id: Uuid, timeout: Duration, stale_after: Duration) -> Option<Self> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            {
+                let mut locks = DEPLOY_LOCKS.lock().unwrap();
+                match locks.get(container_name).copied() {
+                    Some((_, since)) if since.elapsed() < stale_after => {}
+                    held => {
+                        if let Some((holder, since)) = held {
+                            tracing::warn!(
+                                %holder,
+                                container_name,
+                                held_secs = since.elapsed().as_secs(),
+                                "Releasing deploy lock held past the build timeout"
+                            );
+                        }
+                        locks.insert(container_name.to_string(), (build_id, Instant::now()));
+
+                        return Some(Self {
+                            container_name: container_name.to_string(),
+                            build_id,
+                        });
+                    }
+                }
+            }
+
+            if Instant::now() >= deadline {
+                return None;
+            }
+            tokio::time::sleep(DEPLOY_LOCK_POLL_INTERVAL).await;
+        }
+    }
+}
+
+impl Drop for DeployLock {
+    fn drop(&mut self) {
+        let mut locks = DEPLOY_LOCKS.lock().unwrap();
+        // a stuck build finishing late doesn't release the lock of the build that took it over
+        if locks.get(&self.container_name).map(|(holder, _)| *holder) == Some(self.build_id) {
+            locks.remove(&self.container_name);
+        }
+    }
+}
+
+/// Stop a running build. Only the image build can be stopped, once the image exists the deploy
+/// runs to the end. Returns false when the build isn't running or is past that point.
+pub fn cancel(build_id: Uuid) -> bool {
+    match CANCELLATIONS.lock().unwrap().remove(&build_id) {
+        Some(tx) => tx.send(()).is_ok(),
+        None => false,
+    }
+}
+
 #[derive(Error, Debug)]
 #[error("{message:?}")]
 pub struct BuildError {
     message: String,
     inner_error: Option<Box<dyn std::error::Error>>,
 }
+/// What started a build, stored on the build
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildTrigger {
+    #[default]
+    Push,
+    /// an automatic rebuild that refreshes the base image
+    Scheduled,
+    /// a redeploy staff started for many projects at once, see `rebuild`
+    Manual,
+}
+
+impl BuildTrigger {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BuildTrigger::Push => "push",
+            BuildTrigger::Scheduled => "scheduled",
+            BuildTrigger::Manual => "manual",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct BuildQueueItem {
-    pub container_name: String,
     pub container_src: String,
     pub owner: String,
     pub repo: String,
+    pub trigger: BuildTrigger,
+    /// rebuild batch the build belongs to
+    pub batch_id: Option<Uuid>,
+    /// gets the id of the build that takes the item, which is the waiting one when the project
+    /// already has a build queued
+    pub reply: Option<oneshot::Sender<Uuid>>,
 }
 
 #[derive(Debug)]
@@ -40,6 +168,7 @@ pub struct BuildItem {
     pub container_src: String,
     pub owner: String,
     pub repo: String,
+    pub trigger: BuildTrigger,
 }
 
 impl Hash for BuildItem {
@@ -58,31 +187,60 @@ impl Eq for BuildItem {}
 
 pub struct BuildQueue {
     pub build_count: Arc<AtomicUsize>,
+    /// deploys setting up containers at once, a smaller limit than the builds
+    pub deploy_slots: Arc<Semaphore>,
     pub waiting_queue: ConcurrentMutex<VecDeque<BuildItem>>,
     pub waiting_set: ConcurrentMutex<HashSet<String>>,
     pub receive_channel: Receiver<BuildQueueItem>,
     pub pg_pool: PgPool,
+    pub docker: Docker,
     pub config: Settings,
+    /// none when `build.admission` is off
+    pub admission: Option<Admission>,
 }
 
 impl BuildQueue {
-    pub fn new(build_count: usize, pg_pool: PgPool, config: Settings) -> (Self, Sender<BuildQueueItem>) {
+    pub fn new(
+        build_count: usize,
+        pg_pool: PgPool,
+        docker: Docker,
+        config: Settings,
+        admission: Option<Admission>,
+    ) -> (Self, Sender<BuildQueueItem>) {
         let (tx, rx) = mpsc::channel(32);
 
         (
             Self {
                 build_count: Arc::new(AtomicUsize::new(build_count)),
+                deploy_slots: Arc::new(Semaphore::new(config.build.maxdeploys.max(1))),
                 waiting_queue: Arc::new(Mutex::new(VecDeque::new())),
                 waiting_set: Arc::new(Mutex::new(HashSet::new())),
                 receive_channel: rx,
                 pg_pool,
+                docker,
                 config,
+                admission,
             },
             tx,
         )
     }
 }
 
+/// End a build that never left pending with `log`
+async fn fail_pending(pool: &PgPool, build_id: Uuid, log: &str) {
+    if let Err(err) = sqlx::query!(
+        "UPDATE builds SET status = 'failed', finished_at = now(), log = $1 WHERE id = $2 AND status = 'pending'",
+        log,
+        build_id,
+    )
+    .execute(pool)
+    .await
+    {
+        tracing::error!(%err, "Can't fail build: Failed to query database");
+    }
+    events::close(build_id, "failed");
+}
+
 pub async fn trigger_build(
     BuildItem {
         build_id,
@@ -90,9 +248,13 @@ pub async fn trigger_build(
         repo,
         container_src,
         container_name,
+        trigger,
     }: BuildItem,
     pool: PgPool,
+    docker: &Docker,
     config: &Settings,
+    admission: Option<Admission>,
+    deploy_slots: Arc<Semaphore>,
 ) -> Result<String, BuildError> {
     // TODO: need to emmit error somewhere
     let project = match sqlx::query!(
@@ -101,6 +263,7 @@ pub async fn trigger_build(
            JOIN project_owners ON projects.owner_id = project_owners.id
            WHERE project_owners.name = $1
            AND projects.name = $2
+           AND projects.deleted_at IS NULL
         "#,
         owner,
         repo
@@ -142,27 +305,135 @@ pub async fn trigger_build(
         }),
     }?;
 
-    if let Err(err) = sqlx::query!(
-        "UPDATE builds set status = 'building' where id = $1",
-        build_id
+    // registered before the build leaves pending, so a cancel always finds it in one place
+    let (_cancellable, cancel) = Cancellable::register(build_id);
+    let options = BuildOptions {
+        // scheduled rebuilds exist to pick up a patched base image
+        refresh: trigger == BuildTrigger::Scheduled,
+        cancel,
+        events: BuildEvents::new(build_id),
+        deploy_slots,
+    };
+
+    // a held build stays pending, so it can still be cancelled from the queue
+    announcements::wait_for_deploys(&pool).await;
+
+    let lock_timeout = Duration::from_secs(config.build.locktimeout);
+    let stale_after = Duration::from_millis(config.build.timeout as u64) + DEPLOY_LOCK_GRACE;
+    let Some(_deploy_lock) = DeployLock::acquire(&container_name, build_id, lock_timeout, stale_after).await else {
+        let log = format!(
+            "Another deploy of {owner}/{repo} is still in progress after {}s, try again once it has finished",
+            lock_timeout.as_secs()
+        );
+        fail_pending(&pool, build_id, &log).await;
+
+        return Err(BuildError {
+            message: log,
+            inner_error: None,
+        });
+    };
+
+    // checked for every trigger once the checkout is the one that gets built, the build
+    // stays failed and the owner is told, the next push or rebuild tries again
+    if let Some(admission) = &admission {
+        if let Err(at_capacity) = admission.admit(docker, &pool, config.build.max, &container_src).await {
+            tracing::warn!(%owner, %repo, %at_capacity, "Rejected build: Platform at capacity");
+            let log = format!("{at_capacity}\n\n==> hint: push again or ask for a rebuild once other deploys have stopped\n");
+            fail_pending(&pool, build_id, &log).await;
+            notifications::notify_project(
+                &pool,
+                project.id,
+                NotificationType::DeployFailed,
+                serde_json::json!({ "owner": owner, "project": repo, "build_id": build_id }),
+            )
+            .await;
+
+            return Err(BuildError {
+                message: log,
+                inner_error: None,
+            });
+        }
+    }
+
+    let repo_path = format!("{}/{owner}/{repo}.git", config.git.base);
+    let detection = detection::cached(&pool, project.id, &repo_path).await.unwrap_or_else(|err| {
+        tracing::warn!(?err, "Can't detect framework: Failed to query database");
+        None
+    });
+    let (framework, dockerfile) = match &detection {
+        Some(detection) => (detection.framework.as_deref(), detection.dockerfile.as_str()),
+        None => (detect_framework(&container_src), dockerfile_source(&container_src)),
+    };
+
+    match sqlx::query!(
+        r#"UPDATE builds set status = 'building', started_at = now(), framework = $2, dockerfile = $3, commit_sha = $4
+           WHERE id = $1 AND status = 'pending'
+           RETURNING id
+        "#,
+        build_id,
+        framework,
+        dockerfile,
+        git::head_commit(&container_src),
     )
-    .execute(&pool)
+    .fetch_optional(&pool)
     .await
     {
-        return Err(BuildError {
-            message: "Failed to update build status: Failed to query database".to_string(),
-            inner_error: Some(err.into()),
-        });
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            events::close(build_id, "cancelled");
+            return Err(BuildError {
+                message: format!("Build {build_id} was cancelled before it started"),
+                inner_error: None,
+            });
+        }
+        Err(err) => {
+            return Err(BuildError {
+                message: "Failed to update build status: Failed to query database".to_string(),
+                inner_error: Some(err.into()),
+            });
+        }
     }
 
     // TODO: Differentiate types of errors returned by build_docker (ex: ImageBuildError, NetworkCreateError, ContainerAttachError)
     let DockerContainer {
         ip, port, ..
-    } = match build_docker(&owner, &repo, &container_name, &container_src, pool.clone(), config).await {
-        Ok(result) => {
+    } = match build_docker(docker, &owner, &repo, &container_name, &container_src, pool.clone(), config, options).await {
+        Ok(mut result) => {
+            match quota::check(&pool, config, project.id, &container_src, result.image_size).await {
+                Ok(warnings) => {
+                    for warning in warnings {
+                        tracing::warn!(%owner, %repo, ?warning, "Project is nearing a quota");
+                        result.build_log.push_str(&format!("\n==> quota\n{warning}\n"));
+                        notifications::notify_project(
+                            &pool,
+                            project.id,
+                            NotificationType::QuotaWarning,
+                            serde_json::json!({
+                                "owner": owner,
+                                "project": repo,
+                                "quota": warning.quota,
+                                "current": warning.current,
+                                "limit": warning.limit,
+                                "suggestion": warning.suggestion,
+                            }),
+                        )
+                        .await;
+                    }
+                }
+                Err(err) => tracing::error!(?err, "Can't check quotas: Failed to query database"),
+            }
+
             if let Err(err) = sqlx::query!(
-                "UPDATE builds SET status = 'successful', log = $1 WHERE id = $2",
+                r#"UPDATE builds
+                   SET status = 'successful', finished_at = now(), log = $1, container_id = $2, image_id = $3, image_digest = $4, scan_summary = $5, runtime_config = $6
+                   WHERE id = $7
+                "#,
                 result.build_log,
+                result.container_id,
+                result.image_id,
+                result.image_digest,
+                result.scan.map(|summary| serde_json::to_value(summary).unwrap()),
+                serde_json::to_value(&result.snapshot).unwrap(),
                 build_id
             )
             .execute(&pool)
@@ -173,13 +444,47 @@ pub async fn trigger_build(
                     inner_error: Some(err.into()),
                 });
             }
+            events::close(build_id, "successful");
 
             Ok(result)
         }
         Err(err) => {
+            let recovered_from = match err.downcast_ref::<DeployError>() {
+                Some(DeployError::DeployFailed {
+                    recovery: Recovery::Restored { image },
+                    ..
+                }) => Some(image.clone()),
+                _ => None,
+            };
+            let scan_summary = match err.downcast_ref::<DeployError>() {
+                Some(DeployError::ScanFailed { summary }) => Some(serde_json::to_value(summary).unwrap()),
+                _ => None,
+            };
+            let (status, event_status) = match err.downcast_ref::<DeployError>() {
+                Some(DeployError::Cancelled) => (BuildState::CANCELLED, "cancelled"),
+                _ => (BuildState::FAILED, "failed"),
+            };
+            let notification = match err.downcast_ref::<DeployError>() {
+                Some(DeployError::Cancelled) => None,
+                Some(err) if err.is_crash_loop() => Some(NotificationType::CrashLoop),
+                _ => Some(NotificationType::DeployFailed),
+            };
+
+            let log = match hints::hint(&err) {
+                Some(hint) => format!("{err}\n\n==> hint: {hint}\n"),
+                None => err.to_string(),
+            };
+            let log = match trigger {
+                BuildTrigger::Scheduled => format!("==> scheduled rebuild with a fresh base image failed\n{log}"),
+                BuildTrigger::Push | BuildTrigger::Manual => log,
+            };
+
             if let Err(err) = sqlx::query!(
-                "UPDATE builds SET status = 'failed', log = $1 WHERE id = $2",
-                err.to_string(),
+                "UPDATE builds SET status = $1, finished_at = now(), log = $2, recovered_from = $3, scan_summary = $4 WHERE id = $5",
+                status as BuildState,
+                log,
+                recovered_from,
+                scan_summary,
                 build_id
             )
             .execute(&pool)
@@ -192,6 +497,16 @@ pub async fn trigger_build(
                     inner_error: Some(err.into()),
                 });
             }
+            events::close(build_id, event_status);
+            if let Some(notification) = notification {
+                notifications::notify_project(
+                    &pool,
+                    project.id,
+                    notification,
+                    serde_json::json!({ "owner": owner, "project": repo, "build_id": build_id }),
+                )
+                .await;
+            }
 
             return Err(BuildError {
                 message: format!("A build error occured while building repository: {repo}"),
@@ -243,12 +558,16 @@ pub async fn trigger_build(
     Ok(subdomain)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn process_task_poll(
     waiting_queue: ConcurrentMutex<VecDeque<BuildItem>>,
     waiting_set: ConcurrentMutex<HashSet<String>>,
     build_count: Arc<AtomicUsize>,
+    deploy_slots: Arc<Semaphore>,
     pool: PgPool,
+    docker: Docker,
     config: Settings,
+    admission: Option<Admission>,
 ) {
     loop {
         let mut waiting_queue = waiting_queue.lock().await;
@@ -266,17 +585,22 @@ pub async fn process_task_poll(
             {
                 let build_count = Arc::clone(&build_count);
                 let pool = pool.clone();
+                let docker = docker.clone();
                 let config = config.clone();
+                let deploy_slots = Arc::clone(&deploy_slots);
 
                 build_count.fetch_sub(1, Ordering::SeqCst);
                 tokio::spawn(async move {
-                    match trigger_build(build_item, pool, &config).await {
+                    let build_id = build_item.build_id;
+                    match trigger_build(build_item, pool, &docker, &config, admission, deploy_slots).await {
                         Ok(subdomain) => tracing::info!("Project deployed at {subdomain}"),
                         Err(BuildError {
                             message,
                             inner_error,
                         }) => tracing::error!(?inner_error, message),
                     };
+                    // builds that stopped before their status was stored still end their events
+                    events::close(build_id, "failed");
 
                     build_count.fetch_add(1, Ordering::SeqCst);
                 });
@@ -294,20 +618,23 @@ pub async fn process_task_enqueue(
 ) {
     while let Some(message) = receive_channel.recv().await {
         let BuildQueueItem {
-            container_name,
             container_src,
             owner,
             repo,
+            trigger,
+            batch_id,
+            reply,
         } = message;
         let mut waiting_queue = waiting_queue.lock().await;
         let mut waiting_set = waiting_set.lock().await;
 
         let project = match sqlx::query!(
-            r#"SELECT projects.id
+            r#"SELECT projects.id, projects.container_name
                FROM projects
                JOIN project_owners ON projects.owner_id = project_owners.id
                WHERE project_owners.name = $1
                AND projects.name = $2
+               AND projects.deleted_at IS NULL
             "#,
             owner,
             repo
@@ -328,17 +655,53 @@ pub async fn process_task_enqueue(
             }
         };
 
+        let container_name = project.container_name;
+
         if waiting_set.contains(&container_name) {
+            // the queued build picks up this push too. if it was cancelled, the push revives it
+            if let Some(queued) = waiting_queue.iter().find(|item| item.container_name == container_name) {
+                match sqlx::query!(
+                    "UPDATE builds SET status = 'pending', finished_at = NULL, log = '' WHERE id = $1 AND status = 'cancelled'",
+                    queued.build_id,
+                )
+                .execute(&pool)
+                .await
+                {
+                    Ok(result) if result.rows_affected() > 0 => events::open(queued.build_id),
+                    Ok(_) => {}
+                    Err(err) => tracing::error!(%err, "Can't revive cancelled build: Failed to query database"),
+                }
+
+                if let Some(reply) = reply {
+                    let _ = reply.send(queued.build_id);
+                }
+
+                // a rebuild batch follows the queued build instead
+                if let Some(batch_id) = batch_id {
+                    if let Err(err) = sqlx::query!(
+                        "UPDATE builds SET batch_id = $1 WHERE id = $2 AND batch_id IS NULL",
+                        batch_id,
+                        queued.build_id,
+                    )
+                    .execute(&pool)
+                    .await
+                    {
+                        tracing::error!(%err, "Can't add build to rebuild batch: Failed to query database");
+                    }
+                }
+            }
             continue;
         }
 
         let build_id = Uuid::from(Ulid::new());
         match sqlx::query!(
-            r#"INSERT INTO builds (id, project_id)
-               VALUES ($1, $2)
+            r#"INSERT INTO builds (id, project_id, trigger, batch_id)
+               VALUES ($1, $2, $3, $4)
             "#,
             build_id,
             project.id,
+            trigger.as_str(),
+            batch_id,
         )
         .fetch_optional(&pool)
         .await
@@ -356,8 +719,14 @@ pub async fn process_task_enqueue(
             container_src,
             owner,
             repo,
+            trigger,
         };
 
+        if let Some(reply) = reply {
+            let _ = reply.send(build_id);
+        }
+
+        events::open(build_id);
         waiting_set.insert(build_item.container_name.clone());
         waiting_queue.push_back(build_item);
     }
@@ -368,11 +737,15 @@ pub async fn build_queue_handler(build_queue: BuildQueue) {
         let waiting_queue = Arc::clone(&build_queue.waiting_queue);
         let waiting_set = Arc::clone(&build_queue.waiting_set);
         let pool = build_queue.pg_pool.clone();
+        let docker = build_queue.docker.clone();
         let config = build_queue.config.clone();
         let build_count = Arc::clone(&build_queue.build_count);
+        let deploy_slots = Arc::clone(&build_queue.deploy_slots);
+        let admission = build_queue.admission;
 
         tokio::spawn(async move {
-            process_task_poll(waiting_queue, waiting_set, build_count, pool, config).await;
+            process_task_poll(waiting_queue, waiting_set, build_count, deploy_slots, pool, docker, config, admission)
+                .await;
         });
     }
     {
@@ -391,3 +764,156 @@ pub async fn build_queue_handler(build_queue: BuildQueue) {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        configuration,
+        system::{capacity::Capacity, usage::{self, PlatformUsage}},
+    };
+
+    const HOUR: Duration = Duration::from_secs(3600);
+    const GIB: u64 = 1024 * 1024 * 1024;
+
+    /// Locks are shared by the whole process, every test takes its own project
+    fn project() -> String {
+        format!("test-{}", Uuid::new_v4())
+    }
+
+    #[tokio::test]
+    async fn second_deploy_gives_up_after_the_timeout() {
+        let project = project();
+        let _first = DeployLock::acquire(&project, Uuid::new_v4(), Duration::ZERO, HOUR).await.unwrap();
+
+        let started = Instant::now();
+        let second = DeployLock::acquire(&project, Uuid::new_v4(), Duration::from_secs(1), HOUR).await;
+
+        assert!(second.is_none());
+        assert!(started.elapsed() >= Duration::from_secs(1), "{:?}", started.elapsed());
+        assert!(started.elapsed() < Duration::from_secs(3), "{:?}", started.elapsed());
+    }
+
+    #[tokio::test]
+    async fn waiting_deploy_goes_once_the_first_finishes() {
+        let project = project();
+        let first = DeployLock::acquire(&project, Uuid::new_v4(), Duration::ZERO, HOUR).await.unwrap();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            drop(first);
+        });
+
+        let second = DeployLock::acquire(&project, Uuid::new_v4(), Duration::from_secs(5), HOUR).await;
+
+        assert!(second.is_some());
+    }
+
+    #[tokio::test]
+    async fn stuck_deploy_loses_its_lock() {
+        let project = project();
+        let stuck = DeployLock::acquire(&project, Uuid::new_v4(), Duration::ZERO, HOUR).await.unwrap();
+
+        let next = DeployLock::acquire(&project, Uuid::new_v4(), Duration::ZERO, Duration::ZERO).await;
+        assert!(next.is_some());
+
+        // finishing late leaves the lock to the build that took it over
+        drop(stuck);
+        assert!(DeployLock::acquire(&project, Uuid::new_v4(), Duration::ZERO, HOUR).await.is_none());
+
+        drop(next);
+        assert!(DeployLock::acquire(&project, Uuid::new_v4(), Duration::ZERO, HOUR).await.is_some());
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "..", scripts("schema")))]
+    async fn deploys_past_the_free_memory_are_turned_away(pool: PgPool) {
+        let (user_id, owner_id, project_id, build_id) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        sqlx::query("INSERT INTO users (id, username, password, name) VALUES ($1, 'alice', '', 'Alice')")
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO project_owners (id, name) VALUES ($1, 'alice')")
+            .bind(owner_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO users_owners (user_id, owner_id) VALUES ($1, $2)")
+            .bind(user_id)
+            .bind(owner_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO projects (id, owner_id, name, container_name) VALUES ($1, $2, 'blog', $3)")
+            .bind(project_id)
+            .bind(owner_id)
+            .bind(project())
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO builds (id, project_id) VALUES ($1, $2)")
+            .bind(build_id)
+            .bind(project_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let config: Settings = configuration::defaults()
+            .unwrap()
+            .set_override("build.max", 1)
+            .unwrap()
+            .set_override("build.admission", true)
+            .unwrap()
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        // 10 percent of 8 GiB is kept free and the rest is in use, no room for the 256M container
+        usage::set_recent(PlatformUsage {
+            containers: 4,
+            unsampled: 0,
+            memory_used_bytes: 7 * GIB + GIB / 5,
+            memory_total_bytes: Some(8 * GIB),
+            memory_percent: None,
+            cpu_used: 0.0,
+            cpu_total: Some(4),
+            cpu_percent: None,
+            queue: Capacity {
+                queued: 0,
+                running: 0,
+                concurrency: 1,
+                average_build_secs: None,
+                estimated_wait_secs: None,
+            },
+        })
+        .await;
+        let item = BuildItem {
+            build_id,
+            container_name: project(),
+            container_src: std::env::temp_dir().join(project()).to_str().unwrap().to_string(),
+            owner: "alice".to_string(),
+            repo: "blog".to_string(),
+            trigger: BuildTrigger::Push,
+        };
+        // only reached for a sample the test didn't set
+        let docker = Docker::connect_with_local_defaults().unwrap();
+
+        let err = trigger_build(item, pool.clone(), &docker, &config, Admission::new(&config).unwrap(), Arc::new(Semaphore::new(1)))
+            .await
+            .unwrap_err();
+
+        assert!(err.message.starts_with("Platform at capacity: the deploy needs 244.14 MiB of memory, 0 B is free"), "{}", err.message);
+        let (status, log): (String, String) = sqlx::query_as("SELECT status::text, log FROM builds WHERE id = $1")
+            .bind(build_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(status, "failed");
+        assert_eq!(log, err.message);
+        let notified: Vec<NotificationType> = sqlx::query_scalar("SELECT type FROM notifications WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(notified, vec![NotificationType::DeployFailed]);
+    }
+}
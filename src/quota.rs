@@ -0,0 +1,181 @@
+use std::path::Path;
+
+use byte_unit::Byte;
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::configuration::Settings;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Quota {
+    RepoSize,
+    ImageSize,
+    Projects,
+}
+
+impl Quota {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Quota::RepoSize => "repo_size",
+            Quota::ImageSize => "image_size",
+            Quota::Projects => "projects",
+        }
+    }
+
+    fn suggestion(&self) -> &'static str {
+        match self {
+            Quota::RepoSize => "Remove large files from the history and keep datasets out of the repository",
+            Quota::ImageSize => "Add a .dockerignore and drop unused packages from requirements.txt",
+            Quota::Projects => "Delete projects you no longer need",
+        }
+    }
+
+    fn is_size(&self) -> bool {
+        !matches!(self, Quota::Projects)
+    }
+}
+
+/// Sent once when a project crosses the warning threshold of a limit
+#[derive(Serialize, Debug)]
+pub struct QuotaWarning {
+    pub quota: Quota,
+    pub current: u64,
+    pub limit: u64,
+    pub suggestion: &'static str,
+}
+
+impl std::fmt::Display for QuotaWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let format = |value: u64| match self.quota.is_size() {
+            true => Byte::from_bytes(value as u128)
+                .get_appropriate_unit(true)
+                .to_string(),
+            false => value.to_string(),
+        };
+
+        write!(
+            f,
+            "WARNING: {} is at {} of {}. {}",
+            self.quota.as_str().replace('_', " "),
+            format(self.current),
+            format(self.limit),
+            self.suggestion
+        )
+    }
+}
+
+/// Size in bytes of a repository, without the checkout used for builds
+fn repo_size(dir: &Path) -> u64 {
+    fn dir_size(dir: &Path) -> u64 {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return 0,
+        };
+
+        entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => dir_size(&entry.path()),
+                Ok(file_type) if file_type.is_file() => entry.metadata().map(|m| m.len()).unwrap_or(0),
+                _ => 0,
+            })
+            .sum()
+    }
+
+    dir_size(dir).saturating_sub(dir_size(&dir.join("master")))
+}
+
+/// Record that the owner was warned about a quota. Returns false when they already were
+/// since usage last dropped below the threshold.
+async fn mark_notified(pool: &PgPool, project_id: Uuid, quota: Quota) -> Result<bool, sqlx::Error> {
+    let inserted = sqlx::query!(
+        r#"INSERT INTO quota_notifications (project_id, quota) VALUES ($1, $2)
+           ON CONFLICT (project_id, quota) DO NOTHING
+           RETURNING project_id
+        "#,
+        project_id,
+        quota.as_str(),
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(inserted.is_some())
+}
+
+async fn clear_notified(pool: &PgPool, project_id: Uuid, quota: Quota) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM quota_notifications WHERE project_id = $1 AND quota = $2",
+        project_id,
+        quota.as_str(),
+    )
+    .execute(pool)
+    .await
+    .map(|_| ())
+}
+
+/// Check a project against the configured limits after a deploy and return the warnings that
+/// haven't been sent yet
+pub async fn check(
+    pool: &PgPool,
+    config: &Settings,
+    project_id: Uuid,
+    container_src: &str,
+    image_size: Option<i64>,
+) -> Result<Vec<QuotaWarning>, sqlx::Error> {
+    let mut usage = Vec::new();
+
+    match config.quota_repo_size_bytes() {
+        Ok(Some(limit)) => {
+            let repo = Path::new(container_src)
+                .parent()
+                .unwrap_or(Path::new(container_src));
+            usage.push((Quota::RepoSize, repo_size(repo), limit));
+        }
+        Ok(None) => {}
+        Err(err) => tracing::warn!(?err, "Ignoring repo size quota"),
+    }
+
+    match (config.quota_image_size_bytes(), image_size) {
+        (Ok(Some(limit)), Some(size)) => usage.push((Quota::ImageSize, size.max(0) as u64, limit)),
+        (Err(err), _) => tracing::warn!(?err, "Ignoring image size quota"),
+        _ => {}
+    }
+
+    if config.quota.projects > 0 {
+        let record = sqlx::query!(
+            r#"SELECT COUNT(*) AS "count!"
+               FROM projects
+               WHERE owner_id = (SELECT owner_id FROM projects WHERE id = $1)
+            "#,
+            project_id
+        )
+        .fetch_one(pool)
+        .await?;
+        usage.push((Quota::Projects, record.count.max(0) as u64, config.quota.projects));
+    }
+
+    let mut warnings = Vec::new();
+    for (quota, current, limit) in usage {
+        if limit == 0 {
+            continue;
+        }
+
+        if current * 100 < limit * config.quota.warnat as u64 {
+            clear_notified(pool, project_id, quota).await?;
+            continue;
+        }
+
+        if mark_notified(pool, project_id, quota).await? {
+            warnings.push(QuotaWarning {
+                quota,
+                current,
+                limit,
+                suggestion: quota.suggestion(),
+            });
+        }
+    }
+
+    Ok(warnings)
+}
@@ -0,0 +1,621 @@
+use std::{path::Path, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::sync::mpsc::Sender;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{
+    projects::api::BuildState,
+    queue::{BuildQueueItem, BuildTrigger},
+};
+
+/// A queued project whose build doesn't show up after this long never reached the build queue
+const QUEUE_TIMEOUT_SECS: i64 = 60;
+
+/// How often a batch looks at the builds of its current round
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "rebuild_state", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum RebuildState {
+    Pending,
+    /// sent to the build queue, the build of the project tells how far it is
+    Queued,
+    Successful,
+    Failed,
+    Cancelled,
+}
+
+/// Which deployed projects a batch rebuilds, every condition that is given has to hold
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Filter {
+    /// framework of the last deploy, e.g. django, which is the Dockerfile template it was built with
+    pub template: Option<String>,
+    /// owner names, every owner when empty
+    #[serde(default)]
+    pub owners: Vec<String>,
+    /// only projects last deployed before this time
+    pub deployed_before: Option<DateTime<Utc>>,
+    /// skip projects whose last deploy was built from their own Dockerfile
+    #[serde(default)]
+    pub exclude_user_dockerfile: bool,
+}
+
+/// A project a filter picked
+#[derive(Serialize, Debug)]
+pub struct Candidate {
+    pub id: Uuid,
+    pub owner: String,
+    pub project: String,
+    pub framework: Option<String>,
+    /// repository or generated
+    pub dockerfile: Option<String>,
+    pub deployed_at: Option<DateTime<Utc>>,
+}
+
+/// Projects matching a filter, by owner and name. Only projects that were deployed before are
+/// picked, the last successful build is what the filter looks at.
+pub async fn matching(pool: &PgPool, filter: &Filter) -> Result<Vec<Candidate>, sqlx::Error> {
+    sqlx::query_as!(
+        Candidate,
+        r#"SELECT projects.id, project_owners.name AS owner, projects.name AS project,
+                  last.framework AS "framework?", last.dockerfile AS "dockerfile?",
+                  last.finished_at AS "deployed_at?"
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           JOIN LATERAL (
+             SELECT framework, dockerfile, finished_at
+             FROM builds
+             WHERE builds.project_id = projects.id AND builds.status = 'successful'
+             ORDER BY created_at DESC
+             LIMIT 1
+           ) last ON true
+           WHERE projects.deleted_at IS NULL
+           AND ($1::TEXT IS NULL OR last.framework = $1)
+           AND (cardinality($2::TEXT[]) = 0 OR project_owners.name = ANY($2))
+           AND ($3::TIMESTAMPTZ IS NULL OR last.finished_at < $3)
+           AND (NOT $4 OR last.dockerfile IS DISTINCT FROM 'repository')
+           ORDER BY project_owners.name, projects.name
+        "#,
+        filter.template,
+        &filter.owners,
+        filter.deployed_before,
+        filter.exclude_user_dockerfile,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Store a batch of the given projects, `run` rebuilds them
+pub async fn create(
+    pool: &PgPool,
+    created_by: Uuid,
+    filter: &Filter,
+    concurrency: usize,
+    pause_secs: u64,
+    projects: &[Uuid],
+) -> Result<Uuid, sqlx::Error> {
+    let batch_id = Uuid::from(Ulid::new());
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"INSERT INTO rebuild_batches (id, created_by, filter, concurrency, pause_secs)
+           VALUES ($1, $2, $3, $4, $5)
+        "#,
+        batch_id,
+        created_by,
+        serde_json::to_value(filter).unwrap(),
+        concurrency as i32,
+        pause_secs as i32,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"INSERT INTO rebuild_batch_projects (batch_id, project_id)
+           SELECT $1, UNNEST($2::UUID[])
+        "#,
+        batch_id,
+        projects,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(batch_id)
+}
+
+/// Cancel a batch, its projects that weren't queued yet are left out. Gives how many there
+/// were, or none when the batch doesn't exist or has already ended.
+pub async fn cancel(pool: &PgPool, batch_id: Uuid) -> Result<Option<u64>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let batch = sqlx::query!(
+        r#"UPDATE rebuild_batches SET cancelled_at = now()
+           WHERE id = $1 AND finished_at IS NULL AND cancelled_at IS NULL
+           RETURNING id
+        "#,
+        batch_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    if batch.is_none() {
+        return Ok(None);
+    }
+
+    let projects = sqlx::query!(
+        r#"UPDATE rebuild_batch_projects SET status = 'cancelled', finished_at = now()
+           WHERE batch_id = $1 AND status = 'pending'
+        "#,
+        batch_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(Some(projects.rows_affected()))
+}
+
+/// Start the batches that were running when the server stopped
+pub async fn resume(pool: PgPool, base: String, build_channel: Sender<BuildQueueItem>) {
+    let batches = match sqlx::query!(
+        "SELECT id FROM rebuild_batches WHERE finished_at IS NULL AND cancelled_at IS NULL"
+    )
+    .fetch_all(&pool)
+    .await
+    {
+        Ok(batches) => batches,
+        Err(err) => {
+            tracing::error!(?err, "Can't resume rebuild batches: Failed to query database");
+            return;
+        }
+    };
+
+    for batch in batches {
+        tracing::info!(batch_id = %batch.id, "Resuming rebuild batch");
+        tokio::spawn(run(pool.clone(), base.clone(), build_channel.clone(), batch.id));
+    }
+}
+
+/// Rebuild the projects of a batch a round at a time. A round queues `concurrency` projects as
+/// manual builds, and the next one starts `pause_secs` after all of them finished. Progress is
+/// kept in the database only, so a batch picks up where it was after a restart. Stops once the
+/// batch is cancelled.
+pub async fn run(pool: PgPool, base: String, build_channel: Sender<BuildQueueItem>, batch_id: Uuid) {
+    loop {
+        match step(&pool, &base, &build_channel, batch_id).await {
+            Ok(Some(wait)) => tokio::time::sleep(wait).await,
+            Ok(None) => return,
+            Err(err) => {
+                tracing::error!(?err, %batch_id, "Can't run rebuild batch: Failed to query database");
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Move a batch forward, returns how long to wait before the next step or none once the batch
+/// is done
+async fn step(
+    pool: &PgPool,
+    base: &str,
+    build_channel: &Sender<BuildQueueItem>,
+    batch_id: Uuid,
+) -> Result<Option<Duration>, sqlx::Error> {
+    let Some(batch) = sqlx::query!(
+        "SELECT concurrency, pause_secs, finished_at, cancelled_at FROM rebuild_batches WHERE id = $1",
+        batch_id
+    )
+    .fetch_optional(pool)
+    .await?
+    else {
+        return Ok(None);
+    };
+    if batch.finished_at.is_some() || batch.cancelled_at.is_some() {
+        return Ok(None);
+    }
+
+    if update_queued(pool, batch_id).await? > 0 {
+        return Ok(Some(POLL_INTERVAL));
+    }
+
+    // the pause runs from the end of the last round, wherever the server was in between
+    let last_finished = sqlx::query_scalar!(
+        "SELECT MAX(finished_at) FROM rebuild_batch_projects WHERE batch_id = $1",
+        batch_id
+    )
+    .fetch_one(pool)
+    .await?;
+    if let Some(last_finished) = last_finished {
+        let resume_at = last_finished + chrono::Duration::seconds(batch.pause_secs as i64);
+        if let Ok(wait) = (resume_at - Utc::now()).to_std() {
+            return Ok(Some(wait));
+        }
+    }
+
+    let projects = sqlx::query!(
+        r#"SELECT projects.id, projects.name AS project, project_owners.name AS owner
+           FROM rebuild_batch_projects
+           JOIN projects ON rebuild_batch_projects.project_id = projects.id
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE rebuild_batch_projects.batch_id = $1 AND rebuild_batch_projects.status = 'pending'
+           ORDER BY project_owners.name, projects.name
+           LIMIT $2
+        "#,
+        batch_id,
+        batch.concurrency as i64,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    if projects.is_empty() {
+        sqlx::query!("UPDATE rebuild_batches SET finished_at = now() WHERE id = $1", batch_id)
+            .execute(pool)
+            .await?;
+        tracing::info!(%batch_id, "Rebuild batch finished");
+        return Ok(None);
+    }
+
+    for project in projects {
+        let container_src = format!("{base}/{}/{}.git/master", project.owner, project.project);
+        if !Path::new(&container_src).is_dir() {
+            finish(pool, batch_id, project.id, RebuildState::Failed, Some("The project has no checkout to build")).await?;
+            continue;
+        }
+
+        sqlx::query!(
+            r#"UPDATE rebuild_batch_projects SET status = 'queued', queued_at = now()
+               WHERE batch_id = $1 AND project_id = $2
+            "#,
+            batch_id,
+            project.id,
+        )
+        .execute(pool)
+        .await?;
+
+        tracing::info!(%batch_id, owner = %project.owner, project = %project.project, "Queueing rebuild");
+        let item = BuildQueueItem {
+            container_src,
+            owner: project.owner,
+            repo: project.project,
+            trigger: BuildTrigger::Manual,
+            batch_id: Some(batch_id),
+            reply: None,
+        };
+        if build_channel.send(item).await.is_err() {
+            tracing::error!(%batch_id, "Can't queue rebuild: Build queue is closed");
+            return Ok(None);
+        }
+    }
+
+    Ok(Some(POLL_INTERVAL))
+}
+
+/// Take the outcome of finished builds over to the projects of the batch, returns how many
+/// projects are still building
+async fn update_queued(pool: &PgPool, batch_id: Uuid) -> Result<usize, sqlx::Error> {
+    let queued = sqlx::query!(
+        r#"SELECT rebuild_batch_projects.project_id, rebuild_batch_projects.queued_at,
+                  builds.status AS "build_status?: BuildState"
+           FROM rebuild_batch_projects
+           LEFT JOIN builds ON builds.batch_id = rebuild_batch_projects.batch_id
+             AND builds.project_id = rebuild_batch_projects.project_id
+           WHERE rebuild_batch_projects.batch_id = $1 AND rebuild_batch_projects.status = 'queued'
+        "#,
+        batch_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let timeout = Utc::now() - chrono::Duration::seconds(QUEUE_TIMEOUT_SECS);
+    let mut building = 0;
+    for project in queued {
+        let (state, error) = match project.build_status {
+            Some(BuildState::SUCCESSFUL) => (RebuildState::Successful, None),
+            Some(BuildState::FAILED) => (RebuildState::Failed, None),
+            Some(BuildState::CANCELLED) => (RebuildState::Cancelled, None),
+            Some(BuildState::PENDING | BuildState::BUILDING) => {
+                building += 1;
+                continue;
+            }
+            None if project.queued_at.map_or(true, |queued_at| queued_at < timeout) => {
+                (RebuildState::Failed, Some("The build queue didn't take the rebuild"))
+            }
+            None => {
+                building += 1;
+                continue;
+            }
+        };
+        finish(pool, batch_id, project.project_id, state, error).await?;
+    }
+
+    Ok(building)
+}
+
+async fn finish(
+    pool: &PgPool,
+    batch_id: Uuid,
+    project_id: Uuid,
+    state: RebuildState,
+    error: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE rebuild_batch_projects SET status = $1, error = $2, finished_at = now()
+           WHERE batch_id = $3 AND project_id = $4
+        "#,
+        state as RebuildState,
+        error,
+        batch_id,
+        project_id,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use tokio::sync::mpsc::{self, Receiver};
+
+    use super::*;
+
+    async fn owner(pool: &PgPool, name: &str) -> Uuid {
+        let id = Uuid::new_v4();
+        sqlx::query("INSERT INTO project_owners (id, name) VALUES ($1, $2)")
+            .bind(id)
+            .bind(name)
+            .execute(pool)
+            .await
+            .unwrap();
+        id
+    }
+
+    async fn project(pool: &PgPool, owner_id: Uuid, name: &str) -> Uuid {
+        let id = Uuid::new_v4();
+        sqlx::query("INSERT INTO projects (id, owner_id, name, container_name) VALUES ($1, $2, $3, $4)")
+            .bind(id)
+            .bind(owner_id)
+            .bind(name)
+            .bind(format!("{owner_id}-{name}"))
+            .execute(pool)
+            .await
+            .unwrap();
+        id
+    }
+
+    /// A build of the project that finished `days_ago`, of `batch_id` when given
+    async fn build(
+        pool: &PgPool,
+        project_id: Uuid,
+        status: &str,
+        framework: &str,
+        dockerfile: &str,
+        days_ago: i32,
+        batch_id: Option<Uuid>,
+    ) {
+        sqlx::query(
+            r#"INSERT INTO builds (id, project_id, status, framework, dockerfile, batch_id, created_at, finished_at)
+               VALUES ($1, $2, $3::build_state, $4, $5, $6, now() - make_interval(days => $7), now() - make_interval(days => $7))"#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(project_id)
+        .bind(status)
+        .bind(framework)
+        .bind(dockerfile)
+        .bind(batch_id)
+        .bind(days_ago)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    fn names(candidates: Vec<Candidate>) -> Vec<String> {
+        candidates
+            .into_iter()
+            .map(|candidate| format!("{}/{}", candidate.owner, candidate.project))
+            .collect()
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "..", scripts("schema")))]
+    async fn filters_look_at_the_last_successful_build(pool: PgPool) {
+        let alice = owner(&pool, "alice").await;
+        let bob = owner(&pool, "bob").await;
+        let blog = project(&pool, alice, "blog").await;
+        build(&pool, blog, "successful", "django", "generated", 10, None).await;
+        let shop = project(&pool, alice, "shop").await;
+        build(&pool, shop, "successful", "django", "repository", 1, None).await;
+        let api = project(&pool, bob, "api").await;
+        build(&pool, api, "successful", "flask", "generated", 2, None).await;
+        // failed builds deployed nothing
+        build(&pool, api, "failed", "django", "generated", 1, None).await;
+        project(&pool, bob, "new").await;
+        let old = project(&pool, bob, "old").await;
+        build(&pool, old, "successful", "django", "generated", 30, None).await;
+        sqlx::query("UPDATE projects SET deleted_at = now() WHERE id = $1")
+            .bind(old)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let picked = |filter: Filter| {
+            let pool = pool.clone();
+            async move { names(matching(&pool, &filter).await.unwrap()) }
+        };
+
+        assert_eq!(picked(Filter::default()).await, vec!["alice/blog", "alice/shop", "bob/api"]);
+        assert_eq!(
+            picked(Filter { template: Some("django".to_string()), ..Default::default() }).await,
+            vec!["alice/blog", "alice/shop"]
+        );
+        assert_eq!(
+            picked(Filter { owners: vec!["bob".to_string()], ..Default::default() }).await,
+            vec!["bob/api"]
+        );
+        assert_eq!(
+            picked(Filter { deployed_before: Some(Utc::now() - chrono::Duration::days(5)), ..Default::default() }).await,
+            vec!["alice/blog"]
+        );
+        assert_eq!(
+            picked(Filter { exclude_user_dockerfile: true, ..Default::default() }).await,
+            vec!["alice/blog", "bob/api"]
+        );
+    }
+
+    /// A batch over alice's projects, the ones in `checkouts` have something to build
+    async fn batch(
+        pool: &PgPool,
+        projects: &[&str],
+        checkouts: &[&str],
+        concurrency: usize,
+        pause_secs: u64,
+    ) -> (Uuid, Vec<Uuid>, PathBuf) {
+        let base = std::env::temp_dir().join(format!("pws-rebuild-{}", Uuid::new_v4()));
+        for name in checkouts {
+            std::fs::create_dir_all(base.join(format!("alice/{name}.git/master"))).unwrap();
+        }
+
+        let alice = owner(pool, "alice").await;
+        let mut ids = Vec::new();
+        for name in projects {
+            ids.push(project(pool, alice, name).await);
+        }
+        let admin = Uuid::new_v4();
+        sqlx::query("INSERT INTO users (id, username, password, name) VALUES ($1, 'admin', '', 'Admin')")
+            .bind(admin)
+            .execute(pool)
+            .await
+            .unwrap();
+        let batch_id = create(pool, admin, &Filter::default(), concurrency, pause_secs, &ids)
+            .await
+            .unwrap();
+
+        (batch_id, ids, base)
+    }
+
+    async fn states(pool: &PgPool, batch_id: Uuid) -> Vec<(String, RebuildState, Option<String>)> {
+        sqlx::query_as(
+            r#"SELECT projects.name, rebuild_batch_projects.status, rebuild_batch_projects.error
+               FROM rebuild_batch_projects JOIN projects ON projects.id = rebuild_batch_projects.project_id
+               WHERE batch_id = $1
+               ORDER BY projects.name"#,
+        )
+        .bind(batch_id)
+        .fetch_all(pool)
+        .await
+        .unwrap()
+    }
+
+    fn queued(receiver: &mut Receiver<BuildQueueItem>) -> Vec<String> {
+        let mut repos = Vec::new();
+        while let Ok(item) = receiver.try_recv() {
+            assert_eq!(item.trigger, BuildTrigger::Manual);
+            repos.push(item.repo);
+        }
+        repos
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "..", scripts("schema")))]
+    async fn batches_rebuild_a_round_at_a_time(pool: PgPool) {
+        let (batch_id, ids, base) = batch(&pool, &["a", "b", "c", "d"], &["a", "b", "c"], 2, 0).await;
+        let base = base.to_str().unwrap();
+        let (sender, mut receiver) = mpsc::channel(10);
+
+        assert_eq!(step(&pool, base, &sender, batch_id).await.unwrap(), Some(POLL_INTERVAL));
+        assert_eq!(queued(&mut receiver), vec!["a", "b"]);
+
+        // nothing more is queued while the round builds
+        assert_eq!(step(&pool, base, &sender, batch_id).await.unwrap(), Some(POLL_INTERVAL));
+        assert!(queued(&mut receiver).is_empty());
+
+        build(&pool, ids[0], "successful", "django", "generated", 0, Some(batch_id)).await;
+        build(&pool, ids[1], "failed", "django", "generated", 0, Some(batch_id)).await;
+        step(&pool, base, &sender, batch_id).await.unwrap();
+        // d has no checkout, c takes the round alone
+        assert_eq!(queued(&mut receiver), vec!["c"]);
+
+        build(&pool, ids[2], "successful", "django", "generated", 0, Some(batch_id)).await;
+        assert_eq!(step(&pool, base, &sender, batch_id).await.unwrap(), None);
+        std::fs::remove_dir_all(base).unwrap();
+
+        assert_eq!(
+            states(&pool, batch_id).await,
+            vec![
+                ("a".to_string(), RebuildState::Successful, None),
+                ("b".to_string(), RebuildState::Failed, None),
+                ("c".to_string(), RebuildState::Successful, None),
+                ("d".to_string(), RebuildState::Failed, Some("The project has no checkout to build".to_string())),
+            ]
+        );
+        let finished = sqlx::query_scalar::<_, bool>("SELECT finished_at IS NOT NULL FROM rebuild_batches WHERE id = $1")
+            .bind(batch_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert!(finished);
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "..", scripts("schema")))]
+    async fn rounds_wait_the_pause_after_the_last_one(pool: PgPool) {
+        let (batch_id, ids, base) = batch(&pool, &["a", "b"], &["a", "b"], 1, 3600).await;
+        let base = base.to_str().unwrap();
+        let (sender, mut receiver) = mpsc::channel(10);
+
+        step(&pool, base, &sender, batch_id).await.unwrap();
+        build(&pool, ids[0], "successful", "django", "generated", 0, Some(batch_id)).await;
+        let wait = step(&pool, base, &sender, batch_id).await.unwrap().unwrap();
+        std::fs::remove_dir_all(base).unwrap();
+
+        assert!(wait > Duration::from_secs(3590) && wait <= Duration::from_secs(3600), "{wait:?}");
+        assert_eq!(queued(&mut receiver), vec!["a"]);
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "..", scripts("schema")))]
+    async fn builds_the_queue_never_took_fail(pool: PgPool) {
+        let (batch_id, _, base) = batch(&pool, &["a"], &["a"], 1, 0).await;
+        let base = base.to_str().unwrap();
+        let (sender, _receiver) = mpsc::channel(10);
+
+        step(&pool, base, &sender, batch_id).await.unwrap();
+        sqlx::query("UPDATE rebuild_batch_projects SET queued_at = now() - interval '2 minutes'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        step(&pool, base, &sender, batch_id).await.unwrap();
+        std::fs::remove_dir_all(base).unwrap();
+
+        assert_eq!(
+            states(&pool, batch_id).await,
+            vec![("a".to_string(), RebuildState::Failed, Some("The build queue didn't take the rebuild".to_string()))]
+        );
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "..", scripts("schema")))]
+    async fn cancelled_batches_queue_nothing_more(pool: PgPool) {
+        let (batch_id, _, base) = batch(&pool, &["a", "b", "c"], &["a", "b", "c"], 1, 0).await;
+        let base = base.to_str().unwrap();
+        let (sender, mut receiver) = mpsc::channel(10);
+
+        step(&pool, base, &sender, batch_id).await.unwrap();
+        assert_eq!(cancel(&pool, batch_id).await.unwrap(), Some(2));
+        assert_eq!(step(&pool, base, &sender, batch_id).await.unwrap(), None);
+        std::fs::remove_dir_all(base).unwrap();
+
+        assert_eq!(queued(&mut receiver), vec!["a"]);
+        assert_eq!(
+            states(&pool, batch_id).await,
+            vec![
+                ("a".to_string(), RebuildState::Queued, None),
+                ("b".to_string(), RebuildState::Cancelled, None),
+                ("c".to_string(), RebuildState::Cancelled, None),
+            ]
+        );
+        // only once
+        assert_eq!(cancel(&pool, batch_id).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,154 @@
+use sqlx::{PgConnection, PgPool};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::{
+    configuration::Settings,
+    docker::subdomain_for,
+};
+
+#[derive(Error, Debug)]
+pub enum ClaimError {
+    #[error("{subdomain} is used by another project")]
+    Taken { subdomain: String },
+    #[error("{subdomain} is reserved for the platform")]
+    Reserved { subdomain: String },
+    #[error("Failed to query database: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+/// A project whose subdomain was already claimed when the claims were backfilled
+#[derive(Debug)]
+pub struct Conflict {
+    pub project_id: Uuid,
+    pub subdomain: String,
+    pub reason: String,
+}
+
+/// Check that a subdomain is free or already belongs to the project, `None` for a project that
+/// hasn't been created yet
+pub async fn check_subdomain(
+    conn: &mut PgConnection,
+    project_id: Option<Uuid>,
+    subdomain: &str,
+) -> Result<(), ClaimError> {
+    let subdomain = subdomain.to_lowercase();
+
+    let claim = sqlx::query!(
+        "SELECT project_id FROM subdomain_claims WHERE subdomain = $1",
+        subdomain
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    match claim {
+        None => Ok(()),
+        Some(claim) if project_id.is_some() && claim.project_id == project_id => Ok(()),
+        Some(claim) if claim.project_id.is_none() => Err(ClaimError::Reserved { subdomain }),
+        Some(_) => Err(ClaimError::Taken { subdomain }),
+    }
+}
+
+/// Claim a subdomain for a project, replacing the project's previous claim. Fails when the
+/// subdomain is reserved or belongs to another project, so a project can never shadow another
+/// service behind Traefik.
+pub async fn claim_subdomain(
+    conn: &mut PgConnection,
+    project_id: Uuid,
+    subdomain: &str,
+) -> Result<(), ClaimError> {
+    check_subdomain(&mut *conn, Some(project_id), subdomain).await?;
+    let subdomain = subdomain.to_lowercase();
+
+    let claimed = sqlx::query!(
+        r#"INSERT INTO subdomain_claims (subdomain, project_id) VALUES ($1, $2)
+           ON CONFLICT (project_id) DO UPDATE SET subdomain = EXCLUDED.subdomain, claimed_at = now()
+        "#,
+        subdomain,
+        project_id,
+    )
+    .execute(&mut *conn)
+    .await;
+
+    match claimed {
+        Ok(_) => Ok(()),
+        // claimed by another project since the check above
+        Err(sqlx::Error::Database(err)) if err.is_unique_violation() => Err(ClaimError::Taken { subdomain }),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Reserve the configured platform subdomains and claim the subdomain of every project that
+/// doesn't have one yet. Projects that can't get theirs are returned, they fail to deploy until
+/// the conflict is resolved.
+pub async fn backfill(pool: &PgPool, config: &Settings) -> Result<Vec<Conflict>, sqlx::Error> {
+    let reserved = config
+        .application
+        .reservedsubdomains
+        .iter()
+        .map(|subdomain| subdomain.to_lowercase())
+        .collect::<Vec<_>>();
+
+    sqlx::query!(
+        "DELETE FROM subdomain_claims WHERE project_id IS NULL AND NOT (subdomain = ANY($1))",
+        &reserved
+    )
+    .execute(pool)
+    .await?;
+
+    let mut conflicts = Vec::new();
+    for subdomain in reserved {
+        let claim = sqlx::query!(
+            r#"INSERT INTO subdomain_claims (subdomain) VALUES ($1)
+               ON CONFLICT (subdomain) DO UPDATE SET subdomain = EXCLUDED.subdomain
+               RETURNING project_id
+            "#,
+            subdomain
+        )
+        .fetch_one(pool)
+        .await?;
+
+        // the project keeps serving, but new deploys of it are refused
+        if let Some(project_id) = claim.project_id {
+            conflicts.push(Conflict {
+                project_id,
+                subdomain,
+                reason: "reserved for the platform".to_string(),
+            });
+        }
+    }
+
+    let projects = sqlx::query!(
+        r#"SELECT projects.id, projects.name AS project, projects.container_name, project_owners.name AS owner
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.id NOT IN (
+             SELECT project_id FROM subdomain_claims WHERE project_id IS NOT NULL
+           )
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut conn = pool.acquire().await?;
+    for project in projects {
+        let subdomain = subdomain_for(
+            &project.owner,
+            &project.project,
+            &project.container_name,
+            config.application.subdomain,
+        );
+
+        match claim_subdomain(&mut conn, project.id, &subdomain).await {
+            Ok(()) => {}
+            Err(ClaimError::Database(err)) => return Err(err),
+            Err(err) => conflicts.push(Conflict {
+                project_id: project.id,
+                subdomain,
+                reason: err.to_string(),
+            }),
+        }
+    }
+
+    Ok(conflicts)
+}
@@ -0,0 +1,984 @@
+use std::{collections::HashMap, path::Path, process::Stdio};
+
+use async_trait::async_trait;
+use bollard::{
+    container::{
+        Config, CreateContainerOptions, ListContainersOptions, LogOutput, LogsOptions,
+        RemoveContainerOptions, RenameContainerOptions, StartContainerOptions, Stats, StatsOptions,
+        StopContainerOptions, WaitContainerOptions,
+    },
+    errors::Error,
+    image::{ListImagesOptions, TagImageOptions},
+    network::{
+        ConnectNetworkOptions, CreateNetworkOptions, DisconnectNetworkOptions,
+        InspectNetworkOptions, ListNetworksOptions,
+    },
+    service::{
+        ContainerCreateResponse, ContainerInspectResponse, ContainerSummary, ImageInspect,
+        ImageSummary, Ipam, IpamConfig, Network,
+    },
+    Docker,
+};
+use futures::StreamExt;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, BufReader},
+    process::Command,
+};
+
+use crate::events::{BuildEvents, BuildStep};
+
+/// Address ranges of a new network. Subnets that aren't set are picked by the daemon from its
+/// address pools
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Addressing<'a> {
+    pub subnet: Option<&'a str>,
+    /// give containers an ipv6 address next to their ipv4 one
+    pub ipv6: bool,
+    pub ipv6_subnet: Option<&'a str>,
+}
+
+/// An image to build, `docker build` with BuildKit
+#[derive(Debug, Clone)]
+pub struct ImageBuild<'a> {
+    /// tag of the built image
+    pub image: &'a str,
+    pub dockerfile: &'a Path,
+    /// directory sent as build context
+    pub context: &'a str,
+    /// `key=value` labels of the image
+    pub labels: Vec<String>,
+    /// `KEY=VALUE` build args
+    pub build_args: Vec<String>,
+    /// BuildKit secrets as id, environment variable and value, kept out of the build args
+    pub secrets: &'a [(&'static str, &'static str, String)],
+    pub cpu_period: i64,
+    pub cpu_quota: i64,
+    /// pull the base image and build without the layer cache
+    pub refresh: bool,
+}
+
+/// What a finished build printed and whether it succeeded
+#[derive(Debug, Clone)]
+pub struct BuildOutput {
+    pub success: bool,
+    pub log: String,
+}
+
+/// The container operations a deploy needs. `build_docker` only talks to the daemon through
+/// this trait, so the deploy flow doesn't depend on a particular client.
+#[async_trait]
+pub trait ContainerRuntime: Send + Sync {
+    /// Images matching a reference, e.g. `name:tag`
+    async fn list_images(&self, reference: &str) -> Result<Vec<ImageSummary>, Error>;
+    async fn tag_image(&self, image: &str, repo: &str, tag: &str) -> Result<(), Error>;
+    async fn remove_image(&self, image: &str) -> Result<(), Error>;
+    async fn inspect_image(&self, image: &str) -> Result<ImageInspect, Error>;
+    /// Build an image, reporting the build steps to `events` as they start. Dropping the
+    /// future cancels the build
+    async fn build(&self, build: ImageBuild<'_>, events: &BuildEvents) -> Result<BuildOutput, std::io::Error>;
+
+    async fn list_networks(&self, name: &str) -> Result<Vec<Network>, Error>;
+    /// An internal network has no route out of the host
+    async fn create_network(
+        &self,
+        name: &str,
+        internal: bool,
+        addressing: Addressing<'_>,
+        labels: HashMap<String, String>,
+    ) -> Result<(), Error>;
+    async fn inspect_network(&self, id: &str) -> Result<Network, Error>;
+    async fn connect_network(&self, network: &str, container: &str) -> Result<(), Error>;
+    async fn disconnect_network(&self, network: &str, container: &str) -> Result<(), Error>;
+
+    /// Containers, running or not, with exactly this name
+    async fn list_containers(&self, name: &str) -> Result<Vec<ContainerSummary>, Error>;
+    /// Containers, running or not, carrying every one of the `key=value` labels
+    async fn list_labeled_containers(&self, labels: &[String]) -> Result<Vec<ContainerSummary>, Error>;
+    async fn create_container(
+        &self,
+        name: &str,
+        config: Config<String>,
+    ) -> Result<ContainerCreateResponse, Error>;
+    async fn start_container(&self, container: &str) -> Result<(), Error>;
+    /// Stop the container, killing it if it hasn't exited after `timeout` seconds
+    async fn stop_container(&self, container: &str, timeout: i64) -> Result<(), Error>;
+    async fn remove_container(&self, container: &str, force: bool) -> Result<(), Error>;
+    async fn rename_container(&self, container: &str, name: &str) -> Result<(), Error>;
+    async fn inspect_container(&self, container: &str) -> Result<ContainerInspectResponse, Error>;
+    /// Wait for the container to exit and return its exit code
+    async fn wait_container(&self, container: &str) -> Result<i64, Error>;
+    /// Combined stdout and stderr of the container
+    async fn container_logs(&self, container: &str) -> Result<String, Error>;
+    /// One sample of the container's resource usage. The daemon takes about a second, cpu
+    /// usage is measured over it
+    async fn container_stats(&self, container: &str) -> Result<Stats, Error>;
+}
+
+#[async_trait]
+impl ContainerRuntime for Docker {
+    async fn list_images(&self, reference: &str) -> Result<Vec<ImageSummary>, Error> {
+        Docker::list_images(
+            self,
+            Some(ListImagesOptions::<String> {
+                all: false,
+                filters: HashMap::from([("reference".to_string(), vec![reference.to_string()])]),
+                ..Default::default()
+            }),
+        )
+        .await
+    }
+
+    async fn tag_image(&self, image: &str, repo: &str, tag: &str) -> Result<(), Error> {
+        Docker::tag_image(self, image, Some(TagImageOptions { tag, repo })).await
+    }
+
+    async fn remove_image(&self, image: &str) -> Result<(), Error> {
+        Docker::remove_image(self, image, None, None).await.map(|_| ())
+    }
+
+    async fn inspect_image(&self, image: &str) -> Result<ImageInspect, Error> {
+        Docker::inspect_image(self, image).await
+    }
+
+    /// Runs the docker cli, the daemon's build endpoint doesn't take BuildKit secrets. BuildKit
+    /// progress on stderr is read line by line, the child is killed when the future is dropped
+    /// and the build on the daemon with it.
+    async fn build(&self, build: ImageBuild<'_>, events: &BuildEvents) -> Result<BuildOutput, std::io::Error> {
+        let mut cmd = Command::new("docker");
+        cmd.args([
+            "build".to_string(),
+            "--progress=plain".to_string(),
+            format!("--cpu-period={}", build.cpu_period),
+            format!("--cpu-quota={}", build.cpu_quota),
+            "-t".to_string(),
+            build.image.to_string(),
+        ]);
+        cmd.arg("-f").arg(build.dockerfile);
+
+        for label in &build.labels {
+            cmd.arg("--label").arg(label);
+        }
+        for build_arg in &build.build_args {
+            cmd.arg("--build-arg").arg(build_arg);
+        }
+        for (id, env, value) in build.secrets {
+            cmd.arg("--secret").arg(format!("id={id},env={env}")).env(env, value);
+        }
+        if build.refresh {
+            cmd.args(["--pull", "--no-cache"]);
+        }
+
+        let mut child = cmd
+            .arg(build.context)
+            .env("DOCKER_BUILDKIT", "1")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+
+        let read_stdout = async {
+            let mut buf = Vec::new();
+            if let Some(mut stdout) = stdout {
+                stdout.read_to_end(&mut buf).await?;
+            }
+            Ok::<_, std::io::Error>(buf)
+        };
+        let read_stderr = async {
+            let mut buf = Vec::new();
+            if let Some(stderr) = stderr {
+                let mut stderr = BufReader::new(stderr);
+                let mut line = Vec::new();
+                while stderr.read_until(b'\n', &mut line).await? > 0 {
+                    if let Some(step) = BuildStep::from_buildkit(&String::from_utf8_lossy(&line)) {
+                        events.step(step);
+                    }
+                    buf.append(&mut line);
+                }
+            }
+            Ok::<_, std::io::Error>(buf)
+        };
+
+        let (stdout, stderr) = tokio::try_join!(read_stdout, read_stderr)?;
+        let status = child.wait().await?;
+
+        Ok(BuildOutput {
+            success: status.success(),
+            log: build_log(&stdout, &stderr),
+        })
+    }
+
+    async fn list_networks(&self, name: &str) -> Result<Vec<Network>, Error> {
+        Docker::list_networks(
+            self,
+            Some(ListNetworksOptions {
+                filters: HashMap::from([("name".to_string(), vec![name.to_string()])]),
+            }),
+        )
+        .await
+    }
+
+    async fn create_network(
+        &self,
+        name: &str,
+        internal: bool,
+        addressing: Addressing<'_>,
+        labels: HashMap<String, String>,
+    ) -> Result<(), Error> {
+        let subnets = [addressing.subnet, addressing.ipv6_subnet.filter(|_| addressing.ipv6)]
+            .into_iter()
+            .flatten()
+            .map(|subnet| IpamConfig {
+                subnet: Some(subnet.to_string()),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let ipam = Ipam {
+            config: Some(subnets).filter(|subnets| !subnets.is_empty()),
+            ..Default::default()
+        };
+
+        let res = Docker::create_network(
+            self,
+            CreateNetworkOptions {
+                name: name.to_string(),
+                internal,
+                labels,
+                ipam,
+                enable_ipv6: addressing.ipv6,
+                ..Default::default()
+            },
+        )
+        .await?;
+        tracing::info!("create network response-> {:#?}", res);
+
+        Ok(())
+    }
+
+    async fn inspect_network(&self, id: &str) -> Result<Network, Error> {
+        Docker::inspect_network(
+            self,
+            id,
+            Some(InspectNetworkOptions::<&str> {
+                verbose: true,
+                ..Default::default()
+            }),
+        )
+        .await
+    }
+
+    async fn connect_network(&self, network: &str, container: &str) -> Result<(), Error> {
+        Docker::connect_network(
+            self,
+            network,
+            ConnectNetworkOptions {
+                container,
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    async fn disconnect_network(&self, network: &str, container: &str) -> Result<(), Error> {
+        Docker::disconnect_network(
+            self,
+            network,
+            DisconnectNetworkOptions {
+                container,
+                force: true,
+            },
+        )
+        .await
+    }
+
+    async fn list_containers(&self, name: &str) -> Result<Vec<ContainerSummary>, Error> {
+        Docker::list_containers(
+            self,
+            Some(ListContainersOptions::<String> {
+                all: true,
+                filters: HashMap::from([("name".to_string(), vec![format!("^{name}$")])]),
+                ..Default::default()
+            }),
+        )
+        .await
+    }
+
+    async fn list_labeled_containers(&self, labels: &[String]) -> Result<Vec<ContainerSummary>, Error> {
+        Docker::list_containers(
+            self,
+            Some(ListContainersOptions::<String> {
+                all: true,
+                filters: HashMap::from([("label".to_string(), labels.to_vec())]),
+                ..Default::default()
+            }),
+        )
+        .await
+    }
+
+    async fn create_container(
+        &self,
+        name: &str,
+        config: Config<String>,
+    ) -> Result<ContainerCreateResponse, Error> {
+        Docker::create_container(
+            self,
+            Some(CreateContainerOptions {
+                name,
+                platform: None,
+            }),
+            config,
+        )
+        .await
+    }
+
+    async fn start_container(&self, container: &str) -> Result<(), Error> {
+        Docker::start_container(self, container, None::<StartContainerOptions<&str>>).await
+    }
+
+    async fn stop_container(&self, container: &str, timeout: i64) -> Result<(), Error> {
+        Docker::stop_container(self, container, Some(StopContainerOptions { t: timeout })).await
+    }
+
+    async fn remove_container(&self, container: &str, force: bool) -> Result<(), Error> {
+        Docker::remove_container(
+            self,
+            container,
+            Some(RemoveContainerOptions {
+                force,
+                ..Default::default()
+            }),
+        )
+        .await
+    }
+
+    async fn rename_container(&self, container: &str, name: &str) -> Result<(), Error> {
+        Docker::rename_container(self, container, RenameContainerOptions { name }).await
+    }
+
+    async fn inspect_container(&self, container: &str) -> Result<ContainerInspectResponse, Error> {
+        Docker::inspect_container(self, container, None).await
+    }
+
+    async fn wait_container(&self, container: &str) -> Result<i64, Error> {
+        let mut wait = Docker::wait_container(self, container, None::<WaitContainerOptions<&str>>);
+
+        match wait.next().await {
+            Some(Ok(res)) => Ok(res.status_code),
+            // non zero exit codes are reported as an error by the daemon
+            Some(Err(Error::DockerContainerWaitError { code, .. })) => Ok(code),
+            Some(Err(err)) => Err(err),
+            None => Ok(0),
+        }
+    }
+
+    async fn container_logs(&self, container: &str) -> Result<String, Error> {
+        let mut output = String::new();
+        let mut logs = Docker::logs(
+            self,
+            container,
+            Some(LogsOptions::<&str> {
+                stdout: true,
+                stderr: true,
+                ..Default::default()
+            }),
+        );
+
+        while let Some(log) = logs.next().await {
+            match log? {
+                LogOutput::StdOut { message } | LogOutput::StdErr { message } => {
+                    output.push_str(&String::from_utf8_lossy(&message));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(output)
+    }
+
+    async fn container_stats(&self, container: &str) -> Result<Stats, Error> {
+        let mut stats = Docker::stats(
+            self,
+            container,
+            Some(StatsOptions {
+                stream: false,
+                one_shot: false,
+            }),
+        );
+
+        match stats.next().await {
+            Some(stats) => stats,
+            None => Err(Error::DockerStreamError {
+                error: "no stats returned".to_string(),
+            }),
+        }
+    }
+}
+
+/// Combine stdout and stderr of `docker build`. Some tools write progress to stdout and the
+/// output isn't guaranteed to be valid utf-8
+fn build_log(stdout: &[u8], stderr: &[u8]) -> String {
+    let stdout = String::from_utf8_lossy(stdout);
+    let stderr = String::from_utf8_lossy(stderr);
+
+    match stdout.trim().is_empty() {
+        true => stderr.into_owned(),
+        false => format!("{stdout}\n{stderr}"),
+    }
+}
+
+/// A runtime that keeps images, networks and containers in memory, for tests of the deploy
+/// flow. Every call is recorded and the calls it's told to fail answer with a daemon error.
+#[cfg(test)]
+pub mod fake {
+    use std::{future::Future, sync::Mutex};
+
+    use bollard::service::{
+        ContainerState, EndpointSettings, Health, HealthStatusEnum, NetworkContainer,
+        NetworkSettings,
+    };
+    use futures::future::{BoxFuture, FutureExt};
+
+    use super::*;
+
+    /// A build the fake ran, with the Dockerfile as it was when the build started
+    #[derive(Debug, Clone)]
+    pub struct FakeBuild {
+        pub image: String,
+        pub dockerfile: String,
+        /// the ignore rules BuildKit reads next to the Dockerfile
+        pub dockerignore: String,
+        pub build_args: Vec<String>,
+        /// id and value of the BuildKit secrets
+        pub secrets: Vec<(String, String)>,
+        pub refresh: bool,
+    }
+
+    #[derive(Debug, Clone)]
+    struct FakeContainer {
+        id: String,
+        name: String,
+        running: bool,
+        labels: HashMap<String, String>,
+        networks: Vec<String>,
+    }
+
+    #[derive(Default)]
+    struct State {
+        calls: Vec<String>,
+        /// call and target, e.g. `rename_container` and the container's name, to the status
+        /// the next matching call fails with
+        failures: Vec<(String, String, u16)>,
+        /// call and target of the next calls that lose their connection to the daemon
+        disconnects: Vec<(String, String)>,
+        failed_build: Option<String>,
+        /// awaited while an image builds, to look at what the deploy holds meanwhile
+        during_build: Option<Box<dyn Fn() -> BoxFuture<'static, ()> + Send>>,
+        images: Vec<String>,
+        networks: Vec<String>,
+        containers: Vec<FakeContainer>,
+        builds: Vec<FakeBuild>,
+        /// exit code and output of containers by name, 0 and nothing for the others
+        exits: HashMap<String, (i64, String)>,
+        /// containers the network inspects don't list yet, like a daemon slow to register them
+        unlisted: Vec<String>,
+        /// containers the restart policy keeps bringing back up after they exit
+        crashing: Vec<String>,
+        next_id: usize,
+    }
+
+    #[derive(Default)]
+    pub struct FakeRuntime {
+        state: Mutex<State>,
+    }
+
+    fn status(status_code: u16, message: &str) -> Error {
+        Error::DockerResponseServerError {
+            status_code,
+            message: message.to_string(),
+        }
+    }
+
+    /// `name` is short for `name:latest`
+    fn reference(image: &str) -> String {
+        match image.contains(':') {
+            true => image.to_string(),
+            false => format!("{image}:latest"),
+        }
+    }
+
+    impl FakeRuntime {
+        /// Fail the next `call` on `target` with a daemon error of `status_code`
+        pub fn fail(&self, call: &str, target: &str, status_code: u16) {
+            let mut state = self.state.lock().unwrap();
+            state.failures.push((call.to_string(), target.to_string(), status_code));
+        }
+
+        /// Drop the connection of the next `call` on `target`, like a daemon restarting
+        pub fn disconnect(&self, call: &str, target: &str) {
+            let mut state = self.state.lock().unwrap();
+            state.disconnects.push((call.to_string(), target.to_string()));
+        }
+
+        /// Await what `f` returns during every build
+        pub fn during_build<F>(&self, f: impl Fn() -> F + Send + 'static)
+        where
+            F: Future<Output = ()> + Send + 'static,
+        {
+            self.state.lock().unwrap().during_build = Some(Box::new(move || f().boxed()));
+        }
+
+        /// Let the next build exit unsuccessfully with `log`
+        pub fn fail_build(&self, log: &str) {
+            self.state.lock().unwrap().failed_build = Some(log.to_string());
+        }
+
+        pub fn add_image(&self, image: &str) {
+            self.state.lock().unwrap().images.push(reference(image));
+        }
+
+        /// Add a container created by an earlier deploy, returns its id
+        pub fn add_container(&self, name: &str, running: bool) -> String {
+            let mut state = self.state.lock().unwrap();
+            state.next_id += 1;
+            let id = format!("container-{}", state.next_id);
+            state.containers.push(FakeContainer {
+                id: id.clone(),
+                name: name.to_string(),
+                running,
+                labels: HashMap::new(),
+                networks: Vec::new(),
+            });
+            id
+        }
+
+        /// Let the container named `name` exit with `code` and print `log`
+        pub fn exit_with(&self, name: &str, code: i64, log: &str) {
+            let mut state = self.state.lock().unwrap();
+            state.exits.insert(name.to_string(), (code, log.to_string()));
+        }
+
+        /// Let the container named `name` keep crashing with `code` and print `log` each time
+        pub fn crash_loop(&self, name: &str, code: i64, log: &str) {
+            let mut state = self.state.lock().unwrap();
+            state.exits.insert(name.to_string(), (code, log.to_string()));
+            state.crashing.push(name.to_string());
+        }
+
+        /// Leave the container named `name` out of network inspects, only its own inspect
+        /// shows its networks
+        pub fn unlist(&self, name: &str) {
+            self.state.lock().unwrap().unlisted.push(name.to_string());
+        }
+
+        /// Every call so far as `call target`
+        pub fn calls(&self) -> Vec<String> {
+            self.state.lock().unwrap().calls.clone()
+        }
+
+        /// Names of the networks created so far
+        pub fn networks(&self) -> Vec<String> {
+            self.state.lock().unwrap().networks.clone()
+        }
+
+        /// Labels of the container named `name`, none when there is no such container
+        pub fn labels(&self, name: &str) -> HashMap<String, String> {
+            let state = self.state.lock().unwrap();
+            let container = state.containers.iter().find(|container| container.name == name);
+            container.map(|container| container.labels.clone()).unwrap_or_default()
+        }
+
+        pub fn images(&self) -> Vec<String> {
+            self.state.lock().unwrap().images.clone()
+        }
+
+        /// Names of the containers and whether they run
+        pub fn containers(&self) -> Vec<(String, bool)> {
+            let state = self.state.lock().unwrap();
+            state.containers.iter().map(|container| (container.name.clone(), container.running)).collect()
+        }
+
+        pub fn builds(&self) -> Vec<FakeBuild> {
+            self.state.lock().unwrap().builds.clone()
+        }
+
+        /// Record the call, and fail it when it was told to
+        fn call(&self, call: &str, target: &str) -> Result<(), Error> {
+            let mut state = self.state.lock().unwrap();
+            state.calls.push(format!("{call} {target}"));
+
+            let disconnect = state
+                .disconnects
+                .iter()
+                .position(|(failing, failing_target)| failing == call && failing_target == target);
+            if let Some(index) = disconnect {
+                state.disconnects.remove(index);
+                let err = std::io::Error::new(std::io::ErrorKind::ConnectionReset, "injected disconnect");
+                return Err(Error::IOError { err });
+            }
+
+            let failure = state
+                .failures
+                .iter()
+                .position(|(failing, failing_target, _)| failing == call && failing_target == target);
+            match failure {
+                Some(index) => {
+                    let (_, _, status_code) = state.failures.remove(index);
+                    Err(status(status_code, &format!("injected failure of {call}")))
+                }
+                None => Ok(()),
+            }
+        }
+
+        /// Run `f` on the container a name or id refers to
+        fn with_container<T>(&self, container: &str, f: impl FnOnce(&mut FakeContainer) -> Result<T, Error>) -> Result<T, Error> {
+            let mut state = self.state.lock().unwrap();
+            match state.containers.iter_mut().find(|c| c.id == container || c.name == container) {
+                Some(found) => f(found),
+                None => Err(status(404, "No such container")),
+            }
+        }
+
+        fn summary(container: &FakeContainer) -> ContainerSummary {
+            ContainerSummary {
+                id: Some(container.id.clone()),
+                names: Some(vec![format!("/{}", container.name)]),
+                labels: Some(container.labels.clone()),
+                state: Some(match container.running {
+                    true => "running".to_string(),
+                    false => "exited".to_string(),
+                }),
+                ..Default::default()
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ContainerRuntime for FakeRuntime {
+        async fn list_images(&self, image: &str) -> Result<Vec<ImageSummary>, Error> {
+            self.call("list_images", image)?;
+            let wanted = reference(image);
+            let state = self.state.lock().unwrap();
+            Ok(state
+                .images
+                .iter()
+                .filter(|image| **image == wanted)
+                .map(|image| ImageSummary {
+                    id: format!("sha256:{image}"),
+                    repo_tags: vec![image.clone()],
+                    ..Default::default()
+                })
+                .collect())
+        }
+
+        async fn tag_image(&self, image: &str, repo: &str, tag: &str) -> Result<(), Error> {
+            self.call("tag_image", image)?;
+            let mut state = self.state.lock().unwrap();
+            if !state.images.contains(&reference(image)) {
+                return Err(status(404, "No such image"));
+            }
+            state.images.push(format!("{repo}:{tag}"));
+            Ok(())
+        }
+
+        async fn remove_image(&self, image: &str) -> Result<(), Error> {
+            self.call("remove_image", image)?;
+            let mut state = self.state.lock().unwrap();
+            let before = state.images.len();
+            state.images.retain(|existing| *existing != reference(image));
+            match state.images.len() < before {
+                true => Ok(()),
+                false => Err(status(404, "No such image")),
+            }
+        }
+
+        async fn inspect_image(&self, image: &str) -> Result<ImageInspect, Error> {
+            self.call("inspect_image", image)?;
+            let state = self.state.lock().unwrap();
+            match state.images.contains(&reference(image)) {
+                true => Ok(ImageInspect {
+                    id: Some(format!("sha256:{image}")),
+                    size: Some(1024),
+                    ..Default::default()
+                }),
+                false => Err(status(404, "No such image")),
+            }
+        }
+
+        async fn build(&self, build: ImageBuild<'_>, events: &BuildEvents) -> Result<BuildOutput, std::io::Error> {
+            self.call("build", build.image)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
+            let dockerfile = std::fs::read_to_string(build.dockerfile)?;
+            let dockerignore = std::fs::read_to_string(build.dockerfile.with_extension("dockerignore")).unwrap_or_default();
+            events.step(BuildStep::Building);
+
+            let during_build = self.state.lock().unwrap().during_build.as_ref().map(|f| f());
+            if let Some(during_build) = during_build {
+                during_build.await;
+            }
+
+            let mut state = self.state.lock().unwrap();
+            state.builds.push(FakeBuild {
+                image: build.image.to_string(),
+                dockerfile,
+                dockerignore,
+                build_args: build.build_args.clone(),
+                secrets: build
+                    .secrets
+                    .iter()
+                    .map(|(id, _, value)| (id.to_string(), value.clone()))
+                    .collect(),
+                refresh: build.refresh,
+            });
+            if let Some(log) = state.failed_build.take() {
+                return Ok(BuildOutput { success: false, log });
+            }
+            state.images.push(reference(build.image));
+            Ok(BuildOutput {
+                success: true,
+                log: format!("built {}\n", build.image),
+            })
+        }
+
+        async fn list_networks(&self, name: &str) -> Result<Vec<Network>, Error> {
+            self.call("list_networks", name)?;
+            let state = self.state.lock().unwrap();
+            // substrings, like the daemon's name filter
+            Ok(state
+                .networks
+                .iter()
+                .filter(|network| network.contains(name))
+                .map(|network| Network {
+                    id: Some(network.clone()),
+                    name: Some(network.clone()),
+                    ..Default::default()
+                })
+                .collect())
+        }
+
+        async fn create_network(
+            &self,
+            name: &str,
+            _internal: bool,
+            _addressing: Addressing<'_>,
+            _labels: HashMap<String, String>,
+        ) -> Result<(), Error> {
+            self.call("create_network", name)?;
+            self.state.lock().unwrap().networks.push(name.to_string());
+            Ok(())
+        }
+
+        async fn inspect_network(&self, id: &str) -> Result<Network, Error> {
+            self.call("inspect_network", id)?;
+            let state = self.state.lock().unwrap();
+            let containers = state
+                .containers
+                .iter()
+                .enumerate()
+                .filter(|(_, container)| container.networks.iter().any(|network| network == id))
+                .filter(|(_, container)| !state.unlisted.contains(&container.name))
+                .map(|(index, container)| {
+                    let network_container = NetworkContainer {
+                        ipv4_address: Some(format!("10.0.0.{}/24", index + 2)),
+                        ..Default::default()
+                    };
+                    (container.id.clone(), network_container)
+                })
+                .collect();
+
+            Ok(Network {
+                id: Some(id.to_string()),
+                name: Some(id.to_string()),
+                containers: Some(containers),
+                ..Default::default()
+            })
+        }
+
+        async fn connect_network(&self, network: &str, container: &str) -> Result<(), Error> {
+            self.call("connect_network", container)?;
+            self.with_container(container, |found| {
+                found.networks.push(network.to_string());
+                Ok(())
+            })
+        }
+
+        async fn disconnect_network(&self, network: &str, container: &str) -> Result<(), Error> {
+            self.call("disconnect_network", container)?;
+            self.with_container(container, |found| {
+                found.networks.retain(|existing| existing != network);
+                Ok(())
+            })
+        }
+
+        async fn list_containers(&self, name: &str) -> Result<Vec<ContainerSummary>, Error> {
+            self.call("list_containers", name)?;
+            let state = self.state.lock().unwrap();
+            Ok(state
+                .containers
+                .iter()
+                .filter(|container| container.name == name)
+                .map(Self::summary)
+                .collect())
+        }
+
+        async fn list_labeled_containers(&self, labels: &[String]) -> Result<Vec<ContainerSummary>, Error> {
+            self.call("list_labeled_containers", &labels.join(","))?;
+            let state = self.state.lock().unwrap();
+            Ok(state
+                .containers
+                .iter()
+                .filter(|container| {
+                    labels.iter().all(|label| match label.split_once('=') {
+                        Some((key, value)) => container.labels.get(key).map(String::as_str) == Some(value),
+                        None => container.labels.contains_key(label),
+                    })
+                })
+                .map(Self::summary)
+                .collect())
+        }
+
+        async fn create_container(
+            &self,
+            name: &str,
+            config: Config<String>,
+        ) -> Result<ContainerCreateResponse, Error> {
+            self.call("create_container", name)?;
+            if self.state.lock().unwrap().containers.iter().any(|container| container.name == name) {
+                return Err(status(409, "Conflict, the container name is already in use"));
+            }
+
+            let id = self.add_container(name, false);
+            self.with_container(&id, |found| {
+                found.labels = config.labels.unwrap_or_default();
+                Ok(())
+            })?;
+
+            Ok(ContainerCreateResponse { id, warnings: Vec::new() })
+        }
+
+        async fn start_container(&self, container: &str) -> Result<(), Error> {
+            self.call("start_container", container)?;
+            self.with_container(container, |found| match found.running {
+                true => Err(status(304, "Container already started")),
+                false => {
+                    found.running = true;
+                    Ok(())
+                }
+            })
+        }
+
+        async fn stop_container(&self, container: &str, _timeout: i64) -> Result<(), Error> {
+            self.call("stop_container", container)?;
+            self.with_container(container, |found| match found.running {
+                true => {
+                    found.running = false;
+                    Ok(())
+                }
+                false => Err(status(304, "Container already stopped")),
+            })
+        }
+
+        async fn remove_container(&self, container: &str, force: bool) -> Result<(), Error> {
+            self.call("remove_container", container)?;
+            self.with_container(container, |found| match found.running && !force {
+                true => Err(status(409, "You cannot remove a running container")),
+                false => Ok(()),
+            })?;
+
+            let mut state = self.state.lock().unwrap();
+            state.containers.retain(|c| c.id != container && c.name != container);
+            Ok(())
+        }
+
+        async fn rename_container(&self, container: &str, name: &str) -> Result<(), Error> {
+            self.call("rename_container", container)?;
+            if self.state.lock().unwrap().containers.iter().any(|c| c.name == name) {
+                return Err(status(409, "Conflict, the container name is already in use"));
+            }
+
+            self.with_container(container, |found| {
+                found.name = name.to_string();
+                Ok(())
+            })
+        }
+
+        async fn inspect_container(&self, container: &str) -> Result<ContainerInspectResponse, Error> {
+            self.call("inspect_container", container)?;
+            let state = self.state.lock().unwrap();
+            let Some((index, found)) = state
+                .containers
+                .iter()
+                .enumerate()
+                .find(|(_, c)| c.id == container || c.name == container)
+            else {
+                return Err(status(404, "No such container"));
+            };
+
+            // the same addresses the network inspects give
+            let networks = found
+                .networks
+                .iter()
+                .map(|network| {
+                    let endpoint = EndpointSettings {
+                        ip_address: Some(format!("10.0.0.{}", index + 2)),
+                        ..Default::default()
+                    };
+                    (network.clone(), endpoint)
+                })
+                .collect();
+
+            let crashing = state.crashing.contains(&found.name);
+            let health = Health {
+                status: Some(HealthStatusEnum::HEALTHY),
+                ..Default::default()
+            };
+
+            Ok(ContainerInspectResponse {
+                id: Some(found.id.clone()),
+                name: Some(format!("/{}", found.name)),
+                state: Some(ContainerState {
+                    running: Some(found.running),
+                    restarting: Some(crashing),
+                    exit_code: state.exits.get(&found.name).map(|(code, _)| *code),
+                    health: (!crashing).then_some(health),
+                    ..Default::default()
+                }),
+                restart_count: Some(if crashing { 3 } else { 0 }),
+                network_settings: Some(NetworkSettings {
+                    networks: Some(networks),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+        }
+
+        async fn wait_container(&self, container: &str) -> Result<i64, Error> {
+            self.call("wait_container", container)?;
+            let name = self.with_container(container, |found| {
+                found.running = false;
+                Ok(found.name.clone())
+            })?;
+
+            let state = self.state.lock().unwrap();
+            Ok(state.exits.get(&name).map(|(code, _)| *code).unwrap_or(0))
+        }
+
+        async fn container_logs(&self, container: &str) -> Result<String, Error> {
+            self.call("container_logs", container)?;
+            let state = self.state.lock().unwrap();
+            let log = state
+                .containers
+                .iter()
+                .find(|c| c.id == container || c.name == container)
+                .and_then(|found| state.exits.get(&found.name));
+            Ok(log.map(|(_, log)| log.clone()).unwrap_or_default())
+        }
+
+        async fn container_stats(&self, container: &str) -> Result<Stats, Error> {
+            self.call("container_stats", container)?;
+            Err(status(501, "Stats aren't faked"))
+        }
+    }
+}
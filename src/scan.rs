@@ -0,0 +1,144 @@
+use std::{fmt, process::Stdio, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::process::Command;
+
+use crate::configuration::{ScanSettings, ScanSeverity};
+
+/// Placeholder in the scanner command replaced with the image name
+const IMAGE_PLACEHOLDER: &str = "{image}";
+
+/// Exit code of `sh` when the command isn't found
+const COMMAND_NOT_FOUND: i32 = 127;
+
+#[derive(Error, Debug)]
+pub enum ScanError {
+    #[error("no scanner command configured")]
+    NotConfigured,
+    #[error("failed to run the scanner: {0}")]
+    Spawn(#[from] std::io::Error),
+    #[error("the scanner is not installed: {0}")]
+    NotInstalled(String),
+    #[error("scanner did not finish within {0}s")]
+    Timeout(u64),
+    #[error("scanner exited with {code:?}: {stderr}")]
+    Failed { code: Option<i32>, stderr: String },
+    #[error("scanner output is not valid JSON: {0}")]
+    Parse(#[from] serde_json::Error),
+}
+
+/// Findings of an image scan by severity, stored on the build
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct ScanSummary {
+    pub critical: u64,
+    pub high: u64,
+    pub medium: u64,
+    pub low: u64,
+    /// credentials found in the image's files
+    pub secrets: u64,
+}
+
+impl ScanSummary {
+    /// Findings that fail the deploy under the `fail` policy, vulnerabilities at `threshold` or
+    /// worse and any secret
+    pub fn is_blocking(&self, threshold: ScanSeverity) -> bool {
+        let vulnerabilities = [
+            (ScanSeverity::Critical, self.critical),
+            (ScanSeverity::High, self.high),
+            (ScanSeverity::Medium, self.medium),
+            (ScanSeverity::Low, self.low),
+        ];
+
+        self.secrets > 0
+            || vulnerabilities
+                .iter()
+                .any(|(severity, count)| *severity >= threshold && *count > 0)
+    }
+}
+
+impl fmt::Display for ScanSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} critical, {} high, {} medium and {} low vulnerabilities, {} secrets",
+            self.critical, self.high, self.medium, self.low, self.secrets
+        )
+    }
+}
+
+/// The parts of trivy's JSON report the summary is made from
+#[derive(Deserialize, Debug)]
+struct Report {
+    #[serde(rename = "Results", default)]
+    results: Vec<ReportResult>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ReportResult {
+    #[serde(rename = "Vulnerabilities", default)]
+    vulnerabilities: Vec<Vulnerability>,
+    #[serde(rename = "Secrets", default)]
+    secrets: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Vulnerability {
+    #[serde(rename = "Severity", default)]
+    severity: String,
+}
+
+pub fn parse(output: &[u8]) -> Result<ScanSummary, serde_json::Error> {
+    let report = serde_json::from_slice::<Report>(output)?;
+
+    let mut summary = ScanSummary::default();
+    for result in report.results {
+        summary.secrets += result.secrets.len() as u64;
+
+        for vulnerability in result.vulnerabilities {
+            match vulnerability.severity.to_uppercase().as_str() {
+                "CRITICAL" => summary.critical += 1,
+                "HIGH" => summary.high += 1,
+                "MEDIUM" => summary.medium += 1,
+                "LOW" => summary.low += 1,
+                _ => {}
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Run the configured scanner against an image. The scanner is killed when it runs past the
+/// timeout, so a stuck scan can't hold up the deploy. Every error skips the scan rather than
+/// the deploy.
+pub async fn scan(settings: &ScanSettings, image: &str) -> Result<ScanSummary, ScanError> {
+    let command = settings.command.as_ref().ok_or(ScanError::NotConfigured)?;
+
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command.replace(IMAGE_PLACEHOLDER, image))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let output = tokio::time::timeout(Duration::from_secs(settings.timeout), child.wait_with_output())
+        .await
+        .map_err(|_| ScanError::Timeout(settings.timeout))??;
+
+    if output.status.code() == Some(COMMAND_NOT_FOUND) {
+        return Err(ScanError::NotInstalled(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    if !output.status.success() {
+        return Err(ScanError::Failed {
+            code: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+
+    Ok(parse(&output.stdout)?)
+}
@@ -0,0 +1,201 @@
+use std::{path::Path, time::Duration};
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use sqlx::PgPool;
+use tokio::sync::mpsc::Sender;
+
+use crate::queue::{BuildQueueItem, BuildTrigger};
+
+/// Schedule of projects that turn automatic rebuilds on without choosing one, every Sunday at
+/// 03:00 UTC
+pub const DEFAULT_SCHEDULE: &str = "0 3 * * 0";
+
+/// A cron expression: minute, hour, day of month, month and day of week, in UTC. Fields take
+/// `*`, numbers, ranges, lists and steps like `*/15`. `@hourly`, `@daily`, `@weekly` and
+/// `@monthly` are accepted as well.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// cron matches either day field when both are restricted
+    any_day: bool,
+    any_weekday: bool,
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut bits = 0u64;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step = step
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| format!("invalid step in `{part}`"))?;
+                (range, Some(step))
+            }
+            None => (part, None),
+        };
+
+        let number = |value: &str| {
+            value
+                .parse::<u32>()
+                .ok()
+                .filter(|value| (min..=max).contains(value))
+                .ok_or_else(|| format!("`{value}` is not between {min} and {max}"))
+        };
+
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (number(start)?, number(end)?),
+            // `5/10` runs from 5 to the end of the range
+            None if step.is_some() => (number(range)?, max),
+            None => {
+                let value = number(range)?;
+                (value, value)
+            }
+        };
+        if start > end {
+            return Err(format!("range `{range}` is backwards"));
+        }
+
+        for value in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            bits |= 1 << value;
+        }
+    }
+
+    Ok(bits)
+}
+
+impl Schedule {
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let expression = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            expression => expression,
+        };
+
+        let fields = expression.split_whitespace().collect::<Vec<_>>();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err("expected 5 fields: minute hour day-of-month month day-of-week".to_string());
+        };
+
+        // Sunday is both 0 and 7
+        let mut weekdays = parse_field(weekday, 0, 7)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59)?,
+            hours: parse_field(hour, 0, 23)?,
+            days: parse_field(day, 1, 31)?,
+            months: parse_field(month, 1, 12)?,
+            weekdays,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        })
+    }
+
+    /// Whether the schedule fires in the minute of `time`
+    pub fn matches(&self, time: DateTime<Utc>) -> bool {
+        let is_set = |bits: u64, value: u32| bits & (1 << value) != 0;
+
+        let day = is_set(self.days, time.day());
+        let weekday = is_set(self.weekdays, time.weekday().num_days_from_sunday());
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        };
+
+        is_set(self.minutes, time.minute())
+            && is_set(self.hours, time.hour())
+            && is_set(self.months, time.month())
+            && day_matches
+    }
+}
+
+/// Queue the automatic rebuilds that are due, checked at the start of every minute. Rebuilds go
+/// through the build queue like pushes, and a project with a build queued or running is
+/// skipped until its next run.
+pub async fn run_scheduled_rebuilds(pool: PgPool, base: String, build_channel: Sender<BuildQueueItem>) {
+    loop {
+        let wait = 60 - Utc::now().second() as u64;
+        tokio::time::sleep(Duration::from_secs(wait)).await;
+
+        match queue_due(&pool, &base, &build_channel, Utc::now()).await {
+            Ok(true) => {}
+            Ok(false) => {
+                tracing::error!("Can't queue scheduled rebuilds: Build queue is closed");
+                return;
+            }
+            Err(err) => tracing::error!(?err, "Can't queue scheduled rebuilds: Failed to query database"),
+        }
+    }
+}
+
+/// Returns false when the build queue is gone
+async fn queue_due(
+    pool: &PgPool,
+    base: &str,
+    build_channel: &Sender<BuildQueueItem>,
+    now: DateTime<Utc>,
+) -> Result<bool, sqlx::Error> {
+    // only deployed projects, a rebuild of a project that never came up can't be checked
+    let projects = sqlx::query!(
+        r#"SELECT projects.name AS project, project_owners.name AS owner,
+                  projects.auto_rebuild AS "auto_rebuild!"
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.auto_rebuild IS NOT NULL
+           AND projects.deleted_at IS NULL
+           AND EXISTS (
+             SELECT 1 FROM builds WHERE builds.project_id = projects.id AND builds.status = 'successful'
+           )
+           AND NOT EXISTS (
+             SELECT 1 FROM builds WHERE builds.project_id = projects.id AND builds.status IN ('pending', 'building')
+           )
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for project in projects {
+        let schedule = match Schedule::parse(&project.auto_rebuild) {
+            Ok(schedule) => schedule,
+            Err(err) => {
+                tracing::warn!(owner = %project.owner, project = %project.project, %err, "Ignoring invalid rebuild schedule");
+                continue;
+            }
+        };
+        if !schedule.matches(now) {
+            continue;
+        }
+
+        let container_src = format!("{base}/{}/{}.git/master", project.owner, project.project);
+        if !Path::new(&container_src).is_dir() {
+            continue;
+        }
+
+        tracing::info!(owner = %project.owner, project = %project.project, "Queueing scheduled rebuild");
+        let item = BuildQueueItem {
+            container_src,
+            owner: project.owner,
+            repo: project.project,
+            trigger: BuildTrigger::Scheduled,
+            batch_id: None,
+            reply: None,
+        };
+        if build_channel.send(item).await.is_err() {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,141 @@
+use data_encoding::{BASE64, BASE64_NOPAD};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+/// Key types OpenSSH accepts for user authentication
+const KEY_TYPES: [&str; 7] = [
+    "ssh-ed25519",
+    "ssh-rsa",
+    "ecdsa-sha2-nistp256",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp521",
+    "sk-ssh-ed25519@openssh.com",
+    "sk-ecdsa-sha2-nistp256@openssh.com",
+];
+
+#[derive(Error, Debug, PartialEq)]
+pub enum KeyError {
+    #[error("Key must be a single line like `ssh-ed25519 AAAA... comment`")]
+    Format,
+    #[error("Unsupported key type {0}, use one of {}", KEY_TYPES.join(", "))]
+    UnsupportedType(String),
+    #[error("Key data is not valid base64")]
+    Encoding,
+    #[error("Key data doesn't match its type {0}")]
+    Mismatch(String),
+}
+
+/// A public key in the `authorized_keys` format, without options
+#[derive(Debug, Clone)]
+pub struct PublicKey {
+    pub kind: String,
+    /// base64 of the key blob
+    pub data: String,
+    pub comment: Option<String>,
+}
+
+impl PublicKey {
+    pub fn parse(line: &str) -> Result<Self, KeyError> {
+        let line = line.trim();
+        if line.is_empty() || line.contains('\n') {
+            return Err(KeyError::Format);
+        }
+
+        let mut parts = line.splitn(3, char::is_whitespace);
+        let kind = parts.next().ok_or(KeyError::Format)?;
+        let data = parts.next().ok_or(KeyError::Format)?;
+        let comment = parts
+            .next()
+            .map(|comment| comment.trim().to_string())
+            .filter(|comment| !comment.is_empty());
+
+        if !KEY_TYPES.contains(&kind) {
+            return Err(KeyError::UnsupportedType(kind.to_string()));
+        }
+
+        // the blob starts with the key type as a length prefixed string
+        let blob = BASE64.decode(data.as_bytes()).map_err(|_| KeyError::Encoding)?;
+        let embedded = blob
+            .get(..4)
+            .map(|len| u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize)
+            .and_then(|len| blob.get(4..4 + len));
+        if embedded != Some(kind.as_bytes()) {
+            return Err(KeyError::Mismatch(kind.to_string()));
+        }
+
+        Ok(Self {
+            kind: kind.to_string(),
+            data: data.to_string(),
+            comment,
+        })
+    }
+
+    /// `SHA256:...` fingerprint, as printed by `ssh-keygen -l`
+    pub fn fingerprint(&self) -> String {
+        fingerprint(&self.data).unwrap_or_default()
+    }
+}
+
+/// Fingerprint of base64 key data, none when it isn't valid base64
+pub fn fingerprint(data: &str) -> Option<String> {
+    let blob = BASE64.decode(data.as_bytes()).ok()?;
+    Some(format!("SHA256:{}", BASE64_NOPAD.encode(&Sha256::digest(blob))))
+}
+
+/// `ssh://` url of a project repository, `base` as returned by `Settings::ssh_url`
+pub fn clone_url(base: &str, owner: &str, project: &str) -> String {
+    format!("{base}/{owner}/{project}.git")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ED25519: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIIWp24DZc1couHAbOdeTIwPsEwfbaNHb4QHotGs67ipI";
+
+    #[test]
+    fn parses_an_authorized_keys_line() {
+        let key = PublicKey::parse(&format!("  ssh-ed25519 {ED25519} alice@laptop  \n")).unwrap();
+
+        assert_eq!(key.kind, "ssh-ed25519");
+        assert_eq!(key.data, ED25519);
+        assert_eq!(key.comment.as_deref(), Some("alice@laptop"));
+        // as printed by `ssh-keygen -lf`
+        assert_eq!(key.fingerprint(), "SHA256:KZgs4F3D+VJCQAa1z2tXDu2cB39jbcD7YUVYWIdAPhQ");
+    }
+
+    #[test]
+    fn comment_is_optional() {
+        let key = PublicKey::parse(&format!("ssh-ed25519 {ED25519}")).unwrap();
+
+        assert_eq!(key.comment, None);
+    }
+
+    #[test]
+    fn rejects_malformed_keys() {
+        assert_eq!(PublicKey::parse("").unwrap_err(), KeyError::Format);
+        assert_eq!(PublicKey::parse("ssh-ed25519").unwrap_err(), KeyError::Format);
+        assert_eq!(
+            PublicKey::parse(&format!("ssh-ed25519 {ED25519}\nssh-ed25519 {ED25519}")).unwrap_err(),
+            KeyError::Format
+        );
+        assert_eq!(
+            PublicKey::parse(&format!("ssh-dss {ED25519}")).unwrap_err(),
+            KeyError::UnsupportedType("ssh-dss".to_string())
+        );
+        assert_eq!(PublicKey::parse("ssh-ed25519 not*base64").unwrap_err(), KeyError::Encoding);
+        // an ed25519 blob labelled as rsa
+        assert_eq!(
+            PublicKey::parse(&format!("ssh-rsa {ED25519}")).unwrap_err(),
+            KeyError::Mismatch("ssh-rsa".to_string())
+        );
+    }
+
+    #[test]
+    fn clone_url_format() {
+        assert_eq!(
+            clone_url("ssh://git@pws.example:2222", "alice", "blog"),
+            "ssh://git@pws.example:2222/alice/blog.git"
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use axum::extract::{Host, State};
+use axum::extract::{DefaultBodyLimit, Host, State};
 use axum::middleware::Next;
 use axum::response::Redirect;
 use axum::{middleware, routing, Router};
@@ -13,26 +13,60 @@ use hyper::{Body, Method, Request, Response, StatusCode, Uri};
 use sqlx::PgPool;
 use tokio::sync::mpsc::Sender;
 use tower_http::cors::CorsLayer;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
 use tower_http::services::{ServeDir, ServeFile};
 use uuid::Uuid;
 
 use std::net::{SocketAddr, TcpListener};
 
-use crate::auth::User;
-use crate::configuration::Settings;
+use crate::auth::{hashing::Hasher, sessions::{self, SessionLimit}, User};
+use crate::configuration::{NetworkSettings, PauseMode, RebuildSettings, Settings, SsoConfig, SubdomainScheme};
+use crate::daemon_limits;
+use crate::docker::{docker_name, pick_ip};
+use crate::push_checks::PushChecks;
+use crate::push_message::PushMessage;
 use crate::queue::BuildQueueItem;
-use crate::{auth, dashboard, git, owner, projects, telemetry};
+use crate::{admin, auth, dashboard, git, notifications, owner, placeholder, projects, system, telemetry};
 
 #[derive(Clone)]
 pub struct AppState {
     pub base: String,
     pub git_auth: bool,
     pub sso: bool,
+    pub sso_config: SsoConfig,
     pub domain: String,
+    pub subdomain: SubdomainScheme,
     pub client: hyper::client::Client<hyper::client::HttpConnector, hyper::Body>,
     pub pool: PgPool,
+    pub docker: Docker,
     pub build_channel: Sender<BuildQueueItem>,
+    /// builds allowed to run at once
+    pub build_max: usize,
+    /// base of the ssh clone urls, none when ssh is disabled
+    pub ssh_url: Option<String>,
+    pub network: NetworkSettings,
     pub secure: bool,
+    /// what happens to pushes while deploys are paused
+    pub pause_mode: PauseMode,
+    /// prepended to the docker names of projects
+    pub container_prefix: String,
+    pub push_checks: PushChecks,
+    /// footer of the push output
+    pub push_message: PushMessage,
+    /// in hours, how long idempotency keys are kept
+    pub idempotency_ttl: u64,
+    /// in bytes, the largest request body of the api routes
+    pub api_body_limit: usize,
+    /// defaults of rebuild batches
+    pub rebuild: RebuildSettings,
+    /// in hours, how long deleted projects can be restored
+    pub deletion_retention: u64,
+    /// sessions a user can have at once
+    pub session_limit: SessionLimit,
+    /// projects an owner can have, 0 for no limit
+    pub project_quota: u64,
+    /// hashes passwords and git tokens
+    pub hasher: Hasher,
 }
 
 pub async fn run(listener: TcpListener, state: AppState, config: Settings) -> Result<(), String> {
@@ -43,7 +77,7 @@ pub async fn run(listener: TcpListener, state: AppState, config: Settings) -> Re
 
     let cors = CorsLayer::new()
         .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
-        .allow_headers(["Content-Type".parse().unwrap()])
+        .allow_headers(["Content-Type".parse().unwrap(), auth::CSRF_HEADER.parse().unwrap()])
         .allow_origin([
             "http://localhost:8080".parse().unwrap(),
             "http://localhost:5173".parse().unwrap(),
@@ -57,15 +91,32 @@ pub async fn run(listener: TcpListener, state: AppState, config: Settings) -> Re
     let dashboard_router: Router<AppState> = dashboard::api::router(state.clone(), &config).await;
     let project_router = projects::api::router(state.clone(), &config).await;
     let owners_router = owner::api::router(state.clone(), &config).await;
+    let admin_router = admin::api::router(state.clone(), &config).await;
+    let system_router = system::api::router(state.clone(), &config).await;
+    let notifications_router = notifications::api::router(state.clone(), &config).await;
 
-    let app = Router::new()
-        .route("/", routing::any(|| async { Redirect::permanent("/web") }))
-        .merge(git_router)
+    // git routes set their own, larger limit
+    let api_body_limit = config.api_body_limit();
+    let api_router = Router::new()
         .merge(auth_router)
         .merge(dashboard_router)
         .merge(project_router)
         .merge(owners_router)
+        .merge(admin_router)
+        .merge(system_router)
+        .merge(notifications_router)
+        .layer(middleware::from_fn_with_state(state.clone(), sessions::layer))
+        .layer(middleware::from_fn(auth::csrf));
+    let api_router = limit_api_bodies(api_router, api_body_limit);
+
+    let app = Router::new()
+        .route("/", routing::any(|| async { Redirect::permanent("/web") }))
+        .merge(git_router)
+        .merge(api_router)
+        // the id is set before the trace span is made and returned in the response
+        .layer(PropagateRequestIdLayer::x_request_id())
         .layer(http_trace)
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
         // TODO: rethink if we need this here. since it makes all routes under this query the
         // session even if they don't need it
         .layer(
@@ -80,6 +131,8 @@ pub async fn run(listener: TcpListener, state: AppState, config: Settings) -> Re
             ServeDir::new("ui/dist").fallback(ServeFile::new("ui/dist/index.html")),
         )
         // .fallback(fallback)  // Disabled: Traefik handles routing directly
+        // project hosts without a running container end up here through Traefik's catch-all
+        .fallback(placeholder::get)
         .with_state(state.clone())
         // .route_layer(middleware::from_fn_with_state(state, fallback_middleware))  // Disabled with fallback
         .layer(cors);
@@ -97,11 +150,61 @@ pub async fn run(listener: TcpListener, state: AppState, config: Settings) -> Re
         .map_err(|err| format!("failed to start server: {}", err))
 }
 
+/// Keep bodies of the api routes under `limit` bytes, they are buffered before validation
+fn limit_api_bodies<S>(router: Router<S>, limit: usize) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    router
+        .layer(DefaultBodyLimit::max(limit))
+        .layer(middleware::from_fn(move |req: Request<Body>, next: Next<Body>| {
+            reject_oversized_body(limit, req, next)
+        }))
+}
+
+/// Answer requests that announce a body over `limit` with a 413 before anything reads it.
+/// Chunked bodies without a length are cut off by `DefaultBodyLimit` instead.
+async fn reject_oversized_body<B>(
+    limit: usize,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response<UnsyncBoxBody<Bytes, axum::Error>>, Response<Body>> {
+    let length = request
+        .headers()
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+
+    if let Some(length) = length.filter(|length| *length > limit) {
+        tracing::debug!(length, limit, "Rejected oversized request body");
+        let json = serde_json::json!({
+            "message": format!("Request body is larger than the limit of {limit} bytes"),
+            "limit": limit,
+        });
+
+        return Err(Response::builder()
+            .status(StatusCode::PAYLOAD_TOO_LARGE)
+            .body(Body::from(json.to_string()))
+            .unwrap());
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// `ip:80` of a container, ipv6 addresses go in brackets
+fn container_authority(ip: &str) -> String {
+    match ip.contains(':') {
+        true => format!("[{ip}]:80"),
+        false => format!("{ip}:80"),
+    }
+}
+
 pub async fn fallback(
     State(AppState {
-        pool,
         client,
         domain,
+        container_prefix,
+        network: network_settings,
         ..
     }): State<AppState>,
     Host(hostname): Host,
@@ -123,8 +226,14 @@ pub async fn fallback(
     tracing::debug!(domain, "domain {}", domain);
     tracing::debug!(?subdomain, "subdomain {} is accessed", subdomain);
 
+    // the inspect is the only daemon call, the permit isn't held while proxying
+    let permit = match daemon_limits::interactive().await {
+        Ok(permit) => permit,
+        Err(busy) => return busy.response(),
+    };
+
     let ip_address = match Docker::connect_with_local_defaults() {
-        Ok(docker) => match docker.inspect_container(subdomain, None).await {
+        Ok(docker) => match docker.inspect_container(&docker_name(&container_prefix, subdomain), None).await {
             Ok(res) => {
                 let network = match res.network_settings {
                     Some(network) => network,
@@ -148,8 +257,12 @@ pub async fn fallback(
 
                 let project_network = networks.get(&format!("{}-network", subdomain));
                 if let Some(project_network) = project_network {
-                    match &project_network.ip_address {
-                        Some(ip_address) => Ok(ip_address.clone()),
+                    match pick_ip(
+                        project_network.ip_address.clone(),
+                        project_network.global_ipv6_address.clone(),
+                        network_settings.ipv6,
+                    ) {
+                        Some(ip_address) => Ok(ip_address),
                         None => {
                             return Response::builder()
                             .status(StatusCode::BAD_REQUEST)
@@ -174,19 +287,20 @@ pub async fn fallback(
             .body(Body::empty())
             .unwrap()),
     };
+    drop(permit);
 
     if let Ok(ip_address) = ip_address {
-        let uri = format!("http://{}:{}{}", ip_address, 80, uri);
+        let uri = format!("http://{}{}", container_authority(&ip_address), uri);
         *req.uri_mut() = Uri::try_from(uri).unwrap();
         match client.request(req).await {
             Ok(res) => res,
             Err(err) => {
                 tracing::error!(?err, "Can't access container: Failed request to container");
     
-                return Response::builder()
+                Response::builder()
                 .status(StatusCode::BAD_REQUEST)
                 .body(Body::empty())
-                .unwrap();
+                .unwrap()
             }
         }
     } else {
@@ -199,9 +313,10 @@ pub async fn fallback(
 
 pub async fn fallback_middleware(
     State(AppState {
-        pool,
         client,
         domain,
+        container_prefix,
+        network: network_settings,
         ..
     }): State<AppState>,
     Host(hostname): Host,
@@ -223,8 +338,14 @@ pub async fn fallback_middleware(
 
     tracing::debug!(?subdomain, "subdomain {} is accessed", subdomain);
 
+    // the inspect is the only daemon call, the permit isn't held while proxying
+    let permit = match daemon_limits::interactive().await {
+        Ok(permit) => permit,
+        Err(busy) => return Err(busy.response()),
+    };
+
     let ip_address = match Docker::connect_with_local_defaults() {
-        Ok(docker) => match docker.inspect_container(subdomain, None).await {
+        Ok(docker) => match docker.inspect_container(&docker_name(&container_prefix, subdomain), None).await {
             Ok(res) => {
                 let network = match res.network_settings {
                     Some(network) => network,
@@ -248,8 +369,12 @@ pub async fn fallback_middleware(
 
                 let project_network = networks.get(&format!("{}-network", subdomain));
                 if let Some(project_network) = project_network {
-                    match &project_network.ip_address {
-                        Some(ip_address) => Ok(ip_address.clone()),
+                    match pick_ip(
+                        project_network.ip_address.clone(),
+                        project_network.global_ipv6_address.clone(),
+                        network_settings.ipv6,
+                    ) {
+                        Some(ip_address) => Ok(ip_address),
                         None => {
                             return Err(Response::builder()
                             .status(StatusCode::BAD_REQUEST)
@@ -274,9 +399,10 @@ pub async fn fallback_middleware(
             .body(Body::empty())
             .unwrap()),
     };
+    drop(permit);
 
     if let Ok(ip_address) = ip_address {
-        let uri = format!("http://{}:{}{}", ip_address, 80, uri);
+        let uri = format!("http://{}{}", container_authority(&ip_address), uri);
         *req.uri_mut() = Uri::try_from(uri).unwrap();
         match client.request(req).await {
             Ok(res) => Err(res),
@@ -296,3 +422,52 @@ pub async fn fallback_middleware(
             .unwrap())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::Json;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    /// A JSON route behind the limits of the api routes
+    async fn submit(body: Body, length: Option<usize>) -> axum::response::Response {
+        let router = Router::new().route("/", routing::post(|Json(value): Json<serde_json::Value>| async move { Json(value) }));
+
+        let mut request = Request::post("/").header(hyper::header::CONTENT_TYPE, "application/json");
+        if let Some(length) = length {
+            request = request.header(hyper::header::CONTENT_LENGTH, length);
+        }
+        limit_api_bodies(router, 1024).oneshot(request.body(body).unwrap()).await.unwrap()
+    }
+
+    fn environs(size: usize) -> String {
+        serde_json::json!({ "environs": { "BLOB": "x".repeat(size) } }).to_string()
+    }
+
+    #[tokio::test]
+    async fn small_json_bodies_pass() {
+        let body = environs(100);
+        let response = submit(Body::from(body.clone()), Some(body.len())).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn announced_oversized_bodies_get_the_limit() {
+        let body = environs(4096);
+        let response = submit(Body::from(body.clone()), Some(body.len())).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["limit"], 1024);
+        assert_eq!(body["message"], "Request body is larger than the limit of 1024 bytes");
+    }
+
+    #[tokio::test]
+    async fn chunked_oversized_bodies_are_cut_off() {
+        let chunks = environs(4096).into_bytes().chunks(512).map(|chunk| Ok::<_, std::io::Error>(Bytes::copy_from_slice(chunk))).collect::<Vec<_>>();
+        let response = submit(Body::wrap_stream(futures::stream::iter(chunks)), None).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -0,0 +1,239 @@
+use std::fmt;
+
+use bollard::Docker;
+use byte_unit::Byte;
+use config::ConfigError;
+use sqlx::PgPool;
+
+use crate::{
+    configuration::Settings,
+    project_config::ProjectConfig,
+    system::usage::{self, PlatformUsage},
+};
+
+/// What a deploy asks of the host, the limits of the containers it starts
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Demand {
+    pub memory_bytes: u64,
+    pub cpus: f64,
+}
+
+/// The resource a deploy doesn't fit in and by how much
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AtCapacity {
+    Memory { requested: u64, free: u64 },
+    Cpu { requested: f64, free: f64 },
+}
+
+impl fmt::Display for AtCapacity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = |value: u64| Byte::from_bytes(value as u128).get_appropriate_unit(true).to_string();
+
+        match self {
+            AtCapacity::Memory { requested, free } => write!(
+                f,
+                "Platform at capacity: the deploy needs {} of memory, {} is free",
+                bytes(*requested),
+                bytes(*free)
+            ),
+            AtCapacity::Cpu { requested, free } => write!(
+                f,
+                "Platform at capacity: the deploy needs {requested:.2} cpus, {free:.2} are free"
+            ),
+        }
+    }
+}
+
+/// Turns deploys away when the containers they start would push the host past
+/// `100 - build.headroom` percent of its memory or cpus
+#[derive(Debug, Clone, Copy)]
+pub struct Admission {
+    /// percent of the host kept free
+    headroom: u8,
+    web: Demand,
+    worker: Demand,
+    max_workers: usize,
+}
+
+impl Admission {
+    /// None when `build.admission` is off
+    pub fn new(config: &Settings) -> Result<Option<Self>, ConfigError> {
+        if !config.build.admission {
+            return Ok(None);
+        }
+        if config.build.headroom > 100 {
+            return Err(ConfigError::Message("build.headroom: has to be a percentage".to_string()));
+        }
+
+        Ok(Some(Self {
+            headroom: config.build.headroom,
+            web: Demand {
+                memory_bytes: config.container_memory_bytes()?.max(0) as u64,
+                cpus: config.container.cpu,
+            },
+            worker: Demand {
+                memory_bytes: config.worker_memory_bytes()?.max(0) as u64,
+                cpus: config.container.workercpu,
+            },
+            max_workers: config.container.maxworkers,
+        }))
+    }
+
+    /// Limits of the web container and the workers the project's `.pws.toml` asks for, as of
+    /// the checkout at `container_src`
+    pub fn demand(&self, container_src: &str) -> Demand {
+        let workers = match ProjectConfig::load(container_src) {
+            Ok(project_config) if project_config.worker.is_some() => {
+                project_config.workers.unwrap_or(1).min(self.max_workers)
+            }
+            _ => 0,
+        };
+
+        Demand {
+            memory_bytes: self.web.memory_bytes + self.worker.memory_bytes * workers as u64,
+            cpus: self.web.cpus + self.worker.cpus * workers as f64,
+        }
+    }
+
+    /// Whether a deploy of the project at `container_src` fits. A usage sample that can't be
+    /// taken lets the deploy through, the check is no reason to block deploys on its own.
+    pub async fn admit(
+        &self,
+        docker: &Docker,
+        pool: &PgPool,
+        concurrency: usize,
+        container_src: &str,
+    ) -> Result<(), AtCapacity> {
+        let usage = match usage::recent(docker, pool, concurrency).await {
+            Ok(usage) => usage,
+            Err(err) => {
+                tracing::error!(?err, "Can't check capacity: Failed to sample platform usage");
+                return Ok(());
+            }
+        };
+
+        check(&usage, &self.demand(container_src), self.headroom)
+    }
+}
+
+/// Whether `demand` fits between the current usage and the share of the host `headroom`
+/// percent leaves. Resources the daemon doesn't report aren't checked
+pub fn check(usage: &PlatformUsage, demand: &Demand, headroom: u8) -> Result<(), AtCapacity> {
+    let usable = |total: u64| total as f64 * (100 - headroom.min(100)) as f64 / 100.0;
+
+    if let Some(total) = usage.memory_total_bytes {
+        let free = (usable(total) as u64).saturating_sub(usage.memory_used_bytes);
+        if demand.memory_bytes > free {
+            return Err(AtCapacity::Memory {
+                requested: demand.memory_bytes,
+                free,
+            });
+        }
+    }
+
+    if let Some(total) = usage.cpu_total {
+        let free = (usable(total) - usage.cpu_used).max(0.0);
+        if demand.cpus > free {
+            return Err(AtCapacity::Cpu {
+                requested: demand.cpus,
+                free,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::capacity::Capacity;
+
+    const GIB: u64 = 1024 * 1024 * 1024;
+
+    fn usage(memory_used: u64, memory_total: Option<u64>, cpu_used: f64, cpu_total: Option<u64>) -> PlatformUsage {
+        PlatformUsage {
+            containers: 1,
+            unsampled: 0,
+            memory_used_bytes: memory_used,
+            memory_total_bytes: memory_total,
+            memory_percent: None,
+            cpu_used,
+            cpu_total,
+            cpu_percent: None,
+            queue: Capacity {
+                queued: 0,
+                running: 0,
+                concurrency: 1,
+                average_build_secs: None,
+                estimated_wait_secs: None,
+            },
+        }
+    }
+
+    #[test]
+    fn admits_a_deploy_that_fits() {
+        let demand = Demand {
+            memory_bytes: GIB,
+            cpus: 1.0,
+        };
+
+        assert_eq!(check(&usage(4 * GIB, Some(8 * GIB), 1.0, Some(4)), &demand, 10), Ok(()));
+    }
+
+    #[test]
+    fn rejects_a_deploy_over_the_free_memory() {
+        let demand = Demand {
+            memory_bytes: 2 * GIB,
+            cpus: 0.5,
+        };
+
+        // 10 percent of 10 GiB is kept free, 9 GiB usable and 8 GiB used
+        assert_eq!(
+            check(&usage(8 * GIB, Some(10 * GIB), 0.0, Some(4)), &demand, 10),
+            Err(AtCapacity::Memory {
+                requested: 2 * GIB,
+                free: GIB,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_a_deploy_over_the_free_cpus() {
+        let demand = Demand {
+            memory_bytes: 0,
+            cpus: 1.0,
+        };
+
+        assert_eq!(
+            check(&usage(0, Some(GIB), 3.5, Some(4)), &demand, 0),
+            Err(AtCapacity::Cpu {
+                requested: 1.0,
+                free: 0.5,
+            })
+        );
+    }
+
+    #[test]
+    fn skips_resources_the_daemon_doesnt_report() {
+        let demand = Demand {
+            memory_bytes: 100 * GIB,
+            cpus: 100.0,
+        };
+
+        assert_eq!(check(&usage(0, None, 0.0, None), &demand, 10), Ok(()));
+    }
+
+    #[test]
+    fn explains_the_rejection() {
+        let at_capacity = AtCapacity::Cpu {
+            requested: 1.0,
+            free: 0.25,
+        };
+
+        assert_eq!(
+            at_capacity.to_string(),
+            "Platform at capacity: the deploy needs 1.00 cpus, 0.25 are free"
+        );
+    }
+}
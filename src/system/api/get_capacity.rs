@@ -0,0 +1,24 @@
+use axum::extract::State;
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+
+use crate::{projects::api::error_response, startup::AppState, system::capacity};
+
+/// Current load of the build queue, so users can tell whether to push now or wait
+#[tracing::instrument(skip(pool))]
+pub async fn get(State(AppState { pool, build_max, .. }): State<AppState>) -> Response<Body> {
+    let capacity = match capacity::current(&pool, build_max).await {
+        Ok(capacity) => capacity,
+        Err(err) => {
+            tracing::error!(?err, "Can't get capacity: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to query database: {}", err));
+        }
+    };
+
+    let json = serde_json::to_string(&capacity).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
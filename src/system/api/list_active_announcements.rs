@@ -0,0 +1,24 @@
+use axum::extract::State;
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+
+use crate::{announcements, projects::api::error_response, startup::AppState};
+
+/// Announcements to show on every page right now, polled by the dashboard
+#[tracing::instrument(skip(pool))]
+pub async fn get(State(AppState { pool, .. }): State<AppState>) -> Response<Body> {
+    let announcements = match announcements::active(&pool).await {
+        Ok(announcements) => announcements,
+        Err(err) => {
+            tracing::error!(?err, "Can't get announcements: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to query database: {}", err));
+        }
+    };
+
+    let json = serde_json::to_string(&announcements).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,21 @@
+use axum::{middleware, Router, routing::get};
+use axum_extra::routing::RouterExt;
+use hyper::Body;
+
+use crate::{auth::auth, startup::AppState, configuration::Settings};
+
+mod get_capacity;
+mod list_active_announcements;
+
+pub async fn router(_state: AppState, config: &Settings) -> Router<AppState, Body> {
+    let router = Router::new()
+        .route_with_tsr("/api/system/capacity", get(get_capacity::get));
+
+    let router = match config.build.publiccapacity {
+        true => router,
+        false => router.route_layer(middleware::from_fn(auth)),
+    };
+
+    // shown on the login page too
+    router.route_with_tsr("/api/announcements/active", get(list_active_announcements::get))
+}
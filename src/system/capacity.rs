@@ -0,0 +1,241 @@
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Finished builds the average duration is computed from
+const HISTORY: i64 = 50;
+
+/// How busy the build queue is. Counted from the builds table so every server process sees
+/// the same numbers; builds stuck in a state for over a day are left out.
+#[derive(Serialize, Debug, Clone)]
+pub struct Capacity {
+    pub queued: i64,
+    pub running: i64,
+    pub concurrency: usize,
+    /// average of the recent builds, none until a build has finished
+    pub average_build_secs: Option<u64>,
+    /// rough wait before a build pushed now starts, none without build history
+    pub estimated_wait_secs: Option<u64>,
+}
+
+/// Where a pending build stands in the queue
+#[derive(Serialize, Debug, Clone)]
+pub struct QueuePosition {
+    /// 1 for the build that starts next
+    pub position: i64,
+    /// none without build history
+    pub estimated_wait_secs: Option<u64>,
+}
+
+/// Rough wait before a build starts with `busy` builds running or queued ahead of it
+fn estimate_wait(busy: i64, concurrency: usize, average: Option<u64>) -> Option<u64> {
+    let average = average?;
+    let concurrency = concurrency.max(1) as i64;
+    if busy < concurrency {
+        return Some(0);
+    }
+
+    // each round of `concurrency` builds ahead takes about one average build
+    let rounds = (busy - concurrency) / concurrency + 1;
+    Some(rounds as u64 * average)
+}
+
+impl Capacity {
+    fn estimate_wait(&self) -> Option<u64> {
+        estimate_wait(self.queued + self.running, self.concurrency, self.average_build_secs)
+    }
+
+    /// Lines shown to the user at the top of the git push output
+    pub fn push_message(&self) -> String {
+        let mut message = format!(
+            "Build queued: {} waiting, {} of {} builders busy",
+            self.queued, self.running, self.concurrency
+        );
+
+        match self.estimated_wait_secs {
+            Some(0) => message.push_str("\nYour build should start right away"),
+            Some(secs) => message.push_str(&format!(
+                "\nEstimated wait before your build starts: about {} min",
+                (secs + 59) / 60
+            )),
+            None => {}
+        }
+
+        message
+    }
+}
+
+pub async fn current(pool: &PgPool, concurrency: usize) -> Result<Capacity, sqlx::Error> {
+    let counts = sqlx::query!(
+        r#"SELECT
+             COUNT(*) FILTER (WHERE status = 'pending') AS "queued!",
+             COUNT(*) FILTER (WHERE status = 'building') AS "running!"
+           FROM builds
+           WHERE status IN ('pending', 'building')
+           AND created_at > now() - interval '1 day'
+        "#
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let mut capacity = Capacity {
+        queued: counts.queued,
+        running: counts.running,
+        concurrency,
+        average_build_secs: average_build_secs(pool).await?,
+        estimated_wait_secs: None,
+    };
+    capacity.estimated_wait_secs = capacity.estimate_wait();
+
+    Ok(capacity)
+}
+
+/// Average duration of the recent builds, none until a build has finished
+async fn average_build_secs(pool: &PgPool) -> Result<Option<u64>, sqlx::Error> {
+    let average = sqlx::query!(
+        r#"SELECT AVG(EXTRACT(EPOCH FROM finished_at - started_at))::float8 AS average_secs
+           FROM (
+             SELECT started_at, finished_at
+             FROM builds
+             WHERE started_at IS NOT NULL AND finished_at IS NOT NULL
+             ORDER BY finished_at DESC
+             LIMIT $1
+           ) recent
+        "#,
+        HISTORY
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(average
+        .average_secs
+        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .map(|secs| secs.round() as u64))
+}
+
+/// Position of a build in the queue, none once it has left it. Builds start in the order they
+/// were queued, so the position only drops as the builds ahead start.
+pub async fn position(pool: &PgPool, build_id: Uuid, concurrency: usize) -> Result<Option<QueuePosition>, sqlx::Error> {
+    let counts = sqlx::query!(
+        r#"SELECT
+             COUNT(*) FILTER (WHERE builds.status = 'pending' AND builds.created_at < target.created_at) AS "ahead!",
+             COUNT(*) FILTER (WHERE builds.status = 'building') AS "running!"
+           FROM builds, (SELECT created_at FROM builds WHERE id = $1 AND status = 'pending') target
+           WHERE builds.status IN ('pending', 'building')
+           AND builds.created_at > now() - interval '1 day'
+           GROUP BY target.created_at
+        "#,
+        build_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    let Some(counts) = counts else {
+        return Ok(None);
+    };
+
+    Ok(Some(QueuePosition {
+        position: counts.ahead + 1,
+        estimated_wait_secs: estimate_wait(counts.ahead + counts.running, concurrency, average_build_secs(pool).await?),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A project with a build queued at each of `minutes_ago`, oldest first
+    async fn queued(pool: &PgPool, minutes_ago: &[i32]) -> Vec<Uuid> {
+        let (owner_id, project_id) = (Uuid::new_v4(), Uuid::new_v4());
+        sqlx::query("INSERT INTO project_owners (id, name) VALUES ($1, 'alice')")
+            .bind(owner_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO projects (id, owner_id, name, container_name) VALUES ($1, $2, 'blog', 'alice-blog')")
+            .bind(project_id)
+            .bind(owner_id)
+            .execute(pool)
+            .await
+            .unwrap();
+
+        let mut ids = Vec::new();
+        for minutes in minutes_ago {
+            let id = Uuid::new_v4();
+            sqlx::query("INSERT INTO builds (id, project_id, created_at) VALUES ($1, $2, now() - make_interval(mins => $3))")
+                .bind(id)
+                .bind(project_id)
+                .bind(minutes)
+                .execute(pool)
+                .await
+                .unwrap();
+            ids.push(id);
+        }
+        ids
+    }
+
+    async fn set_status(pool: &PgPool, build_id: Uuid, status: &str) {
+        sqlx::query(
+            r#"UPDATE builds SET status = $2::build_state,
+                 started_at = COALESCE(started_at, now() - interval '2 minutes'),
+                 finished_at = CASE WHEN $2 IN ('successful', 'failed') THEN now() END
+               WHERE id = $1"#,
+        )
+        .bind(build_id)
+        .bind(status)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    async fn place(pool: &PgPool, build_id: Uuid) -> Option<i64> {
+        position(pool, build_id, 1).await.unwrap().map(|position| position.position)
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn position_drops_as_the_builds_ahead_start(pool: PgPool) {
+        let builds = queued(&pool, &[3, 2, 1]).await;
+        let last = builds[2];
+        assert_eq!(place(&pool, last).await, Some(3));
+
+        set_status(&pool, builds[0], "building").await;
+        assert_eq!(place(&pool, last).await, Some(2));
+
+        set_status(&pool, builds[0], "successful").await;
+        set_status(&pool, builds[1], "building").await;
+        assert_eq!(place(&pool, last).await, Some(1));
+
+        set_status(&pool, last, "building").await;
+        assert_eq!(place(&pool, last).await, None);
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn builds_queued_later_are_not_ahead(pool: PgPool) {
+        let builds = queued(&pool, &[3, 2, 1]).await;
+
+        assert_eq!(place(&pool, builds[0]).await, Some(1));
+        assert_eq!(place(&pool, builds[1]).await, Some(2));
+    }
+
+    #[sqlx::test(migrations = false, fixtures(path = "../..", scripts("schema")))]
+    async fn wait_counts_the_running_builds_once_there_is_history(pool: PgPool) {
+        let builds = queued(&pool, &[4, 3, 2, 1]).await;
+        let last = builds[3];
+        assert_eq!(position(&pool, last, 1).await.unwrap().unwrap().estimated_wait_secs, None);
+
+        // one two minute build finished, one running and one queued ahead on a single builder
+        set_status(&pool, builds[0], "successful").await;
+        set_status(&pool, builds[1], "building").await;
+
+        let position = position(&pool, last, 1).await.unwrap().unwrap();
+        assert_eq!(position.position, 2);
+        assert_eq!(position.estimated_wait_secs, Some(2 * 120));
+    }
+
+    #[test]
+    fn wait_is_a_round_of_builds_per_builder() {
+        assert_eq!(estimate_wait(1, 2, Some(60)), Some(0));
+        assert_eq!(estimate_wait(2, 2, Some(60)), Some(60));
+        assert_eq!(estimate_wait(5, 2, Some(60)), Some(120));
+        assert_eq!(estimate_wait(5, 2, None), None);
+    }
+}
@@ -0,0 +1,4 @@
+pub mod admission;
+pub mod api;
+pub mod capacity;
+pub mod usage;
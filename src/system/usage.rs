@@ -0,0 +1,368 @@
+use std::time::{Duration, Instant};
+
+use bollard::{
+    container::{MemoryStatsStats, Stats},
+    Docker,
+};
+use futures::{stream, StreamExt};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use sqlx::PgPool;
+use thiserror::Error;
+use tokio::sync::Mutex;
+
+use crate::{
+    docker::OWNER_LABEL,
+    runtime::ContainerRuntime,
+    system::capacity::{self, Capacity},
+};
+
+/// Stats requests in flight at once. Each one keeps the daemon busy for about a second
+const STATS_CONCURRENCY: usize = 8;
+
+/// How long a sample is reused by `recent`
+const SAMPLE_TTL: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    /// the last sample and when it was taken. the lock is held while sampling, so callers
+    /// arriving meanwhile wait for that sample instead of taking their own
+    static ref LAST_SAMPLE: Mutex<Option<(Instant, PlatformUsage)>> = Mutex::new(None);
+}
+
+/// Resources one container uses
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ContainerUsage {
+    /// without the page cache the kernel can drop, like `docker stats` shows it
+    pub memory_bytes: u64,
+    /// cores busy on average over the sample
+    pub cpus: f64,
+}
+
+impl ContainerUsage {
+    pub fn from_stats(stats: &Stats) -> Self {
+        let memory = &stats.memory_stats;
+        let cache = match &memory.stats {
+            Some(MemoryStatsStats::V1(v1)) => v1.total_inactive_file,
+            Some(MemoryStatsStats::V2(v2)) => v2.inactive_file,
+            None => 0,
+        };
+        let memory_bytes = memory.usage.unwrap_or(0).saturating_sub(cache);
+
+        let cpu_delta = stats
+            .cpu_stats
+            .cpu_usage
+            .total_usage
+            .saturating_sub(stats.precpu_stats.cpu_usage.total_usage);
+        let system_delta = stats
+            .cpu_stats
+            .system_cpu_usage
+            .unwrap_or(0)
+            .saturating_sub(stats.precpu_stats.system_cpu_usage.unwrap_or(0));
+        let online = stats
+            .cpu_stats
+            .online_cpus
+            .or_else(|| stats.cpu_stats.cpu_usage.percpu_usage.as_ref().map(|usage| usage.len() as u64))
+            .unwrap_or(1);
+        let cpus = match system_delta {
+            0 => 0.0,
+            _ => cpu_delta as f64 / system_delta as f64 * online as f64,
+        };
+
+        Self { memory_bytes, cpus }
+    }
+}
+
+/// What the host has to give, as the docker daemon reports it
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HostResources {
+    pub memory_bytes: Option<u64>,
+    pub cpus: Option<u64>,
+}
+
+/// Resources the running PWS containers use against what the host has, and the build queue
+#[derive(Serialize, Debug, Clone)]
+pub struct PlatformUsage {
+    /// running containers of projects, including workers and one-off processes
+    pub containers: usize,
+    /// running containers whose stats couldn't be read, left out of the sums
+    pub unsampled: usize,
+    pub memory_used_bytes: u64,
+    /// none when the daemon doesn't report it
+    pub memory_total_bytes: Option<u64>,
+    pub memory_percent: Option<f64>,
+    pub cpu_used: f64,
+    pub cpu_total: Option<u64>,
+    pub cpu_percent: Option<f64>,
+    pub queue: Capacity,
+}
+
+impl PlatformUsage {
+    /// Sum `usages` and compare them to `host`
+    pub fn aggregate(containers: usize, usages: &[ContainerUsage], host: HostResources, queue: Capacity) -> Self {
+        let memory_used_bytes = usages.iter().map(|usage| usage.memory_bytes).sum::<u64>();
+        let cpu_used = usages.iter().map(|usage| usage.cpus).sum::<f64>();
+
+        Self {
+            containers,
+            unsampled: containers.saturating_sub(usages.len()),
+            memory_used_bytes,
+            memory_total_bytes: host.memory_bytes,
+            memory_percent: percent(memory_used_bytes as f64, host.memory_bytes),
+            cpu_used: round(cpu_used),
+            cpu_total: host.cpus,
+            cpu_percent: percent(cpu_used, host.cpus),
+            queue,
+        }
+    }
+}
+
+fn percent(used: f64, total: Option<u64>) -> Option<f64> {
+    total.filter(|total| *total > 0).map(|total| round(used / total as f64 * 100.0))
+}
+
+/// Two decimals are plenty for a sample
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+#[derive(Error, Debug)]
+pub enum UsageError {
+    #[error("Failed to query database: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("Failed to query docker: {0}")]
+    Docker(#[from] bollard::errors::Error),
+}
+
+pub async fn current(docker: &Docker, pool: &PgPool, concurrency: usize) -> Result<PlatformUsage, UsageError> {
+    let labels = [OWNER_LABEL.to_string()];
+    let (containers, info, queue) = tokio::join!(
+        docker.list_labeled_containers(&labels),
+        docker.info(),
+        capacity::current(pool, concurrency),
+    );
+    let (containers, info, queue) = (containers?, info?, queue?);
+
+    let running = containers
+        .into_iter()
+        .filter(|container| container.state.as_deref() == Some("running"))
+        .filter_map(|container| container.id)
+        .collect::<Vec<_>>();
+
+    let usages = stream::iter(running.clone())
+        .map(|id| async move {
+            match docker.container_stats(&id).await {
+                Ok(stats) => Some(ContainerUsage::from_stats(&stats)),
+                Err(err) => {
+                    // it may have stopped since it was listed
+                    tracing::warn!(?err, id, "Can't get container stats");
+                    None
+                }
+            }
+        })
+        .buffer_unordered(STATS_CONCURRENCY)
+        .filter_map(|usage| async move { usage })
+        .collect::<Vec<_>>()
+        .await;
+
+    let host = HostResources {
+        memory_bytes: info.mem_total.and_then(|bytes| u64::try_from(bytes).ok()),
+        cpus: info.ncpu.and_then(|cpus| u64::try_from(cpus).ok()),
+    };
+
+    Ok(PlatformUsage::aggregate(running.len(), &usages, host, queue))
+}
+
+/// A sample at most `SAMPLE_TTL` old, for callers that check usage often, e.g. on every build
+pub async fn recent(docker: &Docker, pool: &PgPool, concurrency: usize) -> Result<PlatformUsage, UsageError> {
+    let mut last = LAST_SAMPLE.lock().await;
+    if let Some((taken, usage)) = last.as_ref() {
+        if taken.elapsed() < SAMPLE_TTL {
+            return Ok(usage.clone());
+        }
+    }
+
+    let usage = current(docker, pool, concurrency).await?;
+    *last = Some((Instant::now(), usage.clone()));
+
+    Ok(usage)
+}
+
+/// Have `recent` return `usage` until it expires, for tests that can't reach a daemon
+#[cfg(test)]
+pub async fn set_recent(usage: PlatformUsage) {
+    *LAST_SAMPLE.lock().await = Some((Instant::now(), usage));
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    const MIB: u64 = 1024 * 1024;
+
+    /// Every counter of cgroup v2 memory stats, all of them zero but the inactive file pages
+    fn memory_v2(inactive_file: u64) -> Value {
+        let mut stats = [
+            "anon", "file", "kernel_stack", "slab", "sock", "shmem", "file_mapped", "file_dirty",
+            "file_writeback", "anon_thp", "inactive_anon", "active_anon", "inactive_file", "active_file",
+            "unevictable", "slab_reclaimable", "slab_unreclaimable", "pgfault", "pgmajfault",
+            "workingset_refault", "workingset_activate", "workingset_nodereclaim", "pgrefill", "pgscan",
+            "pgsteal", "pgactivate", "pgdeactivate", "pglazyfree", "pglazyfreed", "thp_fault_alloc",
+            "thp_collapse_alloc",
+        ]
+        .into_iter()
+        .map(|field| (field.to_string(), json!(0)))
+        .collect::<serde_json::Map<_, _>>();
+        stats.insert("inactive_file".to_string(), json!(inactive_file));
+        Value::Object(stats)
+    }
+
+    fn cpu(total_usage: u64, system_cpu_usage: u64, online_cpus: Option<u64>, percpu: usize) -> Value {
+        json!({
+            "cpu_usage": {
+                "total_usage": total_usage,
+                "usage_in_usermode": 0,
+                "usage_in_kernelmode": 0,
+                "percpu_usage": vec![0; percpu],
+            },
+            "system_cpu_usage": system_cpu_usage,
+            "online_cpus": online_cpus,
+            "throttling_data": { "periods": 0, "throttled_periods": 0, "throttled_time": 0 },
+        })
+    }
+
+    /// Stats as the daemon streams them, `cpu` read a second after `precpu`
+    fn stats(memory: Value, cpu: Value, precpu: Value) -> Stats {
+        serde_json::from_value(json!({
+            "read": "2024-01-01T00:00:01Z",
+            "preread": "2024-01-01T00:00:00Z",
+            "num_procs": 0,
+            "pids_stats": {},
+            "memory_stats": memory,
+            "blkio_stats": {},
+            "cpu_stats": cpu,
+            "precpu_stats": precpu,
+            "storage_stats": {},
+            "name": "/alice-blog",
+            "id": "0123456789ab",
+        }))
+        .unwrap()
+    }
+
+    fn queue() -> Capacity {
+        Capacity {
+            queued: 0,
+            running: 0,
+            concurrency: 1,
+            average_build_secs: None,
+            estimated_wait_secs: None,
+        }
+    }
+
+    #[test]
+    fn memory_leaves_out_the_page_cache() {
+        let stats = stats(
+            json!({ "usage": 300 * MIB, "stats": memory_v2(100 * MIB) }),
+            cpu(0, 0, Some(2), 0),
+            cpu(0, 0, Some(2), 0),
+        );
+
+        assert_eq!(ContainerUsage::from_stats(&stats).memory_bytes, 200 * MIB);
+    }
+
+    #[test]
+    fn memory_without_a_usage_is_nothing() {
+        // what the daemon sends for a container that stopped while its stats were read
+        let stats = stats(json!({}), cpu(0, 0, None, 0), cpu(0, 0, None, 0));
+
+        assert_eq!(ContainerUsage::from_stats(&stats), ContainerUsage::default());
+    }
+
+    #[test]
+    fn cpus_are_the_share_of_the_host_times_its_cores() {
+        // a quarter of the host's time on 4 cores is one core busy
+        let stats = stats(json!({}), cpu(1_250, 10_000, Some(4), 0), cpu(1_000, 9_000, Some(4), 0));
+
+        assert_eq!(ContainerUsage::from_stats(&stats).cpus, 1.0);
+    }
+
+    #[test]
+    fn cores_are_counted_from_the_per_cpu_usage_of_older_daemons() {
+        let stats = stats(json!({}), cpu(500, 1_000, None, 2), cpu(0, 0, None, 2));
+
+        assert_eq!(ContainerUsage::from_stats(&stats).cpus, 1.0);
+    }
+
+    #[test]
+    fn the_first_sample_has_no_cpu() {
+        // the daemon sends empty previous stats with the first sample, and the system time
+        // doesn't move between them
+        let stats = stats(json!({}), cpu(1_000, 9_000, Some(4), 0), cpu(1_000, 9_000, Some(4), 0));
+
+        assert_eq!(ContainerUsage::from_stats(&stats).cpus, 0.0);
+    }
+
+    #[test]
+    fn aggregate_sums_the_sampled_containers() {
+        let usages = [
+            ContainerUsage {
+                memory_bytes: 256 * MIB,
+                cpus: 0.5,
+            },
+            ContainerUsage {
+                memory_bytes: 768 * MIB,
+                cpus: 0.25,
+            },
+        ];
+        let host = HostResources {
+            memory_bytes: Some(4096 * MIB),
+            cpus: Some(2),
+        };
+
+        let usage = PlatformUsage::aggregate(3, &usages, host, queue());
+
+        assert_eq!(usage.containers, 3);
+        assert_eq!(usage.unsampled, 1);
+        assert_eq!(usage.memory_used_bytes, 1024 * MIB);
+        assert_eq!(usage.memory_percent, Some(25.0));
+        assert_eq!(usage.cpu_used, 0.75);
+        assert_eq!(usage.cpu_percent, Some(37.5));
+    }
+
+    #[test]
+    fn aggregate_rounds_to_two_decimals() {
+        let usages = [ContainerUsage {
+            memory_bytes: 1,
+            cpus: 1.0 / 3.0,
+        }];
+        let host = HostResources {
+            memory_bytes: Some(3),
+            cpus: Some(1),
+        };
+
+        let usage = PlatformUsage::aggregate(1, &usages, host, queue());
+
+        assert_eq!(usage.cpu_used, 0.33);
+        assert_eq!(usage.cpu_percent, Some(33.33));
+        assert_eq!(usage.memory_percent, Some(33.33));
+    }
+
+    #[test]
+    fn aggregate_has_no_percent_without_host_totals() {
+        let usages = [ContainerUsage {
+            memory_bytes: MIB,
+            cpus: 1.0,
+        }];
+        let host = HostResources {
+            memory_bytes: None,
+            cpus: Some(0),
+        };
+
+        let usage = PlatformUsage::aggregate(1, &usages, host, queue());
+
+        assert_eq!(usage.memory_percent, None);
+        assert_eq!(usage.cpu_total, Some(0));
+        assert_eq!(usage.cpu_percent, None);
+    }
+}
@@ -1,16 +1,21 @@
-use std::io::{self, Empty, Stderr, StderrLock, Stdout, StdoutLock};
+use std::{
+    io::{self, Empty, Stderr, StderrLock, Stdout, StdoutLock},
+    sync::OnceLock,
+};
 
+use axum::http::Request;
 use config::Config;
-use tracing::{Level, Metadata};
+use thiserror::Error;
+use tracing::{Level, Metadata, Span};
 
 use tower_http::{
     classify::{ServerErrorsAsFailures, SharedClassifier},
-    trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer},
+    trace::{DefaultOnResponse, MakeSpan, TraceLayer},
 };
 use tracing_subscriber::{
-    filter::LevelFilter,
-    fmt::{writer::MakeWriterExt, MakeWriter},
-    EnvFilter,
+    filter::ParseError,
+    fmt::MakeWriter,
+    reload, EnvFilter, Layer, Registry,
 };
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
@@ -85,64 +90,127 @@ impl<'a> MakeWriter<'a> for LogRecorder {
         StdioLock::Stdout(self.stdout.lock())
     }
 }
-pub fn init_tracing() {
-    let log_dev = Config::builder()
+/// How log lines are written, set with `log.format` or `LOG_FORMAT`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// one json object per line with the fields of the event and its spans, for log collectors
+    Json,
+    /// multi-line and colored, for reading in a terminal
+    Pretty,
+}
+
+impl LogFormat {
+    pub fn parse(format: &str) -> Option<Self> {
+        match format.trim().to_lowercase().as_str() {
+            "json" => Some(LogFormat::Json),
+            "pretty" => Some(LogFormat::Pretty),
+            _ => None,
+        }
+    }
+}
+
+/// Read before the rest of the configuration, so its errors are logged. `log.dev` picks the
+/// pretty format when no format is set
+fn log_format() -> LogFormat {
+    let Ok(config) = Config::builder()
         .add_source(config::File::with_name("configuration"))
         .add_source(config::Environment::default().separator("_"))
         .build()
-        .map(|c| c.get_bool("log.dev").unwrap_or(false))
-        .unwrap_or(false);
-
-    let filter = EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| "debug".into())
-        .max_level_hint();
-
-    let level = match filter {
-        Some(LevelFilter::OFF) | None => None,
-        Some(LevelFilter::ERROR) => Some(Level::ERROR),
-        Some(LevelFilter::WARN) => Some(Level::WARN),
-        Some(LevelFilter::INFO) => Some(Level::INFO),
-        Some(LevelFilter::DEBUG) => Some(Level::DEBUG),
-        Some(LevelFilter::TRACE) => Some(Level::TRACE),
+    else {
+        return LogFormat::Json;
     };
 
-    if let Some(level) = level {
-        match log_dev {
-            true => {
-                tracing_subscriber::fmt()
-                    .pretty()
-                    .with_max_level(level)
-                    .with_writer(LogRecorder::new())
-                    .init();
-            }
-            false => {
-                tracing_subscriber::registry()
-                    .with(LevelFilter::TRACE)
-                    // .with(tracing_bunyan_formatter::JsonStorageLayer)
-                    // .with(
-                    //     tracing_bunyan_formatter::BunyanFormattingLayer::new(
-                    //         "pemasak-infra".into(),
-                    //         LogRecorder::new().with_max_level(level),
-                    //     ), // .skip_fields(["hostname"].into_iter())
-                    //        // .expect("failed to init bunyan formatter"),
-                    // )
-                    // // use tracing_subscriber stdout without bunyan
-                    .with(
-                        tracing_subscriber::fmt::layer()
-                            .json()
-                            .with_writer(LogRecorder::new().with_max_level(level))
-                            .with_line_number(true)
-                            .with_file(true)
-                            .with_ansi(false),
-                    )
-                    .init();
-            }
-        }
+    match config.get_string("log.format") {
+        Ok(format) => LogFormat::parse(&format).unwrap_or_else(|| {
+            eprintln!("Unknown log format {format}, expected json or pretty");
+            LogFormat::Json
+        }),
+        Err(_) if config.get_bool("log.dev").unwrap_or(false) => LogFormat::Pretty,
+        Err(_) => LogFormat::Json,
+    }
+}
+
+/// Swaps the filter of the running subscriber, set once by `init_tracing`
+static LOG_FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+#[derive(Error, Debug)]
+pub enum LogFilterError {
+    #[error("Invalid filter: {0}")]
+    Invalid(#[from] ParseError),
+    #[error("Logging isn't set up")]
+    NotInitialized,
+    #[error("Failed to apply filter: {0}")]
+    Reload(#[from] reload::Error),
+}
+
+/// Filter of the logs in use, in `RUST_LOG` syntax
+pub fn log_filter() -> Option<String> {
+    LOG_FILTER.get()?.with_current(|filter| filter.to_string()).ok()
+}
+
+/// Replace the filter of the logs without a restart, e.g. `info,pemasak_infra::docker=debug`
+/// to follow builds closely during an incident. Lasts until the next restart, which goes back
+/// to `RUST_LOG`.
+pub fn set_log_filter(directives: &str) -> Result<(), LogFilterError> {
+    let filter = EnvFilter::builder().parse(directives)?;
+    let handle = LOG_FILTER.get().ok_or(LogFilterError::NotInitialized)?;
+    handle.reload(filter)?;
+    Ok(())
+}
+
+pub fn init_tracing() {
+    let format = log_format();
+
+    // the filter is its own layer so it can be swapped at runtime
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| "debug".into());
+    let (filter, handle) = reload::Layer::new(filter);
+    let _ = LOG_FILTER.set(handle);
+
+    let layer = match format {
+        LogFormat::Pretty => tracing_subscriber::fmt::layer()
+            .pretty()
+            .with_writer(LogRecorder::new())
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_writer(LogRecorder::new())
+            .with_line_number(true)
+            .with_file(true)
+            // the request id and the other fields of the spans an event is in
+            .with_current_span(true)
+            .with_span_list(true)
+            .with_ansi(false)
+            .boxed(),
+    };
+
+    tracing_subscriber::registry().with(filter).with(layer).init();
+}
+
+/// Span of every http request. It carries the id `SetRequestIdLayer` gave the request, so the
+/// log lines of one request can be found by it
+#[derive(Debug, Clone, Default)]
+pub struct RequestSpan;
+
+impl<B> MakeSpan<B> for RequestSpan {
+    fn make_span(&mut self, request: &Request<B>) -> Span {
+        let request_id = request
+            .headers()
+            .get("x-request-id")
+            .and_then(|id| id.to_str().ok())
+            .unwrap_or_default();
+
+        tracing::info_span!(
+            "request",
+            method = %request.method(),
+            uri = %request.uri(),
+            version = ?request.version(),
+            request_id,
+        )
     }
 }
 
-pub fn http_trace_layer() -> TraceLayer<SharedClassifier<ServerErrorsAsFailures>> {
+pub fn http_trace_layer() -> TraceLayer<SharedClassifier<ServerErrorsAsFailures>, RequestSpan> {
     TraceLayer::new_for_http()
-        .make_span_with(DefaultMakeSpan::new().level(Level::INFO))
+        .make_span_with(RequestSpan)
         .on_response(DefaultOnResponse::new().level(Level::INFO))
 }
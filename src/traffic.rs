@@ -0,0 +1,180 @@
+use std::{collections::HashMap, io::SeekFrom, os::unix::fs::MetadataExt, time::Duration};
+
+use anyhow::Result;
+use chrono::{DateTime, DurationRound, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use uuid::Uuid;
+
+use crate::docker::docker_name;
+
+/// Most of the log read at once, a log that fell behind is caught up over several reads
+const MAX_READ_BYTES: u64 = 4 * 1024 * 1024;
+
+/// The fields of a Traefik json access log line that are counted
+#[derive(Deserialize, Debug)]
+struct AccessLogLine {
+    #[serde(rename = "RouterName")]
+    router_name: Option<String>,
+    #[serde(rename = "DownstreamStatus")]
+    status: Option<u16>,
+    #[serde(rename = "StartUTC")]
+    start: Option<DateTime<Utc>>,
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+struct Counts {
+    requests: i64,
+    status_2xx: i64,
+    status_3xx: i64,
+    status_4xx: i64,
+    status_5xx: i64,
+}
+
+impl Counts {
+    fn add(&mut self, status: u16) {
+        self.requests += 1;
+        match status / 100 {
+            2 => self.status_2xx += 1,
+            3 => self.status_3xx += 1,
+            4 => self.status_4xx += 1,
+            5 => self.status_5xx += 1,
+            _ => {}
+        }
+    }
+}
+
+/// Count the requests of every project from the Traefik access log at `path`, checking for new
+/// lines every `interval` seconds. `prefix` is the container prefix of this server, routers of
+/// other servers on the same Traefik are skipped.
+pub async fn run_ingest(pool: PgPool, path: String, prefix: String, interval: u64) {
+    loop {
+        loop {
+            match ingest(&pool, &path, &prefix).await {
+                Ok(true) => continue,
+                Ok(false) => break,
+                Err(err) => {
+                    tracing::error!(?err, "Can't count traffic: Failed to read access log");
+                    break;
+                }
+            }
+        }
+
+        tokio::time::sleep(Duration::from_secs(interval)).await;
+    }
+}
+
+/// Count the lines added to the access log since the last call, returns true when there is
+/// more to read. The counts and the new offset are stored in one transaction, so no line is
+/// counted twice when the server restarts halfway.
+async fn ingest(pool: &PgPool, path: &str, prefix: &str) -> Result<bool> {
+    let mut file = match tokio::fs::File::open(path).await {
+        Ok(file) => file,
+        // Traefik hasn't served anything yet
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err.into()),
+    };
+    let metadata = file.metadata().await?;
+    let inode = metadata.ino() as i64;
+
+    let mut tx = pool.begin().await?;
+    let stored = sqlx::query!(
+        r#"SELECT inode, "offset" FROM traffic_log_offsets WHERE path = $1 FOR UPDATE"#,
+        path
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    // a rotated log is a new file, a truncated one is shorter than what was read of it. both
+    // are read from the start
+    let offset = match stored {
+        Some(stored) if stored.inode == inode && stored.offset as u64 <= metadata.len() => stored.offset as u64,
+        _ => 0,
+    };
+    if offset >= metadata.len() {
+        return Ok(false);
+    }
+
+    file.seek(SeekFrom::Start(offset)).await?;
+    let mut buf = Vec::new();
+    (&mut file).take(MAX_READ_BYTES).read_to_end(&mut buf).await?;
+
+    // a line Traefik is still writing is counted on the next read
+    let end = match buf.iter().rposition(|byte| *byte == b'\n') {
+        Some(end) => end + 1,
+        None if (buf.len() as u64) < MAX_READ_BYTES => return Ok(false),
+        // a line this long isn't an access log line
+        None => buf.len(),
+    };
+
+    let projects = project_routers(pool, prefix).await?;
+    let mut counts: HashMap<(Uuid, DateTime<Utc>), Counts> = HashMap::new();
+    for line in buf[..end].split(|byte| *byte == b'\n') {
+        let Ok(line) = serde_json::from_slice::<AccessLogLine>(line) else {
+            continue;
+        };
+        let (Some(router), Some(status), Some(start)) = (line.router_name, line.status, line.start) else {
+            continue;
+        };
+        // routers of containers are named `<container name>@docker`
+        let router = router.split('@').next().unwrap_or(&router);
+        let Some(project_id) = projects.get(router) else {
+            continue;
+        };
+
+        let hour = start.duration_trunc(chrono::Duration::hours(1)).unwrap_or(start);
+        counts.entry((*project_id, hour)).or_default().add(status);
+    }
+
+    for ((project_id, hour), counts) in counts {
+        sqlx::query!(
+            r#"INSERT INTO project_traffic (project_id, hour, requests, status_2xx, status_3xx, status_4xx, status_5xx)
+               VALUES ($1, $2, $3, $4, $5, $6, $7)
+               ON CONFLICT (project_id, hour) DO UPDATE SET
+                 requests = project_traffic.requests + EXCLUDED.requests,
+                 status_2xx = project_traffic.status_2xx + EXCLUDED.status_2xx,
+                 status_3xx = project_traffic.status_3xx + EXCLUDED.status_3xx,
+                 status_4xx = project_traffic.status_4xx + EXCLUDED.status_4xx,
+                 status_5xx = project_traffic.status_5xx + EXCLUDED.status_5xx
+            "#,
+            project_id,
+            hour,
+            counts.requests,
+            counts.status_2xx,
+            counts.status_3xx,
+            counts.status_4xx,
+            counts.status_5xx,
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let offset = offset + end as u64;
+    sqlx::query!(
+        r#"INSERT INTO traffic_log_offsets (path, inode, "offset")
+           VALUES ($1, $2, $3)
+           ON CONFLICT (path) DO UPDATE SET inode = EXCLUDED.inode, "offset" = EXCLUDED."offset", updated_at = now()
+        "#,
+        path,
+        inode,
+        offset as i64,
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(offset < metadata.len())
+}
+
+/// Projects by the name of their Traefik router, which `build_docker` names after the container
+async fn project_routers(pool: &PgPool, prefix: &str) -> Result<HashMap<String, Uuid>, sqlx::Error> {
+    let projects = sqlx::query!("SELECT id, container_name FROM projects")
+        .fetch_all(pool)
+        .await?;
+
+    Ok(projects
+        .into_iter()
+        .map(|project| (docker_name(prefix, &project.container_name), project.id))
+        .collect())
+}
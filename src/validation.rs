@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use axum::{
+    body::HttpBody,
+    extract::FromRequest,
+    response::Response,
+    BoxError, Json,
+};
+use garde::{Unvalidated, Validate};
+use hyper::{Body, Request, StatusCode};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{de::DeserializeOwned, Serialize};
+use url::Url;
+
+lazy_static! {
+    static ref ENV_KEY_REGEX: Regex = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+}
+
+/// A JSON body that passed its garde rules. A body that doesn't is answered with 400 and the
+/// errors of each field.
+#[derive(Debug)]
+pub struct ValidJson<T>(pub T);
+
+#[derive(Serialize, Debug)]
+struct FieldError {
+    /// path of the field, e.g. `owner` or `environs`
+    field: String,
+    message: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ValidationErrorResponse {
+    message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<FieldError>,
+}
+
+fn error_response(status: StatusCode, message: String, errors: Vec<FieldError>) -> Response<Body> {
+    let json = serde_json::to_string(&ValidationErrorResponse { message, errors }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .body(Body::from(json))
+        .unwrap()
+}
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for ValidJson<T>
+where
+    T: DeserializeOwned + Validate<Context = ()>,
+    S: Send + Sync,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = Response<Body>;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(body) = Json::<Unvalidated<T>>::from_request(req, state)
+            .await
+            .map_err(|rejection| error_response(rejection.status(), rejection.body_text(), Vec::new()))?;
+
+        match body.validate(&()) {
+            Ok(valid) => Ok(Self(valid.into_inner())),
+            Err(errors) => {
+                let errors = errors
+                    .iter()
+                    .map(|(path, error)| FieldError {
+                        field: path.to_string(),
+                        message: error.to_string(),
+                    })
+                    .collect::<Vec<_>>();
+
+                Err(error_response(
+                    StatusCode::BAD_REQUEST,
+                    format!("{} fields are invalid", errors.len()),
+                    errors,
+                ))
+            }
+        }
+    }
+}
+
+/// Name of an environment variable, as shells accept it
+pub fn env_key(value: &str, _ctx: &()) -> garde::Result {
+    if !ENV_KEY_REGEX.is_match(value) {
+        return Err(garde::Error::new(
+            "Key can only contain alphanumeric characters and underscores, and can't start with a digit",
+        ));
+    }
+    Ok(())
+}
+
+/// Environment variables set at once, every key valid and no value empty. The message names
+/// every variable that isn't.
+pub fn environs(value: &BTreeMap<String, String>, ctx: &()) -> garde::Result {
+    let invalid = value
+        .iter()
+        .filter_map(|(key, value)| match (env_key(key, ctx), value.is_empty()) {
+            (Err(err), _) => Some(format!("{key}: {err}")),
+            (Ok(()), true) => Some(format!("{key}: Value cannot be empty")),
+            (Ok(()), false) => None,
+        })
+        .collect::<Vec<_>>();
+
+    match invalid.is_empty() {
+        true => Ok(()),
+        false => Err(garde::Error::new(invalid.join("; "))),
+    }
+}
+
+/// An http or https url, or nothing. Blank counts as nothing, handlers clear the field then
+pub fn http_url(value: &Option<String>, _ctx: &()) -> garde::Result {
+    let Some(value) = value.as_deref().map(str::trim).filter(|value| !value.is_empty()) else {
+        return Ok(());
+    };
+
+    match Url::parse(value) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(()),
+        _ => Err(garde::Error::new("Has to be an http or https url")),
+    }
+}
@@ -1,5 +1,6 @@
 import { useNavigate, useRouter, useSearch } from "@tanstack/react-router";
 import { FC, ReactElement, ReactNode, createContext, useContext, useEffect, useState } from "react";
+import { resetCsrfToken } from "@/lib/utils";
 
 export const AuthContext = createContext({
     user: {
@@ -69,6 +70,7 @@ export default function AuthProvider({ children }: AuthProviderProps): ReactElem
             throw data
         }
 
+        resetCsrfToken()
         await refreshAuthState()
         // I know this is terrible, I hate React, please make setState awaitable holy %@!#
         // @ts-ignore
@@ -4,3 +4,18 @@ import { twMerge } from "tailwind-merge"
 export function cn(...inputs: ClassValue[]) {
   return twMerge(clsx(inputs))
 }
+
+let csrfToken: Promise<string> | undefined
+
+// CSRF token of the session, every POST sends it in the X-CSRF-Token header
+export function getCsrfToken(): Promise<string> {
+  csrfToken ??= fetch(`${import.meta.env.VITE_API_URL}/csrf`, { credentials: "include" })
+    .then((res) => res.json())
+    .then((data) => data.token)
+  return csrfToken
+}
+
+// logging in starts a new session with a new token
+export function resetCsrfToken() {
+  csrfToken = undefined
+}
@@ -2,6 +2,7 @@ import { Button } from '@/components/ui/button';
 import { Input } from '@/components/ui/input';
 import { createLazyFileRoute } from '@tanstack/react-router';
 import { Controller, useForm } from 'react-hook-form';
+import { getCsrfToken } from '@/lib/utils';
 
 import {
   Select,
@@ -48,7 +49,8 @@ function NewProject() {
     const response = await fetch(`${import.meta.env.VITE_API_URL}/project/new`, {
       credentials: "include",
       headers: {
-        "Content-Type": "application/json"
+        "Content-Type": "application/json",
+        "X-CSRF-Token": await getCsrfToken(),
       },
       method: "POST",
       body: JSON.stringify({
@@ -7,6 +7,7 @@ import { createLazyFileRoute, useParams } from '@tanstack/react-router'
 import React, { useEffect, useState } from 'react'
 import { useForm } from 'react-hook-form'
 import useSWR, { useSWRConfig } from 'swr'
+import { getCsrfToken } from '@/lib/utils'
 
 export const Route = createLazyFileRoute('/project/$owner/$project/env')({
   component: ProjectDashboardEnv
@@ -19,7 +20,8 @@ function EnvironmentVariable({ envKey, envValue, owner, project }: { envKey: str
     await fetch(`${import.meta.env.VITE_API_URL}/project/${owner}/${project}/env/delete`, {
       credentials: "include",
       headers: {
-        "Content-Type": "application/json"
+        "Content-Type": "application/json",
+        "X-CSRF-Token": await getCsrfToken(),
       },
       method: "POST",
       body: JSON.stringify({
@@ -87,7 +89,8 @@ function ModifyEnvironDialog({ owner, project, envKey, envValue, children }: { o
     await fetch(`${import.meta.env.VITE_API_URL}/project/${owner}/${project}/env`, {
       credentials: "include",
       headers: {
-        "Content-Type": "application/json"
+        "Content-Type": "application/json",
+        "X-CSRF-Token": await getCsrfToken(),
       },
       method: "POST",
       body: JSON.stringify({
@@ -2,12 +2,13 @@ import { Button } from '@/components/ui/button'
 import { Dialog, DialogClose, DialogContent, DialogDescription, DialogFooter, DialogHeader, DialogTitle, DialogTrigger } from '@/components/ui/dialog'
 import { createLazyFileRoute, useNavigate, useParams } from '@tanstack/react-router'
 import toast from 'react-hot-toast';
+import { getCsrfToken } from '@/lib/utils';
 
 export const Route = createLazyFileRoute('/project/$owner/$project/settings')({
   component: ProjectDashboardSettings
 })
 
-const apiFetcher = (input: URL | RequestInfo, options?: RequestInit) => {
+const apiFetcher = async (input: URL | RequestInfo, options?: RequestInit) => {
   return fetch(
     input,
     {
@@ -15,7 +16,8 @@ const apiFetcher = (input: URL | RequestInfo, options?: RequestInit) => {
       redirect: "follow",
       credentials: "include",
       headers: {
-        "Content-Type": "application/json"
+        "Content-Type": "application/json",
+        "X-CSRF-Token": await getCsrfToken(),
       },
     }
   )